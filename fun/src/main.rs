@@ -1,15 +1,20 @@
 use crate::support::{App, AppBuilder, AppEvent};
 
-use crossbeam_channel::{bounded, unbounded};
-use fun_capture::capture::quartz::QuartzCapture;
-use fun_capture::capture::{Capture, CaptureOpts, Frame};
+use crossbeam_channel::{bounded, unbounded, Sender};
+use fun_capture::capture::replay::{ReplayCapture, Timing};
+use fun_capture::capture::{
+  open, BoxedCapture, Capture, CaptureOpts, Converted, DynCapture, Frame, PixelFormat,
+  VideoFrame,
+};
 use fun_capture::display::{get_primary, Display};
+use fun_capture::{Error, Result};
 use glium::texture::Texture2d;
 use glium::texture::{CompressedSrgbTexture2d, RawImage2d};
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior};
 use glium::GlObject;
 use imgui::*;
 use imgui_glium_renderer::Texture;
+use std::env;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
@@ -24,36 +29,39 @@ fn main() {
   let mut frame_last = Instant::now();
   let mut frame_max = 0.0;
 
+  // Opened here so failures show up straight away, then handed to the frame thread.
+  // Plays back a recording instead, if given one.
+  let (title, capture): (String, Box<dyn DynCapture + Send>) = match env::args().nth(1) {
+    Some(path) => {
+      let capture = ReplayCapture::open(path, Timing::Original).unwrap();
+      let opts = rgba_opts(capture.display());
+      (
+        "fun (replay)".to_owned(),
+        Box::new(Converted::new(capture, &opts)),
+      )
+    }
+    None => {
+      let capture = open_primary().unwrap();
+      (format!("fun ({})", capture.backend()), Box::new(capture))
+    }
+  };
+
   let app = AppBuilder::new()
     .size(500.0, 400.0) //
-    .title("fun")
+    .title(&title)
     .build();
 
   let mut target_id = None;
+  let mut target_size = [0.0; 2];
 
-  let display = get_primary().unwrap();
   let (tx, rx) = unbounded();
-
-  spawn(move || loop {
-    let display = get_primary().unwrap();
-    let mut capture = QuartzCapture::new(CaptureOpts::new(display)).unwrap();
-    let display = get_primary().unwrap();
-
-    match capture.frame() {
-      Frame::Ready(frame) => {
-        let image =
-          RawImage2d::from_raw_rgba(frame.to_vec(), (display.width(), display.height()));
-
-        tx.send(image).ok();
-      }
-      Frame::Blocking => (),
-    }
-  });
+  spawn(move || send_frames(capture, tx));
 
   app.run(move |event| {
     match event {
       AppEvent::Update(gl, renderer) => {
         if let Ok(image) = rx.try_recv() {
+          target_size = [image.width as f32, image.height as f32];
           let texture = Texture2d::new(gl, image).unwrap();
           let texture = Texture {
             texture: Rc::new(texture),
@@ -79,8 +87,7 @@ fn main() {
             //
 
             if let Some(target_id) = target_id {
-              Image::new(target_id, [display.width() as f32, display.height() as f32])
-                .build(ui)
+              Image::new(target_id, target_size).build(ui)
             };
           });
 
@@ -114,3 +121,54 @@ fn main() {
     true
   });
 }
+
+/// Asks for the RGBA glium takes, which the capture converts to if it has to.
+fn rgba_opts(display: Display) -> CaptureOpts {
+  let mut opts = CaptureOpts::new(display);
+  opts.pixel_format(PixelFormat::Rgba8888);
+  opts
+}
+
+fn open_primary() -> Result<BoxedCapture> {
+  open(rgba_opts(get_primary()?))
+}
+
+/// Sends each frame as an image until the capture stops or fails, or the app goes
+/// away. Captures of a display that changed are opened again on the primary display.
+fn send_frames(
+  mut capture: Box<dyn DynCapture + Send>,
+  tx: Sender<RawImage2d<'static, u8>>,
+) {
+  loop {
+    let changed = match capture.next_frame() {
+      Ok(Frame::Ready(frame)) => {
+        if tx.send(to_image(&frame)).is_err() {
+          return;
+        }
+        false
+      }
+      Ok(Frame::Stopped) => return,
+      Ok(_) => false,
+      Err(Error::DisplayChanged(_)) => true,
+      Err(error) => return eprintln!("Capture failed: {}", error),
+    };
+
+    if changed {
+      capture = match open_primary() {
+        Ok(capture) => Box::new(capture),
+        Err(error) => return eprintln!("Failed to reopen the capture: {}", error),
+      };
+    }
+  }
+}
+
+/// Repacks an RGBA frame tightly, as glium expects, dropping row padding.
+fn to_image<F: VideoFrame>(frame: &F) -> RawImage2d<'static, u8> {
+  let (width, height) = (frame.width(), frame.height());
+  let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+  for row in frame.rows() {
+    data.extend_from_slice(row);
+  }
+
+  RawImage2d::from_raw_rgba(data, (width, height))
+}
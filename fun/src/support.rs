@@ -1,6 +1,3 @@
-use fun_capture::capture::quartz::QuartzCapture;
-use fun_capture::capture::CaptureOpts;
-use fun_capture::display::get_primary;
 use glium::glutin::dpi::LogicalSize;
 use glium::glutin::event::{Event, WindowEvent};
 use glium::glutin::event_loop::{ControlFlow, EventLoop};
@@ -1,26 +1,33 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 
-use fun_capture::capture::{Capture, CaptureOpts, Frame};
-use fun_capture::display::get_primary;
-use std::sync::atomic::{AtomicU32, Ordering};
+use fun_capture::capture::{Capture, CaptureOpts};
+use fun_capture::display::Display;
 
 pub fn benchmark(c: &mut Criterion) {
+  c.bench_function("synthetic", |b| {
+    use fun_capture::capture::synthetic::{Pattern, SyntheticCapture};
+
+    let display = Display::synthetic(1920, 1080);
+    let mut capture = SyntheticCapture::new(CaptureOpts::new(display), Pattern::Gradient);
+
+    b.iter(move || {
+      capture.frame().unwrap();
+    });
+  });
+
   #[cfg(target_os = "macos")]
   c.bench_function("quartz", |b| {
     use fun_capture::capture::quartz::QuartzCapture;
+    use fun_capture::capture::Frame;
+    use fun_capture::display::get_primary;
 
     let display = get_primary().unwrap();
     let mut capture = QuartzCapture::new(CaptureOpts::new(display)).unwrap();
-    let bad_frames = AtomicU32::new(0);
 
+    // A quiet screen is idle rather than ready, so only stopping counts as a failure.
     b.iter(move || {
-      match capture.frame() {
-        Frame::Ready(_) => 0,
-        Frame::Blocking => bad_frames.fetch_add(1, Ordering::SeqCst),
-      };
-
-      if bad_frames.load(Ordering::SeqCst) > 10 {
-        panic!("Too many empty frames");
+      if let Frame::Stopped = capture.frame().unwrap() {
+        panic!("Capture stopped");
       }
     });
   });
@@ -28,12 +35,30 @@ pub fn benchmark(c: &mut Criterion) {
   #[cfg(target_os = "windows")]
   c.bench_function("windows_dc", |b| {
     use fun_capture::capture::windows_dc::DisplayContextCapture;
+    use fun_capture::display::get_primary;
 
     let display = get_primary().unwrap();
-    let mut capture = DisplayContextCapture::new(CaptureOpts::new(display));
+    let mut capture = DisplayContextCapture::new(CaptureOpts::new(display)).unwrap();
 
     b.iter(move || {
-      capture.frame();
+      capture.frame().unwrap();
+    });
+  });
+
+  #[cfg(all(target_os = "linux", feature = "x11"))]
+  c.bench_function("x11", |b| {
+    use fun_capture::capture::x11::X11Capture;
+    use fun_capture::capture::Frame;
+    use fun_capture::display::get_primary;
+
+    let display = get_primary().unwrap();
+    let mut capture = X11Capture::new(CaptureOpts::new(display)).unwrap();
+
+    // A quiet screen is idle rather than ready, so only stopping counts as a failure.
+    b.iter(move || {
+      if let Frame::Stopped = capture.frame().unwrap() {
+        panic!("Capture stopped");
+      }
     });
   });
 }
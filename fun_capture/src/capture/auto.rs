@@ -0,0 +1,519 @@
+//! Picking a backend at runtime, so callers needn't name one behind `cfg` gates.
+
+use std::borrow::Cow;
+use std::env;
+use std::fmt::{self, Debug, Formatter};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::capture::desktop::DesktopCapture;
+use crate::capture::synthetic::{Pattern, SyntheticCapture};
+use crate::capture::vnc::VncCapture;
+use crate::capture::{
+  Capture, CaptureOpts, Converted, Frame, FrameInfo, Rect, VideoFrame,
+};
+use crate::display::Display;
+use crate::{Error, Result};
+
+/// Names the backend to use, overriding detection unless [`CaptureOpts::backend`] does.
+pub const BACKEND_VAR: &str = "FUN_CAPTURE_BACKEND";
+
+/// A way of capturing a display, see [`open`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Backend {
+  /// [`DesktopCapture`], for displays from
+  /// [`Display::desktop`](crate::display::Display::desktop), which is always used for
+  /// them.
+  Desktop,
+  /// [`VncCapture`], for displays from
+  /// [`get_vnc_display`](crate::display::get_vnc_display).
+  Vnc,
+  /// `ExtImageCopyCapture`. Needs the `wayland` feature.
+  ExtImageCopy,
+  /// `WlrScreencopyCapture`. Needs the `wayland` feature.
+  WlrScreencopy,
+  /// `PipeWireCapture`, through the ScreenCast portal. Needs the `pipewire` feature.
+  PipeWire,
+  /// `X11Capture` reading back through MIT-SHM. Needs the `x11` feature.
+  X11Shm,
+  /// `X11Capture` reading back with `XGetImage`. Needs the `x11` feature.
+  X11,
+  /// `DrmCapture`. Needs the `drm` feature.
+  Drm,
+  /// [`FbdevCapture`](crate::capture::fbdev::FbdevCapture).
+  Fbdev,
+  /// [`XwdCapture`](crate::capture::xwd::XwdCapture).
+  Xwd,
+  /// [`QuartzCapture`](crate::capture::quartz::QuartzCapture).
+  Quartz,
+  /// [`DisplayContextCapture`](crate::capture::windows_dc::DisplayContextCapture).
+  DisplayContext,
+  /// [`SyntheticCapture`] drawing [`Pattern::Bars`], for
+  /// [`Display::synthetic`](crate::display::Display::synthetic).
+  Synthetic,
+}
+
+impl Backend {
+  /// Every backend, in the order [`open`] tries them.
+  pub const ALL: [Backend; 13] = [
+    Backend::Desktop,
+    Backend::Vnc,
+    Backend::ExtImageCopy,
+    Backend::WlrScreencopy,
+    Backend::PipeWire,
+    Backend::X11Shm,
+    Backend::X11,
+    Backend::Drm,
+    Backend::Fbdev,
+    Backend::Xwd,
+    Backend::Quartz,
+    Backend::DisplayContext,
+    Backend::Synthetic,
+  ];
+
+  /// The name [`BACKEND_VAR`] takes, e.g. `"x11-shm"`.
+  pub fn name(self) -> &'static str {
+    match self {
+      Backend::Desktop => "desktop",
+      Backend::Vnc => "vnc",
+      Backend::PipeWire => "pipewire",
+      Backend::ExtImageCopy => "ext-image-copy",
+      Backend::WlrScreencopy => "wlr-screencopy",
+      Backend::X11Shm => "x11-shm",
+      Backend::X11 => "x11",
+      Backend::Drm => "drm",
+      Backend::Fbdev => "fbdev",
+      Backend::Xwd => "xwd",
+      Backend::Quartz => "quartz",
+      Backend::DisplayContext => "dc",
+      Backend::Synthetic => "synthetic",
+    }
+  }
+
+  /// Whether this backend is worth trying for `display` without being asked to.
+  ///
+  /// The portal picks the monitor itself and may prompt the user, so PipeWire is only
+  /// tried on Wayland, and after the protocols that capture the output asked for.
+  fn detects(self, display: &Display) -> bool {
+    if display.is_desktop() {
+      return self == Backend::Desktop;
+    }
+
+    if display.remote().is_some() {
+      return self == Backend::Vnc;
+    }
+
+    if display.is_synthetic() {
+      return self == Backend::Synthetic;
+    }
+
+    // Left out of this build, so they could only fail.
+    let wayland = matches!(self, Backend::ExtImageCopy | Backend::WlrScreencopy);
+    let x11 = matches!(self, Backend::X11Shm | Backend::X11);
+    if (wayland && !cfg!(feature = "wayland"))
+      || (self == Backend::PipeWire && !cfg!(feature = "pipewire"))
+      || (x11 && !cfg!(feature = "x11"))
+      || (self == Backend::Drm && !cfg!(feature = "drm"))
+    {
+      return false;
+    }
+
+    #[cfg(target_os = "linux")]
+    let detected = {
+      use crate::display::Source;
+
+      matches!(
+        (self, display.source()),
+        (
+          Backend::PipeWire | Backend::ExtImageCopy | Backend::WlrScreencopy,
+          Source::Wayland { .. }
+        ) | (Backend::X11Shm | Backend::X11, Source::X11 { .. })
+          | (Backend::Drm, Source::Drm { .. })
+          | (Backend::Fbdev, Source::Framebuffer { .. })
+          | (Backend::Xwd, Source::Xwd { .. })
+      )
+    };
+
+    #[cfg(target_os = "macos")]
+    let detected = self == Backend::Quartz;
+
+    #[cfg(target_os = "windows")]
+    let detected = self == Backend::DisplayContext;
+
+    detected
+  }
+
+  fn open(self, opts: CaptureOpts) -> Result<BoxedCapture> {
+    #[cfg(all(target_os = "linux", feature = "x11"))]
+    use crate::capture::x11;
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    use crate::capture::{ext_image_copy, wlr_screencopy};
+    #[cfg(target_os = "linux")]
+    use crate::capture::{fbdev, xwd};
+
+    let output = opts.clone();
+    let mut capture = match self {
+      Backend::Desktop => BoxedCapture::new(DesktopCapture::new(opts)?, self),
+      Backend::Vnc => BoxedCapture::new(VncCapture::new(opts)?, self),
+      Backend::Synthetic => {
+        BoxedCapture::new(SyntheticCapture::new(opts, Pattern::Bars), self)
+      }
+      #[cfg(all(target_os = "linux", feature = "pipewire"))]
+      Backend::PipeWire => {
+        BoxedCapture::new(crate::capture::pipewire::PipeWireCapture::new(opts)?, self)
+      }
+      #[cfg(all(target_os = "linux", feature = "wayland"))]
+      Backend::ExtImageCopy => {
+        BoxedCapture::new(ext_image_copy::ExtImageCopyCapture::new(opts)?, self)
+      }
+      #[cfg(all(target_os = "linux", feature = "wayland"))]
+      Backend::WlrScreencopy => {
+        BoxedCapture::new(wlr_screencopy::WlrScreencopyCapture::new(opts)?, self)
+      }
+      #[cfg(all(target_os = "linux", feature = "x11"))]
+      Backend::X11Shm => {
+        let capture = x11::X11Capture::new(opts)?;
+        if !capture.is_shm() {
+          return Err(Error::unavailable("MIT-SHM is not available"));
+        }
+
+        BoxedCapture::new(capture, self)
+      }
+      #[cfg(all(target_os = "linux", feature = "x11"))]
+      Backend::X11 => BoxedCapture::new(x11::X11Capture::new_without_shm(opts)?, self),
+      #[cfg(all(target_os = "linux", feature = "drm"))]
+      Backend::Drm => {
+        BoxedCapture::new(crate::capture::drm::DrmCapture::new(opts)?, self)
+      }
+      #[cfg(target_os = "linux")]
+      Backend::Fbdev => BoxedCapture::new(fbdev::FbdevCapture::new(opts)?, self),
+      #[cfg(target_os = "linux")]
+      Backend::Xwd => BoxedCapture::new(xwd::XwdCapture::new(opts)?, self),
+      #[cfg(target_os = "macos")]
+      Backend::Quartz => {
+        BoxedCapture::new(crate::capture::quartz::QuartzCapture::new(opts)?, self)
+      }
+      #[cfg(target_os = "windows")]
+      Backend::DisplayContext => BoxedCapture::new(
+        crate::capture::windows_dc::DisplayContextCapture::new(opts)?,
+        self,
+      ),
+      #[allow(unreachable_patterns)]
+      _ => {
+        return Err(Error::unavailable(format!(
+          "The {} backend is not available on this platform or in this build",
+          self
+        )))
+      }
+    };
+
+    // Whatever the backend didn't scale or convert itself is left to `Converted`.
+    if output.output_size.is_some() || output.pixel_format.is_some() {
+      capture.inner = Box::new(Converted::new(capture.inner, &output));
+    }
+
+    Ok(capture)
+  }
+}
+
+impl fmt::Display for Backend {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for Backend {
+  type Err = Error;
+
+  fn from_str(name: &str) -> Result<Self> {
+    Backend::ALL
+      .iter()
+      .copied()
+      .find(|backend| backend.name().eq_ignore_ascii_case(name.trim()))
+      .ok_or_else(|| Error::invalid(format!("Unknown backend {:?}", name)))
+  }
+}
+
+/// Opens the best backend for `opts.display`, see [`BoxedCapture::backend`] for which.
+/// Its frames are scaled and converted as `opts` asks, in software if need be.
+///
+/// [`CaptureOpts::backend`], or else [`BACKEND_VAR`], picks one instead, which isn't
+/// fallen back from. Otherwise each backend that might work is tried in the order of
+/// [`Backend::ALL`], returning the first one's error if none does. A desktop is always
+/// captured with [`Backend::Desktop`], which opens each of its displays like this.
+pub fn open(opts: CaptureOpts) -> Result<BoxedCapture> {
+  if opts.display.is_desktop() {
+    return Backend::Desktop.open(opts);
+  }
+
+  let backend = match (opts.backend, env::var(BACKEND_VAR)) {
+    (Some(backend), _) => Some(backend),
+    (None, Ok(name)) if !name.is_empty() => Some(name.parse()?),
+    _ => None,
+  };
+
+  if let Some(backend) = backend {
+    return backend.open(opts);
+  }
+
+  let mut first_error = None;
+  for backend in Backend::ALL {
+    if !backend.detects(&opts.display) {
+      continue;
+    }
+
+    match backend.open(opts.clone()) {
+      Ok(capture) => return Ok(capture),
+      Err(err) => {
+        first_error.get_or_insert(err);
+      }
+    }
+  }
+
+  Err(
+    first_error
+      .unwrap_or_else(|| Error::unavailable("No backend can capture this display")),
+  )
+}
+
+/// Any backend's capture, from [`open`]. Like every backend's, it's `Send`.
+pub struct BoxedCapture {
+  inner: Box<dyn DynCapture + Send>,
+  backend: Backend,
+}
+
+impl BoxedCapture {
+  fn new<C: Capture + Send + 'static>(capture: C, backend: Backend) -> Self {
+    Self {
+      inner: Box::new(capture),
+      backend,
+    }
+  }
+
+  /// The backend that was picked.
+  pub fn backend(&self) -> Backend {
+    self.backend
+  }
+}
+
+impl Debug for BoxedCapture {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_struct("BoxedCapture")
+      .field("backend", &self.backend)
+      .finish_non_exhaustive()
+  }
+}
+
+impl Capture for BoxedCapture {
+  type Frame<'a> = BoxedFrame<'a>;
+
+  fn frame(&mut self) -> Result<Frame<BoxedFrame<'_>>> {
+    (*self.inner).dyn_frame()
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    (*self.inner).dyn_wait(timeout)
+  }
+}
+
+/// [`Capture`] with its frames boxed, so it can be used as a trait object, e.g.
+/// `Box<dyn DynCapture + Send>`. Every capture is one, and boxes of them are captures,
+/// so the methods here are named apart to not clash with those of [`Capture`].
+pub trait DynCapture {
+  /// See [`Capture::frame`].
+  fn dyn_frame(&mut self) -> Result<Frame<BoxedFrame<'_>>>;
+
+  /// See [`Capture::wait`].
+  fn dyn_wait(&mut self, timeout: Duration) -> Result<()>;
+}
+
+impl<C: Capture> DynCapture for C {
+  fn dyn_frame(&mut self) -> Result<Frame<BoxedFrame<'_>>> {
+    Ok(Capture::frame(self)?.map(|frame| BoxedFrame(Box::new(frame))))
+  }
+
+  fn dyn_wait(&mut self, timeout: Duration) -> Result<()> {
+    Capture::wait(self, timeout)
+  }
+}
+
+impl<C: DynCapture + ?Sized> Capture for Box<C> {
+  type Frame<'a>
+    = BoxedFrame<'a>
+  where
+    Self: 'a;
+
+  fn frame(&mut self) -> Result<Frame<BoxedFrame<'_>>> {
+    (**self).dyn_frame()
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    (**self).dyn_wait(timeout)
+  }
+}
+
+trait DebugFrame: VideoFrame + Debug {}
+
+impl<T: VideoFrame + Debug> DebugFrame for T {}
+
+/// Any backend's frame, from a [`DynCapture`].
+#[derive(Debug)]
+pub struct BoxedFrame<'a>(Box<dyn DebugFrame + 'a>);
+
+impl Deref for BoxedFrame<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl VideoFrame for BoxedFrame<'_> {
+  fn info(&self) -> FrameInfo {
+    self.0.info()
+  }
+
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    self.0.dirty_rects()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread::spawn;
+
+  use crate::capture::auto::{open, Backend, BoxedCapture, DynCapture};
+  use crate::capture::synthetic::{Pattern, SyntheticCapture};
+  use crate::capture::{Capture, CaptureOpts, Frame, PixelFormat, VideoFrame};
+  use crate::display::Display;
+  use crate::Error;
+
+  fn assert_send<T: Send>() {}
+
+  #[test]
+  fn test_captures_are_send() {
+    assert_send::<BoxedCapture>();
+    assert_send::<crate::capture::desktop::DesktopCapture>();
+    assert_send::<crate::capture::replay::ReplayCapture>();
+    assert_send::<crate::capture::vnc::VncCapture>();
+
+    #[cfg(target_os = "linux")]
+    {
+      use crate::capture::*;
+
+      assert_send::<fbdev::FbdevCapture>();
+      assert_send::<xwd::XwdCapture>();
+    }
+
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    {
+      use crate::capture::*;
+
+      assert_send::<ext_image_copy::ExtImageCopyCapture>();
+      assert_send::<ext_image_copy::ExtImageCopyFrame>();
+      assert_send::<wlr_screencopy::WlrScreencopyCapture>();
+      assert_send::<wlr_screencopy::WlrScreencopyFrame>();
+    }
+
+    #[cfg(all(target_os = "linux", feature = "x11"))]
+    {
+      use crate::capture::*;
+
+      assert_send::<x11::X11Capture>();
+      assert_send::<x11::X11Frame>();
+      assert_send::<xcomposite::XCompositeCapture>();
+      assert_send::<xcomposite::XCompositeFrame>();
+    }
+
+    #[cfg(all(target_os = "linux", feature = "drm"))]
+    assert_send::<crate::capture::drm::DrmCapture>();
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    assert_send::<crate::capture::pipewire::PipeWireCapture>();
+
+    #[cfg(target_os = "macos")]
+    assert_send::<crate::capture::quartz::QuartzCapture>();
+
+    #[cfg(target_os = "windows")]
+    assert_send::<crate::capture::windows_dc::DisplayContextCapture>();
+  }
+
+  #[test]
+  fn test_dyn_capture() {
+    let display = Display::synthetic(16, 8);
+    let captures: Vec<Box<dyn DynCapture + Send>> = vec![
+      Box::new(SyntheticCapture::new(
+        CaptureOpts::new(display.clone()),
+        Pattern::Bars,
+      )),
+      Box::new(open(CaptureOpts::new(display)).unwrap()),
+    ];
+
+    // Made here, used on another thread.
+    spawn(move || {
+      for mut capture in captures {
+        match capture.next_frame().unwrap() {
+          Frame::Ready(frame) => assert_eq!((frame.width(), frame.height()), (16, 8)),
+          _ => panic!("Expected a frame"),
+        };
+      }
+    })
+    .join()
+    .unwrap();
+  }
+
+  #[test]
+  fn test_backend_names() {
+    for backend in Backend::ALL {
+      assert_eq!(backend.name().parse::<Backend>().unwrap(), backend);
+    }
+
+    assert_eq!("X11-SHM".parse::<Backend>().unwrap(), Backend::X11Shm);
+    assert!(matches!(
+      "x12".parse::<Backend>(),
+      Err(Error::InvalidInput(_))
+    ));
+  }
+
+  #[test]
+  fn test_open_synthetic() {
+    let mut capture = open(CaptureOpts::new(Display::synthetic(16, 8))).unwrap();
+    assert_eq!(capture.backend(), Backend::Synthetic);
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (16, 8));
+        assert_eq!(frame.len(), 16 * 8 * 4);
+      }
+      _ => panic!("Expected a frame"),
+    };
+  }
+
+  #[test]
+  fn test_open_output() {
+    let mut opts = CaptureOpts::new(Display::synthetic(16, 8));
+    opts
+      .output_size(4, 2)
+      .unwrap()
+      .pixel_format(PixelFormat::Rgb565);
+    let mut capture = open(opts).unwrap();
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+        assert_eq!(frame.format(), PixelFormat::Rgb565);
+        assert_eq!(frame.len(), 4 * 2 * 2);
+      }
+      _ => panic!("Expected a frame"),
+    };
+  }
+
+  #[test]
+  fn test_open_override() {
+    let mut opts = CaptureOpts::new(Display::synthetic(16, 8));
+    opts.backend(Backend::Vnc);
+
+    assert!(open(opts).is_err());
+  }
+}
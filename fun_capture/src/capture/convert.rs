@@ -0,0 +1,405 @@
+//! Scaling frames and converting their pixel format in software, for backends that
+//! can't do either themselves.
+
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::time::Duration;
+
+use crate::capture::{
+  Capture, CaptureOpts, Frame, FrameInfo, PixelFormat, Rect, VideoFrame,
+};
+use crate::{Error, Result};
+
+/// A capture whose frames are scaled to [`CaptureOpts::output_size`] and converted to
+/// [`CaptureOpts::pixel_format`], where its backend didn't already.
+///
+/// Frames that already are get handed out as they are, without copying. Scaling
+/// averages the pixels each output pixel covers, so thumbnails don't alias.
+pub struct Converted<C> {
+  inner: C,
+  size: Option<(u32, u32)>,
+  format: Option<PixelFormat>,
+  /// The scaled frame as RGBA, before it's converted.
+  pixels: Vec<[u8; 4]>,
+  data: Vec<u8>,
+}
+
+impl<C: Capture> Converted<C> {
+  pub fn new(capture: C, opts: &CaptureOpts) -> Self {
+    Self {
+      inner: capture,
+      size: opts.output_size,
+      format: opts.pixel_format,
+      pixels: Vec::new(),
+      data: Vec::new(),
+    }
+  }
+
+  pub fn into_inner(self) -> C {
+    self.inner
+  }
+}
+
+impl<C: Capture> Capture for Converted<C> {
+  type Frame<'a>
+    = ConvertedFrame<'a, C::Frame<'a>>
+  where
+    Self: 'a;
+
+  fn frame(&mut self) -> Result<Frame<Self::Frame<'_>>> {
+    let frame = match self.inner.frame()? {
+      Frame::Ready(frame) => frame,
+      frame => return Ok(frame.map(ConvertedFrame::Native)),
+    };
+
+    let info = frame.info();
+    let (width, height) =
+      fit(self.size.unwrap_or((info.width, info.height)), self.format);
+    let format = self.format.unwrap_or(info.format);
+
+    if (width, height, format) == (info.width, info.height, info.format) {
+      return Ok(Frame::Ready(ConvertedFrame::Native(frame)));
+    }
+
+    if info.format == PixelFormat::Nv12 {
+      return Err(Error::unsupported("NV12 frames can't be converted"));
+    }
+
+    let rows = frame.rows().collect::<Vec<_>>();
+    if rows.len() < info.height as usize || rows.is_empty() {
+      return Err(Error::invalid_data("Frame data doesn't match its size"));
+    }
+
+    scale(
+      &rows,
+      info.format,
+      info.width,
+      (width, height),
+      &mut self.pixels,
+    );
+    encode(&self.pixels, width as usize, format, &mut self.data);
+
+    let dirty_rects = frame
+      .dirty_rects()
+      .iter()
+      .map(|&rect| scale_rect(rect, (info.width, info.height), (width, height)))
+      .filter(|rect| rect.width > 0 && rect.height > 0)
+      .collect();
+
+    Ok(Frame::Ready(ConvertedFrame::Converted {
+      data: &self.data,
+      dirty_rects,
+      info: FrameInfo {
+        width,
+        height,
+        stride: width as usize * format.bytes_per_pixel(),
+        format,
+        ..info
+      },
+    }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    self.inner.wait(timeout)
+  }
+}
+
+/// A frame from [`Converted`], either the backend's own or a converted copy.
+#[derive(Debug)]
+pub enum ConvertedFrame<'a, F> {
+  Native(F),
+  Converted {
+    data: &'a [u8],
+    /// The backend's, scaled to cover what they changed.
+    dirty_rects: Vec<Rect>,
+    info: FrameInfo,
+  },
+}
+
+impl<F: VideoFrame> VideoFrame for ConvertedFrame<'_, F> {
+  fn info(&self) -> FrameInfo {
+    match self {
+      ConvertedFrame::Native(frame) => frame.info(),
+      ConvertedFrame::Converted { info, .. } => *info,
+    }
+  }
+
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    match self {
+      ConvertedFrame::Native(frame) => frame.dirty_rects(),
+      ConvertedFrame::Converted { dirty_rects, .. } => Cow::Borrowed(dirty_rects),
+    }
+  }
+}
+
+impl<F: VideoFrame> Deref for ConvertedFrame<'_, F> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      ConvertedFrame::Native(frame) => frame,
+      ConvertedFrame::Converted { data, .. } => data,
+    }
+  }
+}
+
+/// Rounds `size` down to even for NV12, whose chroma covers 2x2 blocks.
+pub(crate) fn fit(
+  (width, height): (u32, u32),
+  format: Option<PixelFormat>,
+) -> (u32, u32) {
+  match format {
+    Some(PixelFormat::Nv12) => (width.max(2) & !1, height.max(2) & !1),
+    _ => (width, height),
+  }
+}
+
+/// The source pixels, from start to end, that each of `to` pixels covers out of `from`.
+/// When enlarging, that's the nearest one.
+fn spans(from: usize, to: u32) -> impl Iterator<Item = (usize, usize)> {
+  let (from, to) = (from as u64, u64::from(to));
+  (0..to).map(move |i| {
+    let start = i * from / to;
+    let end = ((i + 1) * from / to).max(start + 1);
+    (start as usize, end as usize)
+  })
+}
+
+/// Scales `rect` from a frame of size `from` to the smallest rect covering it in one of
+/// size `to`.
+fn scale_rect(rect: Rect, from: (u32, u32), to: (u32, u32)) -> Rect {
+  // Rounding the start down and the end up keeps every pixel it touches.
+  let span = |start: i32, len: u32, from: u32, to: u32| {
+    let (from, to) = (u64::from(from.max(1)), u64::from(to));
+    let start = start.max(0) as u64;
+    let end = start + u64::from(len);
+    let (start, end) = (start * to / from, (end * to).div_ceil(from));
+    (start.min(to) as u32, end.min(to) as u32)
+  };
+
+  let (left, right) = span(rect.x, rect.width, from.0, to.0);
+  let (top, bottom) = span(rect.y, rect.height, from.1, to.1);
+  Rect::new(left as i32, top as i32, right - left, bottom - top)
+}
+
+/// Scales `rows` of `width` pixels in `format` to `size`, averaging what each pixel
+/// covers, into `pixels` as RGBA.
+fn scale(
+  rows: &[&[u8]],
+  format: PixelFormat,
+  width: u32,
+  (to_width, to_height): (u32, u32),
+  pixels: &mut Vec<[u8; 4]>,
+) {
+  let bpp = format.bytes_per_pixel();
+  let columns = spans(width as usize, to_width).collect::<Vec<_>>();
+  let mut sums = vec![[0u64; 4]; columns.len()];
+  pixels.clear();
+
+  for (top, bottom) in spans(rows.len(), to_height) {
+    sums.fill([0; 4]);
+    for row in &rows[top..bottom] {
+      for (sum, &(left, right)) in sums.iter_mut().zip(&columns) {
+        for pixel in row[left * bpp..right * bpp].chunks_exact(bpp) {
+          for (sum, channel) in sum.iter_mut().zip(read(format, pixel)) {
+            *sum += u64::from(channel);
+          }
+        }
+      }
+    }
+
+    for (sum, &(left, right)) in sums.iter().zip(&columns) {
+      let count = ((right - left) * (bottom - top)) as u64;
+      pixels.push(sum.map(|sum| ((sum + count / 2) / count) as u8));
+    }
+  }
+}
+
+/// A pixel's red, green, blue and alpha.
+pub(crate) fn read(format: PixelFormat, pixel: &[u8]) -> [u8; 4] {
+  match format {
+    PixelFormat::Bgrx8888 => [pixel[2], pixel[1], pixel[0], 0xff],
+    PixelFormat::Bgra8888 => [pixel[2], pixel[1], pixel[0], pixel[3]],
+    PixelFormat::Rgbx8888 => [pixel[0], pixel[1], pixel[2], 0xff],
+    PixelFormat::Rgba8888 => [pixel[0], pixel[1], pixel[2], pixel[3]],
+    PixelFormat::Rgb565 => {
+      let word = u16::from_le_bytes([pixel[0], pixel[1]]);
+      let (r, g, b) = (word >> 11, (word >> 5) & 0x3f, word & 0x1f);
+      [
+        (r << 3 | r >> 2) as u8,
+        (g << 2 | g >> 4) as u8,
+        (b << 3 | b >> 2) as u8,
+        0xff,
+      ]
+    }
+    PixelFormat::Nv12 => unreachable!("NV12 frames aren't read per pixel"),
+  }
+}
+
+/// Writes RGBA `pixels`, `width` to a row, to `data` tightly packed in `format`.
+fn encode(pixels: &[[u8; 4]], width: usize, format: PixelFormat, data: &mut Vec<u8>) {
+  data.clear();
+  if format == PixelFormat::Nv12 {
+    return encode_nv12(pixels, width, data);
+  }
+
+  for &[r, g, b, a] in pixels {
+    match format {
+      PixelFormat::Bgrx8888 => data.extend_from_slice(&[b, g, r, 0xff]),
+      PixelFormat::Bgra8888 => data.extend_from_slice(&[b, g, r, a]),
+      PixelFormat::Rgbx8888 => data.extend_from_slice(&[r, g, b, 0xff]),
+      PixelFormat::Rgba8888 => data.extend_from_slice(&[r, g, b, a]),
+      PixelFormat::Rgb565 => {
+        let (r, g, b) = (u16::from(r) >> 3, u16::from(g) >> 2, u16::from(b) >> 3);
+        data.extend_from_slice(&(r << 11 | g << 5 | b).to_le_bytes());
+      }
+      PixelFormat::Nv12 => unreachable!(),
+    }
+  }
+}
+
+/// Like [`encode`] for NV12, with BT.709 coefficients in 8-bit fixed point. `pixels`
+/// must be an even number of rows of an even `width`.
+fn encode_nv12(pixels: &[[u8; 4]], width: usize, data: &mut Vec<u8>) {
+  let clamp = |value: i32| value.clamp(0, 255) as u8;
+
+  data.extend(pixels.iter().map(|&[r, g, b, _]| {
+    clamp((54 * i32::from(r) + 183 * i32::from(g) + 19 * i32::from(b) + 128) >> 8)
+  }));
+
+  for rows in pixels.chunks_exact(width * 2) {
+    let (top, bottom) = rows.split_at(width);
+    for x in (0..width).step_by(2) {
+      let block = [top[x], top[x + 1], bottom[x], bottom[x + 1]];
+      let [r, g, b] = [0, 1, 2].map(|channel| {
+        (block
+          .iter()
+          .map(|pixel| i32::from(pixel[channel]))
+          .sum::<i32>()
+          + 2)
+          / 4
+      });
+
+      data.push(clamp(128 + ((-29 * r - 99 * g + 128 * b + 128) >> 8)));
+      data.push(clamp(128 + ((128 * r - 116 * g - 12 * b + 128) >> 8)));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::convert::{
+    encode, fit, scale, scale_rect, Converted, ConvertedFrame,
+  };
+  use crate::capture::synthetic::{Pattern, SyntheticCapture};
+  use crate::capture::{Capture, CaptureOpts, Frame, PixelFormat, Rect, VideoFrame};
+  use crate::display::Display;
+
+  #[test]
+  fn test_scale() {
+    // Four BGRX pixels a row: white, grey, black, black.
+    let row = [[0xff; 4], [0x80; 4], [0; 4], [0; 4]].concat();
+    let rows = [&row[..], &row[..]];
+    let mut pixels = Vec::new();
+
+    scale(&rows, PixelFormat::Bgrx8888, 4, (2, 1), &mut pixels);
+    assert_eq!(pixels, [[0xc0, 0xc0, 0xc0, 0xff], [0, 0, 0, 0xff]]);
+
+    // Enlarging repeats the nearest pixel.
+    scale(&rows, PixelFormat::Bgrx8888, 4, (8, 3), &mut pixels);
+    assert_eq!(pixels.len(), 24);
+    assert_eq!(
+      pixels[..3],
+      [[0xff; 4], [0xff; 4], [0x80, 0x80, 0x80, 0xff]]
+    );
+  }
+
+  #[test]
+  fn test_encode() {
+    let red = [[0xff, 0, 0, 0x80]];
+    let mut data = Vec::new();
+
+    encode(&red, 1, PixelFormat::Bgra8888, &mut data);
+    assert_eq!(data, [0, 0, 0xff, 0x80]);
+    encode(&red, 1, PixelFormat::Rgbx8888, &mut data);
+    assert_eq!(data, [0xff, 0, 0, 0xff]);
+    encode(&red, 1, PixelFormat::Rgb565, &mut data);
+    assert_eq!(data, [0, 0xf8]);
+  }
+
+  #[test]
+  fn test_encode_nv12() {
+    let mut data = Vec::new();
+
+    encode(&[[0xff; 4]; 4], 2, PixelFormat::Nv12, &mut data);
+    assert_eq!(data, [255, 255, 255, 255, 128, 128]);
+
+    let red = [0xff, 0, 0, 0xff];
+    encode(&[red, red, red, red], 2, PixelFormat::Nv12, &mut data);
+    assert_eq!(data, [54, 54, 54, 54, 99, 255]);
+  }
+
+  #[test]
+  fn test_fit() {
+    assert_eq!(fit((5, 3), Some(PixelFormat::Nv12)), (4, 2));
+    assert_eq!(fit((1, 1), Some(PixelFormat::Nv12)), (2, 2));
+    assert_eq!(fit((5, 3), Some(PixelFormat::Rgb565)), (5, 3));
+  }
+
+  #[test]
+  fn test_scale_rect() {
+    let halve = |rect| scale_rect(rect, (16, 8), (8, 4));
+    assert_eq!(halve(Rect::new(0, 0, 16, 8)), Rect::new(0, 0, 8, 4));
+    assert_eq!(halve(Rect::new(4, 2, 4, 2)), Rect::new(2, 1, 2, 1));
+    assert_eq!(halve(Rect::new(3, 3, 1, 1)), Rect::new(1, 1, 1, 1));
+    assert_eq!(halve(Rect::new(15, 7, 4, 4)), Rect::new(7, 3, 1, 1));
+
+    let double = scale_rect(Rect::new(1, 1, 1, 1), (4, 4), (8, 8));
+    assert_eq!(double, Rect::new(2, 2, 2, 2));
+  }
+
+  #[test]
+  fn test_converted() {
+    let mut opts = CaptureOpts::new(Display::synthetic(17, 8));
+    opts
+      .output_size(9, 4)
+      .unwrap()
+      .pixel_format(PixelFormat::Nv12);
+    let mut capture =
+      Converted::new(SyntheticCapture::new(opts.clone(), Pattern::Bars), &opts);
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame @ ConvertedFrame::Converted { .. }) => {
+        assert_eq!((frame.width(), frame.height()), (8, 4));
+        assert_eq!(frame.format(), PixelFormat::Nv12);
+        assert_eq!(frame.len(), 8 * 4 * 3 / 2);
+        assert_eq!(frame.rows().count(), 6);
+        assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 8, 4)]);
+      }
+      frame => panic!("Expected a converted frame, got {:?}", frame),
+    };
+  }
+
+  #[test]
+  fn test_converted_passthrough() {
+    let mut opts = CaptureOpts::new(Display::synthetic(16, 8));
+    opts.output_size(16, 8).unwrap();
+    let mut capture =
+      Converted::new(SyntheticCapture::new(opts.clone(), Pattern::Bars), &opts);
+
+    assert!(matches!(
+      capture.frame().unwrap(),
+      Frame::Ready(ConvertedFrame::Native(_))
+    ));
+  }
+
+  #[test]
+  fn test_output_opts() {
+    let mut opts = CaptureOpts::new(Display::synthetic(17, 8));
+    assert!(opts.output_size(0, 8).is_err());
+    assert_eq!(opts.target_size(), (17, 8));
+
+    opts.pixel_format(PixelFormat::Nv12);
+    assert_eq!(opts.target_size(), (16, 8));
+  }
+}
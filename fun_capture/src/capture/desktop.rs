@@ -0,0 +1,268 @@
+//! Capturing every display at once, as one image of the whole desktop.
+
+use std::borrow::Cow;
+use std::ops::Deref;
+
+use crate::capture::convert::read;
+use crate::capture::{
+  crop_rects, open, Backend, BoxedCapture, Capture, CaptureOpts, Frame, FrameClock,
+  FrameInfo, PixelFormat, Rect, VideoFrame,
+};
+use crate::{Error, Result};
+
+/// Captures each display of a [`Display::desktop`](crate::display::Display::desktop)
+/// with [`open`], and stitches their frames together where the displays are laid out.
+///
+/// Gaps between displays are black, as is a display until its first frame. A frame
+/// bigger than its display, e.g. one scaled up from logical pixels, is cut off at the
+/// display's bounds. The backend [`CaptureOpts::backend`] asks for captures each display,
+/// while a region, output size and pixel format apply to the whole desktop.
+pub struct DesktopCapture {
+  displays: Vec<DisplayCapture>,
+  width: u32,
+  height: u32,
+  region: Rect,
+  canvas: Vec<u8>,
+  clock: FrameClock,
+}
+
+struct DisplayCapture {
+  capture: BoxedCapture,
+  /// Where the display is on the canvas.
+  bounds: Rect,
+  stopped: bool,
+}
+
+impl DesktopCapture {
+  /// Fails unless `opts.display` is a desktop, or if any of its displays can't be
+  /// captured.
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let desktop = &opts.display;
+    if !desktop.is_desktop() {
+      return Err(Error::invalid("The display is not a desktop"));
+    }
+
+    let displays = desktop
+      .displays()
+      .iter()
+      .map(|display| {
+        let bounds = Rect::new(
+          display.x() - desktop.x(),
+          display.y() - desktop.y(),
+          display.width(),
+          display.height(),
+        );
+
+        let capture = open(CaptureOpts {
+          display: display.clone(),
+          backend: opts.backend.filter(|&backend| backend != Backend::Desktop),
+          region: None,
+          output_size: None,
+          pixel_format: None,
+          ..opts.clone()
+        })?;
+
+        Ok(DisplayCapture {
+          capture,
+          bounds,
+          stopped: false,
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    let (width, height) = (desktop.width(), desktop.height());
+    Ok(Self {
+      displays,
+      width,
+      height,
+      region: opts.source_rect(),
+      canvas: vec![0; width as usize * height as usize * 4],
+      clock: FrameClock::new(),
+    })
+  }
+}
+
+impl Capture for DesktopCapture {
+  type Frame<'a> = DesktopFrame<'a>;
+
+  fn frame(&mut self) -> Result<Frame<DesktopFrame<'_>>> {
+    let mut dirty_rects = Vec::new();
+    let (mut blocking, mut awake) = (false, false);
+
+    for display in self.displays.iter_mut().filter(|display| !display.stopped) {
+      match display.capture.frame()? {
+        Frame::Ready(frame) => {
+          draw(&mut self.canvas, self.width, display.bounds, &frame)?;
+          dirty_rects.push(display.bounds);
+          awake = true;
+        }
+        Frame::Blocking => {
+          blocking = true;
+          awake = true;
+        }
+        Frame::Idle => awake = true,
+        Frame::Blank => {}
+        Frame::Stopped => display.stopped = true,
+      }
+    }
+
+    let dirty_rects = crop_rects(dirty_rects, self.region);
+    if dirty_rects.is_empty() {
+      return Ok(if self.displays.iter().all(|display| display.stopped) {
+        Frame::Stopped
+      } else if blocking {
+        Frame::Blocking
+      } else if awake {
+        Frame::Idle
+      } else {
+        Frame::Blank
+      });
+    }
+
+    let (offset, info) = self
+      .clock
+      .next_bgrx(self.width, self.height)
+      .crop(self.region)?;
+
+    Ok(Frame::Ready(DesktopFrame {
+      data: &self.canvas[offset..],
+      dirty_rects,
+      info,
+    }))
+  }
+}
+
+/// Draws `frame` onto the BGRX `canvas`, `width` pixels wide, within `bounds`.
+fn draw<F: VideoFrame>(
+  canvas: &mut [u8],
+  width: u32,
+  bounds: Rect,
+  frame: &F,
+) -> Result<()> {
+  let format = frame.format();
+  if format == PixelFormat::Nv12 {
+    return Err(Error::unsupported(
+      "NV12 frames can't be drawn onto a desktop",
+    ));
+  }
+
+  let bpp = format.bytes_per_pixel();
+  let columns = frame.width().min(bounds.width) as usize;
+  let rows = frame.rows().take(bounds.height as usize);
+
+  for (y, row) in rows.enumerate() {
+    let start = ((bounds.y as usize + y) * width as usize + bounds.x as usize) * 4;
+    let target = &mut canvas[start..start + columns * 4];
+
+    match format {
+      PixelFormat::Bgrx8888 | PixelFormat::Bgra8888 => {
+        target.copy_from_slice(&row[..columns * 4]);
+      }
+      _ => {
+        for (target, pixel) in target.chunks_exact_mut(4).zip(row.chunks_exact(bpp)) {
+          let [r, g, b, _] = read(format, pixel);
+          target.copy_from_slice(&[b, g, r, 0xff]);
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// A BGRX frame of the whole desktop, borrowing the capture's canvas.
+#[derive(Debug)]
+pub struct DesktopFrame<'a> {
+  data: &'a [u8],
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for DesktopFrame<'_> {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The displays that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for DesktopFrame<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::desktop::DesktopCapture;
+  use crate::capture::{open, Backend, Capture, CaptureOpts, Frame, Rect, VideoFrame};
+  use crate::display::Display;
+
+  /// Two displays side by side, the left one starting further left than the origin
+  /// and shorter, leaving a gap below it.
+  fn desktop() -> Display {
+    Display::desktop(vec![
+      Display::synthetic_at(-100, 0, 100, 50),
+      Display::synthetic_at(0, 10, 200, 50),
+    ])
+    .unwrap()
+  }
+
+  #[test]
+  fn test_desktop_bounds() {
+    let desktop = desktop();
+    assert_eq!((desktop.x(), desktop.y()), (-100, 0));
+    assert_eq!((desktop.width(), desktop.height()), (300, 60));
+    assert_eq!(desktop.displays().len(), 2);
+
+    assert!(Display::desktop(Vec::new()).is_err());
+    assert!(Display::synthetic(1, 1).displays().is_empty());
+  }
+
+  #[test]
+  fn test_capture() {
+    let mut capture = open(CaptureOpts::new(desktop())).unwrap();
+    assert_eq!(capture.backend(), Backend::Desktop);
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (300, 60));
+        // The gray top left corner of each display's bars, and the gaps beside them.
+        assert_eq!(frame.row(0).unwrap()[..3], [191; 3]);
+        assert_eq!(frame.row(10).unwrap()[100 * 4..][..3], [191; 3]);
+        assert_eq!(frame.row(55).unwrap()[..100 * 4], [0; 400][..]);
+        assert_eq!(frame.row(5).unwrap()[100 * 4..], [0; 800][..]);
+      }
+      _ => panic!("Expected a frame"),
+    };
+
+    // The bars never change.
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+  }
+
+  #[test]
+  fn test_capture_region() {
+    let mut opts = CaptureOpts::new(desktop());
+    opts.region(Rect::new(150, 20, 100, 40)).unwrap();
+    let mut capture = DesktopCapture::new(opts).unwrap();
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (100, 40));
+        // Only the right display is in the region.
+        assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 100, 40)]);
+      }
+      _ => panic!("Expected a frame"),
+    };
+  }
+
+  #[test]
+  fn test_not_a_desktop() {
+    assert!(DesktopCapture::new(CaptureOpts::new(Display::synthetic(2, 2))).is_err());
+  }
+}
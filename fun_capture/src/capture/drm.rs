@@ -0,0 +1,619 @@
+use std::fs::{File, OpenOptions};
+use std::ops::Deref;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::fbdev::{Channel, PixelLayout};
+use crate::capture::VideoFrame;
+use crate::capture::{
+  check_size, clip_region, is_throttled, poll_interval, Capture, CaptureOpts, Frame,
+  FrameClock, FrameInfo, Rect,
+};
+use crate::display::{DisplayInfo, Source};
+use crate::ffi::drm::*;
+use crate::ffi::linux::{mmap, munmap, MAP_FAILED, MAP_SHARED, PROT_READ};
+use crate::{Error, Result};
+
+/// Framebuffers mapped at once, enough for a compositor flipping between a few.
+const MAX_MAPPINGS: usize = 4;
+
+/// Connector names by `DRM_MODE_CONNECTOR_*` type, as the kernel gives them.
+const CONNECTOR_TYPES: [&str; 21] = [
+  "Unknown",
+  "VGA",
+  "DVI-I",
+  "DVI-D",
+  "DVI-A",
+  "Composite",
+  "SVIDEO",
+  "LVDS",
+  "Component",
+  "DIN",
+  "DP",
+  "HDMI-A",
+  "HDMI-B",
+  "TV",
+  "eDP",
+  "Virtual",
+  "DSI",
+  "DPI",
+  "Writeback",
+  "SPI",
+  "USB",
+];
+
+/// An open DRM device, e.g. `/dev/dri/card0`.
+#[derive(Debug)]
+pub(crate) struct Card {
+  file: File,
+}
+
+/// A connected connector and what its CRTC is scanning out.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Output {
+  pub connector: u32,
+  pub fb: u32,
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+  /// In hertz.
+  pub refresh_rate: Option<f64>,
+}
+
+impl Card {
+  pub fn open(path: &str) -> Result<Self> {
+    // Read-only is enough to enumerate, just not to map buffers.
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .open(path)
+      .or_else(|_| File::open(path))?;
+
+    Ok(Self { file })
+  }
+
+  fn fd(&self) -> i32 {
+    self.file.as_raw_fd()
+  }
+
+  /// Every connector that is connected and lit.
+  pub fn outputs(&self) -> Result<Vec<Output>> {
+    let connectors = unsafe {
+      let resources = drmModeGetResources(self.fd());
+      if resources.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      let connectors = from_raw_parts(
+        (*resources).connectors,
+        (*resources).count_connectors.max(0) as usize,
+      )
+      .to_vec();
+
+      drmModeFreeResources(resources);
+      connectors
+    };
+
+    Ok(
+      connectors
+        .into_iter()
+        .filter_map(|connector| self.output(connector).ok().flatten())
+        .collect(),
+    )
+  }
+
+  /// What the connector is showing, `None` meaning it's connected but not lit, e.g.
+  /// while the display is switched off.
+  pub fn output(&self, connector_id: u32) -> Result<Option<Output>> {
+    unsafe {
+      let connector = drmModeGetConnector(self.fd(), connector_id);
+      if connector.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      let (connection, encoder_id) = ((*connector).connection, (*connector).encoder_id);
+      drmModeFreeConnector(connector);
+
+      if connection != DRM_MODE_CONNECTED {
+        return Err(Error::disconnected("Connector is not connected"));
+      }
+
+      if encoder_id == 0 {
+        return Ok(None);
+      }
+
+      let encoder = drmModeGetEncoder(self.fd(), encoder_id);
+      if encoder.is_null() {
+        return Ok(None);
+      }
+
+      let crtc_id = (*encoder).crtc_id;
+      drmModeFreeEncoder(encoder);
+
+      let crtc = drmModeGetCrtc(self.fd(), crtc_id);
+      if crtc.is_null() {
+        return Ok(None);
+      }
+
+      let output = if (*crtc).mode_valid != 0 {
+        let mode = &(*crtc).mode;
+        // The clock is in kHz.
+        let dots = f64::from(mode.htotal) * f64::from(mode.vtotal);
+        Some(Output {
+          connector: connector_id,
+          fb: (*crtc).buffer_id,
+          x: (*crtc).x,
+          y: (*crtc).y,
+          width: (*crtc).mode.hdisplay as u32,
+          height: (*crtc).mode.vdisplay as u32,
+          refresh_rate: (dots > 0.0).then(|| f64::from(mode.clock) * 1000.0 / dots),
+        })
+      } else {
+        None
+      };
+
+      drmModeFreeCrtc(crtc);
+      Ok(output)
+    }
+  }
+
+  /// What's known of the monitor on the connector from its name, e.g. `DP-1`, its size
+  /// and its EDID.
+  pub fn info(&self, connector_id: u32) -> Result<DisplayInfo> {
+    unsafe {
+      let connector = drmModeGetConnector(self.fd(), connector_id);
+      if connector.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      let kind = CONNECTOR_TYPES.get((*connector).connector_type as usize);
+      let name = format!(
+        "{}-{}",
+        kind.unwrap_or(&"Unknown"),
+        (*connector).connector_type_id
+      );
+      let (mm_width, mm_height) = ((*connector).mmWidth, (*connector).mmHeight);
+
+      let count = (*connector).count_props.max(0) as usize;
+      let (props, values) = if count == 0 {
+        (&[][..], &[][..])
+      } else {
+        (
+          from_raw_parts((*connector).props, count),
+          from_raw_parts((*connector).prop_values, count),
+        )
+      };
+      let edid = props
+        .iter()
+        .zip(values)
+        .find(|&(&prop, _)| self.property_name(prop).as_deref() == Some("EDID"))
+        .and_then(|(_, &blob)| self.blob(blob as u32));
+
+      drmModeFreeConnector(connector);
+
+      let mut info = DisplayInfo {
+        physical_size: Some((mm_width, mm_height))
+          .filter(|_| mm_width > 0 && mm_height > 0),
+        ..DisplayInfo::default()
+      };
+      if let Some(edid) = edid {
+        info.read_edid(&edid);
+      }
+      info.name = info.name.take().or_else(|| Some(name.clone()));
+      info.identify(&name);
+
+      Ok(info)
+    }
+  }
+
+  fn property_name(&self, property_id: u32) -> Option<String> {
+    unsafe {
+      let property = drmModeGetProperty(self.fd(), property_id);
+      if property.is_null() {
+        return None;
+      }
+
+      let name: Vec<u8> = (*property)
+        .name
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+      drmModeFreeProperty(property);
+
+      Some(String::from_utf8_lossy(&name).into_owned())
+    }
+  }
+
+  /// The contents of blob property value `blob_id`, `None` if there's no such blob.
+  fn blob(&self, blob_id: u32) -> Option<Vec<u8>> {
+    unsafe {
+      let blob = drmModeGetPropertyBlob(self.fd(), blob_id);
+      if blob.is_null() {
+        return None;
+      }
+
+      let data = if (*blob).data.is_null() {
+        Vec::new()
+      } else {
+        from_raw_parts((*blob).data as *const u8, (*blob).length as usize).to_vec()
+      };
+      drmModeFreePropertyBlob(blob);
+
+      Some(data)
+    }
+  }
+}
+
+/// Reads whatever a DRM connector's CRTC is scanning out, without a display server. It's
+/// blank while the connector isn't lit and stops once it's unplugged.
+///
+/// Getting at another client's framebuffer takes DRM master or `CAP_SYS_ADMIN`, and
+/// only linear RGB framebuffers, e.g. dumb buffers, can be read.
+pub struct DrmCapture {
+  card: Card,
+  connector: u32,
+  mappings: Vec<Mapping>,
+  /// The connector's mode when the capture opened.
+  size: (u32, u32),
+  region: Rect,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  /// The last frame's pixels, to tell whether anything changed since.
+  last_data: Vec<u8>,
+  stopped: bool,
+  clock: FrameClock,
+}
+
+impl DrmCapture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let (card, connector) = match opts.display.source() {
+      Source::Drm { card, connector } => (card, *connector),
+      _ => return Err(Error::invalid("Display is not a DRM connector")),
+    };
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Ok(Self {
+      card: Card::open(card)?,
+      connector,
+      mappings: Vec::new(),
+      size: (opts.display.width(), opts.display.height()),
+      region: opts.source_rect(),
+      frame_time,
+      last_frame: None,
+      last_data: Vec::new(),
+      stopped: false,
+      clock: FrameClock::new(),
+    })
+  }
+
+  /// Maps the framebuffer `fb`, reusing the mapping if it's one we've seen before.
+  fn mapping(&mut self, fb: u32) -> Result<&Mapping> {
+    let fb = Fb2::get(&self.card, fb)?;
+    let desc = FbDesc::new(fb.info());
+
+    let index = match self.mappings.iter().position(|cached| cached.desc == desc) {
+      Some(index) => index,
+      None => {
+        // Freed IDs are handed out again, so another buffer may be behind this one.
+        self.mappings.retain(|mapping| mapping.desc.fb != desc.fb);
+        if self.mappings.len() == MAX_MAPPINGS {
+          self.mappings.remove(0);
+        }
+
+        self.mappings.push(Mapping::map(&self.card, fb.info())?);
+        self.mappings.len() - 1
+      }
+    };
+
+    Ok(&self.mappings[index])
+  }
+
+  fn get_frame(&mut self) -> Result<Frame<DrmFrame>> {
+    let output = match self.card.output(self.connector) {
+      Ok(Some(output)) if output.fb != 0 => output,
+      // Nothing is being scanned out, e.g. while the display is blanked.
+      Ok(_) => return Ok(Frame::Blank),
+      // A connector that's plugged back in is a new display, so this one is done.
+      Err(Error::DisplayDisconnected(_)) => {
+        self.stopped = true;
+        return Ok(Frame::Stopped);
+      }
+      Err(err) => return Err(err),
+    };
+
+    check_size(self.size, (output.width, output.height))?;
+    let region = clip_region(self.region, output.width, output.height)?;
+    let mapping = self.mapping(output.fb)?;
+    let layout = mapping.layout;
+    let data = layout.copy(
+      mapping.bytes(),
+      mapping.offset
+        + (output.y as usize + region.y as usize) * mapping.pitch
+        + (output.x as usize + region.x as usize) * layout.bytes_per_pixel,
+      mapping.pitch,
+      region.width as usize,
+      region.height as usize,
+    )?;
+
+    if self.last_frame.is_some() && data == self.last_data {
+      return Ok(Frame::Idle);
+    }
+
+    self.last_data.clone_from(&data);
+    Ok(Frame::Ready(DrmFrame {
+      data,
+      info: self.clock.next_bgrx(region.width, region.height),
+    }))
+  }
+}
+
+impl Capture for DrmCapture {
+  type Frame<'a> = DrmFrame;
+
+  fn frame(&mut self) -> Result<Frame<DrmFrame>> {
+    if self.stopped {
+      return Ok(Frame::Stopped);
+    }
+
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.get_frame()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+    Ok(())
+  }
+}
+
+/// A framebuffer as `drmModeGetFB2` describes it, with its handle closed once dropped.
+struct Fb2<'a> {
+  card: &'a Card,
+  fb: *mut drmModeFB2,
+}
+
+impl<'a> Fb2<'a> {
+  fn get(card: &'a Card, fb_id: u32) -> Result<Self> {
+    let fb = unsafe { drmModeGetFB2(card.fd(), fb_id) };
+    if fb.is_null() {
+      return Err(Error::last_os_error());
+    }
+
+    Ok(Self { card, fb })
+  }
+
+  fn info(&self) -> &drmModeFB2 {
+    unsafe { &*self.fb }
+  }
+}
+
+impl Drop for Fb2<'_> {
+  fn drop(&mut self) {
+    unsafe {
+      let handle = self.info().handles[0];
+      if handle != 0 {
+        drmCloseBufferHandle(self.card.fd(), handle);
+      }
+
+      drmModeFreeFB2(self.fb);
+    }
+  }
+}
+
+/// What a mapping was made from, which has to match for it to be reused.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct FbDesc {
+  fb: u32,
+  handle: u32,
+  pixel_format: u32,
+  modifier: u64,
+  width: u32,
+  height: u32,
+  pitch: u32,
+  offset: u32,
+}
+
+impl FbDesc {
+  fn new(fb: &drmModeFB2) -> Self {
+    Self {
+      fb: fb.fb_id,
+      handle: fb.handles[0],
+      pixel_format: fb.pixel_format,
+      modifier: fb.modifier,
+      width: fb.width,
+      height: fb.height,
+      pitch: fb.pitches[0],
+      offset: fb.offsets[0],
+    }
+  }
+}
+
+/// A framebuffer mapped into our address space.
+struct Mapping {
+  desc: FbDesc,
+  layout: PixelLayout,
+  offset: usize,
+  pitch: usize,
+  data: *mut u8,
+  len: usize,
+}
+
+// The mapping is read-only and owned by the capture alone.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+  fn map(card: &Card, fb: &drmModeFB2) -> Result<Self> {
+    // Handles are withheld from clients that aren't master or privileged.
+    if fb.handles[0] == 0 {
+      return Err(Error::denied(
+        "Framebuffer handles need DRM master or CAP_SYS_ADMIN",
+      ));
+    }
+
+    let linear = fb.flags & DRM_MODE_FB_MODIFIERS == 0
+      || fb.modifier == DRM_FORMAT_MOD_LINEAR
+      || fb.modifier == DRM_FORMAT_MOD_INVALID;
+
+    let layout = layout(fb.pixel_format)
+      .filter(|_| linear)
+      .ok_or_else(|| Error::unsupported("Unsupported framebuffer format"))?;
+
+    let offset = fb.offsets[0] as usize;
+    let pitch = fb.pitches[0] as usize;
+    let len = offset + pitch * fb.height as usize;
+
+    let data = match map_dumb(card, fb.handles[0], len) {
+      Ok(data) => data,
+      Err(_) => map_prime(card, fb.handles[0], len)?,
+    };
+
+    Ok(Self {
+      desc: FbDesc::new(fb),
+      layout,
+      offset,
+      pitch,
+      data,
+      len,
+    })
+  }
+
+  fn bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.data, self.len) }
+  }
+}
+
+impl Drop for Mapping {
+  fn drop(&mut self) {
+    unsafe {
+      munmap(self.data as *mut _, self.len);
+    }
+  }
+}
+
+/// Maps a dumb buffer through the card itself.
+fn map_dumb(card: &Card, handle: u32, len: usize) -> Result<*mut u8> {
+  let mut map = drm_mode_map_dumb {
+    handle,
+    ..Default::default()
+  };
+
+  unsafe {
+    if drmIoctl(
+      card.fd(),
+      DRM_IOCTL_MODE_MAP_DUMB,
+      &mut map as *mut _ as *mut _,
+    ) < 0
+    {
+      return Err(Error::last_os_error());
+    }
+
+    map_fd(card.fd(), len, map.offset as i64)
+  }
+}
+
+/// Maps any other buffer through a dma-buf, which most drivers allow for linear ones.
+fn map_prime(card: &Card, handle: u32, len: usize) -> Result<*mut u8> {
+  let mut fd = -1;
+
+  unsafe {
+    if drmPrimeHandleToFD(card.fd(), handle, DRM_CLOEXEC, &mut fd) < 0 {
+      return Err(Error::last_os_error());
+    }
+
+    // The mapping keeps the buffer alive once the dma-buf is closed.
+    let file = File::from_raw_fd(fd);
+    map_fd(file.as_raw_fd(), len, 0)
+  }
+}
+
+unsafe fn map_fd(fd: i32, len: usize, offset: i64) -> Result<*mut u8> {
+  let data = mmap(null_mut(), len, PROT_READ, MAP_SHARED, fd, offset);
+  if data == MAP_FAILED {
+    return Err(Error::last_os_error());
+  }
+
+  Ok(data as *mut u8)
+}
+
+/// The layout of the DRM formats we can read.
+fn layout(fourcc: u32) -> Option<PixelLayout> {
+  let layout = match fourcc {
+    DRM_FORMAT_XRGB8888 | DRM_FORMAT_ARGB8888 => PixelLayout::BGRX,
+    DRM_FORMAT_XBGR8888 | DRM_FORMAT_ABGR8888 => PixelLayout::new(
+      4,
+      Channel::new(0, 8),
+      Channel::new(8, 8),
+      Channel::new(16, 8),
+    ),
+    DRM_FORMAT_RGB565 => PixelLayout::new(
+      2,
+      Channel::new(11, 5),
+      Channel::new(5, 6),
+      Channel::new(0, 5),
+    ),
+    _ => return None,
+  };
+
+  Some(layout)
+}
+
+/// A frame copied out of the scanout framebuffer, converted to BGRX.
+#[derive(Debug)]
+pub struct DrmFrame {
+  data: Vec<u8>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for DrmFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for DrmFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::drm::{layout, Card};
+  use crate::ffi::drm::DRM_FORMAT_XBGR8888;
+
+  /// Planar YUV, which we leave to the GPU.
+  const DRM_FORMAT_NV12: u32 = 0x3231_564e;
+
+  #[test]
+  fn test_layout() {
+    let xbgr = layout(DRM_FORMAT_XBGR8888).unwrap();
+    let data = xbgr.copy(&[1, 2, 3, 4], 0, 4, 1, 1).unwrap();
+
+    assert_eq!(data, [3, 2, 1, 0xff]);
+    assert!(layout(DRM_FORMAT_NV12).is_none());
+  }
+
+  #[test]
+  fn test_open_missing_card() {
+    assert!(Card::open("/dev/dri/card-missing").is_err());
+  }
+}
@@ -0,0 +1,705 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use wayland_client::globals::{registry_queue_init, GlobalList, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{delegate_dispatch, delegate_noop};
+use wayland_client::{Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1;
+use wayland_protocols::ext::image_capture_source::v1::client::ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1;
+use wayland_protocols::ext::image_capture_source::v1::client::ext_image_capture_source_v1::ExtImageCaptureSourceV1;
+use wayland_protocols::ext::image_capture_source::v1::client::ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1;
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_frame_v1::{
+  self, ExtImageCopyCaptureFrameV1, FailureReason,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_manager_v1::{
+  ExtImageCopyCaptureManagerV1, Options,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_session_v1::{
+  self, ExtImageCopyCaptureSessionV1,
+};
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::ZwlrOutputPowerV1;
+
+use crate::capture::wayland::{
+  connect, dispatch_pending, wait_for_events, OutputPower, Outputs, ShmBuffer, Toplevels,
+};
+use crate::capture::{
+  check_size, crop_rects, is_throttled, throttle_remaining, Capture, CaptureOpts, Frame,
+  FrameClock, FrameInfo, Rect, VideoFrame,
+};
+use crate::display::{Display, Source};
+use crate::{Error, Result};
+
+pub use crate::capture::wayland::Toplevel;
+
+/// Past this many rects a buffer's pending damage is simply the whole buffer.
+const MAX_BUFFER_DAMAGE: usize = 32;
+
+/// Captures a Wayland output or toplevel through `ext_image_copy_capture_manager_v1`,
+/// stopping once the compositor ends the session, e.g. when the toplevel closes.
+///
+/// The protocol always copies whole outputs, so a region is cropped out afterwards.
+pub struct ExtImageCopyCapture {
+  queue: EventQueue<State>,
+  state: State,
+  session: ExtImageCopyCaptureSessionV1,
+  source: ExtImageCaptureSourceV1,
+  /// Only outputs have a power state.
+  power: Option<OutputPower>,
+  /// An output's size when the capture opened. Toplevels resize as they like.
+  size: Option<(u32, u32)>,
+  region: Option<Rect>,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  _conn: Connection,
+}
+
+impl ExtImageCopyCapture {
+  /// Captures the output `opts.display`.
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let (conn, globals, queue, state) = Self::connect(&opts.display)?;
+    let qh = queue.handle();
+
+    let name = match opts.display.source() {
+      Source::Wayland { name, .. } => name,
+      _ => unreachable!("connect only accepts Wayland outputs"),
+    };
+
+    let output = match state.outputs.find(name) {
+      Some(output) => output.output.clone(),
+      None => return Err(Error::unavailable(format!("Output {} not found", name))),
+    };
+
+    let sources = globals
+      .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
+      .map_err(Error::unavailable)?;
+
+    let source = sources.create_source(&output, &qh, ());
+    sources.destroy();
+
+    let mut capture = Self::start(conn, &globals, queue, state, source, &opts)?;
+    capture.power = OutputPower::new(&globals, &qh, &output);
+    capture.size = Some((opts.display.width(), opts.display.height()));
+
+    Ok(capture)
+  }
+
+  /// Captures the toplevel with the given identifier, as listed by [`get_toplevels`], on
+  /// the compositor `opts.display` belongs to.
+  ///
+  /// A region would be relative to a display the toplevel isn't tied to, so none may be
+  /// given.
+  pub fn new_toplevel(opts: CaptureOpts, identifier: &str) -> Result<Self> {
+    if opts.region.is_some() {
+      return Err(Error::invalid("Toplevel captures can't take a region"));
+    }
+
+    let (conn, globals, mut queue, mut state) = Self::connect(&opts.display)?;
+    let qh = queue.handle();
+
+    let sources = globals
+      .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
+      .map_err(Error::unavailable)?;
+
+    let list = bind_toplevel_list(&globals, &mut queue, &mut state)?;
+    let handle = state
+      .toplevels
+      .find(identifier)
+      .map(|toplevel| toplevel.handle.clone());
+
+    let source = handle.map(|handle| sources.create_source(&handle, &qh, ()));
+    sources.destroy();
+
+    // The source outlives the list, which would otherwise keep sending us toplevels.
+    list.destroy();
+
+    match source {
+      Some(source) => Self::start(conn, &globals, queue, state, source, &opts),
+      None => Err(Error::unavailable(format!(
+        "Toplevel {} not found",
+        identifier
+      ))),
+    }
+  }
+
+  fn connect(
+    display: &Display,
+  ) -> Result<(Connection, GlobalList, EventQueue<State>, State)> {
+    let socket = match display.source() {
+      Source::Wayland { socket, .. } => socket.as_deref(),
+      _ => return Err(Error::invalid("Display is not a Wayland output")),
+    };
+
+    let conn = connect(socket)?;
+    let (globals, mut queue) =
+      registry_queue_init::<State>(&conn).map_err(Error::disconnected)?;
+    let qh = queue.handle();
+
+    let shm = globals
+      .bind::<WlShm, _, _>(&qh, 1..=1, ())
+      .map_err(Error::unavailable)?;
+
+    let mut state = State::new(shm);
+    state.outputs.bind_all(&globals, &qh);
+    queue.roundtrip(&mut state).map_err(Error::disconnected)?;
+
+    Ok((conn, globals, queue, state))
+  }
+
+  fn start(
+    conn: Connection,
+    globals: &GlobalList,
+    mut queue: EventQueue<State>,
+    mut state: State,
+    source: ExtImageCaptureSourceV1,
+    opts: &CaptureOpts,
+  ) -> Result<Self> {
+    let qh = queue.handle();
+    let manager = match globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
+    {
+      Ok(manager) => manager,
+      Err(e) => {
+        source.destroy();
+        return Err(Error::unavailable(e));
+      }
+    };
+
+    let options = if opts.cursor {
+      Options::PaintCursors
+    } else {
+      Options::empty()
+    };
+
+    let session = manager.create_session(&source, options, &qh, ());
+    manager.destroy();
+
+    state.buffer_count = opts.frame_queue.max(1) as usize;
+    queue.roundtrip(&mut state).map_err(Error::disconnected)?;
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Ok(Self {
+      queue,
+      state,
+      session,
+      source,
+      power: None,
+      size: None,
+      region: opts.region,
+      frame_time,
+      last_frame: None,
+      _conn: conn,
+    })
+  }
+
+  fn poll(&mut self) -> Result<Frame<ExtImageCopyFrame>> {
+    dispatch_pending(&mut self.queue, &mut self.state)?;
+
+    if self.state.stopped {
+      return Ok(Frame::Stopped);
+    }
+
+    if let (Some(opened), Some(size)) = (self.size, self.state.size) {
+      check_size(opened, size)?;
+    }
+
+    if self.power.as_ref().is_some_and(|power| !power.is_on()) {
+      return Ok(Frame::Blank);
+    }
+
+    let status = self.state.request.as_ref().map(|request| request.status);
+    let frame = match status {
+      Some(Status::Ready) => {
+        let request = self.state.request.take().unwrap();
+        request.frame.destroy();
+
+        let frame = self.state.finish(request);
+        match self.region {
+          Some(region) => Frame::Ready(frame.crop(region)?),
+          None => Frame::Ready(frame),
+        }
+      }
+      Some(Status::Failed(reason)) => {
+        let request = self.state.request.take().unwrap();
+        request.frame.destroy();
+
+        if reason == FailureReason::Stopped {
+          self.state.stopped = true;
+          return Ok(Frame::Stopped);
+        }
+
+        // Buffer constraint failures resolve themselves once the new constraints arrive.
+        Frame::Blocking
+      }
+      // The compositor holds off on copying until something changes.
+      Some(Status::Copying) if self.last_frame.is_some() => return Ok(Frame::Idle),
+      Some(Status::Copying) => return Ok(Frame::Blocking),
+      None => Frame::Blocking,
+    };
+
+    // Start on the next frame straight away, so it's likely done by the next call.
+    self.state.capture(&self.session, &self.queue.handle())?;
+    self.queue.flush().map_err(Error::disconnected)?;
+
+    Ok(frame)
+  }
+}
+
+impl Capture for ExtImageCopyCapture {
+  type Frame<'a> = ExtImageCopyFrame;
+
+  fn frame(&mut self) -> Result<Frame<ExtImageCopyFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.poll()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    match throttle_remaining(self.frame_time, self.last_frame) {
+      Some(remaining) => {
+        sleep(timeout.min(remaining));
+        Ok(())
+      }
+      None => wait_for_events(&mut self.queue, timeout),
+    }
+  }
+}
+
+impl Drop for ExtImageCopyCapture {
+  fn drop(&mut self) {
+    if let Some(request) = self.state.request.take() {
+      request.frame.destroy();
+    }
+
+    self.session.destroy();
+    self.source.destroy();
+  }
+}
+
+/// Lists the toplevels on the compositor `display` belongs to, for
+/// [`ExtImageCopyCapture::new_toplevel`].
+pub fn get_toplevels(display: &Display) -> Result<Vec<Toplevel>> {
+  let (_conn, globals, mut queue, mut state) = ExtImageCopyCapture::connect(display)?;
+  let list = bind_toplevel_list(&globals, &mut queue, &mut state)?;
+  list.destroy();
+
+  Ok(state.toplevels.list())
+}
+
+fn bind_toplevel_list(
+  globals: &GlobalList,
+  queue: &mut EventQueue<State>,
+  state: &mut State,
+) -> Result<ExtForeignToplevelListV1> {
+  let list = globals
+    .bind::<ExtForeignToplevelListV1, _, _>(&queue.handle(), 1..=1, ())
+    .map_err(Error::unavailable)?;
+
+  // One roundtrip for the handles, another for their details.
+  queue.roundtrip(state).map_err(Error::disconnected)?;
+  queue.roundtrip(state).map_err(Error::disconnected)?;
+
+  Ok(list)
+}
+
+/// The buffer layout the session last asked for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Constraints {
+  format: wl_shm::Format,
+  width: u32,
+  height: u32,
+}
+
+impl Constraints {
+  fn stride(&self) -> u32 {
+    self.width * 4
+  }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Status {
+  /// The compositor is copying into our buffer, which it may hold off on until
+  /// something changes.
+  Copying,
+  Ready,
+  Failed(FailureReason),
+}
+
+struct Request {
+  frame: ExtImageCopyCaptureFrameV1,
+  buffer: Arc<ShmBuffer>,
+  dirty_rects: Vec<Rect>,
+  status: Status,
+}
+
+/// A buffer in the pool, along with what changed since it was last copied into.
+struct PooledBuffer {
+  buffer: Arc<ShmBuffer>,
+  /// `None` when the whole buffer is stale, e.g. it has never been copied into.
+  damage: Option<Vec<Rect>>,
+}
+
+struct State {
+  outputs: Outputs,
+  toplevels: Toplevels,
+  shm: WlShm,
+  formats: Vec<wl_shm::Format>,
+  size: Option<(u32, u32)>,
+  constraints: Option<Constraints>,
+  buffers: Vec<PooledBuffer>,
+  buffer_count: usize,
+  request: Option<Request>,
+  stopped: bool,
+  clock: FrameClock,
+}
+
+impl State {
+  fn new(shm: WlShm) -> Self {
+    Self {
+      outputs: Outputs::default(),
+      toplevels: Toplevels::default(),
+      shm,
+      formats: Vec::new(),
+      size: None,
+      constraints: None,
+      buffers: Vec::new(),
+      buffer_count: 1,
+      request: None,
+      stopped: false,
+      clock: FrameClock::new(),
+    }
+  }
+
+  /// Settles on a layout once the compositor has listed every format it accepts.
+  fn negotiate(&mut self) {
+    // Every compositor supports the two 32-bit formats, which are all we can size.
+    let preferred = [wl_shm::Format::Xrgb8888, wl_shm::Format::Argb8888];
+    let format = preferred
+      .iter()
+      .copied()
+      .find(|format| self.formats.contains(format));
+
+    self.constraints = match (format, self.size) {
+      (Some(format), Some((width, height))) => Some(Constraints {
+        format,
+        width,
+        height,
+      }),
+      _ => None,
+    };
+
+    self.formats.clear();
+  }
+
+  /// Asks for the next frame if none is in flight and a buffer is free.
+  fn capture(
+    &mut self,
+    session: &ExtImageCopyCaptureSessionV1,
+    qh: &QueueHandle<State>,
+  ) -> Result<()> {
+    if self.request.is_some() || self.stopped {
+      return Ok(());
+    }
+
+    let constraints = match self.constraints {
+      Some(constraints) => constraints,
+      None => return Ok(()),
+    };
+
+    let matches = |buffer: &ShmBuffer| {
+      buffer.matches(
+        constraints.format,
+        constraints.width,
+        constraints.height,
+        constraints.stride(),
+      )
+    };
+
+    // Buffers sized for previous constraints are useless now.
+    self
+      .buffers
+      .retain(|pooled| Arc::strong_count(&pooled.buffer) > 1 || matches(&pooled.buffer));
+
+    let free = self.buffers.iter().position(|pooled| {
+      Arc::strong_count(&pooled.buffer) == 1 && matches(&pooled.buffer)
+    });
+
+    let index = match free {
+      Some(index) => index,
+      None if self.buffers.len() < self.buffer_count => {
+        let buffer = ShmBuffer::new(
+          &self.shm,
+          qh,
+          constraints.format,
+          constraints.width,
+          constraints.height,
+          constraints.stride(),
+        )?;
+
+        self.buffers.push(PooledBuffer {
+          buffer: Arc::new(buffer),
+          damage: None,
+        });
+
+        self.buffers.len() - 1
+      }
+      None => return Ok(()),
+    };
+
+    let pooled = &mut self.buffers[index];
+    let frame = session.create_frame(qh, ());
+    frame.attach_buffer(&pooled.buffer.buffer);
+
+    match pooled.damage.take() {
+      Some(damage) => {
+        for rect in damage {
+          frame.damage_buffer(rect.x, rect.y, rect.width as i32, rect.height as i32);
+        }
+      }
+      None => {
+        frame.damage_buffer(0, 0, constraints.width as i32, constraints.height as i32)
+      }
+    }
+
+    frame.capture();
+
+    self.request = Some(Request {
+      frame,
+      buffer: pooled.buffer.clone(),
+      dirty_rects: Vec::new(),
+      status: Status::Copying,
+    });
+
+    Ok(())
+  }
+
+  /// Turns a completed request into a frame, passing its damage on to the rest of the
+  /// pool so their next copies know what to refresh.
+  fn finish(&mut self, request: Request) -> ExtImageCopyFrame {
+    for pooled in &mut self.buffers {
+      if Arc::ptr_eq(&pooled.buffer, &request.buffer) {
+        pooled.damage = Some(Vec::new());
+        continue;
+      }
+
+      if let Some(damage) = &mut pooled.damage {
+        damage.extend_from_slice(&request.dirty_rects);
+        if damage.len() > MAX_BUFFER_DAMAGE {
+          pooled.damage = None;
+        }
+      }
+    }
+
+    ExtImageCopyFrame {
+      info: request.buffer.info(&mut self.clock),
+      buffer: request.buffer,
+      offset: 0,
+      dirty_rects: request.dirty_rects,
+    }
+  }
+}
+
+impl AsMut<Outputs> for State {
+  fn as_mut(&mut self) -> &mut Outputs {
+    &mut self.outputs
+  }
+}
+
+impl AsMut<Toplevels> for State {
+  fn as_mut(&mut self) -> &mut Toplevels {
+    &mut self.toplevels
+  }
+}
+
+delegate_dispatch!(State: [WlRegistry: GlobalListContents] => Outputs);
+delegate_dispatch!(State: [WlOutput: u32] => Outputs);
+delegate_dispatch!(State: [ExtForeignToplevelListV1: ()] => Toplevels);
+delegate_dispatch!(State: [ExtForeignToplevelHandleV1: ()] => Toplevels);
+delegate_dispatch!(State: [ZwlrOutputPowerV1: Arc<AtomicBool>] => OutputPower);
+delegate_noop!(State: ZwlrOutputPowerManagerV1);
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: ignore WlShmPool);
+delegate_noop!(State: ignore WlBuffer);
+delegate_noop!(State: ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(State: ExtForeignToplevelImageCaptureSourceManagerV1);
+delegate_noop!(State: ExtImageCaptureSourceV1);
+delegate_noop!(State: ExtImageCopyCaptureManagerV1);
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for State {
+  fn event(
+    state: &mut Self,
+    _: &ExtImageCopyCaptureSessionV1,
+    event: ext_image_copy_capture_session_v1::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    match event {
+      ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+        state.size = Some((width, height))
+      }
+      ext_image_copy_capture_session_v1::Event::ShmFormat { format } => {
+        if let Ok(format) = format.into_result() {
+          state.formats.push(format);
+        }
+      }
+      ext_image_copy_capture_session_v1::Event::Done => state.negotiate(),
+      ext_image_copy_capture_session_v1::Event::Stopped => state.stopped = true,
+      _ => {}
+    }
+  }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for State {
+  fn event(
+    state: &mut Self,
+    frame: &ExtImageCopyCaptureFrameV1,
+    event: ext_image_copy_capture_frame_v1::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<Self>,
+  ) {
+    let request = match &mut state.request {
+      Some(request) if &request.frame == frame => request,
+      _ => return,
+    };
+
+    match event {
+      // Empty rects are a protocol error once handed back through `damage_buffer`.
+      ext_image_copy_capture_frame_v1::Event::Damage {
+        x,
+        y,
+        width,
+        height,
+      } if x >= 0 && y >= 0 && width > 0 && height > 0 => {
+        request
+          .dirty_rects
+          .push(Rect::new(x, y, width as u32, height as u32));
+      }
+      ext_image_copy_capture_frame_v1::Event::Ready => request.status = Status::Ready,
+      ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+        let reason = reason.into_result().unwrap_or(FailureReason::Unknown);
+        request.status = Status::Failed(reason);
+      }
+      _ => {}
+    }
+  }
+}
+
+/// A frame copied into one of the capture's shm buffers, in the `wl_shm` format the
+/// session negotiated, which is handed back to the pool once dropped.
+#[derive(Debug)]
+pub struct ExtImageCopyFrame {
+  buffer: Arc<ShmBuffer>,
+  /// Where the frame starts in the buffer, past whatever was cropped off.
+  offset: usize,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl ExtImageCopyFrame {
+  fn crop(self, region: Rect) -> Result<Self> {
+    let (offset, info) = self.info.crop(region)?;
+
+    Ok(Self {
+      offset,
+      dirty_rects: crop_rects(self.dirty_rects, region),
+      info,
+      ..self
+    })
+  }
+}
+
+impl VideoFrame for ExtImageCopyFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for ExtImageCopyFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.buffer.bytes()[self.offset..]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::ext_image_copy::{get_toplevels, ExtImageCopyCapture};
+  use crate::capture::{Capture, CaptureOpts, Frame, VideoFrame};
+  use crate::display::get_wayland_displays_on;
+  use crate::sway::Sway;
+  use crate::Error;
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn test_capture() {
+    let sway = Sway::start();
+    let display = get_wayland_displays_on(sway.socket())
+      .unwrap()
+      .into_iter()
+      .next()
+      .unwrap();
+
+    let (width, height) = (display.width() as usize, display.height() as usize);
+    let mut capture = ExtImageCopyCapture::new(CaptureOpts::new(display)).unwrap();
+
+    let started = Instant::now();
+    let frame = loop {
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        break frame;
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "No frame captured"
+      );
+    };
+
+    // The first frame of a session always carries full damage.
+    assert_eq!(frame.len(), width * height * 4);
+    assert!(!frame.dirty_rects().is_empty());
+  }
+
+  #[test]
+  fn test_capture_toplevel_not_found() {
+    let sway = Sway::start();
+    let display = get_wayland_displays_on(sway.socket())
+      .unwrap()
+      .into_iter()
+      .next()
+      .unwrap();
+
+    assert!(get_toplevels(&display).unwrap().is_empty());
+
+    let result = ExtImageCopyCapture::new_toplevel(CaptureOpts::new(display), "missing");
+    assert!(matches!(result, Err(Error::BackendUnavailable(_))));
+  }
+}
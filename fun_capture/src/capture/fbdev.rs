@@ -0,0 +1,374 @@
+use std::fs::File;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::VideoFrame;
+use crate::capture::{
+  check_size, clip_region, is_throttled, poll_interval, Capture, CaptureOpts, Frame,
+  FrameClock, FrameInfo, Rect,
+};
+use crate::display::Source;
+use crate::ffi::linux::{
+  fb_bitfield, fb_fix_screeninfo, fb_var_screeninfo, ioctl, mmap, munmap,
+};
+use crate::ffi::linux::{ENODEV, MAP_FAILED, MAP_SHARED, PROT_READ};
+use crate::ffi::linux::{FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO};
+use crate::{Error, Result};
+
+/// Reads a console framebuffer (`/dev/fbN`) straight out of its memory, stopping once
+/// the device is unplugged. Framebuffers don't say whether they're blanked.
+pub struct FbdevCapture {
+  file: File,
+  data: *mut u8,
+  len: usize,
+  /// The framebuffer's resolution when the capture opened.
+  size: (u32, u32),
+  region: Rect,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  /// The last frame's pixels, to tell whether anything changed since.
+  last_data: Vec<u8>,
+  clock: FrameClock,
+}
+
+// The mapping is read-only and owned by the capture alone.
+unsafe impl Send for FbdevCapture {}
+
+impl FbdevCapture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let device = match opts.display.source() {
+      Source::Framebuffer { device } => device,
+      _ => return Err(Error::invalid("Display is not a framebuffer")),
+    };
+
+    let file = File::open(device)?;
+    let (_, fix) = query(&file)?;
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    let mut capture = Self {
+      file,
+      data: null_mut(),
+      len: 0,
+      size: (opts.display.width(), opts.display.height()),
+      region: opts.source_rect(),
+      frame_time,
+      last_frame: None,
+      last_data: Vec::new(),
+      clock: FrameClock::new(),
+    };
+
+    capture.map(fix.smem_len as usize)?;
+    Ok(capture)
+  }
+
+  fn map(&mut self, len: usize) -> Result<()> {
+    self.unmap();
+
+    let data = unsafe {
+      mmap(
+        null_mut(),
+        len,
+        PROT_READ,
+        MAP_SHARED,
+        self.file.as_raw_fd(),
+        0,
+      )
+    };
+
+    if data == MAP_FAILED {
+      return Err(Error::last_os_error());
+    }
+
+    self.data = data as *mut u8;
+    self.len = len;
+
+    Ok(())
+  }
+
+  fn unmap(&mut self) {
+    if !self.data.is_null() {
+      unsafe { munmap(self.data as *mut _, self.len) };
+      self.data = null_mut();
+    }
+  }
+
+  fn get_frame(&mut self) -> Result<Frame<FbdevFrame>> {
+    // Panning can change under us with double buffering, and the geometry with `fbset`.
+    let (var, fix) = match query(&self.file) {
+      Ok(info) => info,
+      Err(Error::Io(err)) if err.raw_os_error() == Some(ENODEV) => {
+        return Ok(Frame::Stopped)
+      }
+      Err(err) => return Err(err),
+    };
+    check_size(self.size, (var.xres, var.yres))?;
+    if fix.smem_len as usize != self.len {
+      self.map(fix.smem_len as usize)?;
+    }
+
+    let layout = PixelLayout::from_var(&var)
+      .ok_or_else(|| Error::unsupported("Unsupported pixel format"))?;
+
+    let region = clip_region(self.region, var.xres, var.yres)?;
+    let memory = unsafe { from_raw_parts(self.data, self.len) };
+    let data = layout.copy(
+      memory,
+      (var.xoffset + region.x as u32) as usize * layout.bytes_per_pixel
+        + (var.yoffset + region.y as u32) as usize * fix.line_length as usize,
+      fix.line_length as usize,
+      region.width as usize,
+      region.height as usize,
+    )?;
+
+    if self.last_frame.is_some() && data == self.last_data {
+      return Ok(Frame::Idle);
+    }
+
+    self.last_data.clone_from(&data);
+    Ok(Frame::Ready(FbdevFrame {
+      data,
+      info: self.clock.next_bgrx(region.width, region.height),
+    }))
+  }
+}
+
+impl Capture for FbdevCapture {
+  type Frame<'a> = FbdevFrame;
+
+  fn frame(&mut self) -> Result<Frame<FbdevFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.get_frame()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+    Ok(())
+  }
+}
+
+impl Drop for FbdevCapture {
+  fn drop(&mut self) {
+    self.unmap();
+  }
+}
+
+/// Reads a framebuffer's variable (mode) and fixed (memory) info.
+pub(crate) fn query(file: &File) -> Result<(fb_var_screeninfo, fb_fix_screeninfo)> {
+  let mut var = fb_var_screeninfo::default();
+  let mut fix = fb_fix_screeninfo::default();
+
+  unsafe {
+    if ioctl(file.as_raw_fd(), FBIOGET_VSCREENINFO, &mut var) < 0 {
+      return Err(Error::last_os_error());
+    }
+
+    if ioctl(file.as_raw_fd(), FBIOGET_FSCREENINFO, &mut fix) < 0 {
+      return Err(Error::last_os_error());
+    }
+  }
+
+  Ok((var, fix))
+}
+
+/// Where a color channel sits within a pixel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Channel {
+  pub offset: u32,
+  pub length: u32,
+}
+
+impl Channel {
+  pub const fn new(offset: u32, length: u32) -> Self {
+    Self { offset, length }
+  }
+
+  /// Extracts the channel from a pixel, scaled to 8 bits.
+  fn get(self, pixel: u32) -> u8 {
+    let max = (1u32 << self.length) - 1;
+    (((pixel >> self.offset) & max) * 255 / max) as u8
+  }
+}
+
+/// A packed little-endian RGB pixel format, which is how both fbdev and DRM describe
+/// the formats we can read.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct PixelLayout {
+  pub bytes_per_pixel: usize,
+  pub red: Channel,
+  pub green: Channel,
+  pub blue: Channel,
+}
+
+impl PixelLayout {
+  /// What every backend hands out: blue, green, red and an unused byte.
+  pub const BGRX: PixelLayout = PixelLayout::new(
+    4,
+    Channel::new(16, 8),
+    Channel::new(8, 8),
+    Channel::new(0, 8),
+  );
+
+  pub const fn new(
+    bytes_per_pixel: usize,
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+  ) -> Self {
+    Self {
+      bytes_per_pixel,
+      red,
+      green,
+      blue,
+    }
+  }
+
+  fn from_var(var: &fb_var_screeninfo) -> Option<Self> {
+    let channel = |field: &fb_bitfield| {
+      let valid = field.msb_right == 0
+        && (1..=8).contains(&field.length)
+        && field.offset + field.length <= var.bits_per_pixel;
+
+      if valid {
+        Some(Channel::new(field.offset, field.length))
+      } else {
+        None
+      }
+    };
+
+    match var.bits_per_pixel {
+      16 | 24 | 32 if var.grayscale == 0 => Some(Self::new(
+        var.bits_per_pixel as usize / 8,
+        channel(&var.red)?,
+        channel(&var.green)?,
+        channel(&var.blue)?,
+      )),
+      _ => None,
+    }
+  }
+
+  /// Copies a `width` by `height` region starting at `offset` in `memory` into a new
+  /// BGRX frame.
+  pub fn copy(
+    &self,
+    memory: &[u8],
+    offset: usize,
+    stride: usize,
+    width: usize,
+    height: usize,
+  ) -> Result<Vec<u8>> {
+    let row_len = width * self.bytes_per_pixel;
+    if height == 0
+      || stride < row_len
+      || offset + stride * (height - 1) + row_len > memory.len()
+    {
+      return Err(Error::invalid_data("Framebuffer is smaller than its mode"));
+    }
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for row in memory[offset..].chunks(stride).take(height) {
+      self.convert_row(&row[..row_len], &mut data);
+    }
+
+    Ok(data)
+  }
+
+  fn convert_row(&self, row: &[u8], data: &mut Vec<u8>) {
+    if *self == Self::BGRX {
+      data.extend_from_slice(row);
+      return;
+    }
+
+    for pixel in row.chunks_exact(self.bytes_per_pixel) {
+      let pixel = pixel
+        .iter()
+        .enumerate()
+        .fold(0u32, |pixel, (i, byte)| pixel | (*byte as u32) << (8 * i));
+
+      data.extend_from_slice(&[
+        self.blue.get(pixel),
+        self.green.get(pixel),
+        self.red.get(pixel),
+        0xff,
+      ]);
+    }
+  }
+}
+
+/// A frame copied out of the framebuffer, converted to BGRX.
+#[derive(Debug)]
+pub struct FbdevFrame {
+  data: Vec<u8>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for FbdevFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for FbdevFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::fbdev::{Channel, PixelLayout};
+
+  #[test]
+  fn test_copy_bgrx() {
+    // Two rows of two pixels, padded to a stride of 12 bytes.
+    let memory = [
+      1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, //
+      9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+    ];
+
+    let data = PixelLayout::BGRX.copy(&memory, 0, 12, 2, 2).unwrap();
+    assert_eq!(
+      data,
+      [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]
+    );
+
+    let data = PixelLayout::BGRX.copy(&memory, 4, 12, 1, 2).unwrap();
+    assert_eq!(data, [5, 6, 7, 8, 13, 14, 15, 16]);
+
+    assert!(PixelLayout::BGRX.copy(&memory, 8, 12, 2, 2).is_err());
+  }
+
+  #[test]
+  fn test_copy_rgb565() {
+    let rgb565 = PixelLayout::new(
+      2,
+      Channel::new(11, 5),
+      Channel::new(5, 6),
+      Channel::new(0, 5),
+    );
+
+    // Pure red, then pure blue.
+    let memory = [0x00, 0xf8, 0x1f, 0x00];
+    let data = rgb565.copy(&memory, 0, 4, 2, 1).unwrap();
+
+    assert_eq!(data, [0, 0, 0xff, 0xff, 0xff, 0, 0, 0xff]);
+  }
+}
@@ -0,0 +1,377 @@
+//! What a frame's bytes mean: its geometry, pixel format and when it was captured.
+
+use std::borrow::Cow;
+use std::iter::FusedIterator;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use crate::capture::{clip_region, Rect};
+use crate::{Error, Result};
+
+/// How a frame's pixels are laid out in memory, byte by byte.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PixelFormat {
+  /// Blue, green, red and an unused byte, which nearly every backend produces.
+  Bgrx8888,
+  /// Blue, green, red and alpha.
+  Bgra8888,
+  /// Red, green, blue and an unused byte.
+  Rgbx8888,
+  /// Red, green, blue and alpha.
+  Rgba8888,
+  /// Little-endian 16-bit words of 5 bits red, 6 green and 5 blue, high to low.
+  Rgb565,
+  /// Full range BT.709 YCbCr 4:2:0 in two planes, `stride` apart row to row: a luma
+  /// byte for each pixel, then a blue and a red chroma byte for each 2x2 block. Frames
+  /// in it have an even width and height.
+  Nv12,
+}
+
+impl PixelFormat {
+  /// Counts only the luma plane for NV12.
+  pub fn bytes_per_pixel(self) -> usize {
+    match self {
+      Self::Nv12 => 1,
+      Self::Rgb565 => 2,
+      _ => 4,
+    }
+  }
+}
+
+/// The geometry and format of a frame, and when it was captured.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameInfo {
+  pub width: u32,
+  pub height: u32,
+  /// Bytes from the start of one row to the next, which may include padding.
+  pub stride: usize,
+  pub format: PixelFormat,
+  /// Time since the capture started, or since the recorded one did for a replay.
+  pub timestamp: Duration,
+  /// Counts up from 0 with each frame, so a gap means frames were skipped.
+  pub sequence: u64,
+}
+
+impl FrameInfo {
+  /// The bytes of pixels in each row, without padding.
+  pub fn row_len(&self) -> usize {
+    self.width as usize * self.format.bytes_per_pixel()
+  }
+
+  /// The rows of bytes in the frame, which for NV12 are the luma rows followed by half
+  /// as many chroma rows.
+  pub fn row_count(&self) -> usize {
+    match self.format {
+      PixelFormat::Nv12 => self.height as usize / 2 * 3,
+      _ => self.height as usize,
+    }
+  }
+
+  /// Describes the part of `region` within a frame laid out like this, along with the
+  /// offset its pixels start at, so it can be cropped without copying. Fails if none of
+  /// it is.
+  pub(crate) fn crop(&self, region: Rect) -> Result<(usize, FrameInfo)> {
+    if self.format == PixelFormat::Nv12 {
+      return Err(Error::unsupported("NV12 frames can't be cropped"));
+    }
+
+    let region = clip_region(region, self.width, self.height)?;
+    let offset =
+      region.y as usize * self.stride + region.x as usize * self.format.bytes_per_pixel();
+
+    Ok((
+      offset,
+      FrameInfo {
+        width: region.width,
+        height: region.height,
+        ..*self
+      },
+    ))
+  }
+}
+
+/// A captured frame, which every backend's frame type implements on top of its bytes.
+pub trait VideoFrame: Deref<Target = [u8]> {
+  fn info(&self) -> FrameInfo;
+
+  fn width(&self) -> u32 {
+    self.info().width
+  }
+
+  fn height(&self) -> u32 {
+    self.info().height
+  }
+
+  fn stride(&self) -> usize {
+    self.info().stride
+  }
+
+  fn format(&self) -> PixelFormat {
+    self.info().format
+  }
+
+  fn timestamp(&self) -> Duration {
+    self.info().timestamp
+  }
+
+  fn sequence(&self) -> u64 {
+    self.info().sequence
+  }
+
+  /// The regions that changed since the previous frame, the whole frame for backends
+  /// that can't tell.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Owned(vec![Rect::new(0, 0, self.width(), self.height())])
+  }
+
+  /// The rows from top to bottom, each without its padding, see
+  /// [`FrameInfo::row_count`].
+  fn rows(&self) -> Rows<'_> {
+    Rows::new(self, &self.info())
+  }
+
+  /// The row `y` without its padding, `None` past the bottom.
+  fn row(&self, y: u32) -> Option<&[u8]> {
+    self.rows().nth(y as usize)
+  }
+
+  /// Copies the frame out, e.g. to keep it after handing its buffer back to the capture.
+  fn to_buffer(&self) -> FrameBuffer {
+    let info = self.info();
+    let len = (info.stride * info.row_count()).min(self.len());
+
+    FrameBuffer {
+      data: self[..len].to_vec(),
+      info,
+    }
+  }
+}
+
+/// An iterator over a frame's rows, see [`VideoFrame::rows`].
+///
+/// Stops early rather than reading past the end of a buffer too short for its height.
+#[derive(Debug, Clone)]
+pub struct Rows<'a> {
+  data: &'a [u8],
+  stride: usize,
+  row_len: usize,
+  remaining: usize,
+}
+
+impl<'a> Rows<'a> {
+  fn new(data: &'a [u8], info: &FrameInfo) -> Self {
+    let row_len = info.row_len();
+    let fits = match data.len().checked_sub(row_len) {
+      Some(_) if info.stride < row_len => 0,
+      Some(rest) if info.stride > 0 => rest / info.stride + 1,
+      Some(_) => 1,
+      None => 0,
+    };
+
+    Self {
+      data,
+      stride: info.stride,
+      row_len,
+      remaining: fits.min(info.row_count()),
+    }
+  }
+}
+
+impl<'a> Iterator for Rows<'a> {
+  type Item = &'a [u8];
+
+  fn next(&mut self) -> Option<&'a [u8]> {
+    if self.remaining == 0 {
+      return None;
+    }
+
+    let row = &self.data[..self.row_len];
+    self.remaining -= 1;
+    if self.remaining > 0 {
+      self.data = &self.data[self.stride..];
+    }
+
+    Some(row)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (self.remaining, Some(self.remaining))
+  }
+}
+
+impl ExactSizeIterator for Rows<'_> {}
+
+impl FusedIterator for Rows<'_> {}
+
+/// A frame that owns its bytes, independent of the capture it came from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FrameBuffer {
+  data: Vec<u8>,
+  info: FrameInfo,
+}
+
+impl FrameBuffer {
+  /// Wraps `data` laid out as `info` describes, failing if it's too short for it.
+  pub fn new(data: Vec<u8>, info: FrameInfo) -> Result<Self> {
+    let rows = Rows::new(&data, &info).len();
+    if rows < info.row_count() || info.stride < info.row_len() {
+      return Err(Error::invalid("Frame data doesn't match its size"));
+    }
+
+    Ok(Self { data, info })
+  }
+
+  /// Copies the rows out without their padding, so the stride is the row length.
+  pub fn packed(&self) -> Self {
+    let mut data = Vec::with_capacity(self.info.row_len() * self.info.row_count());
+    for row in self.rows() {
+      data.extend_from_slice(row);
+    }
+
+    Self {
+      data,
+      info: FrameInfo {
+        stride: self.info.row_len(),
+        ..self.info
+      },
+    }
+  }
+
+  pub fn into_vec(self) -> Vec<u8> {
+    self.data
+  }
+}
+
+impl VideoFrame for FrameBuffer {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for FrameBuffer {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+/// Stamps a capture's frames with their sequence number and capture time.
+#[derive(Debug)]
+pub(crate) struct FrameClock {
+  started: Instant,
+  sequence: u64,
+}
+
+impl FrameClock {
+  pub fn new() -> Self {
+    Self {
+      started: Instant::now(),
+      sequence: 0,
+    }
+  }
+
+  /// Describes the next frame, captured just now.
+  pub fn next(
+    &mut self,
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: PixelFormat,
+  ) -> FrameInfo {
+    let sequence = self.sequence;
+    self.sequence += 1;
+
+    FrameInfo {
+      width,
+      height,
+      stride,
+      format,
+      timestamp: self.started.elapsed(),
+      sequence,
+    }
+  }
+
+  /// Like [`FrameClock::next`] for a tightly packed BGRX frame.
+  pub fn next_bgrx(&mut self, width: u32, height: u32) -> FrameInfo {
+    self.next(width, height, width as usize * 4, PixelFormat::Bgrx8888)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use crate::capture::frame::{FrameBuffer, FrameClock, FrameInfo, PixelFormat, Rows};
+  use crate::capture::{Rect, VideoFrame};
+
+  fn info(width: u32, height: u32, stride: usize) -> FrameInfo {
+    FrameInfo {
+      width,
+      height,
+      stride,
+      format: PixelFormat::Bgrx8888,
+      timestamp: Duration::ZERO,
+      sequence: 0,
+    }
+  }
+
+  #[test]
+  fn test_rows() {
+    // Two one-pixel rows, 12 bytes apart.
+    let data = [[1; 4], [0; 4], [0; 4], [2; 4], [0; 4]].concat();
+    let frame = FrameBuffer::new(data, info(1, 2, 12)).unwrap();
+
+    assert_eq!(frame.rows().collect::<Vec<_>>(), [&[1; 4], &[2; 4]]);
+    assert_eq!(frame.row(1), Some(&[2; 4][..]));
+    assert_eq!(frame.row(2), None);
+    assert_eq!(frame.packed().into_vec(), [1, 1, 1, 1, 2, 2, 2, 2]);
+  }
+
+  #[test]
+  fn test_rows_short_buffer() {
+    assert!(FrameBuffer::new(vec![0; 15], info(1, 2, 12)).is_err());
+    assert!(FrameBuffer::new(vec![0; 16], info(2, 2, 4)).is_err());
+
+    // The last row needs no padding after it.
+    assert!(FrameBuffer::new(vec![0; 16], info(1, 2, 12)).is_ok());
+    assert_eq!(Rows::new(&[0; 15], &info(1, 2, 12)).count(), 1);
+  }
+
+  #[test]
+  fn test_rows_nv12() {
+    let info = FrameInfo {
+      format: PixelFormat::Nv12,
+      ..info(2, 2, 3)
+    };
+
+    // Two luma rows and a chroma row, each padded by a byte.
+    let frame = FrameBuffer::new(vec![1, 2, 0, 3, 4, 0, 5, 6], info).unwrap();
+    assert_eq!(frame.rows().collect::<Vec<_>>(), [[1, 2], [3, 4], [5, 6]]);
+    assert!(FrameBuffer::new(vec![0; 7], info).is_err());
+    assert!(info.crop(Rect::new(0, 0, 1, 1)).is_err());
+  }
+
+  #[test]
+  fn test_crop() {
+    let (offset, cropped) = info(4, 3, 20).crop(Rect::new(1, 1, 2, 2)).unwrap();
+    assert_eq!(offset, 20 + 4);
+    assert_eq!((cropped.width, cropped.height, cropped.stride), (2, 2, 20));
+
+    // Clipped to the frame.
+    let (_, cropped) = info(4, 3, 20).crop(Rect::new(3, 2, 4, 4)).unwrap();
+    assert_eq!((cropped.width, cropped.height), (1, 1));
+    assert!(info(4, 3, 20).crop(Rect::new(4, 0, 1, 1)).is_err());
+  }
+
+  #[test]
+  fn test_clock() {
+    let mut clock = FrameClock::new();
+    let first = clock.next_bgrx(2, 1);
+    let second = clock.next(2, 1, 16, PixelFormat::Rgb565);
+
+    assert_eq!((first.sequence, second.sequence), (0, 1));
+    assert_eq!(first.stride, 8);
+    assert!(second.timestamp >= first.timestamp);
+  }
+}
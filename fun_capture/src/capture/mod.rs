@@ -1,26 +1,315 @@
 use crate::display::Display;
+use crate::{Error, Result};
 use std::fmt::Debug;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
+mod auto;
+pub mod convert;
+pub mod desktop;
+#[cfg(all(target_os = "linux", feature = "drm"))]
+pub mod drm;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod ext_image_copy;
+#[cfg(target_os = "linux")]
+pub mod fbdev;
+mod frame;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub mod pipewire;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub(crate) mod portal;
 #[cfg(target_os = "macos")]
 pub mod quartz;
+pub mod replay;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod synthetic;
+pub mod vnc;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub(crate) mod wayland;
 #[cfg(target_os = "windows")]
 pub mod windows_dc;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod wlr_screencopy;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod x11;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod xcomposite;
+#[cfg(target_os = "linux")]
+pub mod xwd;
 
+pub use auto::{open, Backend, BoxedCapture, BoxedFrame, DynCapture, BACKEND_VAR};
+pub use convert::Converted;
+pub(crate) use frame::FrameClock;
+pub use frame::{FrameBuffer, FrameInfo, PixelFormat, Rows, VideoFrame};
+
+/// What a capture has to show right now.
 #[derive(Debug)]
 pub enum Frame<T: Debug> {
+  /// A new frame.
   Ready(T),
+  /// Nothing yet, e.g. the next frame is still on its way or is being throttled.
   Blocking,
+  /// The display hasn't changed since the last frame, so it's still current.
+  Idle,
+  /// The display is blank or asleep, so there'll be nothing new until it wakes up.
+  Blank,
+  /// The stream ended, e.g. the display or window went away or a recording ran out,
+  /// and no more frames will come.
+  Stopped,
+}
+
+impl<T: Debug> Frame<T> {
+  pub fn map<U: Debug, F: FnOnce(T) -> U>(self, f: F) -> Frame<U> {
+    match self {
+      Frame::Ready(frame) => Frame::Ready(f(frame)),
+      Frame::Blocking => Frame::Blocking,
+      Frame::Idle => Frame::Idle,
+      Frame::Blank => Frame::Blank,
+      Frame::Stopped => Frame::Stopped,
+    }
+  }
+}
+
+impl<T: VideoFrame + Debug> Frame<T> {
+  /// Copies a ready frame out, so it can be kept while the capture hands out more.
+  pub fn to_owned(&self) -> Frame<FrameBuffer> {
+    match self {
+      Frame::Ready(frame) => Frame::Ready(frame.to_buffer()),
+      Frame::Blocking => Frame::Blocking,
+      Frame::Idle => Frame::Idle,
+      Frame::Blank => Frame::Blank,
+      Frame::Stopped => Frame::Stopped,
+    }
+  }
+}
+
+/// A rectangle in display coordinates, e.g. a region that changed since the last frame.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rect {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Rect {
+  pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+    Self {
+      x,
+      y,
+      width,
+      height,
+    }
+  }
+
+  /// The part of this rectangle `other` covers too, `None` if they don't overlap.
+  pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+    let left = self.x.max(other.x);
+    let top = self.y.max(other.y);
+    let right = self.right().min(other.right());
+    let bottom = self.bottom().min(other.bottom());
+
+    (i64::from(left) < right && i64::from(top) < bottom).then(|| {
+      Rect::new(
+        left,
+        top,
+        (right - i64::from(left)) as u32,
+        (bottom - i64::from(top)) as u32,
+      )
+    })
+  }
+
+  /// Whether `other` lies entirely within this rectangle.
+  pub fn contains(&self, other: &Rect) -> bool {
+    self.intersection(other) == Some(*other)
+  }
+
+  fn right(&self) -> i64 {
+    i64::from(self.x) + i64::from(self.width)
+  }
+
+  fn bottom(&self) -> i64 {
+    i64::from(self.y) + i64::from(self.height)
+  }
+}
+
+/// Clips `region` to a `width` by `height` frame, failing if none of it is left, e.g.
+/// once the display shrank.
+pub(crate) fn clip_region(region: Rect, width: u32, height: u32) -> Result<Rect> {
+  region
+    .intersection(&Rect::new(0, 0, width, height))
+    .ok_or_else(|| Error::invalid("Region is outside the display"))
+}
+
+/// Moves `rects` to be relative to `region`, dropping whatever falls outside it.
+pub(crate) fn crop_rects(
+  rects: impl IntoIterator<Item = Rect>,
+  region: Rect,
+) -> Vec<Rect> {
+  rects
+    .into_iter()
+    .filter_map(|rect| rect.intersection(&region))
+    .map(|rect| {
+      Rect::new(
+        rect.x - region.x,
+        rect.y - region.y,
+        rect.width,
+        rect.height,
+      )
+    })
+    .collect()
+}
+
+/// Fails with [`Error::DisplayChanged`] unless a display that was `opened` big is still
+/// `current` big, as frames would no longer fit it.
+pub(crate) fn check_size(opened: (u32, u32), current: (u32, u32)) -> Result<()> {
+  if opened == current {
+    return Ok(());
+  }
+
+  Err(Error::changed(format!(
+    "Display resized from {}x{} to {}x{}",
+    opened.0, opened.1, current.0, current.1
+  )))
 }
 
-pub trait Capture<T: Debug> {
-  fn frame(&mut self) -> Frame<T>;
+/// A source of frames.
+///
+/// Anything but [`Frame::Ready`] means there's nothing new, and says why, so callers
+/// can e.g. repeat the last frame while idle or pause while blank. Once a capture
+/// returns [`Frame::Stopped`] it keeps doing so. An error means something failed and
+/// the capture is unlikely to recover. A capture stops once its display is unplugged,
+/// and fails with [`Error::DisplayChanged`] once it's resized, rather than hand out
+/// frames that no longer match it.
+///
+/// A frame may borrow the capture, e.g. to hand out a locked OS buffer without copying
+/// it, so it has to be dropped, or copied with [`Frame::to_owned`], before the next
+/// call to [`Capture::frame`] or dropping the capture.
+///
+/// [`Capture::frame`] never blocks, while [`Capture::next_frame`] waits for a frame
+/// without spinning.
+///
+/// Every backend's capture is `Send`, so it can be opened on one thread and moved to
+/// another, and [`DynCapture`] lets different backends share a `Box`.
+pub trait Capture {
+  type Frame<'a>: VideoFrame + Debug
+  where
+    Self: 'a;
+
+  fn frame(&mut self) -> Result<Frame<Self::Frame<'_>>>;
+
+  /// Blocks until [`Capture::frame`] may have something new, or `timeout` passes.
+  /// Backends that can't be woken up sleep a little.
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    sleep(timeout.min(POLL_INTERVAL));
+    Ok(())
+  }
+
+  /// Blocks until a frame is ready or the capture stops.
+  fn next_frame(&mut self) -> Result<Frame<Self::Frame<'_>>> {
+    frame_until(self, None)
+  }
+
+  /// Blocks until a frame is ready or the capture stops, returning whatever
+  /// [`Capture::frame`] last said if `timeout` passes first.
+  fn next_frame_timeout(&mut self, timeout: Duration) -> Result<Frame<Self::Frame<'_>>> {
+    // Too long a timeout to represent is as good as none.
+    frame_until(self, Instant::now().checked_add(timeout))
+  }
 }
 
+/// How long to sleep between polls when a backend has nothing to wait on.
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+fn frame_until<C: Capture + ?Sized>(
+  capture: &mut C,
+  deadline: Option<Instant>,
+) -> Result<Frame<C::Frame<'_>>> {
+  loop {
+    // The borrow checker can't tell that a frame we don't return stops borrowing the
+    // capture, so take it through a pointer and drop it before touching `capture`.
+    let frame = unsafe { &mut *(capture as *mut C) }.frame()?;
+    let timeout = match deadline {
+      Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+      None => Duration::MAX,
+    };
+
+    if matches!(frame, Frame::Ready(_) | Frame::Stopped) || timeout.is_zero() {
+      return Ok(frame);
+    }
+
+    drop(frame);
+    capture.wait(timeout)?;
+  }
+}
+
+/// How much longer a capture taking a frame every `frame_time` has to hold off since
+/// its `last_frame`, if at all.
+pub(crate) fn throttle_remaining(
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+) -> Option<Duration> {
+  let remaining = frame_time?.checked_sub(last_frame?.elapsed())?;
+  (!remaining.is_zero()).then_some(remaining)
+}
+
+/// Whether a capture taking a frame every `frame_time` has to hold off since its
+/// `last_frame`.
+pub(crate) fn is_throttled(
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+) -> bool {
+  throttle_remaining(frame_time, last_frame).is_some()
+}
+
+/// How long a capture that can't be woken up should sleep before polling again.
+pub(crate) fn poll_interval(
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+) -> Duration {
+  throttle_remaining(frame_time, last_frame).unwrap_or(POLL_INTERVAL)
+}
+
+/// Blocks until `fd` is readable or `timeout` passes, returning whether it's readable.
+#[cfg(all(
+  target_os = "linux",
+  any(feature = "wayland", feature = "x11", feature = "udev")
+))]
+pub(crate) fn wait_readable(fd: std::os::raw::c_int, timeout: Duration) -> Result<bool> {
+  use crate::ffi::linux::{poll, pollfd, POLLIN};
+
+  let mut fds = pollfd {
+    fd,
+    events: POLLIN,
+    revents: 0,
+  };
+
+  // Round up, so a short wait doesn't turn into a busy loop.
+  let millis = timeout.as_nanos().div_ceil(1_000_000);
+  let millis = millis.min(i32::MAX as u128) as i32;
+
+  if unsafe { poll(&mut fds, 1, millis) } < 0 {
+    let err = std::io::Error::last_os_error();
+    return match err.kind() {
+      std::io::ErrorKind::Interrupted => Ok(false),
+      _ => Err(err.into()),
+    };
+  }
+
+  Ok(fds.revents != 0)
+}
+
+#[derive(Clone)]
 pub struct CaptureOpts {
   pub(crate) cursor: bool,
   pub(crate) display: Display,
   pub(crate) frame_rate: f64,
   pub(crate) frame_queue: u8,
+  pub(crate) backend: Option<Backend>,
+  pub(crate) region: Option<Rect>,
+  pub(crate) output_size: Option<(u32, u32)>,
+  pub(crate) pixel_format: Option<PixelFormat>,
 }
 
 impl CaptureOpts {
@@ -30,6 +319,10 @@ impl CaptureOpts {
       display,
       frame_rate: 0.0,
       frame_queue: 3,
+      backend: None,
+      region: None,
+      output_size: None,
+      pixel_format: None,
     }
   }
 
@@ -47,4 +340,69 @@ impl CaptureOpts {
     self.frame_queue = frame_queue;
     self
   }
+
+  /// Which backend [`open`] uses, rather than detecting one.
+  pub fn backend(&mut self, backend: Backend) -> &mut Self {
+    self.backend = Some(backend);
+    self
+  }
+
+  /// Captures only `region` of the display, relative to its top left corner like dirty
+  /// rects are, e.g. one panel of a big monitor. Frames are the region's size, and
+  /// their dirty rects are relative to it.
+  ///
+  /// Fails unless the region is within the display and not empty.
+  pub fn region(&mut self, region: Rect) -> Result<&mut Self> {
+    let bounds = Rect::new(0, 0, self.display.width(), self.display.height());
+    if region.width == 0 || region.height == 0 || !bounds.contains(&region) {
+      return Err(Error::invalid(format!(
+        "Region {:?} is outside the {}x{} display",
+        region, bounds.width, bounds.height
+      )));
+    }
+
+    self.region = Some(region);
+    Ok(self)
+  }
+
+  /// Scales frames to `width` by `height`, e.g. for thumbnails, rather than the size of
+  /// the display or region. Fails if either is 0.
+  ///
+  /// Backends that can't scale leave it to [`Converted`], which [`open`] wraps them in.
+  pub fn output_size(&mut self, width: u32, height: u32) -> Result<&mut Self> {
+    if width == 0 || height == 0 {
+      return Err(Error::invalid(format!(
+        "Output size {}x{} is empty",
+        width, height
+      )));
+    }
+
+    self.output_size = Some((width, height));
+    Ok(self)
+  }
+
+  /// Converts frames to `format`, e.g. [`PixelFormat::Nv12`] for an encoder, rather than
+  /// whichever the backend produces. For NV12, an odd width or height is rounded down.
+  ///
+  /// Backends that can't produce it leave it to [`Converted`], which [`open`] wraps
+  /// them in.
+  pub fn pixel_format(&mut self, format: PixelFormat) -> &mut Self {
+    self.pixel_format = Some(format);
+    self
+  }
+
+  /// The size frames should be once scaled, for backends that scale them themselves.
+  #[cfg(any(target_os = "macos", test))]
+  pub(crate) fn target_size(&self) -> (u32, u32) {
+    let region = self.source_rect();
+    let size = self.output_size.unwrap_or((region.width, region.height));
+    convert::fit(size, self.pixel_format)
+  }
+
+  /// The part of the display to capture, all of it unless a region was given.
+  pub(crate) fn source_rect(&self) -> Rect {
+    self
+      .region
+      .unwrap_or_else(|| Rect::new(0, 0, self.display.width(), self.display.height()))
+  }
 }
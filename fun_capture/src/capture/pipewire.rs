@@ -0,0 +1,1216 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::mem::size_of;
+use std::ops::Deref;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::io::{IntoRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr::{null, null_mut};
+use std::slice::from_raw_parts;
+use std::sync::{Arc, Condvar, Mutex, Once, Weak};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::portal::ScreenCast;
+use crate::capture::{
+  crop_rects, is_throttled, throttle_remaining, Capture, CaptureOpts, Frame, FrameClock,
+  FrameInfo, Rect, VideoFrame,
+};
+use crate::display::{Display, Source};
+use crate::ffi::pipewire::*;
+use crate::{Error, Result};
+
+/// Past this many rects, damage from skipped frames is simply the whole frame.
+const MAX_MISSED_DAMAGE: usize = 32;
+/// How many damage rects we ask the producer to fit in each buffer's metadata.
+const MAX_BUFFER_DAMAGE: usize = 16;
+
+/// Captures a PipeWire video node, usually one handed out by the ScreenCast portal.
+///
+/// It's blank while the producer pauses the stream, e.g. while its monitor is off, and
+/// stops once the stream disconnects. A region is cropped out of each frame.
+pub struct PipeWireCapture {
+  stream: Stream,
+  region: Option<Rect>,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  clock: FrameClock,
+  _portal: Option<ScreenCast>,
+}
+
+impl PipeWireCapture {
+  /// Asks the ScreenCast portal for a monitor, reusing the restore token stored at
+  /// [`restore_token_path`] for `opts.display` so the user is only prompted once.
+  ///
+  /// The portal decides which monitor is captured. If it says where that is and it isn't
+  /// where `opts.display` is, this fails rather than capture another display.
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let path = restore_token_path(&opts.display);
+    Self::new_with_token_file(opts, path.as_deref())
+  }
+
+  /// Like [`PipeWireCapture::new`], but keeps the restore token in `path`, if any.
+  pub fn new_with_token_file(opts: CaptureOpts, path: Option<&Path>) -> Result<Self> {
+    let restore_token = path
+      .and_then(|path| fs::read_to_string(path).ok())
+      .map(|token| token.trim().to_string())
+      .filter(|token| !token.is_empty());
+
+    let (portal, started) = ScreenCast::start(opts.cursor, restore_token.as_deref())?;
+
+    let display = &opts.display;
+    if let (Some(position), Source::Wayland { .. }) = (started.position, display.source())
+    {
+      if position != (display.x(), display.y()) {
+        return Err(Error::unavailable(format!(
+          "The portal shared the monitor at {:?} rather than at {:?}",
+          position,
+          (display.x(), display.y())
+        )));
+      }
+    }
+
+    // Failing to remember the token only means prompting again next time.
+    if let (Some(path), Some(token)) = (path, &started.restore_token) {
+      if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+      }
+
+      let _ = fs::write(path, token);
+    }
+
+    let stream = Stream::connect(Remote::Fd(started.fd), started.node_id, &opts)?;
+    Ok(Self::with_stream(stream, &opts, Some(portal)))
+  }
+
+  /// Captures `node_id` directly, bypassing the portal, on the PipeWire daemon
+  /// listening at `remote` or the default one when `None`.
+  pub fn from_node(
+    opts: CaptureOpts,
+    remote: Option<&str>,
+    node_id: u32,
+  ) -> Result<Self> {
+    let remote = match remote {
+      Some(remote) => Remote::Name(remote.to_string()),
+      None => Remote::Default,
+    };
+
+    let stream = Stream::connect(remote, node_id, &opts)?;
+    Ok(Self::with_stream(stream, &opts, None))
+  }
+
+  fn with_stream(stream: Stream, opts: &CaptureOpts, portal: Option<ScreenCast>) -> Self {
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Self {
+      stream,
+      region: opts.region,
+      frame_time,
+      last_frame: None,
+      clock: FrameClock::new(),
+      _portal: portal,
+    }
+  }
+
+  fn poll(&mut self) -> Result<Frame<PipeWireFrame>> {
+    let mut shared = self.stream.shared.lock().unwrap();
+    if let Some(error) = &shared.error {
+      return Err(Error::disconnected(error.clone()));
+    }
+
+    if let Some(message) = shared.unsupported.take() {
+      return Err(Error::unsupported(message));
+    }
+
+    let ready = shared.ready.take();
+    let otherwise = if shared.stopped {
+      Frame::Stopped
+    } else if shared.paused {
+      Frame::Blank
+    } else if shared.idle {
+      Frame::Idle
+    } else {
+      Frame::Blocking
+    };
+
+    drop(shared);
+
+    let frame = match ready {
+      Some((data, mut dirty_rects, (width, height))) => {
+        let mut info = self.clock.next_bgrx(width, height);
+        let mut offset = 0;
+        if let Some(region) = self.region {
+          (offset, info) = info.crop(region)?;
+          dirty_rects = crop_rects(dirty_rects, region);
+        }
+
+        Frame::Ready(PipeWireFrame {
+          data,
+          offset,
+          dirty_rects,
+          info,
+          pool: Arc::downgrade(&self.stream.shared),
+        })
+      }
+      None => otherwise,
+    };
+
+    Ok(frame)
+  }
+}
+
+impl Capture for PipeWireCapture {
+  type Frame<'a> = PipeWireFrame;
+
+  fn frame(&mut self) -> Result<Frame<PipeWireFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.poll()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    if let Some(remaining) = throttle_remaining(self.frame_time, self.last_frame) {
+      sleep(timeout.min(remaining));
+      return Ok(());
+    }
+
+    let shared = self.stream.shared.lock().unwrap();
+    let _ = self
+      .stream
+      .changed
+      .wait_timeout_while(shared, timeout, |shared| {
+        shared.ready.is_none()
+          && !shared.stopped
+          && shared.error.is_none()
+          && shared.unsupported.is_none()
+      });
+
+    Ok(())
+  }
+}
+
+/// Where the restore token for `display` is kept by default, under `$XDG_STATE_HOME`.
+/// A token hands back the monitor it was made for, so there's one per compositor and
+/// output.
+pub fn restore_token_path(display: &Display) -> Option<PathBuf> {
+  let state_home = match env::var_os("XDG_STATE_HOME") {
+    Some(state_home) => PathBuf::from(state_home),
+    None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+  };
+
+  let name = format!("screencast-restore-token-{}", token_key(display.source()));
+  Some(state_home.join("fun_capture").join(name))
+}
+
+/// What tells restore tokens apart, fit for a file name.
+fn token_key(source: &Source) -> String {
+  let key = match source {
+    Source::Wayland { socket, name } => {
+      let socket = socket
+        .clone()
+        .or_else(|| env::var("WAYLAND_DISPLAY").ok())
+        .unwrap_or_default();
+
+      format!("{}-{}", socket, name)
+    }
+    _ => String::from("default"),
+  };
+
+  // Sockets may be paths.
+  key.replace('/', "_")
+}
+
+/// How to reach the PipeWire daemon.
+enum Remote {
+  Default,
+  /// A socket name, or an absolute socket path.
+  Name(String),
+  /// An already open connection, as handed out by the portal.
+  Fd(OwnedFd),
+}
+
+/// A connection to the PipeWire daemon, serviced by its own thread.
+struct Core {
+  thread_loop: *mut pw_thread_loop,
+  context: *mut pw_context,
+  core: *mut pw_core,
+}
+
+impl Core {
+  fn connect(remote: Remote) -> Result<Self> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe { pw_init(null_mut(), null_mut()) });
+
+    let name = CString::new("fun_capture").unwrap();
+
+    unsafe {
+      let thread_loop = pw_thread_loop_new(name.as_ptr(), null());
+      if thread_loop.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      let context = pw_context_new(pw_thread_loop_get_loop(thread_loop), null_mut(), 0);
+      if context.is_null() {
+        pw_thread_loop_destroy(thread_loop);
+        return Err(Error::last_os_error());
+      }
+
+      let core = match remote {
+        Remote::Default => pw_context_connect(context, null_mut(), 0),
+        Remote::Name(name) => {
+          let props = properties(&[("remote.name", &name)]);
+          pw_context_connect(context, props, 0)
+        }
+        Remote::Fd(fd) => pw_context_connect_fd(context, fd.into_raw_fd(), null_mut(), 0),
+      };
+
+      if core.is_null() {
+        let error = Error::unavailable(Error::last_os_error());
+        pw_context_destroy(context);
+        pw_thread_loop_destroy(thread_loop);
+        return Err(error);
+      }
+
+      if pw_thread_loop_start(thread_loop) < 0 {
+        pw_core_disconnect(core);
+        pw_context_destroy(context);
+        pw_thread_loop_destroy(thread_loop);
+        return Err(Error::other("Failed to start the PipeWire thread"));
+      }
+
+      Ok(Self {
+        thread_loop,
+        context,
+        core,
+      })
+    }
+  }
+
+  /// Runs `f` with the loop locked, as anything touching PipeWire objects must.
+  fn locked<T>(&self, f: impl FnOnce() -> T) -> T {
+    unsafe { pw_thread_loop_lock(self.thread_loop) };
+    let result = f();
+    unsafe { pw_thread_loop_unlock(self.thread_loop) };
+
+    result
+  }
+}
+
+impl Drop for Core {
+  fn drop(&mut self) {
+    unsafe {
+      pw_thread_loop_stop(self.thread_loop);
+      pw_core_disconnect(self.core);
+      pw_context_destroy(self.context);
+      pw_thread_loop_destroy(self.thread_loop);
+    }
+  }
+}
+
+/// A filled buffer, its damage and its size.
+type ReadyFrame = (Vec<u8>, Vec<Rect>, (u32, u32));
+
+/// What the PipeWire thread shares with the capture.
+#[derive(Default)]
+struct Shared {
+  width: u32,
+  height: u32,
+  /// The newest frame not yet handed out, and its size.
+  ready: Option<ReadyFrame>,
+  /// Damage from frames skipped because every buffer was still held.
+  missed: Vec<Rect>,
+  free: Vec<Vec<u8>>,
+  allocated: usize,
+  capacity: usize,
+  /// Set once the stream has streamed, so a later pause isn't just it starting up.
+  streamed: bool,
+  paused: bool,
+  /// The producer's last buffer changed nothing, e.g. it only moved the cursor.
+  idle: bool,
+  stopped: bool,
+  error: Option<String>,
+  /// Why the producer's last buffer was dropped, reported by the next poll.
+  unsupported: Option<String>,
+}
+
+impl Shared {
+  fn full_rect(&self) -> Rect {
+    Rect::new(0, 0, self.width, self.height)
+  }
+
+  /// A buffer for the next frame, reusing the unclaimed ready frame if the pool is dry.
+  fn take_buffer(&mut self, dirty_rects: &mut Vec<Rect>) -> Option<Vec<u8>> {
+    if let Some(buffer) = self.free.pop() {
+      return Some(buffer);
+    }
+
+    if self.allocated < self.capacity {
+      self.allocated += 1;
+      return Some(Vec::new());
+    }
+
+    let (buffer, stale_rects, _) = self.ready.take()?;
+    dirty_rects.extend(stale_rects);
+
+    Some(buffer)
+  }
+
+  fn miss(&mut self, dirty_rects: Vec<Rect>) {
+    self.missed.extend(dirty_rects);
+    if self.missed.len() > MAX_MISSED_DAMAGE {
+      self.missed = vec![self.full_rect()];
+    }
+  }
+}
+
+/// State handed to the stream callbacks, which must stay put while registered.
+struct Listener {
+  hook: spa_hook,
+  stream: *mut pw_stream,
+  shared: Arc<Mutex<Shared>>,
+  changed: Arc<Condvar>,
+}
+
+static STREAM_EVENTS: pw_stream_events = pw_stream_events {
+  version: PW_VERSION_STREAM_EVENTS,
+  destroy: None,
+  state_changed: Some(on_state_changed),
+  control_info: None,
+  io_changed: None,
+  param_changed: Some(on_param_changed),
+  add_buffer: None,
+  remove_buffer: None,
+  process: Some(on_process),
+  drained: None,
+};
+
+/// An input stream connected to a video node.
+struct Stream {
+  stream: *mut pw_stream,
+  shared: Arc<Mutex<Shared>>,
+  /// Notified whenever the callbacks change `shared`.
+  changed: Arc<Condvar>,
+  _listener: Box<Listener>,
+  core: Core,
+}
+
+// PipeWire objects are only touched with the thread loop locked, from whichever thread.
+unsafe impl Send for Stream {}
+
+impl Stream {
+  fn connect(remote: Remote, node_id: u32, opts: &CaptureOpts) -> Result<Self> {
+    let core = Core::connect(remote)?;
+    let shared = Arc::new(Mutex::new(Shared {
+      capacity: opts.frame_queue.max(1) as usize,
+      ..Shared::default()
+    }));
+    let changed = Arc::new(Condvar::new());
+
+    let mut listener = Box::new(Listener {
+      hook: spa_hook::default(),
+      stream: null_mut(),
+      shared: shared.clone(),
+      changed: changed.clone(),
+    });
+
+    let frame_rate = if opts.frame_rate > 0.0 {
+      opts.frame_rate.round() as u32
+    } else {
+      60
+    };
+
+    let stream = core.locked(|| unsafe {
+      let name = CString::new("fun_capture").unwrap();
+      let props = properties(&[
+        ("media.type", "Video"),
+        ("media.category", "Capture"),
+        ("media.role", "Screen"),
+      ]);
+
+      let stream = pw_stream_new(core.core, name.as_ptr(), props);
+      if stream.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      listener.stream = stream;
+      let data = &mut *listener as *mut Listener as *mut c_void;
+      pw_stream_add_listener(stream, &mut listener.hook, &STREAM_EVENTS, data);
+
+      let format = aligned(enum_format(frame_rate));
+      let mut params = [format.as_ptr() as *const spa_pod];
+
+      let result = pw_stream_connect(
+        stream,
+        SPA_DIRECTION_INPUT,
+        node_id,
+        PW_STREAM_FLAG_AUTOCONNECT | PW_STREAM_FLAG_MAP_BUFFERS,
+        params.as_mut_ptr(),
+        params.len() as u32,
+      );
+
+      if result < 0 {
+        pw_stream_destroy(stream);
+        return Err(io::Error::from_raw_os_error(-result).into());
+      }
+
+      Ok(stream)
+    })?;
+
+    Ok(Self {
+      stream,
+      shared,
+      changed,
+      _listener: listener,
+      core,
+    })
+  }
+}
+
+impl Drop for Stream {
+  fn drop(&mut self) {
+    let stream = self.stream;
+    self.core.locked(|| unsafe {
+      pw_stream_disconnect(stream);
+      pw_stream_destroy(stream);
+    });
+  }
+}
+
+unsafe extern "C" fn on_state_changed(
+  data: *mut c_void,
+  _: c_int,
+  state: c_int,
+  error: *const c_char,
+) {
+  let listener = &*(data as *const Listener);
+  let mut shared = listener.shared.lock().unwrap();
+
+  match state {
+    PW_STREAM_STATE_ERROR if !error.is_null() => {
+      shared.error = Some(CStr::from_ptr(error).to_string_lossy().into_owned())
+    }
+    PW_STREAM_STATE_ERROR => shared.error = Some("Stream failed".to_string()),
+    PW_STREAM_STATE_UNCONNECTED => shared.stopped = true,
+    PW_STREAM_STATE_PAUSED => shared.paused = shared.streamed,
+    PW_STREAM_STATE_STREAMING => {
+      shared.streamed = true;
+      shared.paused = false;
+    }
+    _ => {}
+  }
+
+  listener.changed.notify_all();
+}
+
+unsafe extern "C" fn on_param_changed(data: *mut c_void, id: u32, param: *const spa_pod) {
+  if id != SPA_PARAM_Format || param.is_null() {
+    return;
+  }
+
+  let listener = &*(data as *const Listener);
+  let (width, height) = match parse_video_size(param) {
+    Some(size) => size,
+    None => return,
+  };
+
+  {
+    let mut shared = listener.shared.lock().unwrap();
+    shared.width = width;
+    shared.height = height;
+    shared.missed.clear();
+  }
+
+  let buffers = aligned(buffers_param(None));
+  let meta = aligned(damage_meta_param());
+  let mut params = [
+    buffers.as_ptr() as *const spa_pod,
+    meta.as_ptr() as *const spa_pod,
+  ];
+
+  pw_stream_update_params(listener.stream, params.as_mut_ptr(), params.len() as u32);
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+  let listener = &*(data as *const Listener);
+
+  // Skip straight to the newest buffer, handing older ones back.
+  let mut newest: *mut pw_buffer = null_mut();
+  loop {
+    let buffer = pw_stream_dequeue_buffer(listener.stream);
+    if buffer.is_null() {
+      break;
+    }
+
+    if !newest.is_null() {
+      pw_stream_queue_buffer(listener.stream, newest);
+    }
+
+    newest = buffer;
+  }
+
+  if newest.is_null() {
+    return;
+  }
+
+  let mut shared = listener.shared.lock().unwrap();
+  read_buffer(&mut shared, &*(*newest).buffer);
+  listener.changed.notify_all();
+
+  pw_stream_queue_buffer(listener.stream, newest);
+}
+
+/// Copies a buffer's rows into the next free frame, packed to `width * 4` bytes.
+unsafe fn read_buffer(shared: &mut Shared, buffer: &spa_buffer) {
+  if buffer.n_datas == 0 || shared.width == 0 || shared.height == 0 {
+    return;
+  }
+
+  let data = &*buffer.datas;
+  if data.data.is_null() || data.chunk.is_null() {
+    return;
+  }
+
+  let chunk = &*data.chunk;
+
+  // Producers send empty buffers when only metadata, like the cursor, changed.
+  if chunk.size == 0 {
+    shared.idle = true;
+    return;
+  }
+
+  let row_len = shared.width as usize * 4;
+  let stride = if chunk.stride > 0 {
+    chunk.stride as usize
+  } else {
+    row_len
+  };
+
+  let height = shared.height as usize;
+  let offset = chunk.offset as usize % data.maxsize.max(1) as usize;
+  if stride < row_len || offset + stride * (height - 1) + row_len > data.maxsize as usize
+  {
+    shared.unsupported = Some(format!(
+      "A {}x{} buffer with stride {} at offset {} doesn't fit in {} bytes",
+      shared.width, shared.height, stride, offset, data.maxsize
+    ));
+    return;
+  }
+
+  let mut dirty_rects = std::mem::take(&mut shared.missed);
+  match damage_rects(buffer) {
+    Some(rects) => dirty_rects.extend(rects),
+    None => dirty_rects.push(shared.full_rect()),
+  }
+
+  if dirty_rects.is_empty() {
+    shared.idle = true;
+    return;
+  }
+
+  let mut frame = match shared.take_buffer(&mut dirty_rects) {
+    Some(frame) => frame,
+    None => return shared.miss(dirty_rects),
+  };
+
+  let source = from_raw_parts((data.data as *const u8).add(offset), stride * height);
+
+  frame.clear();
+  frame.reserve(row_len * height);
+  for row in source.chunks(stride).take(height) {
+    frame.extend_from_slice(&row[..row_len]);
+  }
+
+  shared.ready = Some((frame, dirty_rects, (shared.width, shared.height)));
+  shared.idle = false;
+}
+
+/// The producer's damage, or `None` if it doesn't send any.
+unsafe fn damage_rects(buffer: &spa_buffer) -> Option<Vec<Rect>> {
+  if buffer.metas.is_null() {
+    return None;
+  }
+
+  let metas = from_raw_parts(buffer.metas, buffer.n_metas as usize);
+  let meta = metas
+    .iter()
+    .find(|meta| meta.kind == SPA_META_VideoDamage && !meta.data.is_null())?;
+
+  let count = meta.size as usize / size_of::<spa_meta_region>();
+  let regions = from_raw_parts(meta.data as *const spa_meta_region, count);
+
+  // The list ends at the first empty region.
+  let rects = regions
+    .iter()
+    .map(|region| &region.region)
+    .take_while(|region| region.size.width > 0 && region.size.height > 0)
+    .map(|region| {
+      Rect::new(
+        region.position.x,
+        region.position.y,
+        region.size.width,
+        region.size.height,
+      )
+    })
+    .collect();
+
+  Some(rects)
+}
+
+/// A frame copied out of a PipeWire buffer as BGRX, which is handed back to the pool
+/// once dropped.
+#[derive(Debug)]
+pub struct PipeWireFrame {
+  data: Vec<u8>,
+  /// Where the frame starts in `data`, past whatever was cropped off.
+  offset: usize,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+  pool: Weak<Mutex<Shared>>,
+}
+
+impl VideoFrame for PipeWireFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for PipeWireFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data[self.offset..]
+  }
+}
+
+impl Drop for PipeWireFrame {
+  fn drop(&mut self) {
+    if let Some(pool) = self.pool.upgrade() {
+      let data = std::mem::take(&mut self.data);
+      pool.lock().unwrap().free.push(data);
+    }
+  }
+}
+
+unsafe fn properties(pairs: &[(&str, &str)]) -> *mut pw_properties {
+  let args = pairs
+    .iter()
+    .map(|(key, value)| format!("{}=\"{}\"", key, value))
+    .collect::<Vec<_>>()
+    .join(" ");
+
+  let args = CString::new(args).unwrap();
+  pw_properties_new_string(args.as_ptr())
+}
+
+// SPA PODs, which the C API builds with inline helpers we can't link against. Each is
+// a size and type header followed by the body, padded to 8 bytes.
+
+fn pod(kind: u32, body: &[u8]) -> Vec<u8> {
+  let mut pod = Vec::with_capacity(8 + body.len() + 7);
+  pod.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+  pod.extend_from_slice(&kind.to_ne_bytes());
+  pod.extend_from_slice(body);
+  pod.resize((pod.len() + 7) & !7, 0);
+  pod
+}
+
+fn pod_id(id: u32) -> Vec<u8> {
+  pod(SPA_TYPE_Id, &id.to_ne_bytes())
+}
+
+fn pod_int(value: i32) -> Vec<u8> {
+  pod(SPA_TYPE_Int, &value.to_ne_bytes())
+}
+
+fn pair(a: u32, b: u32) -> [u8; 8] {
+  let mut pair = [0; 8];
+  pair[..4].copy_from_slice(&a.to_ne_bytes());
+  pair[4..].copy_from_slice(&b.to_ne_bytes());
+  pair
+}
+
+fn pod_choice(choice: u32, kind: u32, values: &[&[u8]]) -> Vec<u8> {
+  let mut body = Vec::new();
+  body.extend_from_slice(&choice.to_ne_bytes());
+  body.extend_from_slice(&0u32.to_ne_bytes());
+  body.extend_from_slice(&(values[0].len() as u32).to_ne_bytes());
+  body.extend_from_slice(&kind.to_ne_bytes());
+  for value in values {
+    body.extend_from_slice(value);
+  }
+
+  pod(SPA_TYPE_Choice, &body)
+}
+
+fn pod_object(kind: u32, id: u32, props: &[(u32, Vec<u8>)]) -> Vec<u8> {
+  let mut body = Vec::new();
+  body.extend_from_slice(&kind.to_ne_bytes());
+  body.extend_from_slice(&id.to_ne_bytes());
+  for (key, value) in props {
+    body.extend_from_slice(&key.to_ne_bytes());
+    body.extend_from_slice(&0u32.to_ne_bytes());
+    body.extend_from_slice(value);
+  }
+
+  pod(SPA_TYPE_Object, &body)
+}
+
+/// Copies a POD somewhere aligned for PipeWire to read it from.
+fn aligned(pod: Vec<u8>) -> Vec<u64> {
+  pod
+    .chunks(8)
+    .map(|chunk| {
+      let mut word = [0; 8];
+      word[..chunk.len()].copy_from_slice(chunk);
+      u64::from_ne_bytes(word)
+    })
+    .collect()
+}
+
+/// The formats we accept, which are all laid out as BGRA in memory.
+fn enum_format(frame_rate: u32) -> Vec<u8> {
+  let bgrx = SPA_VIDEO_FORMAT_BGRx.to_ne_bytes();
+  let bgra = SPA_VIDEO_FORMAT_BGRA.to_ne_bytes();
+
+  pod_object(
+    SPA_TYPE_OBJECT_Format,
+    SPA_PARAM_EnumFormat,
+    &[
+      (SPA_FORMAT_mediaType, pod_id(SPA_MEDIA_TYPE_video)),
+      (SPA_FORMAT_mediaSubtype, pod_id(SPA_MEDIA_SUBTYPE_raw)),
+      (
+        SPA_FORMAT_VIDEO_format,
+        pod_choice(SPA_CHOICE_Enum, SPA_TYPE_Id, &[&bgrx, &bgrx, &bgra]),
+      ),
+      (
+        SPA_FORMAT_VIDEO_size,
+        pod_choice(
+          SPA_CHOICE_Range,
+          SPA_TYPE_Rectangle,
+          &[&pair(1920, 1080), &pair(1, 1), &pair(16384, 16384)],
+        ),
+      ),
+      (
+        SPA_FORMAT_VIDEO_framerate,
+        pod_choice(
+          SPA_CHOICE_Range,
+          SPA_TYPE_Fraction,
+          &[&pair(frame_rate, 1), &pair(0, 1), &pair(1000, 1)],
+        ),
+      ),
+    ],
+  )
+}
+
+/// Buffers we can read from the CPU, with their layout when we're the producer.
+fn buffers_param(layout: Option<(u32, u32)>) -> Vec<u8> {
+  let data_types: i32 = (1 << SPA_DATA_MemPtr) | (1 << SPA_DATA_MemFd);
+
+  let mut props = vec![
+    (
+      SPA_PARAM_BUFFERS_buffers,
+      pod_choice(
+        SPA_CHOICE_Range,
+        SPA_TYPE_Int,
+        &[
+          &8i32.to_ne_bytes(),
+          &1i32.to_ne_bytes(),
+          &32i32.to_ne_bytes(),
+        ],
+      ),
+    ),
+    (SPA_PARAM_BUFFERS_blocks, pod_int(1)),
+    (
+      SPA_PARAM_BUFFERS_dataType,
+      pod_choice(SPA_CHOICE_Flags, SPA_TYPE_Int, &[&data_types.to_ne_bytes()]),
+    ),
+  ];
+
+  if let Some((size, stride)) = layout {
+    props.push((SPA_PARAM_BUFFERS_size, pod_int(size as i32)));
+    props.push((SPA_PARAM_BUFFERS_stride, pod_int(stride as i32)));
+  }
+
+  pod_object(SPA_TYPE_OBJECT_ParamBuffers, SPA_PARAM_Buffers, &props)
+}
+
+fn damage_meta_param() -> Vec<u8> {
+  let region = size_of::<spa_meta_region>() as i32;
+
+  pod_object(
+    SPA_TYPE_OBJECT_ParamMeta,
+    SPA_PARAM_Meta,
+    &[
+      (SPA_PARAM_META_type, pod_id(SPA_META_VideoDamage)),
+      (
+        SPA_PARAM_META_size,
+        pod_choice(
+          SPA_CHOICE_Range,
+          SPA_TYPE_Int,
+          &[
+            &(region * MAX_BUFFER_DAMAGE as i32).to_ne_bytes(),
+            &region.to_ne_bytes(),
+            &(region * MAX_BUFFER_DAMAGE as i32).to_ne_bytes(),
+          ],
+        ),
+      ),
+    ],
+  )
+}
+
+/// Reads the negotiated size out of a `Format` param.
+unsafe fn parse_video_size(param: *const spa_pod) -> Option<(u32, u32)> {
+  let header = &*param;
+  if header.kind != SPA_TYPE_Object {
+    return None;
+  }
+
+  let bytes = from_raw_parts(param as *const u8, 8 + header.size as usize);
+  let read = |offset: usize| -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(word.try_into().unwrap()))
+  };
+
+  // Skip the pod header and the object's type and id.
+  let mut offset = 16;
+  while offset + 16 <= bytes.len() {
+    let key = read(offset)?;
+    let size = read(offset + 8)? as usize;
+    let mut kind = read(offset + 12)?;
+    let mut value = offset + 16;
+
+    // A fixated choice still wraps its value; take the default.
+    if kind == SPA_TYPE_Choice {
+      kind = read(value + 12)?;
+      value += 16;
+    }
+
+    if key == SPA_FORMAT_VIDEO_size && kind == SPA_TYPE_Rectangle {
+      return Some((read(value)?, read(value + 4)?));
+    }
+
+    offset += 16 + ((size + 7) & !7);
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pipewire_daemon::PipeWireDaemon;
+
+  const WIDTH: u32 = 64;
+  const HEIGHT: u32 = 48;
+  const DAMAGE: Rect = Rect {
+    x: 8,
+    y: 8,
+    width: 16,
+    height: 16,
+  };
+
+  /// A video source painting white frames with a fixed damage rect.
+  struct FakeSource {
+    stream: *mut pw_stream,
+    _listener: Box<Listener>,
+    core: Core,
+  }
+
+  impl FakeSource {
+    fn start(remote: &str) -> Self {
+      static EVENTS: pw_stream_events = pw_stream_events {
+        version: PW_VERSION_STREAM_EVENTS,
+        destroy: None,
+        state_changed: None,
+        control_info: None,
+        io_changed: None,
+        param_changed: Some(on_source_param_changed),
+        add_buffer: None,
+        remove_buffer: None,
+        process: Some(on_source_process),
+        drained: None,
+      };
+
+      let core = Core::connect(Remote::Name(remote.to_string())).unwrap();
+      let mut listener = Box::new(Listener {
+        hook: spa_hook::default(),
+        stream: null_mut(),
+        shared: Arc::default(),
+        changed: Arc::default(),
+      });
+
+      let stream = core.locked(|| unsafe {
+        let name = CString::new("fun_capture-fake-source").unwrap();
+        let props = properties(&[
+          ("media.type", "Video"),
+          ("media.class", "Video/Source"),
+          ("node.name", "fun_capture-fake-source"),
+        ]);
+
+        let stream = pw_stream_new(core.core, name.as_ptr(), props);
+        listener.stream = stream;
+        let data = &mut *listener as *mut Listener as *mut c_void;
+        pw_stream_add_listener(stream, &mut listener.hook, &EVENTS, data);
+
+        let format = aligned(pod_object(
+          SPA_TYPE_OBJECT_Format,
+          SPA_PARAM_EnumFormat,
+          &[
+            (SPA_FORMAT_mediaType, pod_id(SPA_MEDIA_TYPE_video)),
+            (SPA_FORMAT_mediaSubtype, pod_id(SPA_MEDIA_SUBTYPE_raw)),
+            (SPA_FORMAT_VIDEO_format, pod_id(SPA_VIDEO_FORMAT_BGRx)),
+            (
+              SPA_FORMAT_VIDEO_size,
+              pod(SPA_TYPE_Rectangle, &pair(WIDTH, HEIGHT)),
+            ),
+            (
+              SPA_FORMAT_VIDEO_framerate,
+              pod(SPA_TYPE_Fraction, &pair(30, 1)),
+            ),
+          ],
+        ));
+
+        let mut params = [format.as_ptr() as *const spa_pod];
+        let result = pw_stream_connect(
+          stream,
+          SPA_DIRECTION_OUTPUT,
+          PW_ID_ANY,
+          PW_STREAM_FLAG_MAP_BUFFERS,
+          params.as_mut_ptr(),
+          params.len() as u32,
+        );
+
+        assert!(result >= 0, "Failed to connect the fake source");
+        stream
+      });
+
+      Self {
+        stream,
+        _listener: listener,
+        core,
+      }
+    }
+
+    fn node_id(&self) -> u32 {
+      let started = Instant::now();
+      loop {
+        let node_id = self
+          .core
+          .locked(|| unsafe { pw_stream_get_node_id(self.stream) });
+
+        if node_id != PW_ID_ANY {
+          return node_id;
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5), "No node id");
+        std::thread::sleep(Duration::from_millis(10));
+      }
+    }
+  }
+
+  impl Drop for FakeSource {
+    fn drop(&mut self) {
+      let stream = self.stream;
+      self.core.locked(|| unsafe { pw_stream_destroy(stream) });
+    }
+  }
+
+  unsafe extern "C" fn on_source_param_changed(
+    data: *mut c_void,
+    id: u32,
+    param: *const spa_pod,
+  ) {
+    if id != SPA_PARAM_Format || param.is_null() {
+      return;
+    }
+
+    let listener = &*(data as *const Listener);
+    let buffers = aligned(buffers_param(Some((WIDTH * HEIGHT * 4, WIDTH * 4))));
+    let meta = aligned(damage_meta_param());
+    let mut params = [
+      buffers.as_ptr() as *const spa_pod,
+      meta.as_ptr() as *const spa_pod,
+    ];
+
+    pw_stream_update_params(listener.stream, params.as_mut_ptr(), params.len() as u32);
+  }
+
+  unsafe extern "C" fn on_source_process(data: *mut c_void) {
+    let listener = &*(data as *const Listener);
+    let buffer = pw_stream_dequeue_buffer(listener.stream);
+    if buffer.is_null() {
+      return;
+    }
+
+    let spa_buffer = &*(*buffer).buffer;
+    let data = &*spa_buffer.datas;
+    let len = (WIDTH * HEIGHT * 4) as usize;
+    if !data.data.is_null() && data.maxsize as usize >= len {
+      std::ptr::write_bytes(data.data as *mut u8, 0xff, len);
+
+      let chunk = &mut *data.chunk;
+      chunk.offset = 0;
+      chunk.size = len as u32;
+      chunk.stride = (WIDTH * 4) as i32;
+    }
+
+    let metas = from_raw_parts(spa_buffer.metas, spa_buffer.n_metas as usize);
+    if let Some(meta) = metas.iter().find(|meta| meta.kind == SPA_META_VideoDamage) {
+      let count = meta.size as usize / size_of::<spa_meta_region>();
+      let regions =
+        std::slice::from_raw_parts_mut(meta.data as *mut spa_meta_region, count);
+
+      for (i, region) in regions.iter_mut().enumerate() {
+        *region = if i == 0 {
+          spa_meta_region {
+            region: spa_region {
+              position: spa_point {
+                x: DAMAGE.x,
+                y: DAMAGE.y,
+              },
+              size: spa_rectangle {
+                width: DAMAGE.width,
+                height: DAMAGE.height,
+              },
+            },
+          }
+        } else {
+          std::mem::zeroed()
+        };
+      }
+    }
+
+    pw_stream_queue_buffer(listener.stream, buffer);
+  }
+
+  #[test]
+  fn test_token_key() {
+    let wayland = |socket: &str, name: &str| Source::Wayland {
+      socket: Some(socket.to_string()),
+      name: name.to_string(),
+    };
+
+    assert_eq!(token_key(&wayland("wayland-1", "DP-1")), "wayland-1-DP-1");
+    assert_eq!(
+      token_key(&wayland("/run/user/1000/wayland-1", "DP-1")),
+      "_run_user_1000_wayland-1-DP-1"
+    );
+    assert_ne!(
+      token_key(&wayland("wayland-1", "DP-1")),
+      token_key(&wayland("wayland-1", "HDMI-A-1"))
+    );
+    assert_ne!(
+      token_key(&wayland("wayland-1", "DP-1")),
+      token_key(&wayland("wayland-2", "DP-1"))
+    );
+  }
+
+  #[test]
+  fn test_pod_roundtrip() {
+    let format = aligned(pod_object(
+      SPA_TYPE_OBJECT_Format,
+      SPA_PARAM_Format,
+      &[
+        (SPA_FORMAT_VIDEO_format, pod_id(SPA_VIDEO_FORMAT_BGRx)),
+        (
+          SPA_FORMAT_VIDEO_size,
+          pod_choice(SPA_CHOICE_None, SPA_TYPE_Rectangle, &[&pair(640, 480)]),
+        ),
+      ],
+    ));
+
+    let size = unsafe { parse_video_size(format.as_ptr() as *const spa_pod) };
+    assert_eq!(size, Some((640, 480)));
+  }
+
+  #[test]
+  fn test_read_buffer_too_small() {
+    let mut pixels = vec![0u8; 8 * 4];
+    let mut chunk = spa_chunk {
+      offset: 0,
+      size: pixels.len() as u32,
+      stride: 8,
+      flags: 0,
+    };
+    let mut data = spa_data {
+      kind: 0,
+      flags: 0,
+      fd: -1,
+      mapoffset: 0,
+      maxsize: pixels.len() as u32,
+      data: pixels.as_mut_ptr().cast(),
+      chunk: &mut chunk,
+    };
+    let buffer = spa_buffer {
+      n_metas: 0,
+      n_datas: 1,
+      metas: null_mut(),
+      datas: &mut data,
+    };
+
+    // Four 2x1 BGRX rows fit, a fifth doesn't.
+    let mut shared = Shared {
+      width: 2,
+      height: 5,
+      capacity: 1,
+      ..Shared::default()
+    };
+    unsafe { read_buffer(&mut shared, &buffer) };
+    assert!(shared.ready.is_none());
+    assert!(shared.unsupported.is_some());
+
+    shared.height = 4;
+    shared.unsupported = None;
+    unsafe { read_buffer(&mut shared, &buffer) };
+    assert!(shared.ready.is_some());
+    assert!(shared.unsupported.is_none());
+  }
+
+  #[test]
+  fn test_capture() {
+    let daemon = PipeWireDaemon::start();
+    let source = FakeSource::start(daemon.remote());
+
+    // The display only matters to the portal, but options can't do without one.
+    let opts = CaptureOpts::new(Display::synthetic(64, 48));
+    let mut capture =
+      PipeWireCapture::from_node(opts, Some(daemon.remote()), source.node_id()).unwrap();
+
+    let started = Instant::now();
+    let frame = loop {
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        break frame;
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "No frame captured"
+      );
+    };
+
+    assert_eq!(frame.len(), (WIDTH * HEIGHT * 4) as usize);
+    assert!(frame.iter().all(|byte| *byte == 0xff));
+    assert!(!frame.dirty_rects().is_empty());
+    assert!(frame.dirty_rects().iter().all(|rect| *rect == DAMAGE));
+  }
+}
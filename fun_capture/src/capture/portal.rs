@@ -0,0 +1,197 @@
+//! The `org.freedesktop.portal.ScreenCast` D-Bus portal, which asks the user what to
+//! share and hands back a PipeWire node for it.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::unix::io::OwnedFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{self, OwnedObjectPath, OwnedValue, Value};
+use zbus::Message;
+
+use crate::{Error, Result};
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const INTERFACE: &str = "org.freedesktop.portal.ScreenCast";
+
+const SOURCE_TYPE_MONITOR: u32 = 1;
+const CURSOR_MODE_HIDDEN: u32 = 1;
+const CURSOR_MODE_EMBEDDED: u32 = 2;
+const PERSIST_MODE_PERSISTENT: u32 = 2;
+
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// What the portal gave us once the user picked a monitor.
+pub(crate) struct Started {
+  pub node_id: u32,
+  pub fd: OwnedFd,
+  /// Where the monitor is in the compositor's space, if the portal says.
+  pub position: Option<(i32, i32)>,
+  /// Hands the same monitor back next time without asking, if the portal supports it.
+  pub restore_token: Option<String>,
+}
+
+/// A ScreenCast session, closed once dropped.
+pub(crate) struct ScreenCast {
+  conn: Connection,
+  session: OwnedObjectPath,
+}
+
+impl ScreenCast {
+  /// Starts a session capturing a single monitor, prompting the user unless
+  /// `restore_token` is still valid.
+  pub fn start(cursor: bool, restore_token: Option<&str>) -> Result<(Self, Started)> {
+    let conn = Connection::session().map_err(Error::unavailable)?;
+    let portal =
+      Proxy::new(&conn, DESTINATION, PATH, INTERFACE).map_err(Error::unavailable)?;
+
+    let version = portal
+      .get_property::<u32>("version")
+      .map_err(Error::unavailable)?;
+    let cursor_modes = portal
+      .get_property::<u32>("AvailableCursorModes")
+      .unwrap_or(0);
+
+    let results = request(&conn, |token| {
+      let mut options = HashMap::new();
+      options.insert("handle_token", Value::from(token));
+      options.insert("session_handle_token", Value::from(token));
+
+      portal.call_method("CreateSession", &(options,))
+    })?;
+
+    let session = results
+      .get("session_handle")
+      .and_then(|handle| <&str>::try_from(handle).ok())
+      .and_then(|handle| OwnedObjectPath::try_from(handle).ok())
+      .ok_or_else(|| Error::unavailable("Portal did not return a session"))?;
+
+    let screen_cast = Self { conn, session };
+
+    request(&screen_cast.conn, |token| {
+      let mut options = HashMap::new();
+      options.insert("handle_token", Value::from(token));
+      options.insert("types", Value::from(SOURCE_TYPE_MONITOR));
+      options.insert("multiple", Value::from(false));
+
+      let cursor_mode = if cursor {
+        CURSOR_MODE_EMBEDDED
+      } else {
+        CURSOR_MODE_HIDDEN
+      };
+
+      if cursor_modes & cursor_mode != 0 {
+        options.insert("cursor_mode", Value::from(cursor_mode));
+      }
+
+      // Persistence arrived in version 4 of the interface.
+      if version >= 4 {
+        options.insert("persist_mode", Value::from(PERSIST_MODE_PERSISTENT));
+        if let Some(restore_token) = restore_token {
+          options.insert("restore_token", Value::from(restore_token));
+        }
+      }
+
+      portal.call_method("SelectSources", &(&screen_cast.session, options))
+    })?;
+
+    let mut results = request(&screen_cast.conn, |token| {
+      let mut options = HashMap::new();
+      options.insert("handle_token", Value::from(token));
+
+      portal.call_method("Start", &(&screen_cast.session, "", options))
+    })?;
+
+    let (node_id, props) = results
+      .remove("streams")
+      .and_then(|streams| {
+        Vec::<(u32, HashMap<String, OwnedValue>)>::try_from(streams).ok()
+      })
+      .and_then(|streams| streams.into_iter().next())
+      .ok_or_else(|| Error::unavailable("Portal did not return a stream"))?;
+
+    let position = props
+      .get("position")
+      .and_then(|position| position.try_clone().ok())
+      .and_then(|position| <(i32, i32)>::try_from(position).ok());
+
+    let restore_token = results
+      .get("restore_token")
+      .and_then(|token| <&str>::try_from(token).ok())
+      .map(String::from);
+
+    let options: HashMap<&str, Value> = HashMap::new();
+    let fd: zvariant::OwnedFd = portal
+      .call("OpenPipeWireRemote", &(&screen_cast.session, options))
+      .map_err(Error::unavailable)?;
+
+    let started = Started {
+      node_id,
+      fd: fd.into(),
+      position,
+      restore_token,
+    };
+
+    Ok((screen_cast, started))
+  }
+}
+
+impl Drop for ScreenCast {
+  fn drop(&mut self) {
+    let session = Proxy::new(
+      &self.conn,
+      DESTINATION,
+      &self.session,
+      "org.freedesktop.portal.Session",
+    );
+
+    if let Ok(session) = session {
+      let _ = session.call_method("Close", &());
+    }
+  }
+}
+
+/// Makes a portal call, which answers through a `Request` object's `Response` signal
+/// rather than its return value.
+fn request<F>(conn: &Connection, call: F) -> Result<HashMap<String, OwnedValue>>
+where
+  F: FnOnce(&str) -> zbus::Result<Message>,
+{
+  let token = format!(
+    "fun_capture_{}_{}",
+    std::process::id(),
+    NEXT_TOKEN.fetch_add(1, Ordering::SeqCst)
+  );
+
+  // Subscribe before calling, or a fast portal could respond before we're listening.
+  let sender = conn
+    .unique_name()
+    .ok_or_else(|| Error::unavailable("Not connected to the session bus"))?
+    .trim_start_matches(':')
+    .replace('.', "_");
+
+  let path = format!("{}/request/{}/{}", PATH, sender, token);
+  let request = Proxy::new(conn, DESTINATION, path, "org.freedesktop.portal.Request")
+    .map_err(Error::unavailable)?;
+
+  let mut responses = request
+    .receive_signal("Response")
+    .map_err(Error::unavailable)?;
+
+  call(&token).map_err(Error::unavailable)?;
+
+  let response = responses
+    .next()
+    .ok_or_else(|| Error::unavailable("Portal closed the request"))?;
+
+  let (code, results): (u32, HashMap<String, OwnedValue>) =
+    response.body().deserialize().map_err(Error::unavailable)?;
+
+  match code {
+    0 => Ok(results),
+    1 => Err(Error::denied("Screen cast was cancelled")),
+    _ => Err(Error::unavailable("Screen cast request failed")),
+  }
+}
@@ -1,40 +1,75 @@
 use std::borrow::Cow;
 use std::ffi::c_void;
-use std::io::{Error, Result};
 use std::ops::Deref;
 use std::ptr::null_mut;
 use std::slice::from_raw_parts;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use block::ConcreteBlock;
 
-use crate::capture::Frame;
-use crate::capture::{Capture, CaptureOpts};
+use crate::capture::{check_size, Capture, CaptureOpts};
+use crate::capture::{Frame, FrameClock, FrameInfo, PixelFormat, Rect, VideoFrame};
 use crate::ffi::macos::CFDictionaryRef;
 use crate::ffi::macos::{
   cfbool, dispatch_queue_create, dispatch_release, kCFTypeDictionaryKeyCallBacks,
   kCFTypeDictionaryValueCallBacks, kCGDisplayStreamMinimumFrameTime,
   kCGDisplayStreamPreserveAspectRatio, kCGDisplayStreamQueueDepth,
-  kCGDisplayStreamShowCursor, CFDictionaryCreate, CFNumberCreate, CFNumberType, CFRetain,
-  CGDisplayStreamFrameStatus, CGDisplayStreamStart, CGError, DispatchQueue,
+  kCGDisplayStreamShowCursor, kCGDisplayStreamSourceRect, kCGDisplayStreamYCbCrMatrix,
+  kCGDisplayStreamYCbCrMatrix_ITU_R_709_2, CFDictionaryCreate, CFNumberCreate,
+  CFNumberType, CFRetain, CGDisplayStreamFrameStatus, CGDisplayStreamStart,
+  CGDisplayStreamUpdateGetRects, CGDisplayStreamUpdateRectType, CGDisplayStreamUpdateRef,
+  CGError, CGPoint, CGPreflightScreenCaptureAccess, CGRect,
+  CGRectCreateDictionaryRepresentation, CGSize, DispatchQueue,
   IOSurfaceDecrementUseCount, IOSurfaceGetAllocSize, IOSurfaceGetBaseAddress,
+  IOSurfaceGetBaseAddressOfPlane, IOSurfaceGetBytesPerRow,
+  IOSurfaceGetBytesPerRowOfPlane, IOSurfaceGetHeight, IOSurfaceGetWidth,
   IOSurfaceIncrementUseCount, IOSurfaceLock, IOSurfaceRef, IOSurfaceUnlock,
   SURFACE_LOCK_READ_ONLY,
 };
 use crate::ffi::macos::{
   CFRelease, CGDisplayStreamCreateWithDispatchQueue, CGDisplayStreamRef,
-  CGDisplayStreamStop, PixelFormat,
+  CGDisplayStreamStop, PixelFormat as SurfaceFormat,
 };
-use crossbeam_channel::{bounded, Receiver};
+use crate::ffi::macos::{CGDisplayPixelsHigh, CGDisplayPixelsWide};
+use crate::{Error, Result};
+use crossbeam_channel::{bounded, Receiver, SendError, TryRecvError};
 
+/// What the stream callback hands over: a retained surface and its dirty rects, or why
+/// there wasn't one.
+type Update = Frame<(IOSurfaceRef, Vec<Rect>)>;
+
+/// Scales frames and produces BGRA or NV12 itself, leaving other formats to
+/// [`Converted`](crate::capture::Converted).
 pub struct QuartzCapture {
-  rx: Receiver<IOSurfaceRef>,
+  rx: Receiver<Update>,
+  /// An update received while waiting, handed out by the next frame.
+  pending: Option<Update>,
+  /// Set by the callback once the stream stops, e.g. as the display is disconnected.
+  stopped: Arc<AtomicBool>,
   queue: DispatchQueue,
   stream: CGDisplayStreamRef,
+  display: u32,
+  /// The display's mode when the capture opened.
+  size: (u32, u32),
+  format: PixelFormat,
+  clock: FrameClock,
 }
 
+// Display streams, dispatch queues and IOSurfaces are all safe to use from any thread.
+unsafe impl Send for QuartzCapture {}
+
 impl QuartzCapture {
   pub fn new(opts: CaptureOpts) -> Result<Self> {
-    let (tx, rx) = bounded::<IOSurfaceRef>(5);
+    if !unsafe { CGPreflightScreenCaptureAccess() } {
+      return Err(Error::denied(
+        "Screen recording is not allowed for this app",
+      ));
+    }
+
+    let (tx, rx) = bounded::<Update>(5);
+    let stopped = Arc::new(AtomicBool::new(false));
 
     // Create dispatch queue
     let queue = unsafe {
@@ -45,26 +80,63 @@ impl QuartzCapture {
     };
 
     // Create ObjC callback `block`
-    let handler = ConcreteBlock::new(move |status, _, s, _| {
-      if status == CGDisplayStreamFrameStatus::FrameComplete {
-        tx.send(s).unwrap();
-      }
-    })
+    let stream_stopped = stopped.clone();
+    let handler = ConcreteBlock::new(
+      move |status: CGDisplayStreamFrameStatus,
+            _: u64,
+            surface: IOSurfaceRef,
+            update: CGDisplayStreamUpdateRef| match status {
+        CGDisplayStreamFrameStatus::FrameComplete => {
+          // Keep the surface from being reused while it waits to become a frame.
+          unsafe {
+            CFRetain(surface);
+            IOSurfaceIncrementUseCount(surface);
+          }
+
+          // The capture is gone, so nobody will release it.
+          if let Err(SendError(Frame::Ready((surface, _)))) =
+            tx.send(Frame::Ready((surface, Self::dirty_rects(update))))
+          {
+            unsafe {
+              IOSurfaceDecrementUseCount(surface);
+              CFRelease(surface);
+            }
+          }
+        }
+        // Only the latest state matters, so these can be dropped when the queue's full.
+        CGDisplayStreamFrameStatus::FrameIdle => {
+          let _ = tx.try_send(Frame::Idle);
+        }
+        CGDisplayStreamFrameStatus::FrameBlank => {
+          let _ = tx.try_send(Frame::Blank);
+        }
+        // The flag sticks even when the queue's full, while the update wakes up a wait.
+        CGDisplayStreamFrameStatus::Stopped => {
+          stream_stopped.store(true, Ordering::Release);
+          let _ = tx.try_send(Frame::Stopped);
+        }
+      },
+    )
     .copy();
 
+    let (format, surface_format) = match opts.pixel_format {
+      Some(PixelFormat::Nv12) => (PixelFormat::Nv12, SurfaceFormat::YCbCr420Full),
+      _ => (PixelFormat::Bgra8888, SurfaceFormat::Argb8888),
+    };
+
     // Create config dictionary
     let config = Self::build_config(&opts);
     let stream = unsafe {
       let display = opts.display.handle();
-      let output_width = opts.display.width() as usize;
-      let output_height = opts.display.height() as usize;
+      // The stream crops to the region itself, so it's all that's scaled to the output.
+      let (output_width, output_height) = opts.target_size();
 
       // Create display stream
       CGDisplayStreamCreateWithDispatchQueue(
         display,
-        output_width,
-        output_height,
-        PixelFormat::Argb8888,
+        output_width as usize,
+        output_height as usize,
+        surface_format,
         config,
         queue,
         &handler,
@@ -74,68 +146,182 @@ impl QuartzCapture {
     unsafe { CFRelease(config) };
 
     if stream.is_null() {
-      return Err(Error::last_os_error());
+      unsafe { dispatch_release(queue) };
+      return Err(Error::unavailable("Failed to create a display stream"));
     }
 
     let status = unsafe { CGDisplayStreamStart(stream) };
     if status != CGError::Success {
-      return Err(Error::from_raw_os_error(status as i32));
+      unsafe {
+        CFRelease(stream);
+        dispatch_release(queue);
+      }
+
+      return Err(status.into());
+    }
+
+    Ok(Self {
+      rx,
+      pending: None,
+      stopped,
+      queue,
+      stream,
+      display: opts.display.handle(),
+      size: (opts.display.width(), opts.display.height()),
+      format,
+      clock: FrameClock::new(),
+    })
+  }
+
+  fn dirty_rects(update: CGDisplayStreamUpdateRef) -> Vec<Rect> {
+    if update.is_null() {
+      return Vec::new();
     }
 
-    Ok(Self { rx, queue, stream })
+    let mut count = 0;
+    let rects = unsafe {
+      CGDisplayStreamUpdateGetRects(
+        update,
+        CGDisplayStreamUpdateRectType::DirtyRects,
+        &mut count,
+      )
+    };
+
+    if rects.is_null() {
+      return Vec::new();
+    }
+
+    unsafe { from_raw_parts(rects, count) }
+      .iter()
+      .map(|rect| {
+        Rect::new(
+          rect.origin.x as i32,
+          rect.origin.y as i32,
+          rect.size.width as u32,
+          rect.size.height as u32,
+        )
+      })
+      .collect()
   }
 
   fn build_config(opts: &CaptureOpts) -> CFDictionaryRef {
     unsafe {
+      // The stream takes the time between frames, and a whole number of them queued.
+      let frame_time = if opts.frame_rate > 0.0 {
+        1.0 / opts.frame_rate
+      } else {
+        0.0
+      };
+
       let throttle = CFNumberCreate(
         null_mut(),
         CFNumberType::Float64,
-        &opts.frame_rate as *const _ as *const c_void,
+        &frame_time as *const f64 as *const c_void,
       );
 
+      let queue_depth = opts.frame_queue as i32;
       let queue_length = CFNumberCreate(
         null_mut(),
-        CFNumberType::Float64,
-        &opts.frame_queue as *const _ as *const c_void,
+        CFNumberType::SInt32,
+        &queue_depth as *const i32 as *const c_void,
       );
 
-      let keys = [
+      // The same matrix as converting in software, for NV12.
+      let mut keys = vec![
         kCGDisplayStreamShowCursor,
         kCGDisplayStreamPreserveAspectRatio,
         kCGDisplayStreamMinimumFrameTime,
         kCGDisplayStreamQueueDepth,
+        kCGDisplayStreamYCbCrMatrix,
       ];
 
-      let values = [
+      let mut values = vec![
         cfbool(opts.cursor), //
         cfbool(false),
         throttle,
         queue_length,
+        kCGDisplayStreamYCbCrMatrix_ITU_R_709_2,
       ];
 
+      let source_rect = opts.region.map(|region| {
+        CGRectCreateDictionaryRepresentation(CGRect {
+          origin: CGPoint {
+            x: region.x as f64,
+            y: region.y as f64,
+          },
+          size: CGSize {
+            width: region.width as f64,
+            height: region.height as f64,
+          },
+        })
+      });
+
+      if let Some(source_rect) = source_rect {
+        keys.push(kCGDisplayStreamSourceRect);
+        values.push(source_rect);
+      }
+
       let config = CFDictionaryCreate(
         null_mut(),
         keys.as_ptr(),
         values.as_ptr(),
-        2,
+        keys.len() as _,
         &kCFTypeDictionaryKeyCallBacks,
         &kCFTypeDictionaryValueCallBacks,
       );
 
       CFRelease(throttle);
       CFRelease(queue_length);
+      if let Some(source_rect) = source_rect {
+        CFRelease(source_rect);
+      }
 
       config
     }
   }
 }
 
-impl<'a> Capture<QuartzFrame<'a>> for QuartzCapture {
-  fn frame(&mut self) -> Frame<QuartzFrame<'a>> {
-    match self.rx.try_recv() {
-      Ok(surface) => Frame::Ready(QuartzFrame::new(surface)),
-      Err(_) => Frame::Blocking,
+impl Capture for QuartzCapture {
+  type Frame<'a> = QuartzFrame<'a>;
+
+  fn frame(&mut self) -> Result<Frame<QuartzFrame<'_>>> {
+    // The stream goes on scaling to the size it was made for, so it'd hide a mode
+    // change. A display that's gone is 0 by 0, and left for the stream to report.
+    let size = unsafe {
+      (
+        CGDisplayPixelsWide(self.display) as u32,
+        CGDisplayPixelsHigh(self.display) as u32,
+      )
+    };
+    if size != (0, 0) {
+      check_size(self.size, size)?;
     }
+
+    // Frames sent before the stream stopped are still handed out first.
+    let update = match self.pending.take().map_or_else(|| self.rx.try_recv(), Ok) {
+      Ok(update) => update,
+      Err(TryRecvError::Empty) if self.stopped.load(Ordering::Acquire) => Frame::Stopped,
+      Err(TryRecvError::Empty) => Frame::Blocking,
+      Err(TryRecvError::Disconnected) => Frame::Stopped,
+    };
+
+    let (format, clock) = (self.format, &mut self.clock);
+    Ok(update.map(|(surface, dirty_rects)| {
+      QuartzFrame::new(surface, format, dirty_rects, clock)
+    }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    if self.pending.is_some() || self.stopped.load(Ordering::Acquire) {
+      return Ok(());
+    }
+
+    // A disconnected channel is left for `frame` to report.
+    if let Ok(update) = self.rx.recv_timeout(timeout) {
+      self.pending = Some(update);
+    }
+
+    Ok(())
   }
 }
 
@@ -148,41 +334,110 @@ impl Drop for QuartzCapture {
     unsafe {
       let _ = CGDisplayStreamStop(self.stream);
       CFRelease(self.stream);
-      dispatch_release(self.queue)
+      dispatch_release(self.queue);
+
+      for update in self.pending.take().into_iter().chain(self.rx.try_iter()) {
+        if let Frame::Ready((surface, _)) = update {
+          IOSurfaceDecrementUseCount(surface);
+          CFRelease(surface);
+        }
+      }
     }
   }
 }
 
+/// A locked IOSurface from the display stream, laid out as BGRA or NV12 with its rows
+/// padded. NV12 planes that aren't back to back are copied out so that they are.
+///
+/// It borrows the capture, so the surface is back in the stream's pool before the
+/// capture is gone.
 #[derive(Debug)]
 pub struct QuartzFrame<'a> {
   inner: Cow<'a, [u8]>,
   surface: IOSurfaceRef,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
 }
 
-impl QuartzFrame<'_> {
-  pub fn new(surface: IOSurfaceRef) -> Self {
-    let inner = unsafe {
-      CFRetain(surface);
-      IOSurfaceIncrementUseCount(surface);
+impl<'a> QuartzFrame<'a> {
+  /// Takes over a surface retained by the stream callback.
+  pub(crate) fn new(
+    surface: IOSurfaceRef,
+    format: PixelFormat,
+    dirty_rects: Vec<Rect>,
+    clock: &mut FrameClock,
+  ) -> Self {
+    let (inner, info) = unsafe {
       IOSurfaceLock(surface, SURFACE_LOCK_READ_ONLY, null_mut());
 
-      from_raw_parts(
-        IOSurfaceGetBaseAddress(surface) as *const u8,
-        IOSurfaceGetAllocSize(surface),
-      )
+      let width = IOSurfaceGetWidth(surface);
+      let height = IOSurfaceGetHeight(surface);
+      let (inner, stride) = match format {
+        PixelFormat::Nv12 => Self::nv12_planes(surface, width, height),
+        _ => (
+          Cow::Borrowed(from_raw_parts(
+            IOSurfaceGetBaseAddress(surface) as *const u8,
+            IOSurfaceGetAllocSize(surface),
+          )),
+          IOSurfaceGetBytesPerRow(surface),
+        ),
+      };
+
+      let info = clock.next(width as u32, height as u32, stride, format);
+      (inner, info)
     };
 
     Self {
-      inner: Cow::from(inner),
+      inner,
       surface,
+      dirty_rects,
+      info,
     }
   }
+
+  /// The luma and chroma planes of a locked NV12 surface as one buffer, and its stride.
+  unsafe fn nv12_planes(
+    surface: IOSurfaceRef,
+    width: usize,
+    height: usize,
+  ) -> (Cow<'a, [u8]>, usize) {
+    let luma = IOSurfaceGetBaseAddressOfPlane(surface, 0) as *const u8;
+    let chroma = IOSurfaceGetBaseAddressOfPlane(surface, 1) as *const u8;
+    let luma_stride = IOSurfaceGetBytesPerRowOfPlane(surface, 0);
+    let chroma_stride = IOSurfaceGetBytesPerRowOfPlane(surface, 1);
+
+    if chroma_stride == luma_stride && chroma == luma.add(luma_stride * height) {
+      let len = luma_stride * (height / 2 * 3 - 1) + width;
+      return (Cow::Borrowed(from_raw_parts(luma, len)), luma_stride);
+    }
+
+    let mut data = Vec::with_capacity(width * height / 2 * 3);
+    for y in 0..height {
+      data.extend_from_slice(from_raw_parts(luma.add(y * luma_stride), width));
+    }
+    for y in 0..height / 2 {
+      data.extend_from_slice(from_raw_parts(chroma.add(y * chroma_stride), width));
+    }
+
+    (Cow::Owned(data), width)
+  }
+}
+
+impl VideoFrame for QuartzFrame<'_> {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
 }
 
-impl<'a> Deref for QuartzFrame<'a> {
-  type Target = Cow<'a, [u8]>;
+impl Deref for QuartzFrame<'_> {
+  type Target = [u8];
 
-  fn deref<'b>(&'b self) -> &'b Cow<'a, [u8]> {
+  fn deref(&self) -> &[u8] {
     &self.inner
   }
 }
@@ -200,7 +455,7 @@ impl Drop for QuartzFrame<'_> {
 #[cfg(test)]
 mod tests {
   use crate::capture::quartz::QuartzCapture;
-  use crate::capture::{Capture, CaptureOpts, Frame};
+  use crate::capture::{Capture, CaptureOpts, Frame, PixelFormat, VideoFrame};
   use crate::display::get_primary;
   use std::time::Instant;
 
@@ -213,9 +468,8 @@ mod tests {
     let mut size = 0;
 
     for _ in 0..1000 {
-      match capture.frame() {
-        Frame::Ready(frame) => size += frame.len(),
-        Frame::Blocking => (),
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        size += frame.len();
       }
     }
 
@@ -225,4 +479,23 @@ mod tests {
     println!("Mbit   = {}", mbit);
     println!("Mbit/s = {}", mbit / seconds);
   }
+
+  #[test]
+  fn test_capture_nv12() {
+    let mut opts = CaptureOpts::new(get_primary().unwrap());
+    opts
+      .output_size(321, 200)
+      .unwrap()
+      .pixel_format(PixelFormat::Nv12);
+    let mut capture = QuartzCapture::new(opts).unwrap();
+
+    match capture.next_frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (320, 200));
+        assert_eq!(frame.format(), PixelFormat::Nv12);
+        assert_eq!(frame.rows().count(), 300);
+      }
+      _ => panic!("Expected a frame"),
+    };
+  }
 }
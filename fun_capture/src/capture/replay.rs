@@ -0,0 +1,498 @@
+//! Recording frames to a file and replaying them, e.g. to reproduce a bug report.
+//!
+//! A recording is the magic `FUNREC02`, the frame width, height, pixel format and
+//! stride, then one record per frame: its timestamp in microseconds, its length and its
+//! rows, `stride` apart. Everything is little-endian.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::{Capture, Frame, FrameInfo, PixelFormat, VideoFrame};
+use crate::display::Display;
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 8] = b"FUNREC02";
+const HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 12;
+
+/// The pixel formats in the order they're numbered in a recording.
+const FORMATS: [PixelFormat; 6] = [
+  PixelFormat::Bgrx8888,
+  PixelFormat::Bgra8888,
+  PixelFormat::Rgbx8888,
+  PixelFormat::Rgba8888,
+  PixelFormat::Rgb565,
+  PixelFormat::Nv12,
+];
+
+/// Writes frames to a recording for [`ReplayCapture`].
+pub struct Recorder {
+  file: BufWriter<File>,
+  /// The first frame's, which every later one has to match.
+  layout: Option<FrameInfo>,
+}
+
+impl Recorder {
+  /// Creates a recording at `path`, laid out like the first frame written to it.
+  pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+    Ok(Self {
+      file: BufWriter::new(File::create(path)?),
+      layout: None,
+    })
+  }
+
+  /// Appends a frame with its own timestamp. Fails if its size, format or stride
+  /// differs from the first frame's.
+  pub fn write(&mut self, frame: &impl VideoFrame) -> Result<()> {
+    let info = frame.info();
+    match self.layout {
+      Some(layout) => {
+        let layout = (layout.width, layout.height, layout.format, layout.stride);
+        if layout != (info.width, info.height, info.format, info.stride) {
+          return Err(Error::invalid("Frame doesn't match the recording"));
+        }
+      }
+      None => {
+        if info.stride < info.row_len() || info.stride > u32::MAX as usize {
+          return Err(Error::invalid("Frame stride can't be recorded"));
+        }
+
+        let format = FORMATS.iter().position(|&format| format == info.format);
+        self.file.write_all(MAGIC)?;
+        self.file.write_all(&info.width.to_le_bytes())?;
+        self.file.write_all(&info.height.to_le_bytes())?;
+        self
+          .file
+          .write_all(&(format.unwrap() as u32).to_le_bytes())?;
+        self.file.write_all(&(info.stride as u32).to_le_bytes())?;
+        self.layout = Some(info);
+      }
+    }
+
+    // Backends may leave the last row's padding out of the buffer, so it's filled in.
+    let len = info.stride * info.row_count();
+    let data = &frame[..len.min(frame.len())];
+    self
+      .file
+      .write_all(&(info.timestamp.as_micros() as u64).to_le_bytes())?;
+    self.file.write_all(&(len as u32).to_le_bytes())?;
+    self.file.write_all(data)?;
+    self.file.write_all(&vec![0; len - data.len()])?;
+    Ok(())
+  }
+
+  /// Flushes buffered frames to the file, which also happens on drop.
+  pub fn flush(&mut self) -> Result<()> {
+    Ok(self.file.flush()?)
+  }
+}
+
+/// How fast a [`ReplayCapture`] hands out frames.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Timing {
+  /// Each frame becomes ready as long after the first as it was recorded.
+  Original,
+  /// Every call gets the next frame.
+  Fast,
+}
+
+/// Plays back a recording made with [`Recorder`], stopping once it's over unless it
+/// seeks back.
+pub struct ReplayCapture {
+  file: BufReader<File>,
+  /// Every frame's, besides the timestamp and sequence number.
+  layout: FrameInfo,
+  timing: Timing,
+  /// The timestamp and file offset of every frame.
+  index: Vec<(Duration, u64)>,
+  next: usize,
+  /// When playback would have started had it run from the first frame.
+  clock: Option<Instant>,
+}
+
+impl ReplayCapture {
+  pub fn open<P: AsRef<Path>>(path: P, timing: Timing) -> Result<Self> {
+    let mut file = BufReader::new(File::open(path)?);
+    let invalid = |message| Error::invalid_data(message);
+
+    let mut header = [0; HEADER_LEN as usize];
+    file
+      .read_exact(&mut header)
+      .map_err(|_| invalid("Not a recording"))?;
+
+    if &header[..8] != MAGIC {
+      return Err(invalid("Not a recording"));
+    }
+
+    let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+    let format = FORMATS
+      .get(field(16) as usize)
+      .ok_or_else(|| invalid("Unknown pixel format"))?;
+    let layout = FrameInfo {
+      width: field(8),
+      height: field(12),
+      stride: field(20) as usize,
+      format: *format,
+      timestamp: Duration::ZERO,
+      sequence: 0,
+    };
+
+    if layout.stride < layout.row_len() {
+      return Err(invalid("Stride is shorter than a row"));
+    }
+
+    let frame_len = (layout.stride * layout.row_count()) as u64;
+
+    // A recording cut short, e.g. by a crash, keeps every complete frame.
+    let file_len = file.get_ref().metadata()?.len();
+    let mut index = Vec::new();
+    let mut offset = HEADER_LEN;
+    let mut record = [0; RECORD_HEADER_LEN as usize];
+
+    while offset + RECORD_HEADER_LEN + frame_len <= file_len {
+      file.seek(SeekFrom::Start(offset))?;
+      file.read_exact(&mut record)?;
+
+      let timestamp = u64::from_le_bytes(record[..8].try_into().unwrap());
+      let len = u32::from_le_bytes(record[8..].try_into().unwrap());
+      if len as u64 != frame_len {
+        return Err(invalid("Frame size doesn't match the recording"));
+      }
+
+      index.push((Duration::from_micros(timestamp), offset + RECORD_HEADER_LEN));
+      offset += RECORD_HEADER_LEN + frame_len;
+    }
+
+    Ok(Self {
+      file,
+      layout,
+      timing,
+      index,
+      next: 0,
+      clock: None,
+    })
+  }
+
+  pub fn width(&self) -> u32 {
+    self.layout.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.layout.height
+  }
+
+  pub fn format(&self) -> PixelFormat {
+    self.layout.format
+  }
+
+  /// A synthetic display the size of the recording, for code that wants one.
+  pub fn display(&self) -> Display {
+    Display::synthetic(self.layout.width, self.layout.height)
+  }
+
+  /// The number of frames in the recording.
+  pub fn len(&self) -> usize {
+    self.index.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.index.is_empty()
+  }
+
+  /// The timestamp of the last frame.
+  pub fn duration(&self) -> Duration {
+    self
+      .index
+      .last()
+      .map_or(Duration::ZERO, |(timestamp, _)| *timestamp)
+  }
+
+  /// The timestamp of the next frame, or the duration once it's over.
+  pub fn position(&self) -> Duration {
+    self
+      .index
+      .get(self.next)
+      .map_or_else(|| self.duration(), |(timestamp, _)| *timestamp)
+  }
+
+  /// Continues playback from the first frame at or after `position`.
+  pub fn seek(&mut self, position: Duration) {
+    self.next = self
+      .index
+      .partition_point(|(timestamp, _)| *timestamp < position);
+    self.clock = None;
+  }
+
+  /// Continues playback from the frame at `index`.
+  pub fn seek_frame(&mut self, index: usize) {
+    self.next = index.min(self.index.len());
+    self.clock = None;
+  }
+
+  fn is_due(&self, timestamp: Duration) -> bool {
+    match (self.timing, self.clock) {
+      (Timing::Original, Some(clock)) => clock.elapsed() >= timestamp,
+      _ => true,
+    }
+  }
+
+  fn read_frame(&mut self, offset: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0; self.layout.stride * self.layout.row_count()];
+    self.file.seek(SeekFrom::Start(offset))?;
+    self.file.read_exact(&mut data)?;
+
+    Ok(data)
+  }
+}
+
+impl Capture for ReplayCapture {
+  type Frame<'a> = ReplayFrame;
+
+  fn frame(&mut self) -> Result<Frame<ReplayFrame>> {
+    let (timestamp, offset) = match self.index.get(self.next) {
+      Some(entry) => *entry,
+      None => return Ok(Frame::Stopped),
+    };
+
+    if !self.is_due(timestamp) {
+      return Ok(Frame::Blocking);
+    }
+
+    let data = self.read_frame(offset)?;
+    let info = FrameInfo {
+      timestamp,
+      sequence: self.next as u64,
+      ..self.layout
+    };
+
+    self.next += 1;
+    if self.clock.is_none() {
+      self.clock = Instant::now().checked_sub(timestamp);
+    }
+
+    Ok(Frame::Ready(ReplayFrame { data, info }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    let due = match (self.timing, self.clock, self.index.get(self.next)) {
+      (Timing::Original, Some(clock), Some((timestamp, _))) => {
+        timestamp.saturating_sub(clock.elapsed())
+      }
+      _ => Duration::ZERO,
+    };
+
+    sleep(timeout.min(due));
+    Ok(())
+  }
+}
+
+/// A recorded frame, timestamped as it was captured and numbered by its position in
+/// the recording.
+#[derive(Debug)]
+pub struct ReplayFrame {
+  data: Vec<u8>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for ReplayFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for ReplayFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{self, OpenOptions};
+  use std::io::Write;
+  use std::path::PathBuf;
+  use std::time::{Duration, Instant};
+
+  use crate::capture::replay::{Recorder, ReplayCapture, ReplayFrame, Timing};
+  use crate::capture::{Capture, Frame, FrameBuffer, FrameInfo, PixelFormat, VideoFrame};
+
+  /// A 2x1 BGRX frame filled with `i`, captured `i` times 50ms in.
+  fn frame(i: u8) -> FrameBuffer {
+    let info = FrameInfo {
+      width: 2,
+      height: 1,
+      stride: 8,
+      format: PixelFormat::Bgrx8888,
+      timestamp: Duration::from_millis(i as u64 * 50),
+      sequence: i as u64,
+    };
+
+    FrameBuffer::new(vec![i; 8], info).unwrap()
+  }
+
+  /// Records three frames from [`frame`].
+  fn record(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+      "fun_capture-replay-{}-{}",
+      name,
+      std::process::id()
+    ));
+
+    let mut recorder = Recorder::create(&path).unwrap();
+    for i in 0..3u8 {
+      recorder.write(&frame(i)).unwrap();
+    }
+
+    let info = FrameInfo {
+      width: 1,
+      ..frame(0).info()
+    };
+    let smaller = FrameBuffer::new(vec![0; 8], info).unwrap();
+    assert!(recorder.write(&smaller).is_err());
+    path
+  }
+
+  fn ready(frame: Frame<ReplayFrame>) -> ReplayFrame {
+    match frame {
+      Frame::Ready(frame) => frame,
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_replay_fast() {
+    let path = record("fast");
+    let mut replay = ReplayCapture::open(&path, Timing::Fast).unwrap();
+
+    assert_eq!(replay.len(), 3);
+    assert_eq!(replay.duration(), Duration::from_millis(100));
+
+    for i in 0..3u8 {
+      let frame = ready(replay.frame().unwrap());
+      assert_eq!(*frame, [i; 8]);
+      assert_eq!(frame.timestamp(), Duration::from_millis(i as u64 * 50));
+      assert_eq!(
+        (frame.width(), frame.height(), frame.sequence()),
+        (2, 1, i as u64)
+      );
+      assert_eq!((frame.stride(), frame.format()), (8, PixelFormat::Bgrx8888));
+    }
+
+    assert!(matches!(replay.frame().unwrap(), Frame::Stopped));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_replay_original() {
+    let path = record("original");
+    let mut replay = ReplayCapture::open(&path, Timing::Original).unwrap();
+
+    assert_eq!(*ready(replay.frame().unwrap()), [0; 8]);
+    assert!(matches!(replay.frame().unwrap(), Frame::Blocking));
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(*ready(replay.frame().unwrap()), [1; 8]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_next_frame() {
+    let path = record("next");
+    let mut replay = ReplayCapture::open(&path, Timing::Original).unwrap();
+    let start = Instant::now();
+
+    for i in 0..3u8 {
+      assert_eq!(*ready(replay.next_frame().unwrap()), [i; 8]);
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(matches!(replay.next_frame().unwrap(), Frame::Stopped));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_next_frame_timeout() {
+    let path = record("timeout");
+    let mut replay = ReplayCapture::open(&path, Timing::Original).unwrap();
+
+    ready(replay.frame().unwrap());
+    let frame = replay
+      .next_frame_timeout(Duration::from_millis(10))
+      .unwrap();
+    assert!(matches!(frame, Frame::Blocking));
+
+    let frame = replay.next_frame_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(*ready(frame), [1; 8]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_seek() {
+    let path = record("seek");
+    let mut replay = ReplayCapture::open(&path, Timing::Original).unwrap();
+
+    replay.seek(Duration::from_millis(60));
+    assert_eq!(replay.position(), Duration::from_millis(100));
+    assert_eq!(*ready(replay.frame().unwrap()), [2; 8]);
+
+    replay.seek_frame(1);
+    let frame = ready(replay.frame().unwrap());
+    assert_eq!(*frame, [1; 8]);
+    assert_eq!(frame.sequence(), 1);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_layout() {
+    let path = std::env::temp_dir()
+      .join(format!("fun_capture-replay-layout-{}", std::process::id()));
+
+    // Two rows of one RGB565 pixel, 4 bytes apart, without the last row's padding.
+    let info = FrameInfo {
+      width: 1,
+      height: 2,
+      stride: 4,
+      format: PixelFormat::Rgb565,
+      timestamp: Duration::from_millis(20),
+      sequence: 0,
+    };
+    let frame = FrameBuffer::new(vec![1, 2, 0, 0, 3, 4], info).unwrap();
+
+    let mut recorder = Recorder::create(&path).unwrap();
+    recorder.write(&frame).unwrap();
+    drop(recorder);
+
+    let mut replay = ReplayCapture::open(&path, Timing::Fast).unwrap();
+    assert_eq!(replay.format(), PixelFormat::Rgb565);
+
+    let frame = ready(replay.frame().unwrap());
+    assert_eq!(frame.info(), info);
+    assert_eq!(*frame, [1, 2, 0, 0, 3, 4, 0, 0]);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_truncated() {
+    let path = record("truncated");
+    OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .unwrap()
+      .write_all(&[0; 14])
+      .unwrap();
+
+    assert_eq!(ReplayCapture::open(&path, Timing::Fast).unwrap().len(), 3);
+    fs::remove_file(&path).unwrap();
+
+    fs::write(&path, b"not a recording").unwrap();
+    assert!(ReplayCapture::open(&path, Timing::Fast).is_err());
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,272 @@
+//! Captures as [`Stream`]s of frames, for async code, behind the `async` feature.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::spawn;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
+use futures_core::Stream;
+
+use crate::capture::{Capture, CaptureOpts, Frame, FrameBuffer, VideoFrame};
+use crate::Result;
+
+/// How often a capture with nothing to show checks whether the stream was dropped.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A capture running on its own thread, handing frames to a [`Stream`].
+///
+/// Up to [`CaptureOpts::frame_queue`] frames wait to be taken, after which the capture
+/// holds off until one is. The stream ends once the capture stops or fails, the
+/// failure being its last item, and dropping it stops the capture.
+pub struct CaptureStream {
+  rx: Receiver<Result<FrameBuffer>>,
+  waker: Arc<Mutex<Option<Waker>>>,
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CaptureStream {
+  /// Opens a capture with `open` on a new thread, so that it needn't be `Send`, e.g.
+  /// `CaptureStream::new(opts, X11Capture::new)`.
+  pub fn new<C, F>(opts: CaptureOpts, open: F) -> Self
+  where
+    C: Capture,
+    F: FnOnce(CaptureOpts) -> Result<C> + Send + 'static,
+  {
+    let (tx, rx) = bounded(opts.frame_queue.max(1) as usize);
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    {
+      let (waker, cancelled) = (waker.clone(), cancelled.clone());
+      spawn(move || {
+        run(open(opts), tx, &waker, &cancelled);
+        // The sender's gone by now, so this wakes the stream up to end.
+        wake(&waker);
+      });
+    }
+
+    Self {
+      rx,
+      waker,
+      cancelled,
+    }
+  }
+
+  fn try_next(&self) -> Option<Poll<Option<Result<FrameBuffer>>>> {
+    match self.rx.try_recv() {
+      Ok(item) => Some(Poll::Ready(Some(item))),
+      Err(TryRecvError::Disconnected) => Some(Poll::Ready(None)),
+      Err(TryRecvError::Empty) => None,
+    }
+  }
+}
+
+impl Stream for CaptureStream {
+  type Item = Result<FrameBuffer>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+    if let Some(poll) = self.try_next() {
+      return poll;
+    }
+
+    *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+    // A frame sent before the waker was stored wouldn't have woken it.
+    self.try_next().unwrap_or(Poll::Pending)
+  }
+}
+
+impl Drop for CaptureStream {
+  fn drop(&mut self) {
+    // The thread notices on its own, so dropping never blocks the executor.
+    self.cancelled.store(true, Ordering::Release);
+  }
+}
+
+fn run<C: Capture>(
+  capture: Result<C>,
+  tx: Sender<Result<FrameBuffer>>,
+  waker: &Mutex<Option<Waker>>,
+  cancelled: &AtomicBool,
+) {
+  let mut capture = match capture {
+    Ok(capture) => capture,
+    Err(err) => {
+      let _ = tx.send(Err(err));
+      return;
+    }
+  };
+
+  while !cancelled.load(Ordering::Acquire) {
+    let item = match capture.next_frame_timeout(CANCEL_INTERVAL) {
+      Ok(Frame::Ready(frame)) => Ok(frame.to_buffer()),
+      Ok(Frame::Stopped) => return,
+      Ok(_) => continue,
+      Err(err) => Err(err),
+    };
+
+    let failed = item.is_err();
+    // Blocks while the queue's full, failing once the stream's dropped.
+    if tx.send(item).is_err() {
+      return;
+    }
+
+    wake(waker);
+    if failed {
+      return;
+    }
+  }
+}
+
+fn wake(waker: &Mutex<Option<Waker>>) {
+  if let Some(waker) = waker.lock().unwrap().take() {
+    waker.wake();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::thread::sleep;
+  use std::time::{Duration, Instant};
+
+  use futures_lite::future::block_on;
+  use futures_lite::StreamExt;
+
+  use crate::capture::replay::{Recorder, ReplayCapture, Timing};
+  use crate::capture::stream::CaptureStream;
+  use crate::capture::synthetic::{Pattern, SyntheticCapture};
+  use crate::capture::{
+    Capture, CaptureOpts, Frame, FrameBuffer, FrameInfo, PixelFormat, VideoFrame,
+  };
+  use crate::display::Display;
+  use crate::{Error, Result};
+
+  #[test]
+  fn test_stream() {
+    let path =
+      std::env::temp_dir().join(format!("fun_capture-stream-{}", std::process::id()));
+
+    let mut recorder = Recorder::create(&path).unwrap();
+    for i in 0..3u8 {
+      let info = FrameInfo {
+        width: 2,
+        height: 1,
+        stride: 8,
+        format: PixelFormat::Bgrx8888,
+        timestamp: Duration::from_millis(i as u64 * 10),
+        sequence: i as u64,
+      };
+      recorder
+        .write(&FrameBuffer::new(vec![i; 8], info).unwrap())
+        .unwrap();
+    }
+    drop(recorder);
+
+    let replay = path.clone();
+    let stream = CaptureStream::new(CaptureOpts::new(Display::synthetic(2, 1)), |_| {
+      ReplayCapture::open(replay, Timing::Original)
+    });
+
+    let frames = block_on(stream.collect::<Vec<_>>());
+    let frames = frames.into_iter().collect::<Result<Vec<_>>>().unwrap();
+
+    assert_eq!(frames.len(), 3);
+    for (i, frame) in frames.iter().enumerate() {
+      assert_eq!(**frame, [i as u8; 8]);
+      assert_eq!(frame.sequence(), i as u64);
+    }
+
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_open_error() {
+    let stream = CaptureStream::new(
+      CaptureOpts::new(Display::synthetic(2, 1)),
+      |_| -> Result<SyntheticCapture> { Err(Error::unavailable("Nope")) },
+    );
+
+    let items = block_on(stream.collect::<Vec<_>>());
+    assert!(matches!(items[..], [Err(Error::BackendUnavailable(_))]));
+  }
+
+  /// Flags when it's asked for a frame, and when it's dropped.
+  struct Counted {
+    inner: SyntheticCapture,
+    taken: Arc<AtomicBool>,
+    dropped: Arc<AtomicBool>,
+  }
+
+  impl Capture for Counted {
+    type Frame<'a> = <SyntheticCapture as Capture>::Frame<'a>;
+
+    fn frame(&mut self) -> Result<Frame<Self::Frame<'_>>> {
+      self.taken.store(true, Ordering::SeqCst);
+      self.inner.frame()
+    }
+  }
+
+  impl Drop for Counted {
+    fn drop(&mut self) {
+      self.dropped.store(true, Ordering::SeqCst);
+    }
+  }
+
+  #[test]
+  fn test_backpressure_and_cancel() {
+    let (taken, dropped) = (Arc::new(AtomicBool::new(false)), Arc::default());
+    let mut opts = CaptureOpts::new(Display::synthetic(8, 8));
+    opts.frame_queue(2);
+
+    let stream = {
+      let (taken, dropped) = (taken.clone(), Arc::clone(&dropped));
+      CaptureStream::new(opts, move |opts| {
+        Ok(Counted {
+          inner: SyntheticCapture::new(opts, Pattern::Gradient),
+          taken,
+          dropped,
+        })
+      })
+    };
+
+    // With nobody taking frames, the capture stops once the queue's full.
+    let started = Instant::now();
+    while stream.rx.len() < 2 {
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "Queue not filled"
+      );
+      sleep(Duration::from_millis(1));
+    }
+
+    // It may still be reading the frame that doesn't fit, but then it has to hold off.
+    loop {
+      taken.store(false, Ordering::SeqCst);
+      sleep(Duration::from_millis(50));
+      if !taken.load(Ordering::SeqCst) {
+        break;
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "Capture not held off"
+      );
+    }
+    assert_eq!(stream.rx.len(), 2);
+
+    drop(stream);
+    let started = Instant::now();
+    while !dropped.load(Ordering::SeqCst) {
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "Capture not dropped"
+      );
+      sleep(Duration::from_millis(1));
+    }
+  }
+}
@@ -0,0 +1,516 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::{
+  crop_rects, is_throttled, poll_interval, Capture, CaptureOpts, Frame, FrameClock,
+  FrameInfo, Rect, VideoFrame,
+};
+use crate::Result;
+
+/// What a [`SyntheticCapture`] draws.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Pattern {
+  /// SMPTE color bars, which never change after the first frame.
+  Bars,
+  /// A diagonal gradient moving a pixel each frame, so every frame is fully dirty.
+  Gradient,
+  /// Text scrolling right to left across a band in the middle of the screen.
+  ScrollingText(String),
+  /// A few randomly placed rectangles of random colors each frame.
+  DirtyRects,
+}
+
+/// Generates test patterns instead of reading a screen, so code built on [`Capture`]
+/// can be tested and benchmarked without a display. Any display works, though
+/// [`Display::synthetic`](crate::display::Display::synthetic) needs no real one.
+///
+/// Frames are a function of their index alone, so runs are reproducible. A region is
+/// cropped out of the whole pattern, as it would be out of a real screen.
+pub struct SyntheticCapture {
+  pattern: Pattern,
+  width: u32,
+  height: u32,
+  region: Rect,
+  canvas: Vec<u8>,
+  index: u64,
+  rng: u64,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  clock: FrameClock,
+}
+
+impl SyntheticCapture {
+  pub fn new(opts: CaptureOpts, pattern: Pattern) -> Self {
+    let width = opts.display.width();
+    let height = opts.display.height();
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Self {
+      pattern,
+      width,
+      height,
+      region: opts.source_rect(),
+      canvas: vec![0; width as usize * height as usize * 4],
+      index: 0,
+      rng: 0x2545_f491_4f6c_dd1d,
+      frame_time,
+      last_frame: None,
+      clock: FrameClock::new(),
+    }
+  }
+
+  fn full(&self) -> Rect {
+    Rect::new(0, 0, self.width, self.height)
+  }
+
+  /// Draws the next frame onto the canvas, returning what changed.
+  fn draw(&mut self) -> Vec<Rect> {
+    match self.pattern.clone() {
+      Pattern::Bars if self.index == 0 => {
+        self.draw_bars();
+        vec![self.full()]
+      }
+      Pattern::Bars => Vec::new(),
+      Pattern::Gradient => {
+        self.draw_gradient();
+        vec![self.full()]
+      }
+      Pattern::ScrollingText(text) => self.draw_text(&text),
+      Pattern::DirtyRects => self.draw_rects(),
+    }
+  }
+
+  fn fill(&mut self, rect: Rect, color: [u8; 3]) {
+    let pixel = [color[2], color[1], color[0], 0xff];
+    let stride = self.width as usize * 4;
+
+    for y in rect.y as usize..rect.y as usize + rect.height as usize {
+      let start = y * stride + rect.x as usize * 4;
+      let row = &mut self.canvas[start..start + rect.width as usize * 4];
+      for chunk in row.chunks_exact_mut(4) {
+        chunk.copy_from_slice(&pixel);
+      }
+    }
+  }
+
+  /// Fills a band `height` tall at `y` with `bars` as evenly wide as possible.
+  fn fill_bars(&mut self, y: u32, height: u32, bars: &[[u8; 3]]) {
+    let count = bars.len() as u32;
+    for (i, color) in bars.iter().enumerate() {
+      let i = i as u32;
+      let start = self.width * i / count;
+      let end = self.width * (i + 1) / count;
+      self.fill(
+        Rect::new(start as i32, y as i32, end - start, height),
+        *color,
+      );
+    }
+  }
+
+  fn draw_bars(&mut self) {
+    const GRAY: [u8; 3] = [191, 191, 191];
+    const YELLOW: [u8; 3] = [191, 191, 0];
+    const CYAN: [u8; 3] = [0, 191, 191];
+    const GREEN: [u8; 3] = [0, 191, 0];
+    const MAGENTA: [u8; 3] = [191, 0, 191];
+    const RED: [u8; 3] = [191, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 191];
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const MINUS_I: [u8; 3] = [0, 33, 76];
+    const PLUS_Q: [u8; 3] = [50, 0, 106];
+
+    let top = self.height * 2 / 3;
+    let middle = self.height / 12;
+
+    self.fill_bars(0, top, &[GRAY, YELLOW, CYAN, GREEN, MAGENTA, RED, BLUE]);
+    self.fill_bars(
+      top,
+      middle,
+      &[BLUE, BLACK, MAGENTA, BLACK, CYAN, BLACK, GRAY],
+    );
+    self.fill_bars(
+      top + middle,
+      self.height - top - middle,
+      &[MINUS_I, WHITE, PLUS_Q, BLACK, BLACK, BLACK],
+    );
+  }
+
+  fn draw_gradient(&mut self) {
+    let (width, height) = (self.width.max(1) as u64, self.height.max(1) as u64);
+    let t = self.index;
+
+    for (i, pixel) in self.canvas.chunks_exact_mut(4).enumerate() {
+      let x = i as u64 % width;
+      let y = i as u64 / width;
+
+      pixel.copy_from_slice(&[
+        ((x + t) * 256 / width) as u8,
+        ((y + t) * 256 / height) as u8,
+        ((x + y + t) * 256 / (width + height)) as u8,
+        0xff,
+      ]);
+    }
+  }
+
+  fn draw_text(&mut self, text: &str) -> Vec<Rect> {
+    let scale = (self.height / 40).max(1);
+    let band = Rect::new(
+      0,
+      (self.height.saturating_sub(7 * scale) / 2) as i32,
+      self.width,
+      (7 * scale).min(self.height),
+    );
+
+    if self.index == 0 {
+      self.fill(self.full(), [0, 0, 0]);
+    }
+
+    self.fill(band, [0, 0, 0]);
+
+    // Scrolls in from the right edge and out past the left, then around again.
+    let advance = 4 * scale as i64;
+    let text_width = text.chars().count() as i64 * advance;
+    let span = self.width as i64 + text_width;
+    let left = self.width as i64 - (self.index as i64 * scale as i64) % span.max(1);
+    let top = band.y as i64 + scale as i64;
+
+    for (i, c) in text.chars().enumerate() {
+      let glyph = glyph(c);
+      let glyph_x = left + i as i64 * advance;
+
+      for (row, bits) in glyph.iter().enumerate() {
+        for column in 0..3 {
+          if bits & (0b100 >> column) == 0 {
+            continue;
+          }
+
+          let x = glyph_x + column * scale as i64;
+          let y = top + row as i64 * scale as i64;
+          if let Some(rect) = self.clip(x, y, scale, scale) {
+            self.fill(rect, [255, 255, 255]);
+          }
+        }
+      }
+    }
+
+    if self.index == 0 {
+      vec![self.full()]
+    } else {
+      vec![band]
+    }
+  }
+
+  fn draw_rects(&mut self) -> Vec<Rect> {
+    if self.index == 0 {
+      self.fill(self.full(), [0, 0, 0]);
+      return vec![self.full()];
+    }
+
+    let count = 1 + self.random() % 4;
+    let mut dirty_rects = Vec::new();
+
+    for _ in 0..count {
+      let x = self.random() % self.width.max(1) as u64;
+      let y = self.random() % self.height.max(1) as u64;
+      let width = 1 + self.random() % (self.width / 4).max(1) as u64;
+      let height = 1 + self.random() % (self.height / 4).max(1) as u64;
+      let color = self.random().to_le_bytes();
+
+      if let Some(rect) = self.clip(x as i64, y as i64, width as u32, height as u32) {
+        self.fill(rect, [color[0], color[1], color[2]]);
+        dirty_rects.push(rect);
+      }
+    }
+
+    dirty_rects
+  }
+
+  /// Clips a rectangle to the screen, `None` if nothing of it is left.
+  fn clip(&self, x: i64, y: i64, width: u32, height: u32) -> Option<Rect> {
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = (x + width as i64).min(self.width as i64);
+    let bottom = (y + height as i64).min(self.height as i64);
+
+    if left < right && top < bottom {
+      Some(Rect::new(
+        left as i32,
+        top as i32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+      ))
+    } else {
+      None
+    }
+  }
+
+  /// xorshift64, which is plenty for placing rectangles.
+  fn random(&mut self) -> u64 {
+    self.rng ^= self.rng << 13;
+    self.rng ^= self.rng >> 7;
+    self.rng ^= self.rng << 17;
+    self.rng
+  }
+}
+
+impl Capture for SyntheticCapture {
+  type Frame<'a> = SyntheticFrame<'a>;
+
+  fn frame(&mut self) -> Result<Frame<SyntheticFrame<'_>>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let dirty_rects = crop_rects(self.draw(), self.region);
+    self.index += 1;
+
+    // Like a real screen, nothing changing means no frame.
+    if dirty_rects.is_empty() {
+      return Ok(Frame::Idle);
+    }
+
+    self.last_frame = Some(Instant::now());
+    let (offset, info) = self
+      .clock
+      .next_bgrx(self.width, self.height)
+      .crop(self.region)?;
+
+    Ok(Frame::Ready(SyntheticFrame {
+      data: &self.canvas[offset..],
+      dirty_rects,
+      info,
+    }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+    Ok(())
+  }
+}
+
+/// A generated BGRX frame, borrowing the capture's canvas rather than copying it.
+#[derive(Debug)]
+pub struct SyntheticFrame<'a> {
+  data: &'a [u8],
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for SyntheticFrame<'_> {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions drawn over since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for SyntheticFrame<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.data
+  }
+}
+
+/// A 3x5 glyph, a row of three bits each, with a blank row above and below.
+fn glyph(c: char) -> [u8; 7] {
+  let rows = match c.to_ascii_uppercase() {
+    '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+    '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+    '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+    '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+    '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+    '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+    '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+    '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+    '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+    '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+    'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+    'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+    'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+    'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+    'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+    'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+    'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+    'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+    'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+    'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+    'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+    'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+    'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+    'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+    'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+    'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+    'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+    'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+    'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+    'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+    'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+    'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+    'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+    'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+    'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+    'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+    '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+    ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+    '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+    _ => [0; 5],
+  };
+
+  [0, rows[0], rows[1], rows[2], rows[3], rows[4], 0]
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fmt::Debug;
+  use std::time::{Duration, Instant};
+
+  use crate::capture::synthetic::{Pattern, SyntheticCapture};
+  use crate::capture::{Capture, CaptureOpts, Frame, Rect, VideoFrame};
+  use crate::display::Display;
+  use crate::Error;
+
+  fn capture(pattern: Pattern) -> SyntheticCapture {
+    SyntheticCapture::new(CaptureOpts::new(Display::synthetic(64, 48)), pattern)
+  }
+
+  fn ready<T: Debug>(frame: Frame<T>) -> T {
+    match frame {
+      Frame::Ready(frame) => frame,
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_bars() {
+    let mut capture = capture(Pattern::Bars);
+    let frame = ready(capture.frame().unwrap());
+
+    assert_eq!(frame.len(), 64 * 48 * 4);
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 64, 48)]);
+    // Gray, then yellow, in BGRX.
+    assert_eq!(frame[..4], [191, 191, 191, 0xff]);
+    assert_eq!(frame[10 * 4..11 * 4], [0, 191, 191, 0xff]);
+
+    // Bars never change.
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+  }
+
+  #[test]
+  fn test_gradient() {
+    let mut capture = capture(Pattern::Gradient);
+    // The first frame borrows the canvas the second is drawn on, so keep a copy.
+    let first = ready(capture.frame().unwrap().to_owned());
+    let second = ready(capture.frame().unwrap());
+
+    assert_ne!(*first, *second);
+    assert_eq!(*second.dirty_rects(), [Rect::new(0, 0, 64, 48)]);
+  }
+
+  #[test]
+  fn test_scrolling_text() {
+    let mut capture = capture(Pattern::ScrollingText("HELLO".to_owned()));
+    ready(capture.frame().unwrap());
+
+    for _ in 0..19 {
+      ready(capture.frame().unwrap());
+    }
+
+    let frame = ready(capture.frame().unwrap());
+    let band = frame.dirty_rects()[0];
+
+    assert_eq!(band.width, 64);
+    assert!(frame.chunks(4).any(|pixel| pixel == [255, 255, 255, 0xff]));
+  }
+
+  #[test]
+  fn test_dirty_rects() {
+    let mut capture = capture(Pattern::DirtyRects);
+    ready(capture.frame().unwrap());
+
+    for _ in 0..100 {
+      let frame = ready(capture.frame().unwrap());
+      for rect in frame.dirty_rects().iter() {
+        assert!(rect.x as u32 + rect.width <= 64);
+        assert!(rect.y as u32 + rect.height <= 48);
+      }
+    }
+  }
+
+  #[test]
+  fn test_region() {
+    let full = ready(capture(Pattern::Bars).frame().unwrap().to_owned());
+
+    let mut opts = CaptureOpts::new(Display::synthetic(64, 48));
+    opts.region(Rect::new(8, 4, 16, 8)).unwrap();
+    let mut capture = SyntheticCapture::new(opts, Pattern::Bars);
+    let frame = ready(capture.frame().unwrap());
+
+    assert_eq!((frame.width(), frame.height()), (16, 8));
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 16, 8)]);
+    for (y, row) in frame.rows().enumerate() {
+      assert_eq!(row, &full.row(4 + y as u32).unwrap()[8 * 4..24 * 4]);
+    }
+  }
+
+  #[test]
+  fn test_region_outside_display() {
+    let mut opts = CaptureOpts::new(Display::synthetic(64, 48));
+
+    for region in [
+      Rect::new(60, 0, 8, 8),
+      Rect::new(-1, 0, 8, 8),
+      Rect::new(0, 0, 0, 8),
+    ] {
+      assert!(matches!(opts.region(region), Err(Error::InvalidInput(_))));
+    }
+  }
+
+  #[test]
+  fn test_frame_rate() {
+    let mut opts = CaptureOpts::new(Display::synthetic(64, 48));
+    opts.frame_rate(1.0);
+
+    let mut capture = SyntheticCapture::new(opts, Pattern::Gradient);
+    assert!(matches!(capture.frame().unwrap(), Frame::Ready(_)));
+    assert!(matches!(capture.frame().unwrap(), Frame::Blocking));
+  }
+
+  #[test]
+  fn test_next_frame() {
+    let mut opts = CaptureOpts::new(Display::synthetic(64, 48));
+    opts.frame_rate(20.0);
+
+    let mut capture = SyntheticCapture::new(opts, Pattern::Gradient);
+    ready(capture.frame().unwrap());
+
+    let start = Instant::now();
+    ready(capture.next_frame().unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(40));
+  }
+
+  #[test]
+  fn test_next_frame_timeout() {
+    let mut capture = capture(Pattern::Bars);
+    ready(capture.next_frame().unwrap());
+
+    let start = Instant::now();
+    let frame = capture
+      .next_frame_timeout(Duration::from_millis(20))
+      .unwrap();
+
+    assert!(matches!(frame, Frame::Idle));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+  }
+}
@@ -0,0 +1,719 @@
+use std::io::{Error, ErrorKind, Read, Result};
+
+use flate2::{Decompress, FlushDecompress};
+
+use crate::capture::vnc::ReadBytes;
+use crate::capture::Rect;
+
+pub const RAW: i32 = 0;
+pub const COPY_RECT: i32 = 1;
+pub const HEXTILE: i32 = 5;
+pub const TIGHT: i32 = 7;
+pub const ZRLE: i32 = 16;
+/// Asks the server for the cursor shape, so it stops drawing the cursor itself.
+pub const CURSOR: i32 = -239;
+pub const DESKTOP_SIZE: i32 = -223;
+
+const HEXTILE_RAW: u8 = 1;
+const HEXTILE_BACKGROUND: u8 = 2;
+const HEXTILE_FOREGROUND: u8 = 4;
+const HEXTILE_ANY_SUBRECTS: u8 = 8;
+const HEXTILE_SUBRECTS_COLOURED: u8 = 16;
+
+const TIGHT_FILL: u8 = 0x08;
+const TIGHT_JPEG: u8 = 0x09;
+const TIGHT_EXPLICIT_FILTER: u8 = 0x04;
+const TIGHT_FILTER_COPY: u8 = 0;
+const TIGHT_FILTER_PALETTE: u8 = 1;
+const TIGHT_FILTER_GRADIENT: u8 = 2;
+/// Tight sends anything smaller uncompressed.
+const TIGHT_MIN_TO_COMPRESS: usize = 12;
+
+fn invalid(message: &str) -> Error {
+  Error::new(ErrorKind::InvalidData, message)
+}
+
+/// The remote framebuffer, in the BGRX format we ask servers for.
+#[derive(Debug)]
+pub struct Framebuffer {
+  pub width: u32,
+  pub height: u32,
+  pub data: Vec<u8>,
+}
+
+impl Framebuffer {
+  pub fn new(width: u32, height: u32) -> Self {
+    Self {
+      width,
+      height,
+      data: vec![0; width as usize * height as usize * 4],
+    }
+  }
+
+  pub fn full(&self) -> Rect {
+    Rect::new(0, 0, self.width, self.height)
+  }
+
+  fn check(&self, rect: Rect) -> Result<()> {
+    let fits = rect.x as u32 + rect.width <= self.width
+      && rect.y as u32 + rect.height <= self.height;
+
+    if fits {
+      Ok(())
+    } else {
+      Err(invalid("Rectangle is outside the framebuffer"))
+    }
+  }
+
+  fn rows(&mut self, rect: Rect) -> impl Iterator<Item = &mut [u8]> {
+    let stride = self.width as usize * 4;
+    let start = rect.x as usize * 4;
+    let len = rect.width as usize * 4;
+
+    self
+      .data
+      .chunks_exact_mut(stride)
+      .skip(rect.y as usize)
+      .take(rect.height as usize)
+      .map(move |row| &mut row[start..start + len])
+  }
+
+  /// Copies tightly packed pixels into `rect`.
+  fn put(&mut self, rect: Rect, pixels: &[u8]) -> Result<()> {
+    self.check(rect)?;
+
+    let len = rect.width as usize * 4;
+    for (row, pixels) in self.rows(rect).zip(pixels.chunks_exact(len)) {
+      row.copy_from_slice(pixels);
+    }
+
+    Ok(())
+  }
+
+  fn fill(&mut self, rect: Rect, pixel: [u8; 4]) -> Result<()> {
+    self.check(rect)?;
+
+    for row in self.rows(rect) {
+      for chunk in row.chunks_exact_mut(4) {
+        chunk.copy_from_slice(&pixel);
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Copies the `rect` sized area at `x`, `y` to `rect`, which may overlap it.
+  fn copy(&mut self, x: u16, y: u16, rect: Rect) -> Result<()> {
+    let source = Rect::new(x as i32, y as i32, rect.width, rect.height);
+    self.check(source)?;
+
+    let pixels: Vec<u8> = self.rows(source).flat_map(|row| row.to_vec()).collect();
+    self.put(rect, &pixels)
+  }
+}
+
+/// Decodes rectangles, keeping the zlib streams that last the whole connection.
+pub struct Decoder {
+  zrle: Decompress,
+  tight: [Decompress; 4],
+}
+
+impl Decoder {
+  pub fn new() -> Self {
+    Self {
+      zrle: Decompress::new(true),
+      tight: [
+        Decompress::new(true),
+        Decompress::new(true),
+        Decompress::new(true),
+        Decompress::new(true),
+      ],
+    }
+  }
+
+  /// Decodes a rectangle onto `framebuffer`, returning the area that changed.
+  pub fn decode<R: Read>(
+    &mut self,
+    reader: &mut R,
+    framebuffer: &mut Framebuffer,
+    rect: Rect,
+    encoding: i32,
+  ) -> Result<Option<Rect>> {
+    match encoding {
+      RAW => {
+        let pixels = reader.read_vec(rect.width as usize * rect.height as usize * 4)?;
+        framebuffer.put(rect, &pixels)?;
+      }
+      COPY_RECT => {
+        let (x, y) = (reader.read_u16()?, reader.read_u16()?);
+        framebuffer.copy(x, y, rect)?;
+      }
+      HEXTILE => hextile(reader, framebuffer, rect)?,
+      ZRLE => self.zrle(reader, framebuffer, rect)?,
+      TIGHT => self.tight(reader, framebuffer, rect)?,
+      DESKTOP_SIZE => {
+        *framebuffer = Framebuffer::new(rect.width, rect.height);
+        return Ok(Some(framebuffer.full()));
+      }
+      CURSOR => {
+        // The shape, then its mask, neither of which we draw.
+        let mask_len = (rect.width as usize).div_ceil(8) * rect.height as usize;
+        reader.read_vec(rect.width as usize * rect.height as usize * 4 + mask_len)?;
+        return Ok(None);
+      }
+      _ => {
+        return Err(Error::new(
+          ErrorKind::Unsupported,
+          format!("Server used encoding {} we didn't ask for", encoding),
+        ))
+      }
+    }
+
+    Ok(Some(rect))
+  }
+
+  fn zrle<R: Read>(
+    &mut self,
+    reader: &mut R,
+    framebuffer: &mut Framebuffer,
+    rect: Rect,
+  ) -> Result<()> {
+    let len = reader.read_u32()? as usize;
+    let data = inflate(&mut self.zrle, &reader.read_vec(len)?)?;
+    let mut data = &data[..];
+
+    for (tile, pixels) in tiles(rect, 64) {
+      let subencoding = data.read_u8()?;
+      let mut tile_pixels = Vec::with_capacity(pixels * 4);
+
+      match subencoding {
+        0 => {
+          for _ in 0..pixels {
+            tile_pixels.extend_from_slice(&read_cpixel(&mut data)?);
+          }
+        }
+        1 => {
+          framebuffer.fill(tile, read_cpixel(&mut data)?)?;
+          continue;
+        }
+        2..=16 => {
+          let palette = (0..subencoding)
+            .map(|_| read_cpixel(&mut data))
+            .collect::<Result<Vec<_>>>()?;
+
+          let bits = match subencoding {
+            2 => 1,
+            3 | 4 => 2,
+            _ => 4,
+          };
+
+          unpack(&mut data, tile, bits, &palette, &mut tile_pixels)?;
+        }
+        128 => {
+          while tile_pixels.len() < pixels * 4 {
+            let pixel = read_cpixel(&mut data)?;
+            let run = read_run(&mut data, pixels - tile_pixels.len() / 4)?;
+            for _ in 0..run {
+              tile_pixels.extend_from_slice(&pixel);
+            }
+          }
+        }
+        130..=255 => {
+          let palette = (0..subencoding - 128)
+            .map(|_| read_cpixel(&mut data))
+            .collect::<Result<Vec<_>>>()?;
+
+          while tile_pixels.len() < pixels * 4 {
+            let index = data.read_u8()?;
+            let run = if index & 128 != 0 {
+              read_run(&mut data, pixels - tile_pixels.len() / 4)?
+            } else {
+              1
+            };
+
+            let pixel = palette
+              .get((index & 127) as usize)
+              .ok_or_else(|| invalid("ZRLE palette index out of range"))?;
+            for _ in 0..run {
+              tile_pixels.extend_from_slice(pixel);
+            }
+          }
+        }
+        _ => return Err(invalid("Unknown ZRLE subencoding")),
+      }
+
+      framebuffer.put(tile, &tile_pixels)?;
+    }
+
+    Ok(())
+  }
+
+  fn tight<R: Read>(
+    &mut self,
+    reader: &mut R,
+    framebuffer: &mut Framebuffer,
+    rect: Rect,
+  ) -> Result<()> {
+    let control = reader.read_u8()?;
+    for (i, stream) in self.tight.iter_mut().enumerate() {
+      if control & (1 << i) != 0 {
+        stream.reset(true);
+      }
+    }
+
+    let kind = control >> 4;
+    match kind {
+      TIGHT_FILL => return framebuffer.fill(rect, read_tpixel(reader)?),
+      // JPEG is only ever used if we ask for a quality level, which we don't.
+      TIGHT_JPEG => return Err(invalid("Server sent Tight JPEG we didn't ask for")),
+      _ if kind & 0x08 != 0 => return Err(invalid("Unknown Tight compression")),
+      _ => (),
+    }
+
+    let filter = if kind & TIGHT_EXPLICIT_FILTER != 0 {
+      reader.read_u8()?
+    } else {
+      TIGHT_FILTER_COPY
+    };
+
+    let width = rect.width as usize;
+    let (palette, row_len) = match filter {
+      TIGHT_FILTER_COPY | TIGHT_FILTER_GRADIENT => (Vec::new(), width * 3),
+      TIGHT_FILTER_PALETTE => {
+        let colors = reader.read_u8()? as usize + 1;
+        let palette = (0..colors)
+          .map(|_| read_tpixel(reader))
+          .collect::<Result<Vec<_>>>()?;
+
+        let row_len = if colors == 2 {
+          width.div_ceil(8)
+        } else {
+          width
+        };
+        (palette, row_len)
+      }
+      _ => return Err(invalid("Unknown Tight filter")),
+    };
+
+    let len = row_len * rect.height as usize;
+    let data = if len < TIGHT_MIN_TO_COMPRESS {
+      reader.read_vec(len)?
+    } else {
+      let compressed_len = read_compact_len(reader)?;
+      let stream = &mut self.tight[(kind & 0x03) as usize];
+      inflate(stream, &reader.read_vec(compressed_len)?)?
+    };
+
+    if data.len() < len {
+      return Err(invalid("Tight data is truncated"));
+    }
+
+    let mut pixels = Vec::with_capacity(width * rect.height as usize * 4);
+    match filter {
+      TIGHT_FILTER_PALETTE if palette.len() == 2 => {
+        unpack(&mut &data[..], rect, 1, &palette, &mut pixels)?
+      }
+      TIGHT_FILTER_PALETTE => {
+        for index in &data[..len] {
+          let pixel = palette
+            .get(*index as usize)
+            .ok_or_else(|| invalid("Tight palette index out of range"))?;
+          pixels.extend_from_slice(pixel);
+        }
+      }
+      TIGHT_FILTER_GRADIENT => {
+        let mut previous = vec![0u8; row_len];
+        for row in data[..len].chunks_exact(row_len) {
+          let mut current = vec![0u8; row_len];
+          for i in 0..row_len {
+            let left = if i >= 3 { current[i - 3] as i32 } else { 0 };
+            let up_left = if i >= 3 { previous[i - 3] as i32 } else { 0 };
+            let predicted = (left + previous[i] as i32 - up_left).clamp(0, 255);
+            current[i] = row[i].wrapping_add(predicted as u8);
+          }
+
+          for rgb in current.chunks_exact(3) {
+            pixels.extend_from_slice(&[rgb[2], rgb[1], rgb[0], 0xff]);
+          }
+
+          previous = current;
+        }
+      }
+      _ => {
+        for rgb in data[..len].chunks_exact(3) {
+          pixels.extend_from_slice(&[rgb[2], rgb[1], rgb[0], 0xff]);
+        }
+      }
+    }
+
+    framebuffer.put(rect, &pixels)
+  }
+}
+
+fn hextile<R: Read>(
+  reader: &mut R,
+  framebuffer: &mut Framebuffer,
+  rect: Rect,
+) -> Result<()> {
+  let mut background = [0; 4];
+  let mut foreground = [0; 4];
+
+  for (tile, pixels) in tiles(rect, 16) {
+    let mask = reader.read_u8()?;
+    if mask & HEXTILE_RAW != 0 {
+      framebuffer.put(tile, &reader.read_vec(pixels * 4)?)?;
+      continue;
+    }
+
+    if mask & HEXTILE_BACKGROUND != 0 {
+      background = read_pixel(reader)?;
+    }
+
+    framebuffer.fill(tile, background)?;
+
+    if mask & HEXTILE_FOREGROUND != 0 {
+      foreground = read_pixel(reader)?;
+    }
+
+    if mask & HEXTILE_ANY_SUBRECTS == 0 {
+      continue;
+    }
+
+    for _ in 0..reader.read_u8()? {
+      let color = if mask & HEXTILE_SUBRECTS_COLOURED != 0 {
+        read_pixel(reader)?
+      } else {
+        foreground
+      };
+
+      let (xy, wh) = (reader.read_u8()?, reader.read_u8()?);
+      let subrect = Rect::new(
+        tile.x + (xy >> 4) as i32,
+        tile.y + (xy & 15) as i32,
+        (wh >> 4) as u32 + 1,
+        (wh & 15) as u32 + 1,
+      );
+
+      if subrect.x as u32 + subrect.width > tile.x as u32 + tile.width
+        || subrect.y as u32 + subrect.height > tile.y as u32 + tile.height
+      {
+        return Err(invalid("Hextile subrectangle is outside its tile"));
+      }
+
+      framebuffer.fill(subrect, color)?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Splits `rect` into `size` square tiles, left to right then top to bottom, with
+/// their pixel counts.
+fn tiles(rect: Rect, size: u32) -> impl Iterator<Item = (Rect, usize)> {
+  (0..rect.height).step_by(size as usize).flat_map(move |y| {
+    (0..rect.width).step_by(size as usize).map(move |x| {
+      let tile = Rect::new(
+        rect.x + x as i32,
+        rect.y + y as i32,
+        size.min(rect.width - x),
+        size.min(rect.height - y),
+      );
+
+      (tile, tile.width as usize * tile.height as usize)
+    })
+  })
+}
+
+/// A pixel in the format we asked for.
+fn read_pixel<R: Read>(reader: &mut R) -> Result<[u8; 4]> {
+  let mut pixel = [0; 4];
+  reader.read_exact(&mut pixel)?;
+  Ok(pixel)
+}
+
+/// A ZRLE compressed pixel, our pixel format without its unused byte.
+fn read_cpixel<R: Read>(reader: &mut R) -> Result<[u8; 4]> {
+  let mut pixel = [0; 3];
+  reader.read_exact(&mut pixel)?;
+  Ok([pixel[0], pixel[1], pixel[2], 0xff])
+}
+
+/// A Tight pixel, which is red, green and blue whatever the pixel format.
+fn read_tpixel<R: Read>(reader: &mut R) -> Result<[u8; 4]> {
+  let mut pixel = [0; 3];
+  reader.read_exact(&mut pixel)?;
+  Ok([pixel[2], pixel[1], pixel[0], 0xff])
+}
+
+/// A ZRLE run length, at most `max`.
+fn read_run<R: Read>(reader: &mut R, max: usize) -> Result<usize> {
+  let mut run = 1;
+  loop {
+    let byte = reader.read_u8()?;
+    run += byte as usize;
+    if byte != 255 {
+      break;
+    }
+  }
+
+  if run > max {
+    return Err(invalid("ZRLE run overflows its tile"));
+  }
+
+  Ok(run)
+}
+
+/// Tight's length of 7 bits per byte, the high bit meaning another byte follows.
+fn read_compact_len<R: Read>(reader: &mut R) -> Result<usize> {
+  let mut len = 0;
+  for i in 0..3 {
+    let byte = reader.read_u8()? as usize;
+    if i == 2 {
+      return Ok(len | byte << 14);
+    }
+
+    len |= (byte & 0x7f) << (7 * i);
+    if byte & 0x80 == 0 {
+      break;
+    }
+  }
+
+  Ok(len)
+}
+
+/// Unpacks `bits` wide palette indices, most significant first, with rows padded to
+/// a byte.
+fn unpack<R: Read>(
+  reader: &mut R,
+  tile: Rect,
+  bits: u32,
+  palette: &[[u8; 4]],
+  pixels: &mut Vec<u8>,
+) -> Result<()> {
+  let row_len = (tile.width * bits).div_ceil(8);
+  let mask = (1u8 << bits) - 1;
+
+  for _ in 0..tile.height {
+    let row = reader.read_vec(row_len as usize)?;
+    for x in 0..tile.width {
+      let bit = x * bits;
+      let shift = 8 - bits - bit % 8;
+      let index = (row[(bit / 8) as usize] >> shift) & mask;
+      let pixel = palette
+        .get(index as usize)
+        .ok_or_else(|| invalid("Palette index out of range"))?;
+
+      pixels.extend_from_slice(pixel);
+    }
+  }
+
+  Ok(())
+}
+
+/// Inflates everything in `input`, which servers always end with a sync flush.
+fn inflate(stream: &mut Decompress, input: &[u8]) -> Result<Vec<u8>> {
+  let mut output = Vec::with_capacity(input.len() * 4);
+  let mut consumed = 0;
+
+  loop {
+    if output.len() == output.capacity() {
+      output.reserve(output.capacity().max(4096));
+    }
+
+    let (total_in, total_out) = (stream.total_in(), stream.total_out());
+    stream
+      .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+      .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+    consumed += (stream.total_in() - total_in) as usize;
+    let stalled = stream.total_in() == total_in && stream.total_out() == total_out;
+
+    if stalled || (consumed == input.len() && output.len() < output.capacity()) {
+      return Ok(output);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::write::ZlibEncoder;
+  use flate2::{Compress, Compression, FlushCompress};
+
+  use crate::capture::vnc::encodings::*;
+
+  const RED: [u8; 4] = [0, 0, 0xff, 0xff];
+  const BLUE: [u8; 4] = [0xff, 0, 0, 0xff];
+
+  fn pixel(framebuffer: &Framebuffer, x: usize, y: usize) -> [u8; 4] {
+    let i = (y * framebuffer.width as usize + x) * 4;
+    [
+      framebuffer.data[i],
+      framebuffer.data[i + 1],
+      framebuffer.data[i + 2],
+      framebuffer.data[i + 3],
+    ]
+  }
+
+  fn decode(framebuffer: &mut Framebuffer, rect: Rect, encoding: i32, data: &[u8]) {
+    let mut reader = data;
+    Decoder::new()
+      .decode(&mut reader, framebuffer, rect, encoding)
+      .unwrap();
+
+    assert!(reader.is_empty(), "{} bytes left over", reader.len());
+  }
+
+  fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  #[test]
+  fn test_raw_and_copy_rect() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+    decode(&mut framebuffer, Rect::new(0, 0, 1, 1), RAW, &RED);
+
+    // Copy the red pixel one to the right.
+    decode(
+      &mut framebuffer,
+      Rect::new(1, 0, 1, 1),
+      COPY_RECT,
+      &[0, 0, 0, 0],
+    );
+    assert_eq!(pixel(&framebuffer, 1, 0), RED);
+
+    let mut reader = &[0u8, 0, 0, 0][..];
+    let outside = Rect::new(3, 1, 2, 1);
+    assert!(Decoder::new()
+      .decode(&mut reader, &mut framebuffer, outside, COPY_RECT)
+      .is_err());
+  }
+
+  #[test]
+  fn test_hextile() {
+    let mut framebuffer = Framebuffer::new(20, 4);
+    let mut data = Vec::new();
+
+    // A blue tile with a red 2x1 subrectangle at 1, 1.
+    data.push(HEXTILE_BACKGROUND | HEXTILE_FOREGROUND | HEXTILE_ANY_SUBRECTS);
+    data.extend_from_slice(&BLUE);
+    data.extend_from_slice(&RED);
+    data.extend_from_slice(&[1, 0x11, 0x10]);
+
+    // Then the 4 pixel wide tile on the right, the same blue background.
+    data.push(0);
+
+    decode(&mut framebuffer, Rect::new(0, 0, 20, 4), HEXTILE, &data);
+    assert_eq!(pixel(&framebuffer, 0, 0), BLUE);
+    assert_eq!(pixel(&framebuffer, 1, 1), RED);
+    assert_eq!(pixel(&framebuffer, 2, 1), RED);
+    assert_eq!(pixel(&framebuffer, 3, 1), BLUE);
+    assert_eq!(pixel(&framebuffer, 19, 3), BLUE);
+  }
+
+  #[test]
+  fn test_zrle() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+
+    // A two color palette, then a packed palette tile, rows padded to a byte.
+    let tile = [2, 0, 0, 0xff, 0xff, 0, 0, 0b0110_0000, 0b1000_0000];
+    let compressed = zlib(&tile);
+
+    let mut data = (compressed.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&compressed);
+
+    decode(&mut framebuffer, Rect::new(0, 0, 4, 2), ZRLE, &data);
+    assert_eq!(pixel(&framebuffer, 0, 0), RED);
+    assert_eq!(pixel(&framebuffer, 1, 0), BLUE);
+    assert_eq!(pixel(&framebuffer, 2, 0), BLUE);
+    assert_eq!(pixel(&framebuffer, 0, 1), BLUE);
+    assert_eq!(pixel(&framebuffer, 1, 1), RED);
+  }
+
+  #[test]
+  fn test_zrle_rle() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+
+    // Plain RLE: 3 red, then 5 blue.
+    let tile = [128, 0, 0, 0xff, 2, 0xff, 0, 0, 4];
+    let compressed = zlib(&tile);
+
+    let mut data = (compressed.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&compressed);
+
+    decode(&mut framebuffer, Rect::new(0, 0, 4, 2), ZRLE, &data);
+    assert_eq!(pixel(&framebuffer, 2, 0), RED);
+    assert_eq!(pixel(&framebuffer, 3, 0), BLUE);
+    assert_eq!(pixel(&framebuffer, 3, 1), BLUE);
+  }
+
+  #[test]
+  fn test_tight_fill() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+    decode(
+      &mut framebuffer,
+      Rect::new(0, 0, 4, 2),
+      TIGHT,
+      &[TIGHT_FILL << 4, 0xff, 0, 0],
+    );
+
+    assert!(framebuffer.data.chunks(4).all(|pixel| pixel == RED));
+  }
+
+  #[test]
+  fn test_tight_copy() {
+    let mut framebuffer = Framebuffer::new(4, 2);
+
+    // Eight RGB pixels, compressed on stream 1 with a sync flush like servers do.
+    let rgb: Vec<u8> = (0..8).flat_map(|i| [i, 0, 0xff - i]).collect();
+    let mut compress = Compress::new(Compression::default(), true);
+    let mut compressed = Vec::with_capacity(128);
+    compress
+      .compress_vec(&rgb, &mut compressed, FlushCompress::Sync)
+      .unwrap();
+
+    let mut data = vec![0x10, compressed.len() as u8];
+    data.extend_from_slice(&compressed);
+
+    decode(&mut framebuffer, Rect::new(0, 0, 4, 2), TIGHT, &data);
+    assert_eq!(pixel(&framebuffer, 0, 0), [0xff, 0, 0, 0xff]);
+    assert_eq!(pixel(&framebuffer, 3, 1), [0xf8, 0, 7, 0xff]);
+  }
+
+  #[test]
+  fn test_tight_palette() {
+    let mut framebuffer = Framebuffer::new(4, 1);
+
+    // Explicit palette filter, two colors, then a byte of uncompressed bits.
+    let data = [
+      (TIGHT_EXPLICIT_FILTER) << 4,
+      TIGHT_FILTER_PALETTE,
+      1,
+      0xff,
+      0,
+      0,
+      0,
+      0,
+      0xff,
+      0b1010_0000,
+    ];
+
+    decode(&mut framebuffer, Rect::new(0, 0, 4, 1), TIGHT, &data);
+    assert_eq!(pixel(&framebuffer, 0, 0), BLUE);
+    assert_eq!(pixel(&framebuffer, 1, 0), RED);
+    assert_eq!(pixel(&framebuffer, 2, 0), BLUE);
+  }
+
+  #[test]
+  fn test_compact_len() {
+    assert_eq!(read_compact_len(&mut &[0x05][..]).unwrap(), 5);
+    assert_eq!(read_compact_len(&mut &[0x90, 0x4e][..]).unwrap(), 10000);
+    assert_eq!(
+      read_compact_len(&mut &[0xff, 0xff, 0xff][..]).unwrap(),
+      4194303
+    );
+  }
+}
@@ -0,0 +1,635 @@
+use std::borrow::Cow;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::Des;
+
+use crate::capture::vnc::encodings::{Decoder, Framebuffer};
+use crate::capture::{
+  check_size, clip_region, crop_rects, is_throttled, throttle_remaining, Capture,
+  CaptureOpts, Frame, FrameClock, FrameInfo, Rect, VideoFrame,
+};
+use crate::{Error, Result};
+
+mod encodings;
+
+const SECURITY_INVALID: u32 = 0;
+const SECURITY_NONE: u8 = 1;
+const SECURITY_VNC_AUTH: u8 = 2;
+
+const SET_PIXEL_FORMAT: u8 = 0;
+const SET_ENCODINGS: u8 = 2;
+const FRAMEBUFFER_UPDATE_REQUEST: u8 = 3;
+
+const FRAMEBUFFER_UPDATE: u8 = 0;
+const SET_COLOUR_MAP_ENTRIES: u8 = 1;
+const BELL: u8 = 2;
+const SERVER_CUT_TEXT: u8 = 3;
+
+/// 32 bits per pixel, depth 24, little-endian true color with red at bit 16, green
+/// at 8 and blue at 0: BGRX in memory, like every other backend.
+const PIXEL_FORMAT: [u8; 16] = [32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 16, 8, 0, 0, 0, 0];
+
+/// Big-endian reads, which is all RFB uses.
+pub(crate) trait ReadBytes: Read {
+  fn read_u8(&mut self) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    self.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+  }
+
+  fn read_u16(&mut self) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    self.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+  }
+
+  fn read_u32(&mut self) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    self.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+  }
+
+  fn read_vec(&mut self, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    self.read_exact(&mut bytes)?;
+    Ok(bytes)
+  }
+
+  fn read_string(&mut self) -> io::Result<String> {
+    let len = self.read_u32()? as usize;
+    Ok(String::from_utf8_lossy(&self.read_vec(len)?).into_owned())
+  }
+}
+
+impl<R: Read + ?Sized> ReadBytes for R {}
+
+/// An RFB connection that's past the handshake.
+pub(crate) struct Connection {
+  stream: TcpStream,
+  width: u16,
+  height: u16,
+  name: String,
+}
+
+impl Connection {
+  /// Connects to `address`, authenticating with `password` if the server asks.
+  pub fn open(address: &str, password: Option<&str>) -> Result<Self> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+
+    let minor = negotiate_version(&mut stream)?;
+    authenticate(&mut stream, minor, password)?;
+
+    // Shared, so we don't kick out anyone else watching.
+    stream.write_all(&[1])?;
+
+    let width = stream.read_u16()?;
+    let height = stream.read_u16()?;
+    stream.read_vec(PIXEL_FORMAT.len())?;
+    let name = stream.read_string()?;
+
+    Ok(Self {
+      stream,
+      width,
+      height,
+      name,
+    })
+  }
+
+  pub fn width(&self) -> u16 {
+    self.width
+  }
+
+  pub fn height(&self) -> u16 {
+    self.height
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
+
+/// Agrees on 3.3, 3.7 or 3.8, returning the minor version.
+fn negotiate_version(stream: &mut TcpStream) -> Result<u32> {
+  let version = stream.read_vec(12)?;
+  let version = std::str::from_utf8(&version)
+    .ok()
+    .and_then(|version| version.strip_prefix("RFB "))
+    .and_then(|version| version.strip_suffix('\n'))
+    .and_then(|version| version.split_once('.'))
+    .and_then(|(major, minor)| {
+      Some((major.parse::<u32>().ok()?, minor.parse::<u32>().ok()?))
+    })
+    .ok_or_else(|| Error::invalid_data("Not an RFB server"))?;
+
+  let minor = match version {
+    (3, minor) if minor >= 8 => 8,
+    (3, 7) => 7,
+    (3, _) => 3,
+    (major, _) if major > 3 => 8,
+    _ => return Err(Error::unavailable("Unsupported RFB version")),
+  };
+
+  stream.write_all(format!("RFB 003.00{}\n", minor).as_bytes())?;
+  Ok(minor)
+}
+
+fn authenticate(
+  stream: &mut TcpStream,
+  minor: u32,
+  password: Option<&str>,
+) -> Result<()> {
+  let failed = |stream: &mut TcpStream| -> Error {
+    let reason = stream.read_string().unwrap_or_default();
+    Error::denied(reason)
+  };
+
+  let security = if minor == 3 {
+    // The server decides, and only says why it refused if it did.
+    match stream.read_u32()? {
+      SECURITY_INVALID => return Err(failed(stream)),
+      security => security as u8,
+    }
+  } else {
+    let count = stream.read_u8()?;
+    if count == 0 {
+      return Err(failed(stream));
+    }
+
+    let offered = stream.read_vec(count as usize)?;
+    let security = match password {
+      Some(_) if offered.contains(&SECURITY_VNC_AUTH) => SECURITY_VNC_AUTH,
+      _ if offered.contains(&SECURITY_NONE) => SECURITY_NONE,
+      _ if offered.contains(&SECURITY_VNC_AUTH) => SECURITY_VNC_AUTH,
+      _ => {
+        return Err(Error::unavailable(
+          "Server offers no security type we support",
+        ))
+      }
+    };
+
+    stream.write_all(&[security])?;
+    security
+  };
+
+  match security {
+    SECURITY_NONE if minor < 8 => return Ok(()),
+    SECURITY_NONE => (),
+    SECURITY_VNC_AUTH => {
+      let password = password.ok_or_else(|| Error::denied("Server needs a password"))?;
+
+      let mut challenge = [0; 16];
+      stream.read_exact(&mut challenge)?;
+      stream.write_all(&vnc_auth_response(password, challenge))?;
+    }
+    _ => {
+      return Err(Error::unavailable(
+        "Server chose a security type we don't support",
+      ))
+    }
+  }
+
+  if stream.read_u32()? != 0 {
+    let reason = if minor >= 8 {
+      stream.read_string()?
+    } else {
+      "Authentication failed".to_owned()
+    };
+
+    return Err(Error::denied(reason));
+  }
+
+  Ok(())
+}
+
+/// Encrypts the challenge with DES, keyed by the first 8 bytes of the password with
+/// each byte's bits mirrored, as VNC has always done.
+pub(crate) fn vnc_auth_response(password: &str, mut challenge: [u8; 16]) -> [u8; 16] {
+  let mut key = [0; 8];
+  for (key, byte) in key.iter_mut().zip(password.bytes()) {
+    *key = byte.reverse_bits();
+  }
+
+  let cipher = Des::new(GenericArray::from_slice(&key));
+  for block in challenge.chunks_exact_mut(8) {
+    cipher.encrypt_block(GenericArray::from_mut_slice(block));
+  }
+
+  challenge
+}
+
+/// Updates handed over from the reader thread.
+#[derive(Debug, Default)]
+struct Shared {
+  data: Vec<u8>,
+  width: u32,
+  height: u32,
+  dirty_rects: Vec<Rect>,
+  updated: bool,
+  /// Whether a FramebufferUpdateRequest is waiting on an update.
+  requested: bool,
+  /// Whether the server hung up.
+  stopped: bool,
+  error: Option<io::Error>,
+}
+
+/// Captures a VNC server's framebuffer, from a display made with
+/// [`get_vnc_display`](crate::display::get_vnc_display).
+///
+/// A thread reads updates as they arrive. Each frame asks for the next, so the server
+/// only sends what changed since the frame before, and only within the region if
+/// there is one. It's idle until the server does, and stops once it ends the session.
+pub struct VncCapture {
+  stream: TcpStream,
+  /// The framebuffer's size when the capture opened.
+  size: (u32, u32),
+  region: Rect,
+  shared: Arc<Mutex<Shared>>,
+  /// Notified whenever the reader changes `shared`.
+  changed: Arc<Condvar>,
+  reader: Option<JoinHandle<()>>,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  clock: FrameClock,
+}
+
+impl VncCapture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let remote = opts
+      .display
+      .remote()
+      .ok_or_else(|| Error::invalid("Display is not a VNC server"))?;
+
+    let conn = Connection::open(remote.address(), remote.password())?;
+    let mut stream = conn.stream;
+
+    let mut pixel_format = vec![SET_PIXEL_FORMAT, 0, 0, 0];
+    pixel_format.extend_from_slice(&PIXEL_FORMAT);
+    stream.write_all(&pixel_format)?;
+
+    let mut encodings = vec![
+      encodings::TIGHT,
+      encodings::ZRLE,
+      encodings::HEXTILE,
+      encodings::COPY_RECT,
+      encodings::RAW,
+      encodings::DESKTOP_SIZE,
+    ];
+
+    // Servers that can send the cursor separately stop drawing it into the framebuffer.
+    if !opts.cursor {
+      encodings.push(encodings::CURSOR);
+    }
+
+    let mut message = vec![SET_ENCODINGS, 0];
+    message.extend_from_slice(&(encodings.len() as u16).to_be_bytes());
+    for encoding in encodings {
+      message.extend_from_slice(&encoding.to_be_bytes());
+    }
+
+    stream.write_all(&message)?;
+
+    let (width, height) = (conn.width as u32, conn.height as u32);
+    let region = opts.source_rect();
+    request_update(&mut stream, false, region)?;
+
+    let shared = Arc::new(Mutex::new(Shared {
+      width,
+      height,
+      requested: true,
+      ..Default::default()
+    }));
+
+    let changed = Arc::new(Condvar::new());
+
+    let reader = {
+      let stream = stream.try_clone()?;
+      let framebuffer = Framebuffer::new(width, height);
+      let (shared, changed) = (Arc::clone(&shared), Arc::clone(&changed));
+      spawn(move || read_updates(stream, framebuffer, shared, changed))
+    };
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Ok(Self {
+      stream,
+      size: (opts.display.width(), opts.display.height()),
+      region,
+      shared,
+      changed,
+      reader: Some(reader),
+      frame_time,
+      last_frame: None,
+      clock: FrameClock::new(),
+    })
+  }
+}
+
+impl Capture for VncCapture {
+  type Frame<'a> = VncFrame;
+
+  fn frame(&mut self) -> Result<Frame<VncFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let mut shared = self.shared.lock().unwrap();
+    if let Some(err) = &shared.error {
+      return Err(Error::disconnected(err.to_string()));
+    }
+
+    if shared.stopped {
+      return Ok(Frame::Stopped);
+    }
+
+    // The server may have resized the framebuffer since.
+    check_size(self.size, (shared.width, shared.height))?;
+    let region = clip_region(self.region, shared.width, shared.height)?;
+
+    if !shared.requested {
+      if let Err(err) = request_update(&mut self.stream, true, region) {
+        let error = Error::disconnected(err.to_string());
+        shared.error = Some(err);
+        return Err(error);
+      }
+
+      shared.requested = true;
+    }
+
+    // Servers hold incremental updates back until something changes.
+    if !shared.updated && self.last_frame.is_some() {
+      return Ok(Frame::Idle);
+    }
+
+    if !shared.updated {
+      return Ok(Frame::Blocking);
+    }
+
+    shared.updated = false;
+    self.last_frame = Some(Instant::now());
+
+    // Only the region's rows are copied, keeping the framebuffer's stride.
+    let (offset, info) = self
+      .clock
+      .next_bgrx(shared.width, shared.height)
+      .crop(region)?;
+    let len = info.stride * (info.height as usize - 1) + info.row_len();
+
+    Ok(Frame::Ready(VncFrame {
+      data: shared.data[offset..offset + len].to_vec(),
+      dirty_rects: crop_rects(std::mem::take(&mut shared.dirty_rects), region),
+      info,
+    }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    if let Some(remaining) = throttle_remaining(self.frame_time, self.last_frame) {
+      sleep(timeout.min(remaining));
+      return Ok(());
+    }
+
+    let shared = self.shared.lock().unwrap();
+    let _ = self.changed.wait_timeout_while(shared, timeout, |shared| {
+      !shared.updated && !shared.stopped && shared.error.is_none()
+    });
+
+    Ok(())
+  }
+}
+
+impl Drop for VncCapture {
+  fn drop(&mut self) {
+    let _ = self.stream.shutdown(Shutdown::Both);
+    if let Some(reader) = self.reader.take() {
+      let _ = reader.join();
+    }
+  }
+}
+
+/// Asks for an update of `rect`, or only of what changed in it if `incremental`.
+fn request_update(
+  stream: &mut TcpStream,
+  incremental: bool,
+  rect: Rect,
+) -> io::Result<()> {
+  let mut message = vec![FRAMEBUFFER_UPDATE_REQUEST, incremental as u8];
+  for value in [rect.x as u32, rect.y as u32, rect.width, rect.height] {
+    message.extend_from_slice(&(value as u16).to_be_bytes());
+  }
+
+  stream.write_all(&message)
+}
+
+/// Reads server messages until the connection fails or is shut down, handing each
+/// complete update over to `shared`.
+fn read_updates(
+  stream: TcpStream,
+  mut framebuffer: Framebuffer,
+  shared: Arc<Mutex<Shared>>,
+  changed: Arc<Condvar>,
+) {
+  let mut reader = BufReader::new(stream);
+  let mut decoder = Decoder::new();
+
+  let result = (|| -> io::Result<()> {
+    loop {
+      let kind = match reader.read_u8() {
+        Ok(kind) => kind,
+        // Hanging up between messages is how servers end the session.
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(err) => return Err(err),
+      };
+
+      match kind {
+        FRAMEBUFFER_UPDATE => {
+          reader.read_u8()?;
+
+          let mut dirty_rects = Vec::new();
+          for _ in 0..reader.read_u16()? {
+            let x = reader.read_u16()?;
+            let y = reader.read_u16()?;
+            let width = reader.read_u16()?;
+            let height = reader.read_u16()?;
+            let encoding = reader.read_u32()? as i32;
+
+            let rect = Rect::new(x as i32, y as i32, width as u32, height as u32);
+            if let Some(dirty) =
+              decoder.decode(&mut reader, &mut framebuffer, rect, encoding)?
+            {
+              dirty_rects.push(dirty);
+            }
+          }
+
+          let mut shared = shared.lock().unwrap();
+          shared.data.clone_from(&framebuffer.data);
+          shared.width = framebuffer.width;
+          shared.height = framebuffer.height;
+          shared.dirty_rects.extend(dirty_rects);
+          shared.updated = true;
+          shared.requested = false;
+          changed.notify_all();
+        }
+        SET_COLOUR_MAP_ENTRIES => {
+          reader.read_u8()?;
+          reader.read_u16()?;
+          let count = reader.read_u16()?;
+          reader.read_vec(count as usize * 6)?;
+        }
+        BELL => (),
+        SERVER_CUT_TEXT => {
+          reader.read_vec(3)?;
+          reader.read_string()?;
+        }
+        kind => {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown server message {}", kind),
+          ))
+        }
+      }
+    }
+  })();
+
+  let mut shared = shared.lock().unwrap();
+  match result {
+    Ok(()) => shared.stopped = true,
+    Err(err) => shared.error = Some(err),
+  }
+
+  changed.notify_all();
+}
+
+/// A copy of the remote framebuffer, whose size the server may change.
+#[derive(Debug)]
+pub struct VncFrame {
+  data: Vec<u8>,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for VncFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions the server updated since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for VncFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use crate::capture::vnc::{vnc_auth_response, VncCapture, VncFrame};
+  use crate::capture::{Capture, CaptureOpts, Frame, Rect, VideoFrame};
+  use crate::display::get_vnc_display;
+  use crate::vnc_server::VncServer;
+  use crate::Error;
+
+  fn wait_frame(capture: &mut VncCapture) -> VncFrame {
+    match capture.next_frame_timeout(Duration::from_secs(10)).unwrap() {
+      Frame::Ready(frame) => frame,
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_vnc_auth_response() {
+    // Keys are mirrored bit by bit, so an all-zero key is unaffected.
+    let response = vnc_auth_response("", [0; 16]);
+    assert_eq!(response[..8], response[8..]);
+    assert_eq!(
+      response[..8],
+      [0x8c, 0xa6, 0x4d, 0xe9, 0xc1, 0xb1, 0x23, 0xa7]
+    );
+
+    assert_ne!(vnc_auth_response("secret", [0; 16]), response);
+  }
+
+  #[test]
+  fn test_capture() {
+    let server = VncServer::start(32, 16, Some("secret"));
+    let display = get_vnc_display(server.address(), Some("secret")).unwrap();
+
+    assert_eq!(display.width(), 32);
+    assert_eq!(display.height(), 16);
+    assert_eq!(display.remote().unwrap().name(), "fun_capture test");
+
+    let mut capture = VncCapture::new(CaptureOpts::new(display)).unwrap();
+    let frame = wait_frame(&mut capture);
+
+    assert_eq!(frame.len(), 32 * 16 * 4);
+    assert_eq!(
+      (frame.width(), frame.height(), frame.stride()),
+      (32, 16, 32 * 4)
+    );
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 32, 16)]);
+    assert!(frame
+      .chunks(4)
+      .all(|pixel| pixel[..3] == [0x40, 0x40, 0x40]));
+
+    // The next, incremental, update only paints a corner.
+    let frame = wait_frame(&mut capture);
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 2, 2)]);
+    assert_eq!(frame[..4], [0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(frame[8..12], [0x40, 0x40, 0x40, 0]);
+
+    // Then nothing changes.
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+  }
+
+  #[test]
+  fn test_capture_region() {
+    let server = VncServer::start(32, 16, None);
+    let display = get_vnc_display(server.address(), None).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.region(Rect::new(1, 1, 8, 4)).unwrap();
+
+    let mut capture = VncCapture::new(opts).unwrap();
+    let frame = wait_frame(&mut capture);
+
+    assert_eq!((frame.width(), frame.height()), (8, 4));
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 8, 4)]);
+    assert_eq!(frame.rows().count(), 4);
+
+    // Only the part of the white corner within the region is dirty.
+    let frame = wait_frame(&mut capture);
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 1, 1)]);
+    assert_eq!(frame[..4], [0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(frame[4..8], [0x40, 0x40, 0x40, 0]);
+  }
+
+  #[test]
+  fn test_wrong_password() {
+    let server = VncServer::start(32, 16, Some("secret"));
+
+    let err = get_vnc_display(server.address(), Some("wrong"))
+      .err()
+      .unwrap();
+    assert!(matches!(err, Error::PermissionDenied(_)));
+
+    let err = get_vnc_display(server.address(), None).err().unwrap();
+    assert!(matches!(err, Error::PermissionDenied(_)));
+  }
+}
@@ -0,0 +1,638 @@
+//! Plumbing shared by the Wayland backends: connecting, tracking `wl_output`s and
+//! toplevels, and allocating `wl_shm` buffers for the compositor to copy into.
+
+use std::env;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsFd, AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use wayland_client::backend::WaylandError;
+use wayland_client::globals::{GlobalList, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{
+  event_created_child, Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::{
+  self, ExtForeignToplevelHandleV1,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::{
+  self, ExtForeignToplevelListV1,
+};
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::{
+  self, ZwlrOutputPowerV1,
+};
+
+use crate::capture::{wait_readable, FrameClock, FrameInfo, PixelFormat, Rect};
+use crate::ffi::linux::{
+  memfd_create, mmap, munmap, MAP_FAILED, MAP_SHARED, MFD_CLOEXEC,
+};
+use crate::ffi::linux::{PROT_READ, PROT_WRITE};
+use crate::{Error, Result};
+
+/// Connects to the compositor at `socket`, or `$WAYLAND_DISPLAY` when `None`.
+pub(crate) fn connect(socket: Option<&str>) -> Result<Connection> {
+  let socket = match socket {
+    Some(socket) => socket,
+    None => return Connection::connect_to_env().map_err(Error::unavailable),
+  };
+
+  let mut path = PathBuf::from(socket);
+  if path.is_relative() {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+      .ok_or_else(|| Error::unavailable("XDG_RUNTIME_DIR is not set"))?;
+
+    path = PathBuf::from(runtime_dir).join(path);
+  }
+
+  let stream = UnixStream::connect(path)?;
+  Connection::from_socket(stream).map_err(Error::unavailable)
+}
+
+/// Dispatches whatever events have already arrived, without blocking.
+pub(crate) fn dispatch_pending<S>(
+  queue: &mut EventQueue<S>,
+  state: &mut S,
+) -> Result<()> {
+  queue.flush().map_err(Error::disconnected)?;
+
+  if let Some(guard) = queue.prepare_read() {
+    match guard.read() {
+      Ok(_) => {}
+      Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+      Err(e) => return Err(Error::disconnected(e)),
+    }
+  }
+
+  queue.dispatch_pending(state).map_err(Error::disconnected)?;
+  Ok(())
+}
+
+/// Blocks until the compositor sends something or `timeout` passes, leaving the events
+/// for [`dispatch_pending`].
+pub(crate) fn wait_for_events<S>(
+  queue: &mut EventQueue<S>,
+  timeout: Duration,
+) -> Result<()> {
+  queue.flush().map_err(Error::disconnected)?;
+
+  // No guard means events have already been read and are waiting to be dispatched.
+  if let Some(guard) = queue.prepare_read() {
+    if wait_readable(guard.connection_fd().as_raw_fd(), timeout)? {
+      match guard.read() {
+        Ok(_) => {}
+        Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+        Err(e) => return Err(Error::disconnected(e)),
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// What a `wl_output` has told us about itself.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OutputInfo {
+  pub name: Option<String>,
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+  /// How many pixels make up a logical one each way, 0 until the output says.
+  pub scale: i32,
+  pub description: Option<String>,
+  pub make: Option<String>,
+  pub model: Option<String>,
+  /// In millimetres, 0 when it has no size, e.g. a projector.
+  pub physical_width: i32,
+  pub physical_height: i32,
+  pub transform: Option<wl_output::Transform>,
+  /// Of the current mode, in mHz.
+  pub refresh: i32,
+}
+
+impl OutputInfo {
+  /// Turns `rect` in pixels into the smallest logical rect covering it.
+  pub fn to_logical(&self, rect: Rect) -> Rect {
+    let scale = self.scale.max(1);
+    let (left, top) = (rect.x.div_euclid(scale), rect.y.div_euclid(scale));
+    let right = (rect.x + rect.width as i32 + scale - 1).div_euclid(scale);
+    let bottom = (rect.y + rect.height as i32 + scale - 1).div_euclid(scale);
+
+    Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+  }
+}
+
+pub(crate) struct Output {
+  pub global: u32,
+  pub output: WlOutput,
+  pub info: OutputInfo,
+}
+
+impl Output {
+  /// The output's `wl_output.name` (e.g. `DP-1`), stable across reconnects unlike its
+  /// global, which stands in for compositors that predate version 4.
+  pub fn name(&self) -> String {
+    match &self.info.name {
+      Some(name) => name.clone(),
+      None => format!("wl_output-{}", self.global),
+    }
+  }
+}
+
+/// Tracks the outputs bound from the registry, embedded in each backend's state.
+#[derive(Default)]
+pub(crate) struct Outputs {
+  pub outputs: Vec<Output>,
+}
+
+impl Outputs {
+  /// Binds every `wl_output` currently advertised.
+  pub fn bind_all<S>(&mut self, globals: &GlobalList, qh: &QueueHandle<S>)
+  where
+    S: Dispatch<WlOutput, u32> + 'static,
+  {
+    for global in globals.contents().clone_list() {
+      if global.interface != "wl_output" {
+        continue;
+      }
+
+      let output = globals.registry().bind::<WlOutput, _, _>(
+        global.name,
+        global.version.min(4),
+        qh,
+        global.name,
+      );
+
+      self.outputs.push(Output {
+        global: global.name,
+        output,
+        info: OutputInfo::default(),
+      });
+    }
+  }
+
+  pub fn find(&self, name: &str) -> Option<&Output> {
+    self.outputs.iter().find(|output| output.name() == name)
+  }
+
+  /// The output bound from `global`, if it's still around.
+  pub fn get(&self, global: u32) -> Option<&Output> {
+    self.outputs.iter().find(|output| output.global == global)
+  }
+
+  /// Whether the output bound from `global` is still around.
+  pub fn contains(&self, global: u32) -> bool {
+    self.outputs.iter().any(|output| output.global == global)
+  }
+}
+
+impl<S> Dispatch<WlRegistry, GlobalListContents, S> for Outputs
+where
+  S: Dispatch<WlRegistry, GlobalListContents> + AsMut<Outputs>,
+{
+  fn event(
+    state: &mut S,
+    _: &WlRegistry,
+    event: wl_registry::Event,
+    _: &GlobalListContents,
+    _: &Connection,
+    _: &QueueHandle<S>,
+  ) {
+    // New outputs aren't bound after the fact, but ones we hold may go away.
+    if let wl_registry::Event::GlobalRemove { name } = event {
+      let outputs = &mut state.as_mut().outputs;
+      if let Some(index) = outputs.iter().position(|output| output.global == name) {
+        let output = outputs.remove(index);
+        if output.output.version() >= 3 {
+          output.output.release();
+        }
+      }
+    }
+  }
+}
+
+impl<S> Dispatch<WlOutput, u32, S> for Outputs
+where
+  S: Dispatch<WlOutput, u32> + AsMut<Outputs>,
+{
+  fn event(
+    state: &mut S,
+    _: &WlOutput,
+    event: wl_output::Event,
+    global: &u32,
+    _: &Connection,
+    _: &QueueHandle<S>,
+  ) {
+    let output = match state
+      .as_mut()
+      .outputs
+      .iter_mut()
+      .find(|output| output.global == *global)
+    {
+      Some(output) => output,
+      None => return,
+    };
+
+    match event {
+      wl_output::Event::Geometry {
+        x,
+        y,
+        physical_width,
+        physical_height,
+        make,
+        model,
+        transform,
+        ..
+      } => {
+        output.info.x = x;
+        output.info.y = y;
+        output.info.physical_width = physical_width;
+        output.info.physical_height = physical_height;
+        output.info.make = Some(make);
+        output.info.model = Some(model);
+        output.info.transform = transform.into_result().ok();
+      }
+      wl_output::Event::Mode {
+        flags,
+        width,
+        height,
+        refresh,
+      } => {
+        let current = flags
+          .into_result()
+          .is_ok_and(|flags| flags.contains(wl_output::Mode::Current));
+
+        if current {
+          output.info.width = width as u32;
+          output.info.height = height as u32;
+          output.info.refresh = refresh;
+        }
+      }
+      wl_output::Event::Scale { factor } => output.info.scale = factor,
+      wl_output::Event::Name { name } => output.info.name = Some(name),
+      wl_output::Event::Description { description } => {
+        output.info.description = Some(description)
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Whether an output is switched on, according to `zwlr_output_power_manager_v1`.
+pub(crate) struct OutputPower {
+  power: ZwlrOutputPowerV1,
+  on: Arc<AtomicBool>,
+}
+
+impl OutputPower {
+  /// Starts tracking `output`, `None` meaning the compositor doesn't say.
+  pub fn new<S>(
+    globals: &GlobalList,
+    qh: &QueueHandle<S>,
+    output: &WlOutput,
+  ) -> Option<Self>
+  where
+    S: Dispatch<ZwlrOutputPowerManagerV1, ()>
+      + Dispatch<ZwlrOutputPowerV1, Arc<AtomicBool>>
+      + 'static,
+  {
+    let manager = globals
+      .bind::<ZwlrOutputPowerManagerV1, _, _>(qh, 1..=1, ())
+      .ok()?;
+
+    let on = Arc::new(AtomicBool::new(true));
+    let power = manager.get_output_power(output, qh, on.clone());
+
+    // Objects the manager created stay valid without it.
+    manager.destroy();
+
+    Some(Self { power, on })
+  }
+
+  pub fn is_on(&self) -> bool {
+    self.on.load(Ordering::Relaxed)
+  }
+}
+
+impl Drop for OutputPower {
+  fn drop(&mut self) {
+    self.power.destroy();
+  }
+}
+
+impl<S> Dispatch<ZwlrOutputPowerV1, Arc<AtomicBool>, S> for OutputPower
+where
+  S: Dispatch<ZwlrOutputPowerV1, Arc<AtomicBool>>,
+{
+  fn event(
+    _: &mut S,
+    _: &ZwlrOutputPowerV1,
+    event: zwlr_output_power_v1::Event,
+    on: &Arc<AtomicBool>,
+    _: &Connection,
+    _: &QueueHandle<S>,
+  ) {
+    let is_on = match event {
+      zwlr_output_power_v1::Event::Mode { mode } => {
+        mode != WEnum::Value(zwlr_output_power_v1::Mode::Off)
+      }
+      // Another client manages the output's power, or it's gone, so we can't tell.
+      _ => true,
+    };
+
+    on.store(is_on, Ordering::Relaxed);
+  }
+}
+
+/// A window listed by `ext_foreign_toplevel_list_v1`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Toplevel {
+  identifier: String,
+  title: String,
+  app_id: String,
+}
+
+impl Toplevel {
+  /// Unique to this toplevel and never reused, unlike its title.
+  pub fn identifier(&self) -> &str {
+    &self.identifier
+  }
+
+  pub fn title(&self) -> &str {
+    &self.title
+  }
+
+  pub fn app_id(&self) -> &str {
+    &self.app_id
+  }
+}
+
+pub(crate) struct ForeignToplevel {
+  pub handle: ExtForeignToplevelHandleV1,
+  pub info: Toplevel,
+  /// Set once the compositor has sent the first `done`, as before then the toplevel's
+  /// details may be missing.
+  pub done: bool,
+}
+
+/// Tracks the toplevels announced by `ext_foreign_toplevel_list_v1`, embedded in each
+/// backend's state.
+#[derive(Default)]
+pub(crate) struct Toplevels {
+  pub toplevels: Vec<ForeignToplevel>,
+}
+
+impl Toplevels {
+  pub fn find(&self, identifier: &str) -> Option<&ForeignToplevel> {
+    self
+      .toplevels
+      .iter()
+      .find(|toplevel| toplevel.done && toplevel.info.identifier == identifier)
+  }
+
+  pub fn list(&self) -> Vec<Toplevel> {
+    self
+      .toplevels
+      .iter()
+      .filter(|toplevel| toplevel.done)
+      .map(|toplevel| toplevel.info.clone())
+      .collect()
+  }
+}
+
+impl<S> Dispatch<ExtForeignToplevelListV1, (), S> for Toplevels
+where
+  S: Dispatch<ExtForeignToplevelListV1, ()>
+    + Dispatch<ExtForeignToplevelHandleV1, ()>
+    + AsMut<Toplevels>
+    + 'static,
+{
+  fn event(
+    state: &mut S,
+    _: &ExtForeignToplevelListV1,
+    event: ext_foreign_toplevel_list_v1::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<S>,
+  ) {
+    if let ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } = event {
+      state.as_mut().toplevels.push(ForeignToplevel {
+        handle: toplevel,
+        info: Toplevel::default(),
+        done: false,
+      });
+    }
+  }
+
+  event_created_child!(S, ExtForeignToplevelListV1, [
+    ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ()),
+  ]);
+}
+
+impl<S> Dispatch<ExtForeignToplevelHandleV1, (), S> for Toplevels
+where
+  S: Dispatch<ExtForeignToplevelHandleV1, ()> + AsMut<Toplevels>,
+{
+  fn event(
+    state: &mut S,
+    handle: &ExtForeignToplevelHandleV1,
+    event: ext_foreign_toplevel_handle_v1::Event,
+    _: &(),
+    _: &Connection,
+    _: &QueueHandle<S>,
+  ) {
+    let toplevels = &mut state.as_mut().toplevels;
+    let index = match toplevels
+      .iter()
+      .position(|toplevel| &toplevel.handle == handle)
+    {
+      Some(index) => index,
+      None => return,
+    };
+
+    let toplevel = &mut toplevels[index];
+    match event {
+      ext_foreign_toplevel_handle_v1::Event::Identifier { identifier } => {
+        toplevel.info.identifier = identifier
+      }
+      ext_foreign_toplevel_handle_v1::Event::Title { title } => {
+        toplevel.info.title = title
+      }
+      ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+        toplevel.info.app_id = app_id
+      }
+      ext_foreign_toplevel_handle_v1::Event::Done => toplevel.done = true,
+      ext_foreign_toplevel_handle_v1::Event::Closed => {
+        toplevels.remove(index).handle.destroy();
+      }
+      _ => {}
+    }
+  }
+}
+
+/// The layout of a `wl_shm` format, whose names spell out little-endian words.
+pub(crate) fn pixel_format(format: wl_shm::Format) -> Option<PixelFormat> {
+  match format {
+    wl_shm::Format::Xrgb8888 => Some(PixelFormat::Bgrx8888),
+    wl_shm::Format::Argb8888 => Some(PixelFormat::Bgra8888),
+    wl_shm::Format::Xbgr8888 => Some(PixelFormat::Rgbx8888),
+    wl_shm::Format::Abgr8888 => Some(PixelFormat::Rgba8888),
+    wl_shm::Format::Rgb565 => Some(PixelFormat::Rgb565),
+    _ => None,
+  }
+}
+
+/// A `wl_buffer` backed by a memfd we keep mapped to read the compositor's copy.
+pub(crate) struct ShmBuffer {
+  pub buffer: WlBuffer,
+  pub format: wl_shm::Format,
+  pub width: u32,
+  pub height: u32,
+  pub stride: u32,
+  pixel_format: PixelFormat,
+  pool: WlShmPool,
+  data: *mut u8,
+  len: usize,
+}
+
+// The proxies are thread-safe, and the mapping is only written through `&mut self` or
+// by the compositor while a copy into it is pending, when no frame holds it.
+unsafe impl Send for ShmBuffer {}
+unsafe impl Sync for ShmBuffer {}
+
+impl ShmBuffer {
+  pub fn new<S>(
+    shm: &WlShm,
+    qh: &QueueHandle<S>,
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+  ) -> Result<Self>
+  where
+    S: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+  {
+    let pixel_format = pixel_format(format)
+      .ok_or_else(|| Error::unsupported("Unsupported pixel format"))?;
+
+    let len = stride as usize * height as usize;
+    let name = CString::new("fun_capture").unwrap();
+
+    let fd = unsafe { memfd_create(name.as_ptr(), MFD_CLOEXEC) };
+    if fd < 0 {
+      return Err(Error::last_os_error());
+    }
+
+    let file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(len as u64)?;
+
+    let data =
+      unsafe { mmap(null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0) };
+
+    if data == MAP_FAILED {
+      return Err(Error::last_os_error());
+    }
+
+    // The compositor keeps its own reference to the memfd, so ours can close here.
+    let pool = shm.create_pool(file.as_fd(), len as i32, qh, ());
+    let buffer = pool.create_buffer(
+      0,
+      width as i32,
+      height as i32,
+      stride as i32,
+      format,
+      qh,
+      (),
+    );
+
+    Ok(Self {
+      buffer,
+      format,
+      width,
+      height,
+      stride,
+      pixel_format,
+      pool,
+      data: data as *mut u8,
+      len,
+    })
+  }
+
+  pub fn matches(
+    &self,
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+  ) -> bool {
+    self.format == format
+      && self.width == width
+      && self.height == height
+      && self.stride == stride
+  }
+
+  /// Describes the buffer's contents as the next frame from `clock`.
+  pub fn info(&self, clock: &mut FrameClock) -> FrameInfo {
+    clock.next(
+      self.width,
+      self.height,
+      self.stride as usize,
+      self.pixel_format,
+    )
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.data, self.len) }
+  }
+
+  pub fn bytes_mut(&mut self) -> &mut [u8] {
+    unsafe { from_raw_parts_mut(self.data, self.len) }
+  }
+
+  /// Flips the rows in place, for compositors that hand back y-inverted frames.
+  pub fn flip(&mut self) {
+    let stride = self.stride as usize;
+    let height = self.height as usize;
+    let bytes = self.bytes_mut();
+
+    for row in 0..height / 2 {
+      let (top, bottom) = bytes.split_at_mut((height - row - 1) * stride);
+      top[row * stride..(row + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+    }
+  }
+}
+
+impl Drop for ShmBuffer {
+  fn drop(&mut self) {
+    self.buffer.destroy();
+    self.pool.destroy();
+
+    unsafe {
+      munmap(self.data as *mut _, self.len);
+    }
+  }
+}
+
+impl std::fmt::Debug for ShmBuffer {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ShmBuffer")
+      .field("format", &self.format)
+      .field("width", &self.width)
+      .field("height", &self.height)
+      .field("stride", &self.stride)
+      .finish()
+  }
+}
@@ -1,100 +1,222 @@
 use std::ffi::c_void;
-use std::mem::{size_of, transmute};
+use std::mem::size_of;
+use std::ops::Deref;
 use std::ptr::null_mut;
+use std::slice::from_raw_parts;
 
-use winapi::shared::windef::{HBITMAP__, HDC__};
+use winapi::shared::minwindef::TRUE;
+use winapi::shared::windef::{HBITMAP__, HDC__, HMONITOR, RECT};
 use winapi::um::wingdi::{
   BitBlt, CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, SelectObject,
   BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS, SRCCOPY,
 };
-use winapi::um::winuser::{GetDC, ReleaseDC};
+use winapi::um::winuser::{
+  GetDC, GetMonitorInfoW, MonitorFromRect, ReleaseDC, MONITORINFO, MONITOR_DEFAULTTONULL,
+};
 
-use crate::capture::{Capture, CaptureOpts, Frame};
-use crate::display::Display;
-use std::io::Error;
-use winapi::_core::ptr::null;
+use crate::capture::{
+  check_size, Capture, CaptureOpts, Frame, FrameClock, FrameInfo, Rect, VideoFrame,
+};
+use crate::{Error, Result};
 
+/// Copies a display out of the screen DC with GDI, stopping once no monitor is left
+/// where the display was and failing once the monitor there is laid out differently.
+/// GDI can't tell whether anything changed or the display is off.
+///
+/// Only the region, if any, is copied.
 pub struct DisplayContextCapture {
   x: i32,
   y: i32,
   width: u32,
   height: u32,
+  region: Rect,
+  stopped: bool,
+  /// Synthetic displays have no monitor of their own to compare with.
+  synthetic: bool,
 
   hdc: *mut HDC__,
   bmp: *mut HBITMAP__,
   bmp_old: *mut c_void,
+  /// The DIB section's pixels, which GDI owns until the bitmap is deleted.
+  bits: *const u8,
+  clock: FrameClock,
 }
 
+// Memory DCs and DIB sections aren't tied to the thread that made them, and the screen
+// DC is got and released within each frame.
+unsafe impl Send for DisplayContextCapture {}
+
 impl DisplayContextCapture {
-  pub fn new(opts: CaptureOpts) -> Self {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
     unsafe {
       let hdc = CreateCompatibleDC(null_mut());
-      let bmp = Self::create_bitmap(&opts.display, hdc);
+      if hdc.is_null() {
+        return Err(Error::last_os_error());
+      }
+
+      let region = opts.source_rect();
+      let (bmp, bits) = match Self::create_bitmap(region.width, region.height, hdc) {
+        Ok(bitmap) => bitmap,
+        Err(err) => {
+          DeleteDC(hdc);
+          return Err(err);
+        }
+      };
+
       let bmp_old = SelectObject(hdc, bmp as *mut c_void);
 
-      Self {
+      Ok(Self {
         x: opts.display.x(),
         y: opts.display.y(),
         width: opts.display.width(),
         height: opts.display.height(),
+        region,
+        stopped: false,
+        synthetic: opts.display.is_synthetic(),
 
         hdc,
         bmp,
         bmp_old,
-      }
+        bits,
+        clock: FrameClock::new(),
+      })
     }
   }
 
-  unsafe fn create_bitmap(display: &Display, hdc: *mut HDC__) -> *mut HBITMAP__ {
+  unsafe fn create_bitmap(
+    width: u32,
+    height: u32,
+    hdc: *mut HDC__,
+  ) -> Result<(*mut HBITMAP__, *const u8)> {
     let mut info = BITMAPINFO::default();
-    let mut info_header = &mut info.bmiHeader;
+    let info_header = &mut info.bmiHeader;
 
     info_header.biSize = size_of::<BITMAPINFOHEADER>() as u32;
     info_header.biBitCount = 32;
-    info_header.biWidth = display.width() as i32;
-    info_header.biHeight = display.height() as i32;
+    info_header.biWidth = width as i32;
+    // Negative for rows top to bottom, like every other backend.
+    info_header.biHeight = -(height as i32);
     info_header.biPlanes = 1;
 
-    let bitmap = CreateDIBSection(
-      hdc,
-      &info,
-      DIB_RGB_COLORS,
-      // holy unsafe batman
-      null_mut(),
-      null_mut(),
-      0,
-    );
+    let mut bits = null_mut();
+    let bitmap = CreateDIBSection(hdc, &info, DIB_RGB_COLORS, &mut bits, null_mut(), 0);
 
     if bitmap.is_null() {
-      panic!("Failed to create bitmap: {:?}", Error::last_os_error());
+      return Err(Error::last_os_error());
+    }
+
+    Ok((bitmap, bits as *const u8))
+  }
+
+  /// The monitor most of the display is on now, null if there's none.
+  fn monitor(&self) -> HMONITOR {
+    let rect = RECT {
+      left: self.x,
+      top: self.y,
+      right: self.x + self.width as i32,
+      bottom: self.y + self.height as i32,
+    };
+
+    unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONULL) }
+  }
+
+  /// Fails unless `monitor` still has the display's bounds.
+  fn check_bounds(&self, monitor: HMONITOR) -> Result<()> {
+    let mut info = MONITORINFO {
+      cbSize: size_of::<MONITORINFO>() as u32,
+      ..MONITORINFO::default()
+    };
+    if unsafe { GetMonitorInfoW(monitor, &mut info) } != TRUE {
+      return Err(Error::last_os_error());
+    }
+
+    // Displays cover the whole monitor, taskbar included.
+    let bounds = info.rcMonitor;
+    let size = (
+      (bounds.right - bounds.left) as u32,
+      (bounds.bottom - bounds.top) as u32,
+    );
+    check_size((self.width, self.height), size)?;
+    if (bounds.left, bounds.top) != (self.x, self.y) {
+      return Err(Error::changed(format!(
+        "Display moved from {},{} to {},{}",
+        self.x, self.y, bounds.left, bounds.top
+      )));
     }
 
-    bitmap
+    Ok(())
   }
 }
 
 impl Capture for DisplayContextCapture {
-  fn frame(&mut self) -> Frame {
+  type Frame<'a> = DisplayContextFrame<'a>;
+
+  fn frame(&mut self) -> Result<Frame<DisplayContextFrame<'_>>> {
+    let monitor = self.monitor();
+    if self.stopped || monitor.is_null() {
+      self.stopped = true;
+      return Ok(Frame::Stopped);
+    }
+
+    if !self.synthetic {
+      self.check_bounds(monitor)?;
+    }
+
     unsafe {
       let hdc = self.hdc;
       let hdc_target = GetDC(null_mut());
+      if hdc_target.is_null() {
+        return Err(Error::last_os_error());
+      }
 
-      BitBlt(
+      let copied = BitBlt(
         hdc,
         0,
         0,
-        self.width as i32,
-        self.height as i32,
+        self.region.width as i32,
+        self.region.height as i32,
         hdc_target,
-        self.x,
-        self.y,
+        self.x + self.region.x,
+        self.y + self.region.y,
         SRCCOPY,
       );
 
       ReleaseDC(null_mut(), hdc_target);
+
+      // Fails while e.g. the secure desktop is showing, which passes.
+      if copied == 0 {
+        return Ok(Frame::Blocking);
+      }
     }
 
-    Frame::Blocking
+    let (width, height) = (self.region.width, self.region.height);
+    let len = width as usize * height as usize * 4;
+
+    Ok(Frame::Ready(DisplayContextFrame {
+      data: unsafe { from_raw_parts(self.bits, len) },
+      info: self.clock.next_bgrx(width, height),
+    }))
+  }
+}
+
+/// The capture's DIB section as BGRX, borrowed until the next frame.
+#[derive(Debug)]
+pub struct DisplayContextFrame<'a> {
+  data: &'a [u8],
+  info: FrameInfo,
+}
+
+impl VideoFrame for DisplayContextFrame<'_> {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for DisplayContextFrame<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.data
   }
 }
 
@@ -0,0 +1,522 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_shm::{self, WlShm};
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::{delegate_dispatch, delegate_noop};
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::{
+  self, ZwlrScreencopyFrameV1,
+};
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
+use wayland_protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::ZwlrOutputPowerV1;
+use wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1;
+
+use crate::capture::wayland::{
+  connect, dispatch_pending, pixel_format, wait_for_events, OutputPower, Outputs,
+  ShmBuffer,
+};
+use crate::capture::{
+  check_size, crop_rects, is_throttled, throttle_remaining, Capture, CaptureOpts, Frame,
+  FrameClock, FrameInfo, Rect, VideoFrame,
+};
+use crate::display::Source;
+use crate::{Error, Result};
+
+/// Captures a Wayland output through wlroots' `zwlr_screencopy_manager_v1`, stopping
+/// once the output is unplugged.
+///
+/// A region is copied by the compositor, rounded out to whole logical pixels on scaled
+/// outputs, and whatever the rounding added is cropped off again.
+pub struct WlrScreencopyCapture {
+  queue: EventQueue<State>,
+  state: State,
+  manager: ZwlrScreencopyManagerV1,
+  output: WlOutput,
+  global: u32,
+  power: Option<OutputPower>,
+  /// The output's mode when the capture opened.
+  size: (u32, u32),
+  region: Option<Rect>,
+  cursor: bool,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  _conn: Connection,
+}
+
+impl WlrScreencopyCapture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let (socket, name) = match opts.display.source() {
+      Source::Wayland { socket, name } => (socket.as_deref(), name),
+      _ => return Err(Error::invalid("Display is not a Wayland output")),
+    };
+
+    let conn = connect(socket)?;
+    let (globals, mut queue) =
+      registry_queue_init::<State>(&conn).map_err(Error::disconnected)?;
+    let qh = queue.handle();
+
+    let manager = globals
+      .bind::<ZwlrScreencopyManagerV1, _, _>(&qh, 1..=3, ())
+      .map_err(Error::unavailable)?;
+
+    let shm = globals
+      .bind::<WlShm, _, _>(&qh, 1..=1, ())
+      .map_err(Error::unavailable)?;
+
+    let mut state = State {
+      outputs: Outputs::default(),
+      shm,
+      buffers: Vec::new(),
+      buffer_count: opts.frame_queue.max(1) as usize,
+      request: None,
+      clock: FrameClock::new(),
+    };
+
+    state.outputs.bind_all(&globals, &qh);
+    queue.roundtrip(&mut state).map_err(Error::disconnected)?;
+
+    let (output, global) = match state.outputs.find(name) {
+      Some(output) => (output.output.clone(), output.global),
+      None => return Err(Error::unavailable(format!("Output {} not found", name))),
+    };
+
+    let power = OutputPower::new(&globals, &qh, &output);
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    Ok(Self {
+      queue,
+      state,
+      manager,
+      output,
+      global,
+      power,
+      size: (opts.display.width(), opts.display.height()),
+      region: opts.region,
+      cursor: opts.cursor,
+      frame_time,
+      last_frame: None,
+      _conn: conn,
+    })
+  }
+
+  /// Asks the compositor for the next frame, which it fills in asynchronously.
+  fn request(&mut self) {
+    let qh = self.queue.handle();
+    let info = self
+      .state
+      .outputs
+      .get(self.global)
+      .map(|output| &output.info);
+
+    let (frame, crop) = match (self.region, info) {
+      (Some(region), Some(info)) => {
+        let (logical, scale) = (info.to_logical(region), info.scale.max(1));
+        let frame = self.manager.capture_output_region(
+          self.cursor as i32,
+          &self.output,
+          logical.x,
+          logical.y,
+          logical.width as i32,
+          logical.height as i32,
+          &qh,
+          (),
+        );
+
+        let crop = Rect::new(
+          region.x - logical.x * scale,
+          region.y - logical.y * scale,
+          region.width,
+          region.height,
+        );
+
+        (frame, Some(crop))
+      }
+      _ => (
+        self
+          .manager
+          .capture_output(self.cursor as i32, &self.output, &qh, ()),
+        None,
+      ),
+    };
+
+    self.state.request = Some(Request {
+      frame,
+      crop,
+      format: None,
+      buffer: None,
+      dirty_rects: Vec::new(),
+      y_invert: false,
+      status: Status::Negotiating,
+    });
+  }
+
+  fn poll(&mut self) -> Result<Frame<WlrScreencopyFrame>> {
+    if !self.state.outputs.contains(self.global) {
+      return Ok(Frame::Stopped);
+    }
+
+    if self.state.request.is_none() {
+      self.request();
+    }
+
+    dispatch_pending(&mut self.queue, &mut self.state)?;
+
+    if let Some(output) = self.state.outputs.get(self.global) {
+      check_size(self.size, (output.info.width, output.info.height))?;
+    }
+
+    if self.power.as_ref().is_some_and(|power| !power.is_on()) {
+      return Ok(Frame::Blank);
+    }
+
+    let status = self.state.request.as_ref().map(|request| request.status);
+    match status {
+      Some(Status::WaitingForBuffer) => {
+        self.state.copy(&self.queue.handle())?;
+        Ok(Frame::Blocking)
+      }
+      Some(Status::Ready) => {
+        let request = self.state.request.take().unwrap();
+        request.frame.destroy();
+
+        let frame = self.state.finish(request);
+
+        // Start on the next frame straight away, so it's likely done by the next call.
+        self.request();
+        self.queue.flush().map_err(Error::disconnected)?;
+
+        Ok(frame.map_or(Frame::Blocking, Frame::Ready))
+      }
+      Some(Status::Failed) => {
+        let request = self.state.request.take().unwrap();
+        request.frame.destroy();
+
+        // Copies fail e.g. while the output changes mode, so ask again next time.
+        Ok(Frame::Blocking)
+      }
+      // Version 2 only copies once something changed, so a wait means nothing did.
+      Some(Status::Copying)
+        if self.last_frame.is_some() && self.manager.version() >= 2 =>
+      {
+        Ok(Frame::Idle)
+      }
+      _ => Ok(Frame::Blocking),
+    }
+  }
+}
+
+impl Capture for WlrScreencopyCapture {
+  type Frame<'a> = WlrScreencopyFrame;
+
+  fn frame(&mut self) -> Result<Frame<WlrScreencopyFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.poll()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    match throttle_remaining(self.frame_time, self.last_frame) {
+      Some(remaining) => {
+        sleep(timeout.min(remaining));
+        Ok(())
+      }
+      None => wait_for_events(&mut self.queue, timeout),
+    }
+  }
+}
+
+impl Drop for WlrScreencopyCapture {
+  fn drop(&mut self) {
+    if let Some(request) = self.state.request.take() {
+      request.frame.destroy();
+    }
+
+    self.manager.destroy();
+  }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Status {
+  /// Waiting for the compositor to describe the buffer it wants.
+  Negotiating,
+  /// Every buffer is still held by a frame.
+  WaitingForBuffer,
+  /// The compositor is copying into our buffer.
+  Copying,
+  Ready,
+  Failed,
+}
+
+struct Request {
+  frame: ZwlrScreencopyFrameV1,
+  /// The part of the buffer that was asked for, if not all of it.
+  crop: Option<Rect>,
+  format: Option<(wl_shm::Format, u32, u32, u32)>,
+  buffer: Option<Arc<ShmBuffer>>,
+  dirty_rects: Vec<Rect>,
+  y_invert: bool,
+  status: Status,
+}
+
+struct State {
+  outputs: Outputs,
+  shm: WlShm,
+  buffers: Vec<Arc<ShmBuffer>>,
+  buffer_count: usize,
+  request: Option<Request>,
+  clock: FrameClock,
+}
+
+impl State {
+  /// Copies the pending request into a free buffer, allocating one if the pool has room.
+  fn copy(&mut self, qh: &QueueHandle<State>) -> Result<()> {
+    let request = match &mut self.request {
+      Some(request) => request,
+      None => return Ok(()),
+    };
+
+    let (format, width, height, stride) = match request.format {
+      Some(format) => format,
+      None => {
+        request.status = Status::Failed;
+        return Ok(());
+      }
+    };
+
+    // Buffers sized for a previous mode are useless now.
+    self.buffers.retain(|buffer| {
+      Arc::strong_count(buffer) > 1 || buffer.matches(format, width, height, stride)
+    });
+
+    let free = self.buffers.iter().find(|buffer| {
+      Arc::strong_count(buffer) == 1 && buffer.matches(format, width, height, stride)
+    });
+
+    let buffer = match free {
+      Some(buffer) => buffer.clone(),
+      None if self.buffers.len() < self.buffer_count => {
+        let buffer = Arc::new(ShmBuffer::new(
+          &self.shm, qh, format, width, height, stride,
+        )?);
+
+        self.buffers.push(buffer.clone());
+        buffer
+      }
+      None => {
+        request.status = Status::WaitingForBuffer;
+        return Ok(());
+      }
+    };
+
+    // Version 2 waits for damage before copying, so unchanged outputs cost nothing.
+    if request.frame.version() >= 2 {
+      request.frame.copy_with_damage(&buffer.buffer);
+    } else {
+      request.frame.copy(&buffer.buffer);
+    }
+
+    request.buffer = Some(buffer);
+    request.status = Status::Copying;
+
+    Ok(())
+  }
+
+  /// Turns a completed request into a frame, flipping it upright if need be.
+  fn finish(&mut self, request: Request) -> Option<WlrScreencopyFrame> {
+    let mut buffer = request.buffer?;
+    let mut dirty_rects = request.dirty_rects;
+
+    if request.y_invert {
+      // Drop the pool's reference while flipping, so the frame's is unique.
+      self.buffers.retain(|pooled| !Arc::ptr_eq(pooled, &buffer));
+      if let Some(buffer) = Arc::get_mut(&mut buffer) {
+        buffer.flip();
+      }
+
+      self.buffers.push(buffer.clone());
+
+      for rect in &mut dirty_rects {
+        rect.y = buffer.height as i32 - rect.y - rect.height as i32;
+      }
+    }
+
+    let info = buffer.info(&mut self.clock);
+    let (offset, info) = match request.crop {
+      Some(crop) => {
+        dirty_rects = crop_rects(dirty_rects, crop);
+        info.crop(crop).ok()?
+      }
+      None => (0, info),
+    };
+
+    Some(WlrScreencopyFrame {
+      buffer,
+      offset,
+      dirty_rects,
+      info,
+    })
+  }
+}
+
+impl AsMut<Outputs> for State {
+  fn as_mut(&mut self) -> &mut Outputs {
+    &mut self.outputs
+  }
+}
+
+delegate_dispatch!(State: [WlRegistry: GlobalListContents] => Outputs);
+delegate_dispatch!(State: [WlOutput: u32] => Outputs);
+delegate_dispatch!(State: [ZwlrOutputPowerV1: Arc<AtomicBool>] => OutputPower);
+delegate_noop!(State: ZwlrOutputPowerManagerV1);
+delegate_noop!(State: ignore WlShm);
+delegate_noop!(State: ignore WlShmPool);
+delegate_noop!(State: ignore WlBuffer);
+delegate_noop!(State: ZwlrScreencopyManagerV1);
+
+impl Dispatch<ZwlrScreencopyFrameV1, ()> for State {
+  fn event(
+    state: &mut Self,
+    frame: &ZwlrScreencopyFrameV1,
+    event: zwlr_screencopy_frame_v1::Event,
+    _: &(),
+    _: &Connection,
+    qh: &QueueHandle<Self>,
+  ) {
+    let request = match &mut state.request {
+      Some(request) if &request.frame == frame => request,
+      _ => return,
+    };
+
+    match event {
+      zwlr_screencopy_frame_v1::Event::Buffer {
+        format,
+        width,
+        height,
+        stride,
+      } => {
+        // Without a format we can describe, the request fails for want of a buffer.
+        if let Some(format) = format
+          .into_result()
+          .ok()
+          .filter(|format| pixel_format(*format).is_some())
+        {
+          request.format = Some((format, width, height, stride));
+        }
+
+        // Before version 3 there is exactly one buffer event and no `buffer_done`.
+        if frame.version() < 3 {
+          let _ = state.copy(qh);
+        }
+      }
+      zwlr_screencopy_frame_v1::Event::BufferDone => {
+        let _ = state.copy(qh);
+      }
+      zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+        request.y_invert = flags
+          .into_result()
+          .is_ok_and(|flags| flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert));
+      }
+      zwlr_screencopy_frame_v1::Event::Damage {
+        x,
+        y,
+        width,
+        height,
+      } => {
+        request
+          .dirty_rects
+          .push(Rect::new(x as i32, y as i32, width, height));
+      }
+      zwlr_screencopy_frame_v1::Event::Ready { .. } => request.status = Status::Ready,
+      zwlr_screencopy_frame_v1::Event::Failed => request.status = Status::Failed,
+      _ => {}
+    }
+  }
+}
+
+/// A frame copied into one of the capture's shm buffers, in the `wl_shm` format the
+/// compositor picked, which is handed back to the pool once dropped.
+#[derive(Debug)]
+pub struct WlrScreencopyFrame {
+  buffer: Arc<ShmBuffer>,
+  /// Where the frame starts in the buffer, past whatever was cropped off.
+  offset: usize,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for WlrScreencopyFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for WlrScreencopyFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.buffer.bytes()[self.offset..]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::capture::wlr_screencopy::WlrScreencopyCapture;
+  use crate::capture::{Capture, CaptureOpts, Frame, VideoFrame};
+  use crate::display::get_wayland_displays_on;
+  use crate::sway::Sway;
+  use std::time::{Duration, Instant};
+
+  #[test]
+  fn test_capture() {
+    let sway = Sway::start();
+    let display = get_wayland_displays_on(sway.socket())
+      .unwrap()
+      .into_iter()
+      .next()
+      .unwrap();
+
+    let (width, height) = (display.width() as usize, display.height() as usize);
+    let mut capture = WlrScreencopyCapture::new(CaptureOpts::new(display)).unwrap();
+
+    let started = Instant::now();
+    let frame = loop {
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        break frame;
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "No frame captured"
+      );
+    };
+
+    assert!(frame.len() >= width * height * 4);
+    assert!(!frame.dirty_rects().is_empty());
+  }
+}
@@ -0,0 +1,1068 @@
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::ops::Deref;
+use std::os::raw::{c_char, c_int};
+use std::ptr::{null, null_mut};
+use std::rc::Rc;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::{
+  check_size, crop_rects, is_throttled, poll_interval, throttle_remaining, wait_readable,
+};
+use crate::capture::{
+  Capture, CaptureOpts, Frame, FrameClock, FrameInfo, PixelFormat, Rect, VideoFrame,
+};
+use crate::display::{x11_config_time, x11_screen_displays, Source};
+use crate::ffi::linux::{shmat, shmctl, shmdt, shmget, IPC_CREAT, IPC_PRIVATE, IPC_RMID};
+use crate::ffi::x11::{
+  AllPlanes, DPMSCapable, DPMSInfo, DPMSModeOn, DPMSQueryExtension, Damage, Drawable,
+  False, LSBFirst, Time, Window, XCloseDisplay, XConnectionNumber, XDamageCreate,
+  XDamageDestroy, XDamageNotify, XDamageQueryExtension, XDamageQueryVersion,
+  XDamageReportNonEmpty, XDamageSubtract, XDefaultDepth, XDefaultVisual, XDestroyImage,
+  XDisplay, XErrorEvent, XErrorHandler, XEvent, XFixesCreateRegion, XFixesDestroyRegion,
+  XFixesFetchRegion, XFixesGetCursorImage, XFixesQueryExtension, XFixesQueryVersion,
+  XFree, XGetImage, XImage, XNextEvent, XOpenDisplay, XPending, XRootWindow,
+  XSetErrorHandler, XShmAttach, XShmCreateImage, XShmDetach, XShmGetImage,
+  XShmQueryExtension, XShmSegmentInfo, XSync, XserverRegion, ZPixmap,
+};
+use crate::{Error, Result};
+
+/// The connections with requests being checked, see [`Connection::checked`].
+static CHECKS: Mutex<Checks> = Mutex::new(Checks {
+  previous: None,
+  pending: Vec::new(),
+});
+
+struct Checks {
+  /// The error handler to put back once no connection is being checked.
+  previous: XErrorHandler,
+  /// Each connection being checked, by address, and the first error it got.
+  pending: Vec<(usize, Option<u8>)>,
+}
+
+/// Xlib's default error handler exits the process, so errors on connections being
+/// checked are recorded for them instead. Any other connection's go to the handler
+/// there was before.
+unsafe extern "C" fn error_handler(display: XDisplay, event: *mut XErrorEvent) -> c_int {
+  let previous = {
+    let mut checks = CHECKS.lock().unwrap();
+    let pending = checks
+      .pending
+      .iter_mut()
+      .find(|(raw, _)| *raw == display as usize);
+    match pending {
+      Some((_, error)) => {
+        error.get_or_insert((*event).error_code);
+        return 0;
+      }
+      None => checks.previous,
+    }
+  };
+
+  match previous {
+    Some(previous) => previous(display, event),
+    None => 0,
+  }
+}
+
+/// An owned Xlib connection.
+#[derive(Debug)]
+pub(crate) struct Connection {
+  raw: XDisplay,
+  server: Option<String>,
+}
+
+impl Connection {
+  /// Connects to `server`, or to `$DISPLAY` when `None`.
+  pub fn open(server: Option<&str>) -> Result<Self> {
+    let name = match server {
+      Some(server) => Some(CString::new(server).map_err(Error::invalid)?),
+      None => None,
+    };
+
+    let raw = unsafe { XOpenDisplay(name.as_ref().map_or(null(), |name| name.as_ptr())) };
+    if raw.is_null() {
+      return Err(Error::unavailable(format!(
+        "Failed to open X display {}",
+        server.unwrap_or("$DISPLAY")
+      )));
+    }
+
+    Ok(Self {
+      raw,
+      server: server.map(ToOwned::to_owned),
+    })
+  }
+
+  pub fn raw(&self) -> XDisplay {
+    self.raw
+  }
+
+  pub fn server(&self) -> Option<&str> {
+    self.server.as_deref()
+  }
+
+  /// Makes `requests` and round-trips to the server, failing with the first X error
+  /// they raised. Xlib's error handler is only replaced until then, so requests that
+  /// may fail belong in here: errors outside of it go to the application's handler,
+  /// which by default exits.
+  pub fn checked<T>(&self, requests: impl FnOnce(XDisplay) -> T) -> Result<T> {
+    let key = self.raw as usize;
+    {
+      let mut checks = CHECKS.lock().unwrap();
+      if checks.pending.is_empty() {
+        checks.previous = unsafe { XSetErrorHandler(Some(error_handler)) };
+      }
+      checks.pending.push((key, None));
+    }
+
+    let result = requests(self.raw);
+    unsafe { XSync(self.raw, False) };
+
+    let error = {
+      let mut checks = CHECKS.lock().unwrap();
+      let i = checks.pending.iter().position(|(raw, _)| *raw == key);
+      let (_, error) = checks.pending.swap_remove(i.unwrap());
+      if checks.pending.is_empty() {
+        unsafe { XSetErrorHandler(checks.previous) };
+      }
+
+      error
+    };
+
+    match error {
+      Some(code) => Err(Error::Backend {
+        backend: "x11",
+        code: code as i64,
+      }),
+      None => Ok(result),
+    }
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    unsafe { XCloseDisplay(self.raw) };
+  }
+}
+
+pub struct X11Capture {
+  conn: Rc<Connection>,
+  root: Window,
+  monitor: Monitor,
+  x: i32,
+  y: i32,
+  width: u32,
+  height: u32,
+  cursor: bool,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  shm: Option<ShmPool>,
+  damage: Option<DamageTracker>,
+  dpms: bool,
+  clock: FrameClock,
+}
+
+// The connection is shared only between the capture's own parts and, without
+// `XInitThreads`, is only ever used by whichever thread has the capture. Frames hold no
+// reference to it, see `ShmPool`.
+unsafe impl Send for X11Capture {}
+
+impl X11Capture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    Self::open(opts, true)
+  }
+
+  /// Like [`X11Capture::new`], but always reads frames back with `XGetImage`.
+  pub fn new_without_shm(opts: CaptureOpts) -> Result<Self> {
+    Self::open(opts, false)
+  }
+
+  fn open(opts: CaptureOpts, shm: bool) -> Result<Self> {
+    let display = &opts.display;
+    let (server, screen) = match display.source() {
+      Source::X11 { server, screen } => (server.as_deref(), *screen),
+      _ => return Err(Error::invalid("Display is not an X11 screen")),
+    };
+
+    let conn = Rc::new(Connection::open(server)?);
+    let root = unsafe { XRootWindow(conn.raw(), screen) };
+
+    let cursor = opts.cursor
+      && unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        XFixesQueryExtension(conn.raw(), &mut event_base, &mut error_base) != False
+      };
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    // A monitor is part of its screen's root window, so a region is read out of the
+    // root window offset by where the monitor is.
+    let region = opts.source_rect();
+    let (x, y) = (display.x() + region.x, display.y() + region.y);
+
+    // Remote servers advertise MIT-SHM but refuse to attach, so any failure here just
+    // leaves us on the `XGetImage` path.
+    let shm = if shm {
+      let count = opts.frame_queue.max(1) as usize;
+      ShmPool::new(&conn, screen, region.width, region.height, count).ok()
+    } else {
+      None
+    };
+
+    // Without XDamage every call reads the whole screen back, as if it all changed.
+    let damage = DamageTracker::new(&conn, root).ok();
+
+    let dpms = unsafe {
+      let (mut event_base, mut error_base) = (0, 0);
+      DPMSQueryExtension(conn.raw(), &mut event_base, &mut error_base) != False
+        && DPMSCapable(conn.raw()) != False
+    };
+
+    let monitor = Monitor {
+      screen,
+      id: display.info().id,
+      bounds: Rect::new(display.x(), display.y(), display.width(), display.height()),
+      config: x11_config_time(&conn, screen),
+      stopped: false,
+    };
+
+    Ok(Self {
+      root,
+      monitor,
+      x,
+      y,
+      width: region.width,
+      height: region.height,
+      cursor,
+      frame_time,
+      last_frame: None,
+      shm,
+      damage,
+      dpms,
+      clock: FrameClock::new(),
+      conn,
+    })
+  }
+
+  /// Whether frames are read back through MIT-SHM rather than `XGetImage`.
+  pub fn is_shm(&self) -> bool {
+    self.shm.is_some()
+  }
+
+  /// Whether changes are tracked with XDamage, rather than reading every frame back.
+  pub fn is_damage_tracked(&self) -> bool {
+    self.damage.is_some()
+  }
+
+  /// Whether DPMS has put the monitors in standby, suspended them or turned them off.
+  fn is_blank(&self) -> bool {
+    if !self.dpms {
+      return false;
+    }
+
+    let (mut level, mut enabled) = (DPMSModeOn, 0);
+    let status = unsafe { DPMSInfo(self.conn.raw(), &mut level, &mut enabled) };
+    status != 0 && enabled != 0 && level != DPMSModeOn
+  }
+
+  /// Gets the regions changed since the last frame, `None` meaning nothing changed.
+  fn get_dirty_rects(&mut self) -> Result<Option<Vec<Rect>>> {
+    let full = Rect::new(0, 0, self.width, self.height);
+    let damage = match &mut self.damage {
+      Some(damage) => damage,
+      None => return Ok(Some(vec![full])),
+    };
+
+    if self.last_frame.is_none() {
+      damage.take()?;
+      return Ok(Some(vec![full]));
+    }
+
+    if !damage.poll() {
+      return Ok(None);
+    }
+
+    let region = Rect::new(self.x, self.y, self.width, self.height);
+    let rects = crop_rects(damage.take()?, region);
+
+    // Whatever changed was outside the region.
+    Ok((!rects.is_empty()).then_some(rects))
+  }
+
+  /// Reads the next frame, `None` meaning every SHM segment is still held by a frame.
+  fn get_frame(&mut self) -> Result<Option<FrameInner>> {
+    if let Some(pool) = &mut self.shm {
+      let shm = match pool.acquire() {
+        Some(shm) => shm,
+        None => return Ok(None),
+      };
+
+      let (root, x, y, image) = (self.root, self.x, self.y, shm.image.raw);
+      let captured = self.conn.checked(|raw| unsafe {
+        XShmGetImage(raw, root, image, x, y, AllPlanes) != False
+      });
+
+      if let Ok(true) = captured {
+        if let (true, Some(shm_mut)) = (self.cursor, Arc::get_mut(shm)) {
+          draw_cursor(&self.conn, (self.x, self.y), &mut shm_mut.image);
+        }
+
+        return Ok(Some(FrameInner::Shm(shm.clone())));
+      }
+
+      self.shm = None;
+    }
+
+    let mut image = self.get_image()?;
+    if self.cursor {
+      draw_cursor(&self.conn, (self.x, self.y), &mut image);
+    }
+
+    Ok(Some(FrameInner::Image(image)))
+  }
+
+  fn get_image(&self) -> Result<Image> {
+    Image::get(
+      &self.conn,
+      self.root,
+      self.x,
+      self.y,
+      self.width,
+      self.height,
+    )
+  }
+}
+
+/// Blends the current cursor sprite over `image`, X never includes it in read backs.
+pub(crate) fn draw_cursor(conn: &Connection, offset: (i32, i32), image: &mut Image) {
+  if image.bits_per_pixel() != 32 {
+    return;
+  }
+
+  let cursor = unsafe { XFixesGetCursorImage(conn.raw()) };
+  if cursor.is_null() {
+    return;
+  }
+
+  unsafe {
+    let cursor_ref = &*cursor;
+    let pixels = from_raw_parts(
+      cursor_ref.pixels,
+      cursor_ref.width as usize * cursor_ref.height as usize,
+    );
+
+    let (stride, size) = (image.stride(), image.size());
+    blend_cursor(
+      image.bytes_mut(),
+      stride,
+      size,
+      pixels.iter().map(|pixel| *pixel as u32),
+      cursor_ref.width as i32,
+      (
+        cursor_ref.x as i32 - cursor_ref.xhot as i32 - offset.0,
+        cursor_ref.y as i32 - cursor_ref.yhot as i32 - offset.1,
+      ),
+    );
+
+    XFree(cursor as *mut _);
+  }
+}
+
+/// Alpha-blends premultiplied ARGB `cursor` pixels, `cursor_width` wide, into a BGRX
+/// `buffer` at `origin`, clipping against `size`.
+fn blend_cursor(
+  buffer: &mut [u8],
+  stride: usize,
+  size: (i32, i32),
+  cursor: impl Iterator<Item = u32>,
+  cursor_width: i32,
+  origin: (i32, i32),
+) {
+  for (i, argb) in cursor.enumerate() {
+    let x = origin.0 + i as i32 % cursor_width;
+    let y = origin.1 + i as i32 / cursor_width;
+    if x < 0 || y < 0 || x >= size.0 || y >= size.1 {
+      continue;
+    }
+
+    let alpha = argb >> 24;
+    if alpha == 0 {
+      continue;
+    }
+
+    let offset = y as usize * stride + x as usize * 4;
+    let pixel = &mut buffer[offset..offset + 3];
+    for (channel, dst) in pixel.iter_mut().enumerate() {
+      let src = (argb >> (channel * 8)) & 0xff;
+      *dst = (src + *dst as u32 * (255 - alpha) / 255) as u8;
+    }
+  }
+}
+
+impl Capture for X11Capture {
+  type Frame<'a> = X11Frame;
+
+  fn frame(&mut self) -> Result<Frame<X11Frame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    if !self.monitor.check(&self.conn)? {
+      return Ok(Frame::Stopped);
+    }
+
+    if self.is_blank() {
+      return Ok(Frame::Blank);
+    }
+
+    // Damage is only taken once there's somewhere to put the frame, so that it isn't
+    // lost while every SHM segment is still held.
+    if let Some(pool) = &mut self.shm {
+      if pool.acquire().is_none() {
+        return Ok(Frame::Blocking);
+      }
+    }
+
+    let dirty_rects = match self.get_dirty_rects()? {
+      Some(dirty_rects) => dirty_rects,
+      None => return Ok(Frame::Idle),
+    };
+
+    let inner = match self.get_frame()? {
+      Some(inner) => inner,
+      None => return Ok(Frame::Blocking),
+    };
+
+    let info = inner.image().info(&mut self.clock)?;
+    self.last_frame = Some(Instant::now());
+
+    Ok(Frame::Ready(X11Frame {
+      inner,
+      dirty_rects,
+      info,
+    }))
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    // Damage only helps once there's a frame to compare to and somewhere to put the next.
+    let waitable = self.last_frame.is_some()
+      && throttle_remaining(self.frame_time, self.last_frame).is_none()
+      && !self.is_blank()
+      && self
+        .shm
+        .as_mut()
+        .is_none_or(|pool| pool.acquire().is_some());
+
+    match &mut self.damage {
+      Some(damage) if waitable => damage.wait(timeout),
+      _ => {
+        sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+        Ok(())
+      }
+    }
+  }
+}
+
+/// The RandR monitor a capture is of, to notice it being unplugged or rearranged.
+struct Monitor {
+  screen: i32,
+  id: Option<String>,
+  bounds: Rect,
+  /// When RandR last changed the screen, `None` without RandR.
+  config: Option<(Time, Time)>,
+  stopped: bool,
+}
+
+impl Monitor {
+  /// Whether the monitor is still there, failing if it's been moved or resized. The
+  /// monitors are only listed again once RandR says something changed.
+  fn check(&mut self, conn: &Connection) -> Result<bool> {
+    if self.stopped || self.config.is_none() {
+      return Ok(!self.stopped);
+    }
+
+    let config = x11_config_time(conn, self.screen);
+    if config == self.config {
+      return Ok(true);
+    }
+
+    let display = x11_screen_displays(conn, self.screen)
+      .into_iter()
+      .find(|display| display.info().id == self.id);
+    let display = match display {
+      Some(display) => display,
+      None => {
+        self.stopped = true;
+        return Ok(false);
+      }
+    };
+
+    let bounds = Rect::new(display.x(), display.y(), display.width(), display.height());
+    check_size(
+      (self.bounds.width, self.bounds.height),
+      (bounds.width, bounds.height),
+    )?;
+    if (bounds.x, bounds.y) != (self.bounds.x, self.bounds.y) {
+      return Err(Error::changed(format!(
+        "Display moved from {},{} to {},{}",
+        self.bounds.x, self.bounds.y, bounds.x, bounds.y
+      )));
+    }
+
+    // Not moved on after a failure, so the capture keeps failing.
+    self.config = config;
+    Ok(true)
+  }
+}
+
+/// An owned `XImage`.
+#[derive(Debug)]
+pub(crate) struct Image {
+  raw: *mut XImage,
+}
+
+impl Image {
+  /// Reads back a `width` by `height` area of `drawable` at `x`, `y`.
+  pub fn get(
+    conn: &Connection,
+    drawable: Drawable,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+  ) -> Result<Self> {
+    let image = conn.checked(|raw| unsafe {
+      XGetImage(raw, drawable, x, y, width, height, AllPlanes, ZPixmap)
+    })?;
+
+    if image.is_null() {
+      return Err(Error::other("XGetImage returned no image"));
+    }
+
+    Ok(Self { raw: image })
+  }
+
+  /// How the pixels are laid out, if it's a format frames can describe.
+  pub fn format(&self) -> Option<PixelFormat> {
+    let image = unsafe { &*self.raw };
+    if image.byte_order != LSBFirst {
+      return None;
+    }
+
+    let masks = (image.red_mask, image.green_mask, image.blue_mask);
+    match (image.bits_per_pixel, image.depth, masks) {
+      (32, 32, (0xff_0000, 0xff00, 0xff)) => Some(PixelFormat::Bgra8888),
+      (32, _, (0xff_0000, 0xff00, 0xff)) => Some(PixelFormat::Bgrx8888),
+      (32, 32, (0xff, 0xff00, 0xff_0000)) => Some(PixelFormat::Rgba8888),
+      (32, _, (0xff, 0xff00, 0xff_0000)) => Some(PixelFormat::Rgbx8888),
+      (16, _, (0xf800, 0x7e0, 0x1f)) => Some(PixelFormat::Rgb565),
+      _ => None,
+    }
+  }
+
+  /// Describes the image as the next frame from `clock`.
+  pub fn info(&self, clock: &mut FrameClock) -> Result<FrameInfo> {
+    let format = self
+      .format()
+      .ok_or_else(|| Error::unsupported("Unsupported pixel format"))?;
+
+    let (width, height) = self.size();
+    Ok(clock.next(width as u32, height as u32, self.stride(), format))
+  }
+
+  pub fn bits_per_pixel(&self) -> i32 {
+    unsafe { (*self.raw).bits_per_pixel }
+  }
+
+  pub fn stride(&self) -> usize {
+    unsafe { (*self.raw).bytes_per_line as usize }
+  }
+
+  pub fn size(&self) -> (i32, i32) {
+    unsafe { ((*self.raw).width, (*self.raw).height) }
+  }
+
+  pub fn len(&self) -> usize {
+    self.stride() * self.size().1 as usize
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts((*self.raw).data as *const u8, self.len()) }
+  }
+
+  pub fn bytes_mut(&mut self) -> &mut [u8] {
+    unsafe { from_raw_parts_mut((*self.raw).data as *mut u8, self.len()) }
+  }
+}
+
+// `XDestroyImage` only frees client memory, so an image can go wherever its pixels do.
+unsafe impl Send for Image {}
+unsafe impl Sync for Image {}
+
+impl Drop for Image {
+  fn drop(&mut self) {
+    unsafe {
+      XDestroyImage(self.raw);
+    }
+  }
+}
+
+/// An `XImage` whose pixels live in a SysV shared memory segment attached to the server.
+///
+/// The server side is detached by the [`ShmPool`], so dropping one never touches the
+/// connection and a frame holding it can be dropped on any thread.
+#[derive(Debug)]
+struct ShmImage {
+  image: Image,
+  info: Box<XShmSegmentInfo>,
+}
+
+impl ShmImage {
+  fn new(conn: &Connection, screen: i32, width: u32, height: u32) -> Result<Self> {
+    let raw = conn.raw();
+    let mut info = Box::new(XShmSegmentInfo {
+      shmseg: 0,
+      shmid: -1,
+      shmaddr: null_mut(),
+      read_only: False,
+    });
+
+    unsafe {
+      let image = XShmCreateImage(
+        raw,
+        XDefaultVisual(raw, screen),
+        XDefaultDepth(raw, screen) as u32,
+        ZPixmap,
+        null_mut(),
+        &mut *info,
+        width,
+        height,
+      );
+
+      if image.is_null() {
+        return Err(Error::unavailable("XShmCreateImage returned no image"));
+      }
+
+      let image = Image { raw: image };
+
+      info.shmid = shmget(IPC_PRIVATE, image.len(), IPC_CREAT | 0o600);
+      if info.shmid < 0 {
+        return Err(Error::last_os_error());
+      }
+
+      info.shmaddr = shmat(info.shmid, null(), 0) as *mut c_char;
+      if info.shmaddr as isize == -1 {
+        let error = Error::last_os_error();
+        shmctl(info.shmid, IPC_RMID, null_mut());
+        return Err(error);
+      }
+
+      (*image.raw).data = info.shmaddr;
+
+      // Marking the segment for removal straight away means the kernel frees it once
+      // both we and the server detach, even if we never get to `Drop`.
+      let attached = conn.checked(|raw| XShmAttach(raw, &mut *info) != False);
+      shmctl(info.shmid, IPC_RMID, null_mut());
+
+      match attached {
+        Ok(true) => Ok(Self { image, info }),
+        attached => {
+          shmdt(info.shmaddr as *const _);
+          Err(
+            attached
+              .err()
+              .unwrap_or_else(|| Error::unavailable("XShmAttach failed")),
+          )
+        }
+      }
+    }
+  }
+}
+
+// Only the mapping is left to clean up, which any thread can do.
+unsafe impl Send for ShmImage {}
+unsafe impl Sync for ShmImage {}
+
+impl Drop for ShmImage {
+  fn drop(&mut self) {
+    unsafe {
+      shmdt(self.info.shmaddr as *const _);
+    }
+  }
+}
+
+/// A fixed set of SHM images, reused once the frames borrowing them are dropped.
+struct ShmPool {
+  conn: Rc<Connection>,
+  images: Vec<Arc<ShmImage>>,
+}
+
+impl ShmPool {
+  fn new(
+    conn: &Rc<Connection>,
+    screen: i32,
+    width: u32,
+    height: u32,
+    count: usize,
+  ) -> Result<Self> {
+    if unsafe { XShmQueryExtension(conn.raw()) } == False {
+      return Err(Error::unavailable("MIT-SHM is not available"));
+    }
+
+    // Built up in place, so the images made before a failure are detached again.
+    let mut pool = Self {
+      conn: conn.clone(),
+      images: Vec::with_capacity(count),
+    };
+
+    for _ in 0..count {
+      let image = ShmImage::new(conn, screen, width, height)?;
+      pool.images.push(Arc::new(image));
+    }
+
+    Ok(pool)
+  }
+
+  /// Gets an image no frame is currently borrowing.
+  fn acquire(&mut self) -> Option<&mut Arc<ShmImage>> {
+    self
+      .images
+      .iter_mut()
+      .find(|image| Arc::strong_count(image) == 1)
+  }
+}
+
+impl Drop for ShmPool {
+  fn drop(&mut self) {
+    // Frames still holding an image keep the pixels mapped until they're dropped too.
+    let _ = self.conn.checked(|raw| unsafe {
+      for image in &self.images {
+        XShmDetach(raw, &*image.info as *const _ as *mut _);
+      }
+    });
+  }
+}
+
+/// Reports changes to a window through the XDamage extension.
+pub(crate) struct DamageTracker {
+  conn: Rc<Connection>,
+  damage: Damage,
+  region: XserverRegion,
+  notify_event: c_int,
+  damaged: bool,
+}
+
+impl DamageTracker {
+  pub fn new(conn: &Rc<Connection>, window: Window) -> Result<Self> {
+    let raw = conn.raw();
+    let (mut event_base, mut error_base) = (0, 0);
+
+    unsafe {
+      if XDamageQueryExtension(raw, &mut event_base, &mut error_base) == False {
+        return Err(Error::unavailable("XDamage is not available"));
+      }
+
+      // Both extensions refuse requests from clients that never negotiated a version.
+      let (mut major, mut minor) = (1, 1);
+      XDamageQueryVersion(raw, &mut major, &mut minor);
+      let (mut major, mut minor) = (2, 0);
+      XFixesQueryVersion(raw, &mut major, &mut minor);
+
+      // Made inside the check, so whatever was created is destroyed again on failure.
+      conn.checked(|raw| Self {
+        conn: conn.clone(),
+        damage: XDamageCreate(raw, window, XDamageReportNonEmpty),
+        region: XFixesCreateRegion(raw, null_mut(), 0),
+        notify_event: event_base + XDamageNotify,
+        damaged: false,
+      })
+    }
+  }
+
+  /// Drains pending events, returning whether damage was reported since the last `take`.
+  pub fn poll(&mut self) -> bool {
+    let raw = self.conn.raw();
+    let mut event = XEvent::default();
+
+    while unsafe { XPending(raw) } > 0 {
+      unsafe { XNextEvent(raw, &mut event) };
+      if event.kind == self.notify_event {
+        self.damaged = true;
+      }
+    }
+
+    self.damaged
+  }
+
+  /// Blocks until damage or any other event is reported, or `timeout` passes.
+  pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+    if !self.poll() {
+      wait_readable(unsafe { XConnectionNumber(self.conn.raw()) }, timeout)?;
+    }
+
+    Ok(())
+  }
+
+  /// Takes the damaged rectangles, in window coordinates, reported so far.
+  pub fn take(&mut self) -> Result<Vec<Rect>> {
+    let (damage, region) = (self.damage, self.region);
+    let mut count = 0;
+
+    let rects = self.conn.checked(|raw| unsafe {
+      XDamageSubtract(raw, damage, 0, region);
+      XFixesFetchRegion(raw, region, &mut count)
+    })?;
+    self.damaged = false;
+
+    if rects.is_null() {
+      return Ok(Vec::new());
+    }
+
+    let dirty_rects = unsafe { from_raw_parts(rects, count as usize) }
+      .iter()
+      .map(|rect| {
+        Rect::new(
+          rect.x as i32,
+          rect.y as i32,
+          rect.width as u32,
+          rect.height as u32,
+        )
+      })
+      .collect();
+
+    unsafe { XFree(rects as *mut _) };
+
+    Ok(dirty_rects)
+  }
+}
+
+impl Drop for DamageTracker {
+  fn drop(&mut self) {
+    let _ = self.conn.checked(|raw| unsafe {
+      XDamageDestroy(raw, self.damage);
+      XFixesDestroyRegion(raw, self.region);
+    });
+  }
+}
+
+/// A frame in the server's native `ZPixmap` layout, either read back with `XGetImage`
+/// or borrowed from the capture's MIT-SHM pool until dropped.
+#[derive(Debug)]
+pub struct X11Frame {
+  inner: FrameInner,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for X11Frame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+#[derive(Debug)]
+enum FrameInner {
+  Image(Image),
+  Shm(Arc<ShmImage>),
+}
+
+impl FrameInner {
+  fn image(&self) -> &Image {
+    match self {
+      FrameInner::Image(image) => image,
+      FrameInner::Shm(shm) => &shm.image,
+    }
+  }
+}
+
+impl Deref for X11Frame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.inner.image().bytes()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{blend_cursor, Connection};
+  use crate::capture::x11::{X11Capture, X11Frame};
+  use crate::capture::{Capture, CaptureOpts, Frame, PixelFormat, Rect, VideoFrame};
+  use crate::display::get_primary_on;
+  use crate::ffi::x11::{XClearWindow, XRootWindow, XSetWindowBackground};
+  use crate::xvfb::Xvfb;
+  use std::time::{Duration, Instant};
+
+  /// Paints the root window of screen 0 of `server` white.
+  fn paint_white(server: &str) {
+    let painter = Connection::open(Some(server)).unwrap();
+    painter
+      .checked(|raw| unsafe {
+        let root = XRootWindow(raw, 0);
+        XSetWindowBackground(raw, root, 0x00ff_ffff);
+        XClearWindow(raw, root);
+      })
+      .unwrap();
+  }
+
+  /// Takes frames until one is ready, failing after 5 seconds.
+  fn wait_ready(capture: &mut X11Capture) -> X11Frame {
+    let started = Instant::now();
+    loop {
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        return frame;
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "No damage reported"
+      );
+    }
+  }
+
+  #[test]
+  fn test_capture() {
+    let xvfb = Xvfb::start_with(&["64x48x24"], &["-wr"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.cursor(false);
+
+    let mut capture = X11Capture::new(opts).unwrap();
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!(frame.len(), 64 * 48 * 4);
+        assert_eq!((frame.width(), frame.height()), (64, 48));
+        assert_eq!(frame.format(), PixelFormat::Bgrx8888);
+        assert!(frame
+          .rows()
+          .all(|row| row.chunks(4).all(|pixel| pixel[..3] == [0xff, 0xff, 0xff])));
+      }
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_capture_region() {
+    let xvfb = Xvfb::start_with(&["64x48x24"], &["-wr"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.cursor(false).region(Rect::new(8, 4, 16, 8)).unwrap();
+
+    let mut capture = X11Capture::new(opts).unwrap();
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (16, 8));
+        assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 16, 8)]);
+        assert!(frame
+          .rows()
+          .all(|row| row.chunks(4).all(|pixel| pixel[..3] == [0xff, 0xff, 0xff])));
+      }
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_capture_frame_rate() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.frame_rate(1.0);
+
+    let mut capture = X11Capture::new(opts).unwrap();
+    assert!(matches!(capture.frame().unwrap(), Frame::Ready(_)));
+    assert!(matches!(capture.frame().unwrap(), Frame::Blocking));
+  }
+
+  #[test]
+  fn test_capture_shm() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.frame_queue(1);
+
+    let mut capture = X11Capture::new(opts).unwrap();
+    assert!(capture.is_shm());
+
+    let frame = match capture.frame().unwrap() {
+      Frame::Ready(frame) => frame,
+      _ => panic!("Expected a frame"),
+    };
+
+    assert_eq!(frame.len(), 64 * 48 * 4);
+
+    // Nothing changed, so there's no need for a segment.
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+
+    // The only segment is still borrowed by `frame`, and the damage waits for it.
+    paint_white(xvfb.name());
+    assert!(matches!(capture.frame().unwrap(), Frame::Blocking));
+    drop(frame);
+
+    let frame = wait_ready(&mut capture);
+    assert!(!frame.dirty_rects().is_empty());
+  }
+
+  #[test]
+  fn test_capture_shm_fallback() {
+    let xvfb = Xvfb::start_with(&["64x48x24"], &["-extension", "MIT-SHM"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+
+    let mut capture = X11Capture::new(CaptureOpts::new(display)).unwrap();
+    assert!(!capture.is_shm());
+    assert!(
+      matches!(capture.frame().unwrap(), Frame::Ready(frame) if frame.len() == 64 * 48 * 4)
+    );
+  }
+
+  #[test]
+  fn test_capture_damage() {
+    let xvfb = Xvfb::start_with(&["64x48x24"], &["-br"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.cursor(false);
+
+    let mut capture = X11Capture::new(opts).unwrap();
+    assert!(capture.is_damage_tracked());
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 64, 48)]),
+      _ => panic!("Expected a frame"),
+    }
+
+    // Nothing has been drawn since the first frame.
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+
+    paint_white(xvfb.name());
+    let frame = wait_ready(&mut capture);
+
+    assert!(!frame.dirty_rects().is_empty());
+    assert!(frame
+      .chunks(4)
+      .all(|pixel| pixel[..3] == [0xff, 0xff, 0xff]));
+  }
+
+  #[test]
+  fn test_blend_cursor() {
+    let mut buffer = vec![0x10; 4 * 4 * 4];
+    let cursor = [0xff00_00ff, 0x8000_0080, 0x0000_0000, 0xff_ff_ff_ff];
+
+    blend_cursor(&mut buffer, 16, (4, 4), cursor.iter().copied(), 2, (3, 3));
+
+    // Only the top-left cursor pixel lands inside the buffer.
+    assert_eq!(&buffer[60..64], &[0xff, 0x00, 0x00, 0x10]);
+    assert!(buffer[..60].iter().all(|&b| b == 0x10));
+  }
+}
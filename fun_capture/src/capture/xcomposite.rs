@@ -0,0 +1,758 @@
+//! Per-window capture through the X Composite extension, which keeps a redirected
+//! window's contents in an offscreen pixmap even while other windows cover it.
+
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::ops::Deref;
+use std::os::raw::{c_int, c_long, c_uchar, c_ulong};
+use std::ptr::null_mut;
+use std::rc::Rc;
+use std::slice::from_raw_parts;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::x11::{draw_cursor, Connection, DamageTracker, Image};
+use crate::capture::{is_throttled, poll_interval};
+use crate::capture::{
+  Capture, CaptureOpts, Frame, FrameClock, FrameInfo, Rect, VideoFrame,
+};
+use crate::display::{Display, Source};
+use crate::ffi::x11::{
+  AnyPropertyType, Atom, BadDrawable, BadMatch, BadWindow, CompositeRedirectAutomatic,
+  False, InputOutput, IsViewable, Pixmap, StructureNotifyMask, Success, True, Window,
+  XCompositeNameWindowPixmap, XCompositeQueryExtension, XCompositeQueryVersion,
+  XCompositeRedirectWindow, XCompositeUnredirectWindow, XFixesQueryExtension, XFree,
+  XFreePixmap, XGetWindowAttributes, XGetWindowProperty, XInternAtom, XQueryTree,
+  XRootWindow, XSelectInput, XTranslateCoordinates, XWindowAttributes, XA_CARDINAL,
+  XA_WINDOW, XA_WM_CLASS, XA_WM_NAME,
+};
+use crate::{Error, Result};
+
+/// Far more than any title or client list needs, in 32-bit units.
+const MAX_PROPERTY_LEN: c_long = 1 << 20;
+
+/// A top-level window, as listed by [`get_windows`].
+#[derive(Debug, Clone)]
+pub struct X11Window {
+  id: u64,
+  title: String,
+  class: String,
+  pid: Option<u32>,
+  geometry: Rect,
+}
+
+impl X11Window {
+  /// The window's XID, for [`XCompositeCapture::new`].
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn title(&self) -> &str {
+    &self.title
+  }
+
+  /// The application class from `WM_CLASS`, e.g. `Firefox`.
+  pub fn class(&self) -> &str {
+    &self.class
+  }
+
+  /// The process that owns the window, if it set `_NET_WM_PID`.
+  pub fn pid(&self) -> Option<u32> {
+    self.pid
+  }
+
+  /// Where the window is on its screen, without the border or any decorations.
+  pub fn geometry(&self) -> Rect {
+    self.geometry
+  }
+}
+
+/// Captures a single window, resizing frames along with it. It's blank while the window
+/// is unmapped and stops once it's destroyed.
+pub struct XCompositeCapture {
+  conn: Rc<Connection>,
+  root: Window,
+  window: Window,
+  cursor: bool,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  pixmap: Option<WindowPixmap>,
+  damage: Option<DamageTracker>,
+  clock: FrameClock,
+}
+
+// As with `X11Capture`, the connection never leaves the capture's own parts.
+unsafe impl Send for XCompositeCapture {}
+
+impl XCompositeCapture {
+  /// Captures the window with the given id, as listed by [`get_windows`], on the X
+  /// server `opts.display` belongs to.
+  ///
+  /// A region would be relative to a display the window isn't tied to, so none may be
+  /// given.
+  pub fn new(opts: CaptureOpts, window: u64) -> Result<Self> {
+    if opts.region.is_some() {
+      return Err(Error::invalid("Window captures can't take a region"));
+    }
+
+    let (server, screen) = x11_source(&opts.display)?;
+    let conn = Rc::new(Connection::open(server)?);
+    let raw = conn.raw();
+
+    unsafe {
+      let (mut event_base, mut error_base) = (0, 0);
+      if XCompositeQueryExtension(raw, &mut event_base, &mut error_base) == False {
+        return Err(Error::unavailable("Composite is not available"));
+      }
+
+      // Naming window pixmaps arrived in 0.2.
+      let (mut major, mut minor) = (0, 2);
+      XCompositeQueryVersion(raw, &mut major, &mut minor);
+      if (major, minor) < (0, 2) {
+        return Err(Error::unavailable("Composite 0.2 is not available"));
+      }
+    }
+
+    let root = unsafe { XRootWindow(raw, screen) };
+    let window = window as Window;
+
+    // The server only stops redirecting once every client that asked has undone it, so
+    // this leaves any compositing manager alone.
+    conn
+      .checked(|raw| unsafe {
+        XCompositeRedirectWindow(raw, window, CompositeRedirectAutomatic)
+      })
+      .map_err(|_| Error::invalid(format!("Window {:#x} not found", window)))?;
+
+    let cursor = opts.cursor
+      && unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        XFixesQueryExtension(raw, &mut event_base, &mut error_base) != False
+      };
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    // Wakes up a wait when the window is unmapped, resized or destroyed, not just drawn.
+    unsafe { XSelectInput(raw, window, StructureNotifyMask) };
+    let damage = DamageTracker::new(&conn, window).ok();
+
+    Ok(Self {
+      conn,
+      root,
+      window,
+      cursor,
+      frame_time,
+      last_frame: None,
+      pixmap: None,
+      damage,
+      clock: FrameClock::new(),
+    })
+  }
+
+  /// Whether changes are tracked with XDamage, rather than reading every frame back.
+  pub fn is_damage_tracked(&self) -> bool {
+    self.damage.is_some()
+  }
+
+  /// Reads the window back, unless it's unmapped, gone or hasn't changed.
+  fn get_frame(&mut self) -> Result<Frame<XCompositeFrame>> {
+    let attributes = match get_attributes(&self.conn, self.window) {
+      Ok(attributes) => attributes,
+      Err(Error::Backend { code, .. }) if code == BadWindow as i64 => {
+        self.pixmap = None;
+        return Ok(Frame::Stopped);
+      }
+      Err(err) => return Err(err),
+    };
+
+    // An unmapped window loses its pixmap, and gets a new one once mapped again.
+    if attributes.map_state != IsViewable {
+      self.pixmap = None;
+      return Ok(Frame::Blank);
+    }
+
+    let (width, height) = (attributes.width as u32, attributes.height as u32);
+    let resized = match &self.pixmap {
+      Some(pixmap) => !pixmap.fits(&attributes),
+      None => true,
+    };
+
+    if resized {
+      // A pixmap keeps the contents it was named with, so a new size needs a new one.
+      self.pixmap = None;
+      self.pixmap = Some(WindowPixmap::new(&self.conn, self.window, &attributes)?);
+    }
+
+    let full = Rect::new(0, 0, width, height);
+    let dirty_rects = match &mut self.damage {
+      Some(damage) => {
+        let damaged = damage.poll();
+        if resized || self.last_frame.is_none() {
+          damage.take()?;
+          vec![full]
+        } else if damaged {
+          damage.take()?
+        } else {
+          return Ok(Frame::Idle);
+        }
+      }
+      None => vec![full],
+    };
+
+    // The pixmap includes the border, which isn't part of the window's contents.
+    let (pixmap, border) = match &self.pixmap {
+      Some(pixmap) => (pixmap.raw, pixmap.border),
+      None => unreachable!("the pixmap is named above"),
+    };
+
+    let mut image = match Image::get(&self.conn, pixmap, border, border, width, height) {
+      Ok(image) => image,
+      // The window was destroyed or unmapped since its attributes were read.
+      Err(Error::Backend { code, .. })
+        if [BadDrawable, BadMatch, BadWindow].contains(&(code as c_int)) =>
+      {
+        self.pixmap = None;
+        return match get_attributes(&self.conn, self.window) {
+          Err(Error::Backend { code, .. }) if code == BadWindow as i64 => {
+            Ok(Frame::Stopped)
+          }
+          _ => Ok(Frame::Blank),
+        };
+      }
+      Err(err) => return Err(err),
+    };
+    if self.cursor {
+      draw_cursor(&self.conn, self.origin()?, &mut image);
+    }
+
+    let info = image.info(&mut self.clock)?;
+    Ok(Frame::Ready(XCompositeFrame {
+      image,
+      dirty_rects,
+      info,
+    }))
+  }
+
+  /// Where the window currently is on the root window.
+  fn origin(&self) -> Result<(i32, i32)> {
+    translate(&self.conn, self.window, self.root)
+  }
+}
+
+impl Capture for XCompositeCapture {
+  type Frame<'a> = XCompositeFrame;
+
+  fn frame(&mut self) -> Result<Frame<XCompositeFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.get_frame()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    let throttled = is_throttled(self.frame_time, self.last_frame);
+
+    match &mut self.damage {
+      Some(damage) if !throttled => damage.wait(timeout),
+      _ => {
+        sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+        Ok(())
+      }
+    }
+  }
+}
+
+impl Drop for XCompositeCapture {
+  fn drop(&mut self) {
+    self.pixmap = None;
+    self.damage = None;
+
+    // The window may be long gone, which is no error of ours.
+    let _ = self.conn.checked(|raw| unsafe {
+      XCompositeUnredirectWindow(raw, self.window, CompositeRedirectAutomatic)
+    });
+  }
+}
+
+/// The pixmap a redirected window was drawn into when it was named.
+struct WindowPixmap {
+  conn: Rc<Connection>,
+  raw: Pixmap,
+  width: c_int,
+  height: c_int,
+  border: c_int,
+}
+
+impl WindowPixmap {
+  fn new(
+    conn: &Rc<Connection>,
+    window: Window,
+    attributes: &XWindowAttributes,
+  ) -> Result<Self> {
+    let raw = conn.checked(|raw| unsafe { XCompositeNameWindowPixmap(raw, window) })?;
+
+    Ok(Self {
+      conn: conn.clone(),
+      raw,
+      width: attributes.width,
+      height: attributes.height,
+      border: attributes.border_width,
+    })
+  }
+
+  /// Whether the window is still the size this pixmap was named at.
+  fn fits(&self, attributes: &XWindowAttributes) -> bool {
+    (self.width, self.height, self.border)
+      == (attributes.width, attributes.height, attributes.border_width)
+  }
+}
+
+impl Drop for WindowPixmap {
+  fn drop(&mut self) {
+    let _ = self
+      .conn
+      .checked(|raw| unsafe { XFreePixmap(raw, self.raw) });
+  }
+}
+
+/// A window's contents in the server's native `ZPixmap` layout, at the size the window
+/// was when it was read back.
+#[derive(Debug)]
+pub struct XCompositeFrame {
+  image: Image,
+  dirty_rects: Vec<Rect>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for XCompositeFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+
+  /// The regions that changed since the previous frame, all of it after a resize.
+  fn dirty_rects(&self) -> Cow<'_, [Rect]> {
+    Cow::Borrowed(&self.dirty_rects)
+  }
+}
+
+impl Deref for XCompositeFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.image.bytes()
+  }
+}
+
+/// Lists the top-level windows on the X screen `display` belongs to, for
+/// [`XCompositeCapture::new`].
+///
+/// Windows come from the window manager's `_NET_CLIENT_LIST`, or without one, from the
+/// mapped children of the root window.
+pub fn get_windows(display: &Display) -> Result<Vec<X11Window>> {
+  let (server, screen) = x11_source(display)?;
+  let conn = Connection::open(server)?;
+  let root = unsafe { XRootWindow(conn.raw(), screen) };
+
+  let client_list = atom(&conn, "_NET_CLIENT_LIST");
+  let ids = match get_property(&conn, root, client_list, XA_WINDOW) {
+    Some(property) => property.longs().to_vec(),
+    None => get_children(&conn, root)?,
+  };
+
+  Ok(
+    ids
+      .into_iter()
+      .filter_map(|id| get_window(&conn, root, id).ok())
+      .collect(),
+  )
+}
+
+fn get_window(conn: &Connection, root: Window, window: Window) -> Result<X11Window> {
+  let attributes = get_attributes(conn, window)?;
+  let (x, y) = translate(conn, window, root)?;
+
+  let utf8 = atom(conn, "UTF8_STRING");
+  let title = get_property(conn, window, atom(conn, "_NET_WM_NAME"), utf8)
+    .or_else(|| get_property(conn, window, XA_WM_NAME, AnyPropertyType))
+    .map_or_else(String::new, |property| property.text());
+
+  // `WM_CLASS` holds the instance and then the class, each NUL-terminated.
+  let class = get_property(conn, window, XA_WM_CLASS, AnyPropertyType)
+    .and_then(|property| {
+      let text = property.text();
+      text.split('\0').nth(1).map(ToOwned::to_owned)
+    })
+    .unwrap_or_default();
+
+  let pid = get_property(conn, window, atom(conn, "_NET_WM_PID"), XA_CARDINAL)
+    .and_then(|property| property.longs().first().map(|pid| *pid as u32));
+
+  Ok(X11Window {
+    id: window,
+    title,
+    class,
+    pid,
+    geometry: Rect::new(x, y, attributes.width as u32, attributes.height as u32),
+  })
+}
+
+/// The root's mapped children, which without a window manager are the applications'
+/// own windows.
+fn get_children(conn: &Connection, root: Window) -> Result<Vec<Window>> {
+  let (mut root_return, mut parent) = (0, 0);
+  let mut children = null_mut();
+  let mut count = 0;
+
+  let status = unsafe {
+    XQueryTree(
+      conn.raw(),
+      root,
+      &mut root_return,
+      &mut parent,
+      &mut children,
+      &mut count,
+    )
+  };
+
+  if status == 0 {
+    return Err(Error::other("XQueryTree failed"));
+  }
+
+  if children.is_null() {
+    return Ok(Vec::new());
+  }
+
+  let windows = unsafe { from_raw_parts(children, count as usize) }.to_vec();
+  unsafe { XFree(children as *mut _) };
+
+  Ok(
+    windows
+      .into_iter()
+      .filter(|window| {
+        get_attributes(conn, *window).is_ok_and(|attributes| {
+          attributes.map_state == IsViewable
+            && attributes.class == InputOutput
+            && attributes.override_redirect == False
+        })
+      })
+      .collect(),
+  )
+}
+
+fn get_attributes(conn: &Connection, window: Window) -> Result<XWindowAttributes> {
+  let mut attributes = unsafe { std::mem::zeroed::<XWindowAttributes>() };
+  let status =
+    conn.checked(|raw| unsafe { XGetWindowAttributes(raw, window, &mut attributes) })?;
+
+  if status == 0 {
+    return Err(Error::invalid(format!("Window {:#x} not found", window)));
+  }
+
+  Ok(attributes)
+}
+
+/// Translates the origin of `window` into `dest` coordinates.
+fn translate(conn: &Connection, window: Window, dest: Window) -> Result<(i32, i32)> {
+  let (mut x, mut y, mut child) = (0, 0, 0);
+  let same_screen = conn.checked(|raw| unsafe {
+    XTranslateCoordinates(raw, window, dest, 0, 0, &mut x, &mut y, &mut child)
+  })?;
+
+  if same_screen == False {
+    return Err(Error::invalid("Window is on another screen"));
+  }
+
+  Ok((x, y))
+}
+
+/// Looks up an atom, `0` if no client ever interned it, which no property is set with.
+fn atom(conn: &Connection, name: &str) -> Atom {
+  let name = CString::new(name).unwrap();
+  unsafe { XInternAtom(conn.raw(), name.as_ptr(), True) }
+}
+
+/// Reads the whole of a property, `None` when it isn't set, has another type, or the
+/// window is gone.
+fn get_property(
+  conn: &Connection,
+  window: Window,
+  property: Atom,
+  kind: Atom,
+) -> Option<Property> {
+  if property == 0 {
+    return None;
+  }
+
+  let (mut actual_kind, mut format) = (0, 0);
+  let (mut count, mut bytes_after) = (0, 0);
+  let mut data = null_mut();
+
+  let status = conn.checked(|raw| unsafe {
+    XGetWindowProperty(
+      raw,
+      window,
+      property,
+      0,
+      MAX_PROPERTY_LEN,
+      False,
+      kind,
+      &mut actual_kind,
+      &mut format,
+      &mut count,
+      &mut bytes_after,
+      &mut data,
+    )
+  });
+
+  if status.map_or(true, |status| status != Success) || data.is_null() {
+    return None;
+  }
+
+  let property = Property {
+    data,
+    format,
+    count: count as usize,
+  };
+
+  // A mismatched type comes back with no items, just the actual type.
+  if actual_kind == 0 || (kind != AnyPropertyType && actual_kind != kind) {
+    return None;
+  }
+
+  Some(property)
+}
+
+/// A property value returned by Xlib.
+struct Property {
+  data: *mut c_uchar,
+  format: c_int,
+  count: usize,
+}
+
+impl Property {
+  /// The items of a 32-bit property, which Xlib hands out as `long`s.
+  fn longs(&self) -> &[c_ulong] {
+    match self.format {
+      32 => unsafe { from_raw_parts(self.data as *const c_ulong, self.count) },
+      _ => &[],
+    }
+  }
+
+  /// An 8-bit property as text, without the trailing NUL some clients include.
+  fn text(&self) -> String {
+    if self.format != 8 {
+      return String::new();
+    }
+
+    let bytes = unsafe { from_raw_parts(self.data, self.count) };
+    let text = String::from_utf8_lossy(bytes);
+    text.trim_end_matches('\0').to_owned()
+  }
+}
+
+impl Drop for Property {
+  fn drop(&mut self) {
+    unsafe { XFree(self.data as *mut _) };
+  }
+}
+
+fn x11_source(display: &Display) -> Result<(Option<&str>, i32)> {
+  match display.source() {
+    Source::X11 { server, screen } => Ok((server.as_deref(), *screen)),
+    _ => Err(Error::invalid("Display is not an X11 screen")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::ffi::CString;
+  use std::os::raw::c_ulong;
+  use std::time::{Duration, Instant};
+
+  use super::{get_windows, XCompositeCapture, XCompositeFrame};
+  use crate::capture::x11::Connection;
+  use crate::capture::{Capture, CaptureOpts, Frame, Rect, VideoFrame};
+  use crate::display::get_primary_on;
+  use crate::ffi::x11::{
+    False, PropModeReplace, Window, XChangeProperty, XClearWindow, XCreateSimpleWindow,
+    XDestroyWindow, XInternAtom, XMapWindow, XResizeWindow, XRootWindow, XStoreName,
+    XUnmapWindow, XA_CARDINAL, XA_STRING, XA_WM_CLASS,
+  };
+  use crate::xvfb::Xvfb;
+  use crate::Error;
+
+  /// Creates and maps a borderless window filled with `background`.
+  fn create_window(conn: &Connection, geometry: Rect, background: c_ulong) -> Window {
+    conn
+      .checked(|raw| unsafe {
+        let root = XRootWindow(raw, 0);
+        let window = XCreateSimpleWindow(
+          raw,
+          root,
+          geometry.x,
+          geometry.y,
+          geometry.width,
+          geometry.height,
+          0,
+          0,
+          background,
+        );
+
+        XMapWindow(raw, window);
+        window
+      })
+      .unwrap()
+  }
+
+  fn wait_for_frame(
+    capture: &mut XCompositeCapture,
+    ready: impl Fn(&XCompositeFrame) -> bool,
+  ) -> XCompositeFrame {
+    let started = Instant::now();
+    loop {
+      if let Frame::Ready(frame) = capture.frame().unwrap() {
+        if ready(&frame) {
+          return frame;
+        }
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "No matching frame"
+      );
+    }
+  }
+
+  #[test]
+  fn test_get_windows() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let conn = Connection::open(Some(xvfb.name())).unwrap();
+    let window = create_window(&conn, Rect::new(5, 6, 20, 10), 0);
+
+    let properties = |raw| unsafe {
+      let title = CString::new("Fun window").unwrap();
+      XStoreName(raw, window, title.as_ptr());
+
+      let class = b"fun\0Fun\0";
+      XChangeProperty(
+        raw,
+        window,
+        XA_WM_CLASS,
+        XA_STRING,
+        8,
+        PropModeReplace,
+        class.as_ptr(),
+        class.len() as i32,
+      );
+
+      let pid: c_ulong = 1234;
+      let pid_atom = CString::new("_NET_WM_PID").unwrap();
+      XChangeProperty(
+        raw,
+        window,
+        XInternAtom(raw, pid_atom.as_ptr(), False),
+        XA_CARDINAL,
+        32,
+        PropModeReplace,
+        &pid as *const c_ulong as *const u8,
+        1,
+      );
+    };
+    conn.checked(properties).unwrap();
+
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let windows = get_windows(&display).unwrap();
+    let found = windows
+      .iter()
+      .find(|found| found.id() == window as u64)
+      .unwrap();
+
+    assert_eq!(found.title(), "Fun window");
+    assert_eq!(found.class(), "Fun");
+    assert_eq!(found.pid(), Some(1234));
+    assert_eq!(found.geometry(), Rect::new(5, 6, 20, 10));
+  }
+
+  #[test]
+  fn test_capture_occluded() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let conn = Connection::open(Some(xvfb.name())).unwrap();
+    let window = create_window(&conn, Rect::new(4, 4, 20, 10), 0x00ff_0000);
+    create_window(&conn, Rect::new(0, 0, 64, 48), 0x00ff_ffff);
+
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut opts = CaptureOpts::new(display);
+    opts.cursor(false);
+
+    let mut capture = XCompositeCapture::new(opts, window as u64).unwrap();
+
+    // The window repaints into its pixmap from now on, however covered it is.
+    conn
+      .checked(|raw| unsafe { XClearWindow(raw, window) })
+      .unwrap();
+
+    let frame = wait_for_frame(&mut capture, |frame| {
+      frame.chunks(4).all(|pixel| pixel[..3] == [0, 0, 0xff])
+    });
+
+    assert_eq!((frame.width(), frame.height()), (20, 10));
+    assert_eq!(frame.len(), 20 * 10 * 4);
+  }
+
+  #[test]
+  fn test_capture_resize_unmap_and_destroy() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let conn = Connection::open(Some(xvfb.name())).unwrap();
+    let window = create_window(&conn, Rect::new(0, 0, 20, 10), 0x00ff_0000);
+
+    let display = get_primary_on(xvfb.name()).unwrap();
+    let mut capture =
+      XCompositeCapture::new(CaptureOpts::new(display), window as u64).unwrap();
+
+    let frame = wait_for_frame(&mut capture, |_| true);
+    assert_eq!((frame.width(), frame.height()), (20, 10));
+
+    conn
+      .checked(|raw| unsafe { XResizeWindow(raw, window, 30, 12) })
+      .unwrap();
+
+    let frame = wait_for_frame(&mut capture, |frame| frame.width() == 30);
+    assert_eq!(frame.height(), 12);
+    assert_eq!(frame.len(), 30 * 12 * 4);
+    assert_eq!(*frame.dirty_rects(), [Rect::new(0, 0, 30, 12)]);
+
+    conn
+      .checked(|raw| unsafe { XUnmapWindow(raw, window) })
+      .unwrap();
+    assert!(matches!(capture.frame().unwrap(), Frame::Blank));
+
+    conn
+      .checked(|raw| unsafe { XMapWindow(raw, window) })
+      .unwrap();
+    wait_for_frame(&mut capture, |frame| frame.width() == 30);
+
+    conn
+      .checked(|raw| unsafe { XDestroyWindow(raw, window) })
+      .unwrap();
+    assert!(matches!(capture.frame().unwrap(), Frame::Stopped));
+  }
+
+  #[test]
+  fn test_capture_missing_window() {
+    let xvfb = Xvfb::start(&["64x48x24"]);
+    let display = get_primary_on(xvfb.name()).unwrap();
+
+    let result = XCompositeCapture::new(CaptureOpts::new(display), 0x7fff_ffff);
+    assert!(matches!(result, Err(Error::InvalidInput(_))));
+  }
+}
@@ -0,0 +1,462 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::ops::Deref;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::capture::fbdev::{Channel, PixelLayout};
+use crate::capture::VideoFrame;
+use crate::capture::{
+  check_size, clip_region, is_throttled, poll_interval, Capture, CaptureOpts, Frame,
+  FrameClock, FrameInfo, Rect,
+};
+use crate::display::Source;
+use crate::ffi::linux::{mmap, munmap, MAP_FAILED, MAP_SHARED, PROT_READ};
+use crate::{Error, Result};
+
+const XWD_FILE_VERSION: u32 = 7;
+const Z_PIXMAP: u32 = 2;
+const MSB_FIRST: u32 = 1;
+const TRUE_COLOR: u32 = 4;
+const DIRECT_COLOR: u32 = 5;
+
+/// The fixed part of the header, 25 big-endian words.
+const HEADER_LEN: usize = 100;
+/// Each colormap entry: a pixel, three 16-bit colors, flags and padding.
+const COLOR_LEN: usize = 12;
+
+/// The parts of an XWD header we need to read the image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Header {
+  pub width: u32,
+  pub height: u32,
+  pub depth: u32,
+  pub msb_first: bool,
+  pub bits_per_pixel: u32,
+  pub bytes_per_line: u32,
+  pub red_mask: u32,
+  pub green_mask: u32,
+  pub blue_mask: u32,
+  /// Where the image starts, after the window name and colormap.
+  pub offset: usize,
+}
+
+impl Header {
+  pub fn parse(data: &[u8]) -> Result<Self> {
+    let invalid = |message| Error::invalid_data(message);
+
+    if data.len() < HEADER_LEN {
+      return Err(invalid("XWD header is truncated"));
+    }
+
+    // The header is big-endian whatever the pixels are.
+    let word = |i: usize| u32::from_be_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+
+    if word(1) != XWD_FILE_VERSION {
+      return Err(invalid("Not an XWD version 7 file"));
+    }
+
+    if word(2) != Z_PIXMAP || !matches!(word(13), TRUE_COLOR | DIRECT_COLOR) {
+      return Err(Error::unsupported(
+        "Only true color ZPixmap XWD files are supported",
+      ));
+    }
+
+    let header_size = word(0) as usize;
+    let ncolors = word(19) as usize;
+    if header_size < HEADER_LEN {
+      return Err(invalid("XWD header is truncated"));
+    }
+
+    Ok(Self {
+      depth: word(3),
+      width: word(4),
+      height: word(5),
+      msb_first: word(7) == MSB_FIRST,
+      bits_per_pixel: word(11),
+      bytes_per_line: word(12),
+      red_mask: word(14),
+      green_mask: word(15),
+      blue_mask: word(16),
+      offset: header_size + ncolors * COLOR_LEN,
+    })
+  }
+
+  fn layout(&self) -> Option<PixelLayout> {
+    if !matches!(self.bits_per_pixel, 16 | 24 | 32) {
+      return None;
+    }
+
+    let channel = |mask: u32| {
+      let offset = mask.trailing_zeros();
+      let length = mask.count_ones();
+      if length == 0 || length > 8 || mask >> offset != (1 << length) - 1 {
+        return None;
+      }
+
+      if !self.msb_first {
+        Some(Channel::new(offset, length))
+      } else if offset.is_multiple_of(8) && length == 8 {
+        // Whole bytes can be read little-endian from the mirrored position.
+        Some(Channel::new(self.bits_per_pixel - 8 - offset, length))
+      } else {
+        None
+      }
+    };
+
+    Some(PixelLayout::new(
+      self.bits_per_pixel as usize / 8,
+      channel(self.red_mask)?,
+      channel(self.green_mask)?,
+      channel(self.blue_mask)?,
+    ))
+  }
+}
+
+/// Reads an XWD file that's kept up to date by its writer, like the screens of an
+/// `Xvfb -fbdir`, without talking to the X server at all. It stops once the file is
+/// deleted.
+pub struct XwdCapture {
+  file: File,
+  data: *mut u8,
+  len: usize,
+  /// The screen's size when the capture opened.
+  size: (u32, u32),
+  region: Rect,
+  frame_time: Option<Duration>,
+  last_frame: Option<Instant>,
+  /// The last frame's pixels, to tell whether anything changed since.
+  last_data: Vec<u8>,
+  clock: FrameClock,
+}
+
+// The mapping is read-only and owned by the capture alone.
+unsafe impl Send for XwdCapture {}
+
+impl XwdCapture {
+  pub fn new(opts: CaptureOpts) -> Result<Self> {
+    let path = match opts.display.source() {
+      Source::Xwd { path } => path,
+      _ => return Err(Error::invalid("Display is not an XWD file")),
+    };
+
+    let file = File::open(path)?;
+
+    let frame_time = if opts.frame_rate > 0.0 {
+      Some(Duration::from_secs_f64(1.0 / opts.frame_rate))
+    } else {
+      None
+    };
+
+    let mut capture = Self {
+      file,
+      data: null_mut(),
+      len: 0,
+      size: (opts.display.width(), opts.display.height()),
+      region: opts.source_rect(),
+      frame_time,
+      last_frame: None,
+      last_data: Vec::new(),
+      clock: FrameClock::new(),
+    };
+
+    capture.map()?;
+    Header::parse(capture.bytes())?;
+
+    Ok(capture)
+  }
+
+  /// Maps the whole file, again if it's changed size since we last did.
+  fn map(&mut self) -> Result<()> {
+    let len = self.file.metadata()?.len() as usize;
+    if len == self.len && !self.data.is_null() {
+      return Ok(());
+    }
+
+    self.unmap();
+
+    let data = unsafe {
+      mmap(
+        null_mut(),
+        len,
+        PROT_READ,
+        MAP_SHARED,
+        self.file.as_raw_fd(),
+        0,
+      )
+    };
+
+    if data == MAP_FAILED {
+      return Err(Error::last_os_error());
+    }
+
+    self.data = data as *mut u8;
+    self.len = len;
+
+    Ok(())
+  }
+
+  fn unmap(&mut self) {
+    if !self.data.is_null() {
+      unsafe { munmap(self.data as *mut _, self.len) };
+      self.data = null_mut();
+      self.len = 0;
+    }
+  }
+
+  fn bytes(&self) -> &[u8] {
+    unsafe { from_raw_parts(self.data, self.len) }
+  }
+
+  fn get_frame(&mut self) -> Result<Frame<XwdFrame>> {
+    // Our mapping outlives the file, but nobody will write to it anymore.
+    if self.file.metadata()?.nlink() == 0 {
+      return Ok(Frame::Stopped);
+    }
+
+    self.map()?;
+
+    let header = Header::parse(self.bytes())?;
+    check_size(self.size, (header.width, header.height))?;
+    let layout = header
+      .layout()
+      .ok_or_else(|| Error::unsupported("Unsupported pixel format"))?;
+
+    let region = clip_region(self.region, header.width, header.height)?;
+    let data = layout.copy(
+      self.bytes(),
+      header.offset
+        + region.y as usize * header.bytes_per_line as usize
+        + region.x as usize * layout.bytes_per_pixel,
+      header.bytes_per_line as usize,
+      region.width as usize,
+      region.height as usize,
+    )?;
+
+    if self.last_frame.is_some() && data == self.last_data {
+      return Ok(Frame::Idle);
+    }
+
+    self.last_data.clone_from(&data);
+    Ok(Frame::Ready(XwdFrame {
+      data,
+      info: self.clock.next_bgrx(region.width, region.height),
+    }))
+  }
+}
+
+impl Capture for XwdCapture {
+  type Frame<'a> = XwdFrame;
+
+  fn frame(&mut self) -> Result<Frame<XwdFrame>> {
+    if is_throttled(self.frame_time, self.last_frame) {
+      return Ok(Frame::Blocking);
+    }
+
+    let frame = self.get_frame()?;
+    if let Frame::Ready(_) = frame {
+      self.last_frame = Some(Instant::now());
+    }
+
+    Ok(frame)
+  }
+
+  fn wait(&mut self, timeout: Duration) -> Result<()> {
+    sleep(timeout.min(poll_interval(self.frame_time, self.last_frame)));
+    Ok(())
+  }
+}
+
+impl Drop for XwdCapture {
+  fn drop(&mut self) {
+    self.unmap();
+  }
+}
+
+/// A frame copied out of the XWD file, converted to BGRX.
+#[derive(Debug)]
+pub struct XwdFrame {
+  data: Vec<u8>,
+  info: FrameInfo,
+}
+
+impl VideoFrame for XwdFrame {
+  fn info(&self) -> FrameInfo {
+    self.info
+  }
+}
+
+impl Deref for XwdFrame {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.data
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+  use std::path::{Path, PathBuf};
+  use std::thread::sleep;
+  use std::time::{Duration, Instant};
+
+  use crate::capture::xwd::{Header, XwdCapture};
+  use crate::capture::{Capture, CaptureOpts, Frame, VideoFrame};
+  use crate::display::get_xvfb_displays_on;
+  use crate::xvfb::Xvfb;
+  use crate::Error;
+
+  /// Writes a one-row, 32bpp XWD file with a 256 entry colormap, like Xvfb's.
+  fn write_xwd(dir: &Path, msb_first: bool, pixels: &[u8]) {
+    let name = b"Xvfb main window\0";
+    let width = pixels.len() as u32 / 4;
+    let header_size = 100 + name.len() as u32;
+
+    let words = [
+      header_size,
+      7,
+      2,
+      24,
+      width,
+      1,
+      0,
+      msb_first as u32,
+      32,
+      msb_first as u32,
+      32,
+      32,
+      width * 4,
+      4,
+      0xff_0000,
+      0xff00,
+      0xff,
+      8,
+      256,
+      256,
+      width,
+      1,
+      0,
+      0,
+      0,
+    ];
+
+    let mut data: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+    data.extend_from_slice(name);
+    data.resize(data.len() + 256 * 12, 0);
+    data.extend_from_slice(pixels);
+
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("Xvfb_screen0"), data).unwrap();
+  }
+
+  fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fun_capture-xwd-{}-{}", name, std::process::id()))
+  }
+
+  fn capture_one(dir: &Path) -> Vec<u8> {
+    let display = get_xvfb_displays_on(dir.to_str().unwrap())
+      .unwrap()
+      .remove(0);
+    let mut capture = XwdCapture::new(CaptureOpts::new(display)).unwrap();
+
+    match capture.frame().unwrap() {
+      Frame::Ready(frame) => {
+        assert_eq!((frame.width(), frame.height()), (frame.len() as u32 / 4, 1));
+        frame.to_vec()
+      }
+      _ => panic!("Expected a frame"),
+    }
+  }
+
+  #[test]
+  fn test_parse_header() {
+    assert!(Header::parse(&[0; 40]).is_err());
+    assert!(Header::parse(&[0; 100]).is_err());
+  }
+
+  #[test]
+  fn test_capture_lsb_first() {
+    let dir = temp_dir("lsb");
+    write_xwd(&dir, false, &[1, 2, 3, 0, 4, 5, 6, 0]);
+
+    assert_eq!(capture_one(&dir), [1, 2, 3, 0, 4, 5, 6, 0]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_capture_msb_first() {
+    let dir = temp_dir("msb");
+    write_xwd(&dir, true, &[0, 3, 2, 1, 0, 6, 5, 4]);
+
+    assert_eq!(capture_one(&dir), [1, 2, 3, 0xff, 4, 5, 6, 0xff]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_idle_and_stopped() {
+    let dir = temp_dir("states");
+    write_xwd(&dir, false, &[1, 2, 3, 0]);
+
+    let display = get_xvfb_displays_on(dir.to_str().unwrap())
+      .unwrap()
+      .remove(0);
+    let mut capture = XwdCapture::new(CaptureOpts::new(display)).unwrap();
+
+    assert!(matches!(capture.frame().unwrap(), Frame::Ready(_)));
+    assert!(matches!(capture.frame().unwrap(), Frame::Idle));
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(capture.frame().unwrap(), Frame::Stopped));
+  }
+
+  #[test]
+  fn test_resized() {
+    let dir = temp_dir("resized");
+    write_xwd(&dir, false, &[1, 2, 3, 0]);
+
+    let display = get_xvfb_displays_on(dir.to_str().unwrap())
+      .unwrap()
+      .remove(0);
+    let mut capture = XwdCapture::new(CaptureOpts::new(display)).unwrap();
+    assert!(matches!(capture.frame().unwrap(), Frame::Ready(_)));
+
+    write_xwd(&dir, false, &[1, 2, 3, 0, 4, 5, 6, 0]);
+    let err = capture.frame().err().unwrap();
+    assert!(matches!(err, Error::DisplayChanged(_)));
+    assert_eq!(
+      err.to_string(),
+      "Display changed: Display resized from 1x1 to 2x1"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_capture_xvfb() {
+    let dir = temp_dir("xvfb");
+    fs::create_dir_all(&dir).unwrap();
+
+    let _xvfb =
+      Xvfb::start_with(&["64x48x24"], &["-wr", "-fbdir", dir.to_str().unwrap()]);
+
+    // Xvfb creates the file a little after its socket.
+    let started = Instant::now();
+    while !dir.join("Xvfb_screen0").exists() {
+      assert!(started.elapsed() < Duration::from_secs(10));
+      sleep(Duration::from_millis(10));
+    }
+
+    let frame = capture_one(&dir);
+    assert_eq!(frame.len(), 64 * 48 * 4);
+    assert!(frame
+      .chunks(4)
+      .all(|pixel| pixel[..3] == [0xff, 0xff, 0xff]));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
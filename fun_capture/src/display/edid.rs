@@ -0,0 +1,152 @@
+//! Reading what a monitor says about itself, mostly out of its EDID.
+
+use crate::display::DisplayInfo;
+
+/// Decodes a PNP manufacturer ID, three letters of five bits each packed into `code`,
+/// e.g. `"DEL"`.
+#[cfg(any(
+  all(target_os = "linux", any(feature = "x11", feature = "drm")),
+  target_os = "macos"
+))]
+pub(crate) fn manufacturer(code: u16) -> Option<String> {
+  let letters = [10, 5, 0].map(|shift| ((code >> shift) & 0x1f) as u8);
+  if letters.iter().all(|letter| (1..=26).contains(letter)) {
+    Some(
+      letters
+        .iter()
+        .map(|&letter| (b'@' + letter) as char)
+        .collect(),
+    )
+  } else {
+    None
+  }
+}
+
+impl DisplayInfo {
+  /// Fills in what `edid` says that isn't known yet, if it is an EDID.
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "drm")))]
+  pub(crate) fn read_edid(&mut self, edid: &[u8]) {
+    const HEADER: [u8; 8] = [0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0];
+    if edid.len() < 128 || edid[..8] != HEADER {
+      return;
+    }
+
+    // Display descriptors, rather than detailed timings, start with three zeros.
+    let (mut name, mut serial) = (None, None);
+    for descriptor in edid[54..126].chunks_exact(18) {
+      if descriptor[..3] != [0; 3] {
+        continue;
+      }
+
+      let text = &descriptor[5..];
+      let end = text.iter().position(|&b| b == b'\n').unwrap_or(text.len());
+      let text = String::from_utf8_lossy(&text[..end]).trim().to_owned();
+      match descriptor[3] {
+        0xfc if !text.is_empty() => name = Some(text),
+        0xff if !text.is_empty() => serial = Some(text),
+        _ => {}
+      }
+    }
+
+    let product = u16::from_le_bytes([edid[10], edid[11]]);
+    let serial_number = u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]);
+    let serial =
+      serial.or_else(|| Some(serial_number.to_string()).filter(|_| serial_number != 0));
+
+    self.make = self
+      .make
+      .take()
+      .or_else(|| manufacturer(u16::from_be_bytes([edid[8], edid[9]])));
+    self.model = self
+      .model
+      .take()
+      .or_else(|| name.clone())
+      .or_else(|| Some(format!("{:04X}", product)));
+    self.serial = self.serial.take().or(serial);
+    self.name = self.name.take().or(name);
+
+    // In centimetres, or an aspect ratio if either is 0.
+    if self.physical_size.is_none() && edid[21] != 0 && edid[22] != 0 {
+      self.physical_size = Some((u32::from(edid[21]) * 10, u32::from(edid[22]) * 10));
+    }
+  }
+
+  /// Sets [`DisplayInfo::id`] from the make, model and serial, or to `connector` for a
+  /// monitor without a serial.
+  pub(crate) fn identify(&mut self, connector: &str) {
+    self.id = Some(match (&self.make, &self.model, &self.serial) {
+      (Some(make), Some(model), Some(serial)) => format!("{}-{}-{}", make, model, serial),
+      _ => connector.to_owned(),
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #[cfg(any(
+    all(target_os = "linux", any(feature = "x11", feature = "drm")),
+    target_os = "macos"
+  ))]
+  use crate::display::edid::manufacturer;
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "drm")))]
+  use crate::display::DisplayInfo;
+
+  #[cfg(any(
+    all(target_os = "linux", any(feature = "x11", feature = "drm")),
+    target_os = "macos"
+  ))]
+  #[test]
+  fn test_manufacturer() {
+    assert_eq!(manufacturer(0x10ac).as_deref(), Some("DEL"));
+    assert_eq!(manufacturer(0x4c2d).as_deref(), Some("SAM"));
+    assert_eq!(manufacturer(0), None);
+  }
+
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "drm")))]
+  fn edid() -> Vec<u8> {
+    let mut edid = vec![0; 128];
+    edid[..8].copy_from_slice(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0]);
+    edid[8..16].copy_from_slice(&[0x10, 0xac, 0x3f, 0xa0, 0x4c, 0x30, 0x4d, 0x42]);
+    edid[21..23].copy_from_slice(&[60, 34]);
+    // A detailed timing, then the name.
+    edid[54] = 0x4d;
+    edid[72..90].copy_from_slice(b"\0\0\0\xfc\0DELL U2720Q\n ");
+    edid
+  }
+
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "drm")))]
+  #[test]
+  fn test_read_edid() {
+    let mut info = DisplayInfo::default();
+    info.read_edid(&edid());
+    info.identify("DP-1");
+
+    assert_eq!(info.name.as_deref(), Some("DELL U2720Q"));
+    assert_eq!(info.make.as_deref(), Some("DEL"));
+    assert_eq!(info.model.as_deref(), Some("DELL U2720Q"));
+    assert_eq!(info.serial.as_deref(), Some("1112354892"));
+    assert_eq!(info.physical_size, Some((600, 340)));
+    assert_eq!(info.id.as_deref(), Some("DEL-DELL U2720Q-1112354892"));
+
+    // A serial string outranks the number, and what's already known is kept.
+    let mut edid = edid();
+    edid[90..108].copy_from_slice(b"\0\0\0\xff\0ABC123\n      ");
+    let mut info = DisplayInfo {
+      physical_size: Some((597, 336)),
+      ..DisplayInfo::default()
+    };
+    info.read_edid(&edid);
+    assert_eq!(info.serial.as_deref(), Some("ABC123"));
+    assert_eq!(info.physical_size, Some((597, 336)));
+  }
+
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "drm")))]
+  #[test]
+  fn test_read_not_edid() {
+    let mut info = DisplayInfo::default();
+    info.read_edid(&[0; 128]);
+    info.identify("HDMI-A-1");
+    assert_eq!(info.make, None);
+    assert_eq!(info.id.as_deref(), Some("HDMI-A-1"));
+  }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::vec::IntoIter;
+
+use crate::capture::drm::Card;
+use crate::display::linux::{Display, Source};
+use crate::display::DisplayKind;
+use crate::{Error, Result};
+
+/// Lists the DRM cards, `/dev/dri/cardN`, in order.
+fn cards() -> Vec<String> {
+  let mut cards: Vec<String> = fs::read_dir("/dev/dri")
+    .into_iter()
+    .flatten()
+    .flatten()
+    .filter(|entry| entry.file_name().to_string_lossy().starts_with("card"))
+    .map(|entry| entry.path().to_string_lossy().into_owned())
+    .collect();
+
+  cards.sort();
+  cards
+}
+
+/// Lists the lit connectors of `card`, or of every card when `None`, the first standing
+/// in as the primary display.
+pub fn get_displays_on(card: Option<&str>) -> Result<IntoIter<Display>> {
+  let paths = match card {
+    Some(card) => vec![card.to_owned()],
+    None => cards(),
+  };
+
+  let mut displays = Vec::new();
+  let mut last_error = None;
+
+  for path in paths {
+    let outputs = Card::open(&path).and_then(|card| Ok((card.outputs()?, card)));
+    let (outputs, device) = match (outputs, card) {
+      (Ok(outputs), _) => outputs,
+      (Err(err), Some(_)) => return Err(err),
+      (Err(err), None) => {
+        last_error = Some(err);
+        continue;
+      }
+    };
+
+    for output in outputs {
+      let mut info = device.info(output.connector).unwrap_or_default();
+      info.refresh_rate = output.refresh_rate;
+
+      displays.push(Display {
+        x: output.x as i32,
+        y: output.y as i32,
+        width: output.width,
+        height: output.height,
+        kind: if displays.is_empty() {
+          DisplayKind::Primary
+        } else {
+          DisplayKind::Standard
+        },
+        source: Source::Drm {
+          card: path.clone(),
+          connector: output.connector,
+        },
+        info,
+      });
+    }
+  }
+
+  if displays.is_empty() {
+    return Err(
+      last_error.unwrap_or_else(|| Error::unavailable("No lit DRM connectors found")),
+    );
+  }
+
+  Ok(displays.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::get_displays_on;
+
+  #[test]
+  fn test_get_displays_missing_card() {
+    assert!(get_displays_on(Some("/dev/dri/card-missing")).is_err());
+  }
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, File};
+use std::vec::IntoIter;
+
+use crate::capture::fbdev::query;
+use crate::display::linux::{Display, Source};
+use crate::display::{DisplayInfo, DisplayKind, Rotation};
+use crate::ffi::linux::{fb_fix_screeninfo, fb_var_screeninfo};
+use crate::{Error, Result};
+
+/// Lists the framebuffers, `/dev/fbN`, in order, `/dev/fb0` being the primary.
+pub fn get_displays() -> Result<IntoIter<Display>> {
+  let mut devices: Vec<(u32, String)> = fs::read_dir("/dev")?
+    .flatten()
+    .filter_map(|entry| {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let index = name.strip_prefix("fb")?.parse().ok()?;
+      Some((index, entry.path().to_string_lossy().into_owned()))
+    })
+    .collect();
+
+  devices.sort();
+
+  let mut displays = Vec::new();
+  for (_, device) in devices {
+    let (var, fix) = match File::open(&device)
+      .map_err(Error::from)
+      .and_then(|file| query(&file))
+    {
+      Ok(screeninfo) => screeninfo,
+      Err(_) => continue,
+    };
+    let info = get_info(&device, &var, &fix);
+
+    displays.push(Display {
+      x: 0,
+      y: 0,
+      width: var.xres,
+      height: var.yres,
+      kind: if displays.is_empty() {
+        DisplayKind::Primary
+      } else {
+        DisplayKind::Standard
+      },
+      source: Source::Framebuffer { device },
+      info,
+    });
+  }
+
+  if displays.is_empty() {
+    return Err(Error::unavailable("No framebuffers found"));
+  }
+
+  Ok(displays.into_iter())
+}
+
+/// What the driver says about the framebuffer at `device`, which is little: its own name
+/// and, if it set them, the timings and the size in millimetres.
+fn get_info(
+  device: &str,
+  var: &fb_var_screeninfo,
+  fix: &fb_fix_screeninfo,
+) -> DisplayInfo {
+  // Not NUL-terminated if it fills the array.
+  let id: Vec<u8> = fix
+    .id
+    .iter()
+    .take_while(|&&c| c != 0)
+    .map(|&c| c as u8)
+    .collect();
+  let id = String::from_utf8_lossy(&id).trim().to_owned();
+
+  // Unset sizes are 0 or all ones.
+  let known = |mm: u32| mm != 0 && mm != u32::MAX;
+  let sum = |lengths: [u32; 4]| lengths.iter().copied().map(f64::from).sum::<f64>();
+  let line = sum([var.left_margin, var.xres, var.right_margin, var.hsync_len]);
+  let lines = sum([var.upper_margin, var.yres, var.lower_margin, var.vsync_len]);
+
+  let mut info = DisplayInfo {
+    name: Some(id).filter(|id| !id.is_empty()),
+    // The pixel clock is a period in picoseconds.
+    refresh_rate: Some(1e12 / (f64::from(var.pixclock) * line * lines))
+      .filter(|_| var.pixclock != 0),
+    rotation: match var.rotate {
+      1 => Rotation::Right,
+      2 => Rotation::Inverted,
+      3 => Rotation::Left,
+      _ => Rotation::Normal,
+    },
+    physical_size: Some((var.width, var.height))
+      .filter(|_| known(var.width) && known(var.height)),
+    ..DisplayInfo::default()
+  };
+
+  info.identify(device.trim_start_matches("/dev/"));
+  info
+}
@@ -0,0 +1,225 @@
+use std::time::Duration;
+use std::vec::IntoIter;
+
+use crate::display::{DisplayInfo, DisplayKind};
+use crate::{Error, Result};
+
+#[cfg(feature = "drm")]
+mod drm;
+mod fbdev;
+#[cfg(feature = "udev")]
+mod udev;
+#[cfg(feature = "wayland")]
+mod wayland;
+#[cfg(feature = "x11")]
+mod x11;
+mod xwd;
+
+#[cfg(feature = "drm")]
+pub use self::drm::get_displays_on as get_drm_displays_on;
+pub use self::fbdev::get_displays as get_framebuffer_displays;
+#[cfg(feature = "wayland")]
+pub use self::wayland::get_displays_on as get_wayland_displays_on;
+#[cfg(feature = "x11")]
+pub(crate) use self::x11::{
+  config_time as x11_config_time, screen_displays as x11_screen_displays,
+};
+#[cfg(feature = "x11")]
+pub use self::x11::{get_displays_on, get_primary_on};
+pub use self::xwd::get_displays_on as get_xvfb_displays_on;
+
+/// Where a display comes from, as there is no single display server on Linux.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+  /// Screen `screen` of the X server `server`, `None` meaning `$DISPLAY`.
+  X11 { server: Option<String>, screen: i32 },
+  /// The `wl_output` called `name` on the compositor at `socket`, `None` meaning
+  /// `$WAYLAND_DISPLAY`.
+  Wayland {
+    socket: Option<String>,
+    name: String,
+  },
+  /// The connector `connector` of the DRM device at `card`, e.g. `/dev/dri/card0`.
+  Drm { card: String, connector: u32 },
+  /// The console framebuffer at `device`, e.g. `/dev/fb0`.
+  Framebuffer { device: String },
+  /// An XWD file kept up to date by its writer, e.g. `Xvfb_screen0` of `Xvfb -fbdir`.
+  Xwd { path: String },
+  /// Not a screen of this machine, see
+  /// [`Display::synthetic`](crate::display::Display::synthetic),
+  /// [`Display::desktop`](crate::display::Display::desktop) and
+  /// [`get_vnc_display`](crate::display::get_vnc_display).
+  Synthetic,
+}
+
+#[derive(Clone, Debug)]
+pub struct Display {
+  x: i32,
+  y: i32,
+  width: u32,
+  height: u32,
+  kind: DisplayKind,
+  source: Source,
+  info: DisplayInfo,
+}
+
+impl Display {
+  pub(crate) fn synthetic(x: i32, y: i32, width: u32, height: u32) -> Self {
+    Self {
+      x,
+      y,
+      width,
+      height,
+      kind: DisplayKind::Primary,
+      source: Source::Synthetic,
+      info: DisplayInfo::default(),
+    }
+  }
+
+  /// Where this display comes from, and so which backends can capture it.
+  pub fn source(&self) -> &Source {
+    &self.source
+  }
+
+  pub(crate) fn is_synthetic(&self) -> bool {
+    self.source == Source::Synthetic
+  }
+
+  pub fn x(&self) -> i32 {
+    self.x
+  }
+
+  pub fn y(&self) -> i32 {
+    self.y
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn kind(&self) -> DisplayKind {
+    self.kind
+  }
+
+  /// What's known about the monitor, nothing for a synthetic display.
+  pub fn info(&self) -> DisplayInfo {
+    self.info.clone()
+  }
+}
+
+/// Wayland sessions usually run an X server too, but it only sees X clients.
+#[cfg(feature = "wayland")]
+fn is_wayland_session() -> bool {
+  std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+#[cfg(feature = "x11")]
+fn is_x11_session() -> bool {
+  std::env::var_os("DISPLAY").is_some()
+}
+
+/// Without a display server there's only the console, through DRM or failing that fbdev.
+fn get_console_displays() -> Result<IntoIter<Display>> {
+  #[cfg(feature = "drm")]
+  return drm::get_displays_on(None).or_else(|_| fbdev::get_displays());
+
+  #[cfg(not(feature = "drm"))]
+  fbdev::get_displays()
+}
+
+/// Display servers left out of the build are passed over, down to the console.
+pub fn get_primary() -> Result<Display> {
+  #[cfg(feature = "wayland")]
+  if is_wayland_session() {
+    return wayland::get_primary_on(None);
+  }
+
+  #[cfg(feature = "x11")]
+  if is_x11_session() {
+    return x11::get_primary_on(None);
+  }
+
+  get_console_displays()?
+    .find(|display| display.kind == DisplayKind::Primary)
+    .ok_or_else(|| Error::unavailable("No displays found"))
+}
+
+pub fn get_displays() -> Result<IntoIter<Display>> {
+  #[cfg(feature = "wayland")]
+  if is_wayland_session() {
+    return wayland::get_displays_on(None);
+  }
+
+  #[cfg(feature = "x11")]
+  if is_x11_session() {
+    return x11::get_displays_on(None);
+  }
+
+  get_console_displays()
+}
+
+/// What wakes a [`DisplayWatcher`](crate::display::DisplayWatcher) up once the displays
+/// [`get_displays`] lists may have changed.
+// Without any of the features there are no variants, and it's never made.
+pub(crate) enum Notifier {
+  #[cfg(feature = "wayland")]
+  Wayland(wayland::Notifier),
+  #[cfg(feature = "x11")]
+  X11(x11::Notifier),
+  #[cfg(feature = "udev")]
+  Udev(udev::Notifier),
+}
+
+impl Notifier {
+  pub fn new() -> Result<Self> {
+    #[cfg(feature = "wayland")]
+    if is_wayland_session() {
+      return wayland::Notifier::new(None).map(Self::Wayland);
+    }
+
+    #[cfg(feature = "x11")]
+    if is_x11_session() {
+      return x11::Notifier::new(None).map(Self::X11);
+    }
+
+    #[cfg(feature = "udev")]
+    return udev::Notifier::new().map(Self::Udev);
+
+    #[cfg(not(feature = "udev"))]
+    Err(Error::unavailable(
+      "Console notifications need the udev feature",
+    ))
+  }
+
+  /// Whether anything happened since the last call, without blocking.
+  pub fn changed(&mut self) -> Result<bool> {
+    match *self {
+      #[cfg(feature = "wayland")]
+      Self::Wayland(ref mut notifier) => notifier.changed(),
+      #[cfg(feature = "x11")]
+      Self::X11(ref mut notifier) => notifier.changed(),
+      #[cfg(feature = "udev")]
+      Self::Udev(ref mut notifier) => notifier.changed(),
+    }
+  }
+
+  /// Blocks until something may have happened, or `timeout` passes.
+  #[cfg_attr(
+    not(any(feature = "wayland", feature = "x11", feature = "udev")),
+    allow(unused_variables)
+  )]
+  pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+    match *self {
+      #[cfg(feature = "wayland")]
+      Self::Wayland(ref mut notifier) => notifier.wait(timeout),
+      #[cfg(feature = "x11")]
+      Self::X11(ref mut notifier) => notifier.wait(timeout),
+      #[cfg(feature = "udev")]
+      Self::Udev(ref mut notifier) => notifier.wait(timeout),
+    }
+  }
+}
@@ -0,0 +1,89 @@
+use std::ptr::null;
+use std::time::Duration;
+
+use crate::capture::wait_readable;
+use crate::ffi::udev::{
+  udev, udev_device_unref, udev_monitor, udev_monitor_enable_receiving,
+  udev_monitor_filter_add_match_subsystem_devtype, udev_monitor_get_fd,
+  udev_monitor_new_from_netlink, udev_monitor_receive_device, udev_monitor_unref,
+  udev_new, udev_unref,
+};
+use crate::{Error, Result};
+
+/// Wakes a [`DisplayWatcher`](crate::display::DisplayWatcher) up whenever udev reports
+/// a DRM device or framebuffer changing, e.g. as a monitor is plugged in.
+pub(crate) struct Notifier {
+  udev: *mut udev,
+  monitor: *mut udev_monitor,
+}
+
+// Neither is shared with anything else, and udev only cares that they aren't used from
+// two threads at once.
+unsafe impl Send for Notifier {}
+
+impl Notifier {
+  pub fn new() -> Result<Self> {
+    let udev = unsafe { udev_new() };
+    if udev.is_null() {
+      return Err(Error::unavailable("Failed to create a udev context"));
+    }
+
+    let monitor =
+      unsafe { udev_monitor_new_from_netlink(udev, b"udev\0".as_ptr().cast()) };
+    if monitor.is_null() {
+      unsafe { udev_unref(udev) };
+      return Err(Error::unavailable("Failed to monitor udev events"));
+    }
+
+    let notifier = Self { udev, monitor };
+
+    // Connectors change on their card, framebuffers come and go as `graphics` devices.
+    for subsystem in [&b"drm\0"[..], b"graphics\0"] {
+      let status = unsafe {
+        udev_monitor_filter_add_match_subsystem_devtype(
+          monitor,
+          subsystem.as_ptr().cast(),
+          null(),
+        )
+      };
+      if status < 0 {
+        return Err(Error::unavailable("Failed to filter udev events"));
+      }
+    }
+
+    if unsafe { udev_monitor_enable_receiving(monitor) } < 0 {
+      return Err(Error::unavailable("Failed to receive udev events"));
+    }
+
+    Ok(notifier)
+  }
+
+  /// Drains the events that arrived, returning whether there were any. The socket
+  /// doesn't block.
+  pub fn changed(&mut self) -> Result<bool> {
+    let mut changed = false;
+    loop {
+      let device = unsafe { udev_monitor_receive_device(self.monitor) };
+      if device.is_null() {
+        return Ok(changed);
+      }
+
+      unsafe { udev_device_unref(device) };
+      changed = true;
+    }
+  }
+
+  pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+    wait_readable(unsafe { udev_monitor_get_fd(self.monitor) }, timeout)?;
+    Ok(())
+  }
+}
+
+impl Drop for Notifier {
+  fn drop(&mut self) {
+    unsafe {
+      udev_monitor_unref(self.monitor);
+      udev_unref(self.udev);
+    }
+  }
+}
@@ -0,0 +1,237 @@
+use std::time::Duration;
+use std::vec::IntoIter;
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_output::{self, Transform, WlOutput};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::{
+  delegate_dispatch, Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+
+use crate::capture::wayland::{
+  connect, dispatch_pending, wait_for_events, Output, OutputInfo, Outputs,
+};
+use crate::display::linux::{Display, Source};
+use crate::display::{DisplayInfo, DisplayKind, Rotation};
+use crate::{Error, Result};
+
+struct State {
+  outputs: Outputs,
+}
+
+impl AsMut<Outputs> for State {
+  fn as_mut(&mut self) -> &mut Outputs {
+    &mut self.outputs
+  }
+}
+
+delegate_dispatch!(State: [WlRegistry: GlobalListContents] => Outputs);
+delegate_dispatch!(State: [WlOutput: u32] => Outputs);
+
+pub fn get_primary_on(socket: Option<&str>) -> Result<Display> {
+  get_displays_on(socket)?
+    .next()
+    .ok_or_else(|| Error::unavailable("Compositor has no outputs"))
+}
+
+/// Lists the compositor's outputs, the first standing in as the primary display since
+/// Wayland has no such notion.
+pub fn get_displays_on(socket: Option<&str>) -> Result<IntoIter<Display>> {
+  let conn = connect(socket)?;
+  let (globals, mut queue) =
+    registry_queue_init::<State>(&conn).map_err(Error::disconnected)?;
+  let mut state = State {
+    outputs: Outputs::default(),
+  };
+
+  state.outputs.bind_all(&globals, &queue.handle());
+  queue.roundtrip(&mut state).map_err(Error::disconnected)?;
+
+  let displays: Vec<_> = state
+    .outputs
+    .outputs
+    .iter()
+    .enumerate()
+    .map(|(i, output)| Display {
+      x: output.info.x,
+      y: output.info.y,
+      width: output.info.width,
+      height: output.info.height,
+      kind: if i == 0 {
+        DisplayKind::Primary
+      } else {
+        DisplayKind::Standard
+      },
+      source: Source::Wayland {
+        socket: socket.map(ToOwned::to_owned),
+        name: output.name(),
+      },
+      info: get_info(output),
+    })
+    .collect();
+
+  Ok(displays.into_iter())
+}
+
+/// What the compositor says about `output`. It doesn't pass on serials, so outputs are
+/// told apart by their names.
+fn get_info(output: &Output) -> DisplayInfo {
+  let info = &output.info;
+  let known = |text: &Option<String>| text.clone().filter(|text| !text.is_empty());
+
+  let mut display_info = DisplayInfo {
+    name: known(&info.description).or_else(|| Some(output.name())),
+    scale: Some(f64::from(info.scale)).filter(|_| info.scale > 0),
+    refresh_rate: Some(f64::from(info.refresh) / 1000.0).filter(|_| info.refresh > 0),
+    // Transforms turn counter-clockwise.
+    rotation: match info.transform {
+      Some(Transform::_90 | Transform::Flipped90) => Rotation::Left,
+      Some(Transform::_180 | Transform::Flipped180) => Rotation::Inverted,
+      Some(Transform::_270 | Transform::Flipped270) => Rotation::Right,
+      _ => Rotation::Normal,
+    },
+    physical_size: Some((info.physical_width as u32, info.physical_height as u32))
+      .filter(|_| info.physical_width > 0 && info.physical_height > 0),
+    make: known(&info.make),
+    model: known(&info.model),
+    ..DisplayInfo::default()
+  };
+
+  display_info.identify(&output.name());
+  display_info
+}
+
+/// Wakes a [`DisplayWatcher`](crate::display::DisplayWatcher) up whenever the
+/// compositor adds, removes or changes an output.
+pub(crate) struct Notifier {
+  queue: EventQueue<Watch>,
+  state: Watch,
+  _conn: Connection,
+}
+
+impl Notifier {
+  pub fn new(socket: Option<&str>) -> Result<Self> {
+    let conn = connect(socket)?;
+    let (globals, mut queue) =
+      registry_queue_init::<Watch>(&conn).map_err(Error::disconnected)?;
+    let mut state = Watch {
+      outputs: Outputs::default(),
+      changed: false,
+    };
+
+    state.outputs.bind_all(&globals, &queue.handle());
+    queue.roundtrip(&mut state).map_err(Error::disconnected)?;
+    state.changed = false;
+
+    Ok(Self {
+      queue,
+      state,
+      _conn: conn,
+    })
+  }
+
+  /// Dispatches the events that arrived, returning whether any were about outputs.
+  pub fn changed(&mut self) -> Result<bool> {
+    dispatch_pending(&mut self.queue, &mut self.state)?;
+    Ok(std::mem::take(&mut self.state.changed))
+  }
+
+  pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+    wait_for_events(&mut self.queue, timeout)
+  }
+}
+
+struct Watch {
+  outputs: Outputs,
+  changed: bool,
+}
+
+impl AsMut<Outputs> for Watch {
+  fn as_mut(&mut self) -> &mut Outputs {
+    &mut self.outputs
+  }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for Watch {
+  fn event(
+    state: &mut Self,
+    registry: &WlRegistry,
+    event: wl_registry::Event,
+    data: &GlobalListContents,
+    conn: &Connection,
+    qh: &QueueHandle<Self>,
+  ) {
+    match &event {
+      // Unlike captures, the watcher binds outputs as they come, to hear of their modes.
+      wl_registry::Event::Global {
+        name,
+        interface,
+        version,
+      } if interface == "wl_output" => {
+        let output = registry.bind::<WlOutput, _, _>(*name, (*version).min(4), qh, *name);
+        state.outputs.outputs.push(Output {
+          global: *name,
+          output,
+          info: OutputInfo::default(),
+        });
+        state.changed = true;
+      }
+      wl_registry::Event::GlobalRemove { name } => {
+        state.changed |= state.outputs.contains(*name);
+      }
+      _ => {}
+    }
+
+    <Outputs as Dispatch<WlRegistry, GlobalListContents, Self>>::event(
+      state, registry, event, data, conn, qh,
+    );
+  }
+}
+
+impl Dispatch<WlOutput, u32> for Watch {
+  fn event(
+    state: &mut Self,
+    output: &WlOutput,
+    event: wl_output::Event,
+    global: &u32,
+    conn: &Connection,
+    qh: &QueueHandle<Self>,
+  ) {
+    // Versions 2 and up end every batch of changes with `done`, version 1 has no batches.
+    if matches!(event, wl_output::Event::Done) || output.version() < 2 {
+      state.changed = true;
+    }
+
+    <Outputs as Dispatch<WlOutput, u32, Self>>::event(
+      state, output, event, global, conn, qh,
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::get_displays_on;
+  use crate::display::linux::{Display, Source};
+  use crate::display::DisplayKind;
+  use crate::sway::Sway;
+
+  #[test]
+  fn test_get_displays() {
+    let sway = Sway::start();
+    let displays: Vec<Display> = get_displays_on(Some(sway.socket())).unwrap().collect();
+
+    assert_eq!(displays.len(), 1);
+    assert_eq!(displays[0].kind(), DisplayKind::Primary);
+    assert!(displays[0].width() > 0);
+    assert!(displays[0].height() > 0);
+    assert_eq!(
+      displays[0].source(),
+      &Source::Wayland {
+        socket: Some(sway.socket().to_owned()),
+        name: "HEADLESS-1".to_owned()
+      }
+    );
+    // Compositors don't pass serials on, so outputs go by their names.
+    assert_eq!(displays[0].info().id.as_deref(), Some("HEADLESS-1"));
+  }
+}
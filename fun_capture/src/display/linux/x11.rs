@@ -0,0 +1,357 @@
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+use std::time::Duration;
+use std::vec::IntoIter;
+
+use crate::capture::wait_readable;
+use crate::capture::x11::Connection;
+use crate::display::linux::{Display, Source};
+use crate::display::{DisplayInfo, DisplayKind, Rotation};
+use crate::ffi::x11::{
+  AnyPropertyType, False, RRCrtcChangeNotifyMask, RROutput, RROutputChangeNotifyMask,
+  RRScreenChangeNotifyMask, RR_Connected, RR_Rotate_180, RR_Rotate_270, RR_Rotate_90,
+  Rotation as XRotation, Success, Time, True, XConnectionNumber, XDefaultScreen,
+  XDisplayHeight, XDisplayHeightMM, XDisplayWidth, XDisplayWidthMM, XEvent, XFree,
+  XInternAtom, XNextEvent, XPending, XRRFreeCrtcInfo, XRRFreeOutputInfo,
+  XRRFreeScreenResources, XRRGetCrtcInfo, XRRGetOutputInfo, XRRGetOutputPrimary,
+  XRRGetOutputProperty, XRRGetScreenResourcesCurrent, XRRModeInfo, XRRQueryExtension,
+  XRRScreenResources, XRRSelectInput, XRootWindow, XScreenCount,
+};
+use crate::{Error, Result};
+
+/// `len` items at `ptr`, which Xlib leaves null when there are none.
+unsafe fn slice<'a, T>(ptr: *const T, len: i32) -> &'a [T] {
+  if ptr.is_null() || len <= 0 {
+    &[]
+  } else {
+    from_raw_parts(ptr, len as usize)
+  }
+}
+
+/// The monitors of X screen `screen`, one per lit RandR output, or the whole screen
+/// without RandR.
+pub(crate) fn screen_displays(conn: &Connection, screen: i32) -> Vec<Display> {
+  let mut displays = output_displays(conn, screen);
+  if displays.is_empty() {
+    displays.push(screen_display(conn, screen));
+  }
+
+  if screen != unsafe { XDefaultScreen(conn.raw()) } {
+    for display in &mut displays {
+      display.kind = DisplayKind::Standard;
+    }
+  } else if displays
+    .iter()
+    .all(|display| display.kind != DisplayKind::Primary)
+  {
+    displays[0].kind = DisplayKind::Primary;
+  }
+
+  displays
+}
+
+fn screen_display(conn: &Connection, screen: i32) -> Display {
+  let raw = conn.raw();
+  let (mm_width, mm_height) =
+    unsafe { (XDisplayWidthMM(raw, screen), XDisplayHeightMM(raw, screen)) };
+
+  let mut info = DisplayInfo {
+    name: Some(format!("Screen {}", screen)),
+    physical_size: Some((mm_width as u32, mm_height as u32))
+      .filter(|_| mm_width > 0 && mm_height > 0),
+    ..DisplayInfo::default()
+  };
+  info.identify(&format!("screen{}", screen));
+
+  Display {
+    x: 0,
+    y: 0,
+    width: unsafe { XDisplayWidth(raw, screen) } as u32,
+    height: unsafe { XDisplayHeight(raw, screen) } as u32,
+    kind: DisplayKind::Standard,
+    source: Source::X11 {
+      server: conn.server().map(ToOwned::to_owned),
+      screen,
+    },
+    info,
+  }
+}
+
+fn has_randr(conn: &Connection) -> bool {
+  let (mut event_base, mut error_base) = (0, 0);
+  unsafe { XRRQueryExtension(conn.raw(), &mut event_base, &mut error_base) != False }
+}
+
+/// When RandR last set up screen `screen`'s CRTCs and outputs, which moves on with any
+/// change to its monitors, `None` without RandR.
+pub(crate) fn config_time(conn: &Connection, screen: i32) -> Option<(Time, Time)> {
+  if !has_randr(conn) {
+    return None;
+  }
+
+  let resources =
+    unsafe { XRRGetScreenResourcesCurrent(conn.raw(), XRootWindow(conn.raw(), screen)) };
+  if resources.is_null() {
+    return None;
+  }
+
+  let times = unsafe { ((*resources).timestamp, (*resources).configTimestamp) };
+  unsafe { XRRFreeScreenResources(resources) };
+  Some(times)
+}
+
+fn output_displays(conn: &Connection, screen: i32) -> Vec<Display> {
+  let raw = conn.raw();
+  if !has_randr(conn) {
+    return Vec::new();
+  }
+
+  let root = unsafe { XRootWindow(raw, screen) };
+  let resources = unsafe { XRRGetScreenResourcesCurrent(raw, root) };
+  if resources.is_null() {
+    return Vec::new();
+  }
+
+  let primary = unsafe { XRRGetOutputPrimary(raw, root) };
+  let outputs = unsafe { slice((*resources).outputs, (*resources).noutput) };
+  let displays = outputs
+    .iter()
+    .filter_map(|&output| {
+      let mut display = unsafe { output_display(conn, screen, resources, output) }?;
+      if output == primary {
+        display.kind = DisplayKind::Primary;
+      }
+      Some(display)
+    })
+    .collect();
+
+  unsafe { XRRFreeScreenResources(resources) };
+  displays
+}
+
+/// The monitor on `output`, `None` unless it's connected and lit.
+unsafe fn output_display(
+  conn: &Connection,
+  screen: i32,
+  resources: *mut XRRScreenResources,
+  output: RROutput,
+) -> Option<Display> {
+  let raw = conn.raw();
+  let output_info = XRRGetOutputInfo(raw, resources, output);
+  if output_info.is_null() {
+    return None;
+  }
+
+  let crtc = if (*output_info).connection == RR_Connected && (*output_info).crtc != 0 {
+    XRRGetCrtcInfo(raw, resources, (*output_info).crtc)
+  } else {
+    null_mut()
+  };
+
+  let display = (!crtc.is_null()).then(|| {
+    let connector = slice((*output_info).name as *const u8, (*output_info).nameLen);
+    let connector = String::from_utf8_lossy(connector).into_owned();
+    let (mm_width, mm_height) = ((*output_info).mm_width, (*output_info).mm_height);
+
+    let modes = slice((*resources).modes, (*resources).nmode);
+    let mut info = DisplayInfo {
+      refresh_rate: modes
+        .iter()
+        .find(|mode| mode.id == (*crtc).mode)
+        .and_then(refresh_rate),
+      rotation: rotation((*crtc).rotation),
+      physical_size: Some((mm_width as u32, mm_height as u32))
+        .filter(|_| mm_width > 0 && mm_height > 0),
+      ..DisplayInfo::default()
+    };
+
+    if let Some(edid) = get_edid(conn, output) {
+      info.read_edid(&edid);
+    }
+    info.name = info.name.take().or_else(|| Some(connector.clone()));
+    info.identify(&connector);
+
+    Display {
+      x: (*crtc).x,
+      y: (*crtc).y,
+      width: (*crtc).width,
+      height: (*crtc).height,
+      kind: DisplayKind::Standard,
+      source: Source::X11 {
+        server: conn.server().map(ToOwned::to_owned),
+        screen,
+      },
+      info,
+    }
+  });
+
+  if !crtc.is_null() {
+    XRRFreeCrtcInfo(crtc);
+  }
+  XRRFreeOutputInfo(output_info);
+  display
+}
+
+fn rotation(rotation: XRotation) -> Rotation {
+  if rotation & RR_Rotate_90 != 0 {
+    Rotation::Left
+  } else if rotation & RR_Rotate_180 != 0 {
+    Rotation::Inverted
+  } else if rotation & RR_Rotate_270 != 0 {
+    Rotation::Right
+  } else {
+    Rotation::Normal
+  }
+}
+
+fn refresh_rate(mode: &XRRModeInfo) -> Option<f64> {
+  let dots = f64::from(mode.hTotal) * f64::from(mode.vTotal);
+  (dots > 0.0).then(|| mode.dotClock as f64 / dots)
+}
+
+/// Reads the EDID the server got from the monitor on `output`, if any.
+fn get_edid(conn: &Connection, output: RROutput) -> Option<Vec<u8>> {
+  let atom = unsafe { XInternAtom(conn.raw(), b"EDID\0".as_ptr().cast(), True) };
+  if atom == 0 {
+    return None;
+  }
+
+  let (mut kind, mut format, mut count, mut bytes_after) = (0, 0, 0, 0);
+  let mut data = null_mut();
+  let status = unsafe {
+    // The base block and up to three extensions, in 32-bit units.
+    XRRGetOutputProperty(
+      conn.raw(),
+      output,
+      atom,
+      0,
+      128,
+      False,
+      False,
+      AnyPropertyType,
+      &mut kind,
+      &mut format,
+      &mut count,
+      &mut bytes_after,
+      &mut data,
+    )
+  };
+  if status != Success || data.is_null() {
+    return None;
+  }
+
+  let edid = (format == 8).then(|| unsafe { slice(data, count as i32) }.to_vec());
+  unsafe { XFree(data.cast()) };
+  edid
+}
+
+pub fn get_primary_on(server: Option<&str>) -> Result<Display> {
+  let conn = Connection::open(server)?;
+  let screen = unsafe { XDefaultScreen(conn.raw()) };
+
+  screen_displays(&conn, screen)
+    .into_iter()
+    .find(|display| display.kind == DisplayKind::Primary)
+    .ok_or_else(|| Error::unavailable("No displays found"))
+}
+
+pub fn get_displays_on(server: Option<&str>) -> Result<IntoIter<Display>> {
+  let conn = Connection::open(server)?;
+  let count = unsafe { XScreenCount(conn.raw()) };
+  let displays: Vec<_> = (0..count)
+    .flat_map(|screen| screen_displays(&conn, screen))
+    .collect();
+
+  Ok(displays.into_iter())
+}
+
+/// Wakes a [`DisplayWatcher`](crate::display::DisplayWatcher) up whenever RandR
+/// reconfigures a screen of the X server, e.g. as a monitor is plugged in or rotated.
+pub(crate) struct Notifier {
+  conn: Connection,
+}
+
+// The connection is the notifier's own, and only used by whoever has it.
+unsafe impl Send for Notifier {}
+
+impl Notifier {
+  pub fn new(server: Option<&str>) -> Result<Self> {
+    let conn = Connection::open(server)?;
+    if !has_randr(&conn) {
+      return Err(Error::unavailable("RandR is not available"));
+    }
+
+    let mask =
+      RRScreenChangeNotifyMask | RRCrtcChangeNotifyMask | RROutputChangeNotifyMask;
+    conn.checked(|raw| {
+      for screen in 0..unsafe { XScreenCount(raw) } {
+        unsafe { XRRSelectInput(raw, XRootWindow(raw, screen), mask) };
+      }
+    })?;
+
+    Ok(Self { conn })
+  }
+
+  /// Drains the events that arrived, returning whether there were any. RandR events are
+  /// all we asked for.
+  pub fn changed(&mut self) -> Result<bool> {
+    let raw = self.conn.raw();
+    let mut event = XEvent::default();
+    let mut changed = false;
+
+    while unsafe { XPending(raw) } > 0 {
+      unsafe { XNextEvent(raw, &mut event) };
+      changed = true;
+    }
+
+    Ok(changed)
+  }
+
+  pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+    wait_readable(unsafe { XConnectionNumber(self.conn.raw()) }, timeout)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{get_displays_on, get_primary_on};
+  use crate::display::linux::{Display, Source};
+  use crate::display::DisplayKind;
+  use crate::xvfb::Xvfb;
+
+  #[test]
+  fn test_get_primary() {
+    let xvfb = Xvfb::start(&["640x480x24"]);
+    let display = get_primary_on(Some(xvfb.name())).unwrap();
+
+    assert_eq!(display.x(), 0);
+    assert_eq!(display.y(), 0);
+    assert_eq!(display.width(), 640);
+    assert_eq!(display.height(), 480);
+    assert_eq!(display.kind(), DisplayKind::Primary);
+    assert_eq!(
+      display.source(),
+      &Source::X11 {
+        server: Some(xvfb.name().to_owned()),
+        screen: 0
+      }
+    );
+
+    let info = display.info();
+    assert!(info.name.is_some());
+    assert!(info.id.is_some());
+  }
+
+  #[test]
+  fn test_get_displays() {
+    let xvfb = Xvfb::start(&["640x480x24", "320x240x24"]);
+    let displays: Vec<Display> = get_displays_on(Some(xvfb.name())).unwrap().collect();
+
+    assert_eq!(displays.len(), 2);
+    assert_eq!(displays[0].kind(), DisplayKind::Primary);
+    assert_eq!((displays[0].width(), displays[0].height()), (640, 480));
+    assert_eq!(displays[1].kind(), DisplayKind::Standard);
+    assert_eq!((displays[1].width(), displays[1].height()), (320, 240));
+  }
+}
@@ -0,0 +1,67 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::vec::IntoIter;
+
+use crate::capture::xwd::Header;
+use crate::display::linux::{Display, Source};
+use crate::display::{DisplayInfo, DisplayKind};
+use crate::{Error, Result};
+
+/// Lists the screens an `Xvfb -fbdir fbdir` writes out, `Xvfb_screenN`, screen 0
+/// being the primary.
+pub fn get_displays_on(fbdir: &str) -> Result<IntoIter<Display>> {
+  let mut screens: Vec<(u32, String)> = fs::read_dir(fbdir)?
+    .flatten()
+    .filter_map(|entry| {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let screen = name.strip_prefix("Xvfb_screen")?.parse().ok()?;
+      Some((screen, entry.path().to_string_lossy().into_owned()))
+    })
+    .collect();
+
+  screens.sort();
+
+  let displays = screens
+    .into_iter()
+    .map(|(screen, path)| {
+      let header = read_header(Path::new(&path))?;
+
+      // The dump says nothing of the monitor, if there even is one.
+      let name = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy());
+      let mut info = DisplayInfo {
+        name: name.map(|name| name.into_owned()),
+        ..DisplayInfo::default()
+      };
+      info.identify(&path);
+
+      Ok(Display {
+        x: 0,
+        y: 0,
+        width: header.width,
+        height: header.height,
+        kind: if screen == 0 {
+          DisplayKind::Primary
+        } else {
+          DisplayKind::Standard
+        },
+        source: Source::Xwd { path },
+        info,
+      })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  if displays.is_empty() {
+    return Err(Error::unavailable("No Xvfb screens found"));
+  }
+
+  Ok(displays.into_iter())
+}
+
+fn read_header(path: &Path) -> Result<Header> {
+  let mut data = Vec::new();
+  File::open(path)?.take(100).read_to_end(&mut data)?;
+  Header::parse(&data)
+}
@@ -1,50 +1,156 @@
-use std::io::{Error, Result};
+use std::convert::TryFrom;
 use std::vec::IntoIter;
 
-use crate::display::DisplayKind;
+use crate::capture::Rect;
+use crate::display::edid::manufacturer;
+use crate::display::{DisplayInfo, DisplayKind, Rotation};
 use crate::ffi::macos::{
-  CGDisplayBounds, CGDisplayIsMain, CGDisplayPixelsHigh, CGDisplayPixelsWide, CGError,
-  CGGetOnlineDisplayList, CGMainDisplayID,
+  CGDisplayBounds, CGDisplayCopyDisplayMode, CGDisplayIsBuiltin, CGDisplayIsMain,
+  CGDisplayModeGetPixelWidth, CGDisplayModeGetRefreshRate, CGDisplayModeGetWidth,
+  CGDisplayModeRelease, CGDisplayModelNumber, CGDisplayPixelsHigh, CGDisplayPixelsWide,
+  CGDisplayRotation, CGDisplayScreenSize, CGDisplaySerialNumber, CGDisplayVendorNumber,
+  CGError, CGGetOnlineDisplayList, CGMainDisplayID,
 };
+use crate::Result;
 
 #[derive(Copy, Clone, Debug)]
-pub struct Display(u32);
+pub struct Display {
+  id: u32,
+  /// The bounds of a synthetic display, which CoreGraphics knows nothing about.
+  synthetic: Option<Rect>,
+}
 
 impl Display {
+  fn new(id: u32) -> Self {
+    Self {
+      id,
+      synthetic: None,
+    }
+  }
+
+  /// Synthetic displays have the null display ID.
+  pub(crate) fn synthetic(x: i32, y: i32, width: u32, height: u32) -> Self {
+    Self {
+      id: 0,
+      synthetic: Some(Rect::new(x, y, width, height)),
+    }
+  }
+
+  pub(crate) fn is_synthetic(&self) -> bool {
+    self.synthetic.is_some()
+  }
+
   pub fn handle(&self) -> u32 {
-    self.0
+    self.id
   }
 
   pub fn x(&self) -> i32 {
-    unsafe { CGDisplayBounds(self.0) }.origin.x as i32
+    match self.synthetic {
+      Some(bounds) => bounds.x,
+      None => unsafe { CGDisplayBounds(self.id) }.origin.x as i32,
+    }
   }
 
   pub fn y(&self) -> i32 {
-    unsafe { CGDisplayBounds(self.0) }.origin.y as i32
+    match self.synthetic {
+      Some(bounds) => bounds.y,
+      None => unsafe { CGDisplayBounds(self.id) }.origin.y as i32,
+    }
   }
 
   pub fn width(&self) -> u32 {
-    unsafe { CGDisplayPixelsWide(self.0) as u32 }
+    match self.synthetic {
+      Some(bounds) => bounds.width,
+      None => unsafe { CGDisplayPixelsWide(self.id) as u32 },
+    }
   }
 
   pub fn height(&self) -> u32 {
-    unsafe { CGDisplayPixelsHigh(self.0) as u32 }
+    match self.synthetic {
+      Some(bounds) => bounds.height,
+      None => unsafe { CGDisplayPixelsHigh(self.id) as u32 },
+    }
   }
 
   pub fn kind(&self) -> DisplayKind {
-    let is_main = unsafe { CGDisplayIsMain(self.0) };
+    if self.synthetic.is_some() {
+      return DisplayKind::Primary;
+    }
+
+    let is_main = unsafe { CGDisplayIsMain(self.id) };
     if is_main == 1 {
       DisplayKind::Primary
     } else {
       DisplayKind::Standard
     }
   }
+
+  /// What CoreGraphics knows about the monitor, nothing for a synthetic display. The
+  /// make and model are the EDID's PNP ID and product code, as CoreGraphics has no names.
+  pub fn info(&self) -> DisplayInfo {
+    if self.synthetic.is_some() {
+      return DisplayInfo::default();
+    }
+
+    let id = self.id;
+    let (vendor, model, serial) = unsafe {
+      (
+        CGDisplayVendorNumber(id),
+        CGDisplayModelNumber(id),
+        CGDisplaySerialNumber(id),
+      )
+    };
+    let size = unsafe { CGDisplayScreenSize(id) };
+
+    let mut info = DisplayInfo {
+      // Clockwise, in degrees.
+      rotation: match unsafe { CGDisplayRotation(id) } as i32 {
+        90 => Rotation::Right,
+        180 => Rotation::Inverted,
+        270 => Rotation::Left,
+        _ => Rotation::Normal,
+      },
+      physical_size: Some((size.width.round() as u32, size.height.round() as u32))
+        .filter(|&(width, height)| width > 0 && height > 0),
+      make: u16::try_from(vendor).ok().and_then(manufacturer),
+      model: Some(format!("{:04X}", model)).filter(|_| model != 0),
+      serial: Some(serial.to_string()).filter(|_| serial != 0),
+      ..DisplayInfo::default()
+    };
+
+    unsafe {
+      let mode = CGDisplayCopyDisplayMode(id);
+      if !mode.is_null() {
+        let refresh_rate = CGDisplayModeGetRefreshRate(mode);
+        let (pixels, points) = (
+          CGDisplayModeGetPixelWidth(mode),
+          CGDisplayModeGetWidth(mode),
+        );
+        CGDisplayModeRelease(mode);
+
+        // Built-in panels often report 0.
+        info.refresh_rate = Some(refresh_rate).filter(|&rate| rate > 0.0);
+        info.scale = Some(pixels as f64 / points as f64).filter(|_| points > 0);
+      }
+    }
+
+    info.name = if unsafe { CGDisplayIsBuiltin(id) } != 0 {
+      Some("Built-in Display".to_owned())
+    } else {
+      match (&info.make, &info.model) {
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        _ => None,
+      }
+    };
+    info.identify(&id.to_string());
+    info
+  }
 }
 
 pub struct Displays(IntoIter<u32>);
 
 pub fn get_primary() -> Result<Display> {
-  Ok(Display(unsafe { CGMainDisplayID() }))
+  Ok(Display::new(unsafe { CGMainDisplayID() }))
 }
 
 pub fn get_displays() -> Result<Displays> {
@@ -59,7 +165,7 @@ pub fn get_displays() -> Result<Displays> {
   };
 
   if error != CGError::Success {
-    return Err(Error::from_raw_os_error(error as i32));
+    return Err(error.into());
   }
 
   unsafe {
@@ -73,7 +179,7 @@ impl Iterator for Displays {
   type Item = Display;
 
   fn next(&mut self) -> Option<Self::Item> {
-    self.0.next().map(Display)
+    self.0.next().map(Display::new)
   }
 }
 
@@ -1,38 +1,254 @@
-use std::io::Result;
 use std::ops::Deref;
 
+use crate::capture::vnc::Connection;
+use crate::{Error, Result};
+
+mod edid;
+#[cfg(target_os = "linux")]
+mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+mod watch;
 #[cfg(target_os = "windows")]
 mod windows;
 
 mod imp {
+  #[cfg(target_os = "linux")]
+  pub use super::linux::*;
   #[cfg(target_os = "macos")]
   pub use super::macos::*;
   #[cfg(target_os = "windows")]
   pub use super::windows::*;
 }
 
+#[cfg(target_os = "linux")]
+pub use imp::Source;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub(crate) use imp::{x11_config_time, x11_screen_displays};
+pub use watch::{DisplayEvent, DisplayWatcher};
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum DisplayKind {
   Primary,
   Standard,
 }
 
-pub struct Display(imp::Display);
+/// Which way a display's picture is turned, named like `xrandr --rotate`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub enum Rotation {
+  #[default]
+  Normal,
+  /// Turned 90° counter-clockwise, e.g. for a monitor stood on its right side.
+  Left,
+  Inverted,
+  /// Turned 90° clockwise.
+  Right,
+}
+
+/// What's known about a monitor besides where it is, from a [`Display`]'s `info`.
+/// Anything the platform or monitor doesn't say is `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DisplayInfo {
+  /// For people to pick from, e.g. `"DELL U2720Q"`, or else the port, e.g. `"DP-1"`.
+  pub name: Option<String>,
+  /// Picks the same monitor out across reboots: its make, model and serial if it has a
+  /// serial, so it's found whichever port it's on, or else the port.
+  pub id: Option<String>,
+  /// Pixels to a logical pixel each way, e.g. 2 on a HiDPI display.
+  pub scale: Option<f64>,
+  /// In hertz.
+  pub refresh_rate: Option<f64>,
+  pub rotation: Rotation,
+  /// Width and height in millimetres.
+  pub physical_size: Option<(u32, u32)>,
+  /// The manufacturer, usually its three letter PNP ID from the EDID, e.g. `"DEL"`.
+  pub make: Option<String>,
+  pub model: Option<String>,
+  pub serial: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Display {
+  inner: imp::Display,
+  remote: Option<Remote>,
+  /// The displays this one is made of, if it's a desktop.
+  displays: Vec<Display>,
+}
+
+impl Display {
+  fn new(inner: imp::Display) -> Self {
+    Self {
+      inner,
+      remote: None,
+      displays: Vec::new(),
+    }
+  }
+
+  /// A `width` by `height` display that doesn't exist, for
+  /// [`SyntheticCapture`](crate::capture::synthetic::SyntheticCapture) on machines with
+  /// no screen. Other backends refuse it.
+  pub fn synthetic(width: u32, height: u32) -> Self {
+    Self::synthetic_at(0, 0, width, height)
+  }
+
+  /// Like [`Display::synthetic`], placed at `x`, `y` so that it can stand in for one of
+  /// several monitors, e.g. in a [`Display::desktop`].
+  pub fn synthetic_at(x: i32, y: i32, width: u32, height: u32) -> Self {
+    Self::new(imp::Display::synthetic(x, y, width, height))
+  }
+
+  /// A pseudo-display covering all of `displays` as they're laid out, for
+  /// [`DesktopCapture`](crate::capture::desktop::DesktopCapture). Its bounds are the
+  /// smallest that hold every display, so they may start at a negative `x` or `y` and
+  /// take in gaps between displays.
+  ///
+  /// Fails if there are no displays.
+  pub fn desktop(displays: Vec<Display>) -> Result<Self> {
+    let mut bounds = displays.iter().map(|display| {
+      let (x, y) = (i64::from(display.x()), i64::from(display.y()));
+      (
+        x,
+        y,
+        x + i64::from(display.width()),
+        y + i64::from(display.height()),
+      )
+    });
+
+    let first = bounds
+      .next()
+      .ok_or_else(|| Error::invalid("A desktop needs at least one display"))?;
+    let (left, top, right, bottom) = bounds.fold(first, |union, display| {
+      (
+        union.0.min(display.0),
+        union.1.min(display.1),
+        union.2.max(display.2),
+        union.3.max(display.3),
+      )
+    });
+
+    Ok(Self {
+      displays,
+      ..Self::synthetic_at(
+        left as i32,
+        top as i32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+      )
+    })
+  }
+
+  /// The displays this desktop is made of, none unless it's a [`Display::desktop`].
+  pub fn displays(&self) -> &[Display] {
+    &self.displays
+  }
+
+  pub(crate) fn is_desktop(&self) -> bool {
+    !self.displays.is_empty()
+  }
+
+  /// The machine this display is really on, if it isn't this one.
+  pub fn remote(&self) -> Option<&Remote> {
+    self.remote.as_ref()
+  }
+}
+
+/// A display on another machine, reached over VNC.
+#[derive(Clone)]
+pub struct Remote {
+  address: String,
+  password: Option<String>,
+  name: String,
+}
+
+impl Remote {
+  /// The server's address, e.g. `"localhost:5900"`.
+  pub fn address(&self) -> &str {
+    &self.address
+  }
+
+  /// The desktop name the server gave.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub(crate) fn password(&self) -> Option<&str> {
+    self.password.as_deref()
+  }
+}
 
 pub fn get_primary() -> Result<Display> {
-  imp::get_primary().map(Display)
+  imp::get_primary().map(Display::new)
 }
 
 pub fn get_displays() -> Result<Vec<Display>> {
-  imp::get_displays().map(|inner| inner.map(Display).collect())
+  imp::get_displays().map(|inner| inner.map(Display::new).collect())
+}
+
+/// Gets every display as one [`Display::desktop`].
+pub fn get_desktop() -> Result<Display> {
+  Display::desktop(get_displays()?)
+}
+
+/// Gets the primary display of the X server `server` (e.g. `":1"`) rather than `$DISPLAY`.
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub fn get_primary_on(server: &str) -> Result<Display> {
+  imp::get_primary_on(Some(server)).map(Display::new)
+}
+
+/// Gets the displays of the X server `server` (e.g. `":1"`) rather than `$DISPLAY`.
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub fn get_displays_on(server: &str) -> Result<Vec<Display>> {
+  imp::get_displays_on(Some(server)).map(|inner| inner.map(Display::new).collect())
+}
+
+/// Gets the outputs of the Wayland compositor at `socket`, either a path or a name
+/// relative to `$XDG_RUNTIME_DIR`, rather than `$WAYLAND_DISPLAY`.
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub fn get_wayland_displays_on(socket: &str) -> Result<Vec<Display>> {
+  imp::get_wayland_displays_on(Some(socket))
+    .map(|inner| inner.map(Display::new).collect())
+}
+
+/// Gets the lit connectors of the DRM device at `card` (e.g. `"/dev/dri/card0"`), for
+/// consoles without a display server. Needs the `drm` feature.
+#[cfg(all(target_os = "linux", feature = "drm"))]
+pub fn get_drm_displays_on(card: &str) -> Result<Vec<Display>> {
+  imp::get_drm_displays_on(Some(card)).map(|inner| inner.map(Display::new).collect())
+}
+
+/// Gets the console framebuffers, `/dev/fbN`.
+#[cfg(target_os = "linux")]
+pub fn get_framebuffer_displays() -> Result<Vec<Display>> {
+  imp::get_framebuffer_displays().map(|inner| inner.map(Display::new).collect())
+}
+
+/// Gets the screens of an Xvfb started with `-fbdir fbdir`, read from the XWD files it
+/// writes there rather than through the X server.
+#[cfg(target_os = "linux")]
+pub fn get_xvfb_displays_on(fbdir: &str) -> Result<Vec<Display>> {
+  imp::get_xvfb_displays_on(fbdir).map(|inner| inner.map(Display::new).collect())
+}
+
+/// Connects to the VNC server at `address` (e.g. `"localhost:5900"`) to get its
+/// framebuffer as a display, for [`VncCapture`](crate::capture::vnc::VncCapture).
+pub fn get_vnc_display(address: &str, password: Option<&str>) -> Result<Display> {
+  let conn = Connection::open(address, password)?;
+
+  Ok(Display {
+    inner: imp::Display::synthetic(0, 0, conn.width() as u32, conn.height() as u32),
+    remote: Some(Remote {
+      address: address.to_owned(),
+      password: password.map(ToOwned::to_owned),
+      name: conn.name().to_owned(),
+    }),
+    displays: Vec::new(),
+  })
 }
 
 impl Deref for Display {
   type Target = imp::Display;
 
   fn deref(&self) -> &Self::Target {
-    &self.0
+    &self.inner
   }
 }
@@ -0,0 +1,260 @@
+//! Noticing displays being plugged in, unplugged or reconfigured.
+
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use crate::display::imp::Notifier;
+use crate::display::{get_displays, Display};
+use crate::{Error, Result};
+
+/// How often displays are listed again where nothing says they changed.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Elsewhere nothing says when displays change, so they're listed again every so often.
+#[cfg(not(target_os = "linux"))]
+enum Notifier {}
+
+#[cfg(not(target_os = "linux"))]
+impl Notifier {
+  fn new() -> Result<Self> {
+    Err(Error::unavailable("No display change notifications"))
+  }
+
+  fn changed(&mut self) -> Result<bool> {
+    match *self {}
+  }
+
+  fn wait(&mut self, _: Duration) -> Result<()> {
+    match *self {}
+  }
+}
+
+/// A change to the displays a [`DisplayWatcher`] watches.
+// Events are few and far between, so boxing `Changed` would only make it awkward.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum DisplayEvent {
+  Added(Display),
+  Removed(Display),
+  /// The display was moved, resized, rotated or otherwise changed. Captures of `old`
+  /// fail with [`Error::DisplayChanged`] and have to be opened again on `new`.
+  Changed {
+    old: Display,
+    new: Display,
+  },
+}
+
+/// Watches the displays [`get_displays`] lists for monitors being plugged in, unplugged
+/// or reconfigured.
+///
+/// On Linux the display server, or udev on a console with the `udev` feature, says when
+/// to look: RandR on X11, the registry and `wl_output` on Wayland. Elsewhere, or if that
+/// fails, the displays are listed again every second.
+///
+/// Displays are told apart by [`DisplayInfo::id`](crate::display::DisplayInfo::id), so
+/// a monitor that comes back on another connector is removed and added again.
+pub struct DisplayWatcher {
+  list: Box<dyn FnMut() -> Result<Vec<Display>> + Send>,
+  displays: Vec<Display>,
+  notifier: Option<Notifier>,
+  last_scan: Instant,
+  events: VecDeque<DisplayEvent>,
+}
+
+impl DisplayWatcher {
+  /// Starts watching from the displays as they are now.
+  pub fn new() -> Result<Self> {
+    // Without notifications the displays are just listed more often.
+    Self::with_lister(get_displays, Notifier::new().ok())
+  }
+
+  fn with_lister(
+    mut list: impl FnMut() -> Result<Vec<Display>> + Send + 'static,
+    notifier: Option<Notifier>,
+  ) -> Result<Self> {
+    Ok(Self {
+      displays: list()?,
+      list: Box::new(list),
+      notifier,
+      last_scan: Instant::now(),
+      events: VecDeque::new(),
+    })
+  }
+
+  /// The displays as they were last listed.
+  pub fn displays(&self) -> &[Display] {
+    &self.displays
+  }
+
+  /// The next change, `None` if there's none yet. Never blocks, though it may list the
+  /// displays again.
+  pub fn poll(&mut self) -> Result<Option<DisplayEvent>> {
+    if self.events.is_empty() && self.is_stale()? {
+      self.rescan()?;
+    }
+
+    Ok(self.events.pop_front())
+  }
+
+  /// Blocks until the next change, `None` if `timeout` passes first.
+  pub fn wait(&mut self, timeout: Duration) -> Result<Option<DisplayEvent>> {
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+      if let Some(event) = self.poll()? {
+        return Ok(Some(event));
+      }
+
+      let remaining = match deadline {
+        Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+        None => Duration::MAX,
+      };
+      if remaining.is_zero() {
+        return Ok(None);
+      }
+
+      match &mut self.notifier {
+        Some(notifier) => notifier.wait(remaining)?,
+        None => {
+          let next_scan = RESCAN_INTERVAL.saturating_sub(self.last_scan.elapsed());
+          sleep(remaining.min(next_scan));
+        }
+      }
+    }
+  }
+
+  fn is_stale(&mut self) -> Result<bool> {
+    match &mut self.notifier {
+      Some(notifier) => notifier.changed(),
+      None => Ok(self.last_scan.elapsed() >= RESCAN_INTERVAL),
+    }
+  }
+
+  fn rescan(&mut self) -> Result<()> {
+    let displays = match (self.list)() {
+      Ok(displays) => displays,
+      // Consoles list no displays at all as unavailable, e.g. with every monitor
+      // unplugged.
+      Err(Error::BackendUnavailable(_)) => Vec::new(),
+      Err(err) => return Err(err),
+    };
+
+    self.events.extend(diff(&self.displays, &displays));
+    self.displays = displays;
+    self.last_scan = Instant::now();
+    Ok(())
+  }
+}
+
+/// What tells a display apart from the others: its id, or where it's listed if it has
+/// none.
+fn key(display: &Display, index: usize) -> String {
+  display.info().id.unwrap_or_else(|| index.to_string())
+}
+
+fn is_same(old: &Display, new: &Display) -> bool {
+  let layout = |display: &Display| {
+    (
+      display.x(),
+      display.y(),
+      display.width(),
+      display.height(),
+      display.kind(),
+    )
+  };
+
+  layout(old) == layout(new) && old.info() == new.info()
+}
+
+/// The events that turn `old` into `new`, removals first.
+fn diff(old: &[Display], new: &[Display]) -> Vec<DisplayEvent> {
+  let old_keys: Vec<_> = old.iter().enumerate().map(|(i, d)| key(d, i)).collect();
+  let new_keys: Vec<_> = new.iter().enumerate().map(|(i, d)| key(d, i)).collect();
+
+  let removed = old
+    .iter()
+    .zip(&old_keys)
+    .filter(|(_, key)| !new_keys.contains(key))
+    .map(|(display, _)| DisplayEvent::Removed(display.clone()));
+
+  let added_or_changed = new.iter().zip(&new_keys).filter_map(|(display, key)| {
+    match old_keys.iter().position(|old_key| old_key == key) {
+      Some(i) if is_same(&old[i], display) => None,
+      Some(i) => Some(DisplayEvent::Changed {
+        old: old[i].clone(),
+        new: display.clone(),
+      }),
+      None => Some(DisplayEvent::Added(display.clone())),
+    }
+  });
+
+  removed.chain(added_or_changed).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use std::time::{Duration, Instant};
+
+  use crate::display::watch::{DisplayEvent, DisplayWatcher};
+  use crate::display::Display;
+
+  fn assert_send<T: Send>() {}
+
+  /// A watcher of whatever `displays` holds, listed again every second.
+  fn watcher(displays: &Arc<Mutex<Vec<Display>>>) -> DisplayWatcher {
+    let displays = Arc::clone(displays);
+    DisplayWatcher::with_lister(move || Ok(displays.lock().unwrap().clone()), None)
+      .unwrap()
+  }
+
+  fn size(display: &Display) -> (u32, u32) {
+    (display.width(), display.height())
+  }
+
+  #[test]
+  fn test_watch() {
+    assert_send::<DisplayWatcher>();
+
+    let displays = Arc::new(Mutex::new(vec![Display::synthetic(64, 48)]));
+    let mut watcher = watcher(&displays);
+    assert_eq!(watcher.displays().len(), 1);
+    assert!(watcher.poll().unwrap().is_none());
+
+    displays
+      .lock()
+      .unwrap()
+      .push(Display::synthetic_at(64, 0, 32, 24));
+    match watcher.wait(Duration::from_secs(5)).unwrap() {
+      Some(DisplayEvent::Added(display)) => assert_eq!(display.x(), 64),
+      _ => panic!("Expected an added display"),
+    }
+    assert_eq!(watcher.displays().len(), 2);
+
+    displays.lock().unwrap()[0] = Display::synthetic(128, 96);
+    match watcher.wait(Duration::from_secs(5)).unwrap() {
+      Some(DisplayEvent::Changed { old, new }) => {
+        assert_eq!((size(&old), size(&new)), ((64, 48), (128, 96)))
+      }
+      _ => panic!("Expected a changed display"),
+    }
+
+    displays.lock().unwrap().pop();
+    match watcher.wait(Duration::from_secs(5)).unwrap() {
+      Some(DisplayEvent::Removed(display)) => assert_eq!(size(&display), (32, 24)),
+      _ => panic!("Expected a removed display"),
+    }
+    assert_eq!(watcher.displays().len(), 1);
+  }
+
+  #[test]
+  fn test_wait_timeout() {
+    let displays = Arc::new(Mutex::new(vec![Display::synthetic(64, 48)]));
+    let mut watcher = watcher(&displays);
+
+    let started = Instant::now();
+    assert!(watcher.wait(Duration::from_millis(50)).unwrap().is_none());
+    assert!(started.elapsed() >= Duration::from_millis(50));
+  }
+}
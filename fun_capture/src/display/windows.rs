@@ -1,18 +1,22 @@
-use std::io::{Error, Result};
 use std::mem::size_of;
 use std::ptr::{null, null_mut};
 use std::vec::IntoIter;
 
 use winapi::shared::minwindef::{BOOL, LPARAM, TRUE};
-use winapi::shared::windef::{HDC, HMONITOR, LPRECT, POINT};
+use winapi::shared::windef::{HDC, HMONITOR, LPRECT, POINT, RECT};
+use winapi::um::wingdi::{
+  CreateDCW, DeleteDC, GetDeviceCaps, DEVMODEW, DISPLAY_DEVICEW, DMDO_180, DMDO_270,
+  DMDO_90, HORZSIZE, VERTSIZE,
+};
 use winapi::um::winuser::{
-  EnumDisplayMonitors, GetMonitorInfoW, MonitorFromPoint, MONITORINFO,
-  MONITORINFOF_PRIMARY,
+  EnumDisplayDevicesW, EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW,
+  MonitorFromPoint, ENUM_CURRENT_SETTINGS, MONITORINFOEXW, MONITORINFOF_PRIMARY,
 };
 
-use crate::display::DisplayKind;
+use crate::display::{DisplayInfo, DisplayKind, Rotation};
+use crate::{Error, Result};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Display {
   x: i32,
   y: i32,
@@ -20,15 +24,19 @@ pub struct Display {
   height: u32,
   kind: DisplayKind,
   handle: HMONITOR,
+  info: DisplayInfo,
 }
 
+// A monitor handle is just an identifier, which any thread may use.
+unsafe impl Send for Display {}
+
 impl Display {
-  fn new(info: MONITORINFO, handle: HMONITOR) -> Self {
+  fn new(info: MONITORINFOEXW, handle: HMONITOR) -> Self {
     Self {
-      x: info.rcWork.left,
-      y: info.rcWork.top,
-      width: (info.rcWork.right - info.rcWork.left) as u32,
-      height: (info.rcWork.bottom - info.rcWork.top) as u32,
+      x: info.rcMonitor.left,
+      y: info.rcMonitor.top,
+      width: (info.rcMonitor.right - info.rcMonitor.left) as u32,
+      height: (info.rcMonitor.bottom - info.rcMonitor.top) as u32,
 
       handle,
       kind: if info.dwFlags == MONITORINFOF_PRIMARY {
@@ -36,9 +44,27 @@ impl Display {
       } else {
         DisplayKind::Standard
       },
+      info: unsafe { get_info(&info.szDevice, &info.rcMonitor) },
+    }
+  }
+
+  /// A null monitor handle marks a synthetic display.
+  pub(crate) fn synthetic(x: i32, y: i32, width: u32, height: u32) -> Self {
+    Self {
+      x,
+      y,
+      width,
+      height,
+      kind: DisplayKind::Primary,
+      handle: null_mut(),
+      info: DisplayInfo::default(),
     }
   }
 
+  pub(crate) fn is_synthetic(&self) -> bool {
+    self.handle.is_null()
+  }
+
   pub fn x(&self) -> i32 {
     self.x
   }
@@ -58,18 +84,94 @@ impl Display {
   pub fn kind(&self) -> DisplayKind {
     self.kind
   }
+
+  /// What's known about the monitor, nothing for a synthetic display.
+  pub fn info(&self) -> DisplayInfo {
+    self.info.clone()
+  }
+}
+
+fn to_string(wide: &[u16]) -> String {
+  let end = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+  String::from_utf16_lossy(&wide[..end])
+}
+
+/// What Windows knows of the monitor on the adapter output `device`, e.g.
+/// `\\.\DISPLAY1`, which is at `bounds` on the desktop.
+unsafe fn get_info(device: &[u16], bounds: &RECT) -> DisplayInfo {
+  let mut info = DisplayInfo::default();
+
+  let mut mode = DEVMODEW {
+    dmSize: size_of::<DEVMODEW>() as u16,
+    ..Default::default()
+  };
+  if EnumDisplaySettingsW(device.as_ptr(), ENUM_CURRENT_SETTINGS, &mut mode) == TRUE {
+    // 0 and 1 stand for the hardware's default rate.
+    if mode.dmDisplayFrequency > 1 {
+      info.refresh_rate = Some(f64::from(mode.dmDisplayFrequency));
+    }
+
+    // Orientations are measured clockwise.
+    info.rotation = match mode.u1.s2().dmDisplayOrientation {
+      DMDO_90 => Rotation::Right,
+      DMDO_180 => Rotation::Inverted,
+      DMDO_270 => Rotation::Left,
+      _ => Rotation::Normal,
+    };
+
+    // Processes that aren't DPI-aware see scaled monitors smaller, in logical pixels.
+    let width = bounds.right - bounds.left;
+    if width > 0 && mode.dmPelsWidth > 0 {
+      info.scale = Some(f64::from(mode.dmPelsWidth) / f64::from(width));
+    }
+  }
+
+  let dc = CreateDCW(null(), device.as_ptr(), null(), null());
+  if !dc.is_null() {
+    let (width, height) = (GetDeviceCaps(dc, HORZSIZE), GetDeviceCaps(dc, VERTSIZE));
+    if width > 0 && height > 0 {
+      info.physical_size = Some((width as u32, height as u32));
+    }
+    DeleteDC(dc);
+  }
+
+  // The monitor's device ID, e.g. `MONITOR\DEL40A3\{...}\0001`, holds its PNP ID and
+  // product code, though not its serial.
+  let mut monitor = DISPLAY_DEVICEW {
+    cb: size_of::<DISPLAY_DEVICEW>() as u32,
+    ..Default::default()
+  };
+  let id = if EnumDisplayDevicesW(device.as_ptr(), 0, &mut monitor, 0) == TRUE {
+    let product = to_string(&monitor.DeviceID)
+      .split('\\')
+      .nth(1)
+      .filter(|product| product.len() == 7)
+      .map(ToOwned::to_owned);
+    if let Some(product) = product {
+      info.make = Some(product[..3].to_owned());
+      info.model = Some(product[3..].to_owned());
+    }
+
+    info.name = Some(to_string(&monitor.DeviceString));
+    Some(to_string(&monitor.DeviceID))
+  } else {
+    None
+  };
+
+  info.identify(&id.unwrap_or_else(|| to_string(device)));
+  info
 }
 
 pub fn get_primary() -> Result<Display> {
   let point = POINT::default();
   let handle = unsafe { MonitorFromPoint(point, MONITORINFOF_PRIMARY) };
 
-  let mut info = MONITORINFO::default();
+  let mut info = MONITORINFOEXW::default();
   let info_ptr: *mut _ = &mut info;
 
-  info.cbSize = size_of::<MONITORINFO>() as u32;
+  info.cbSize = size_of::<MONITORINFOEXW>() as u32;
 
-  let result = unsafe { GetMonitorInfoW(handle, info_ptr) };
+  let result = unsafe { GetMonitorInfoW(handle, info_ptr as *mut _) };
   if result == TRUE {
     Ok(Display::new(info, handle))
   } else {
@@ -97,13 +199,13 @@ unsafe extern "system" fn display_enumerator(
   _: LPRECT,
   data: LPARAM,
 ) -> BOOL {
-  let monitors: &mut Vec<Display> = std::mem::transmute(data);
-  let mut info = MONITORINFO::default();
+  let monitors = &mut *(data as *mut Vec<Display>);
+  let mut info = MONITORINFOEXW::default();
   let info_ptr: *mut _ = &mut info;
 
-  info.cbSize = size_of::<MONITORINFO>() as u32;
+  info.cbSize = size_of::<MONITORINFOEXW>() as u32;
 
-  let result = GetMonitorInfoW(handle, info_ptr);
+  let result = GetMonitorInfoW(handle, info_ptr as *mut _);
   if result == TRUE {
     monitors.push(Display::new(info, handle));
   }
@@ -0,0 +1,149 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Why a display couldn't be found or captured.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+  /// The backend can't work here, e.g. there's no X server, the compositor lacks the
+  /// protocol, or a library is missing. Another backend may still work.
+  BackendUnavailable(String),
+  /// The OS or the user refused, e.g. screen recording isn't allowed or the portal
+  /// dialog was cancelled.
+  PermissionDenied(String),
+  /// The display, window or connection went away.
+  DisplayDisconnected(String),
+  /// The display was resized, moved or rotated since the capture opened, so frames
+  /// would no longer match it. Open a new capture, e.g. on [`DisplayEvent::Changed`].
+  ///
+  /// [`DisplayEvent::Changed`]: crate::display::DisplayEvent::Changed
+  DisplayChanged(String),
+  /// The display's pixels are in a layout frames can't describe.
+  UnsupportedFormat(String),
+  /// An argument or option that can't work, e.g. a window id that doesn't exist.
+  InvalidInput(String),
+  /// An error code from the backend's own API, which isn't an OS error number.
+  Backend { backend: &'static str, code: i64 },
+  /// Reading or writing failed, e.g. a recording or a socket.
+  Io(io::Error),
+}
+
+impl Error {
+  pub(crate) fn unavailable(message: impl Display) -> Self {
+    Self::BackendUnavailable(message.to_string())
+  }
+
+  pub(crate) fn denied(message: impl Display) -> Self {
+    Self::PermissionDenied(message.to_string())
+  }
+
+  pub(crate) fn disconnected(message: impl Display) -> Self {
+    Self::DisplayDisconnected(message.to_string())
+  }
+
+  pub(crate) fn changed(message: impl Display) -> Self {
+    Self::DisplayChanged(message.to_string())
+  }
+
+  pub(crate) fn unsupported(message: impl Display) -> Self {
+    Self::UnsupportedFormat(message.to_string())
+  }
+
+  pub(crate) fn invalid(message: impl Display) -> Self {
+    Self::InvalidInput(message.to_string())
+  }
+
+  /// Malformed data from a file or a peer, as an I/O error.
+  pub(crate) fn invalid_data(message: &str) -> Self {
+    Self::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+  }
+
+  /// Something that shouldn't happen, as an I/O error.
+  #[cfg(all(target_os = "linux", any(feature = "x11", feature = "pipewire")))]
+  pub(crate) fn other(message: &str) -> Self {
+    Self::Io(io::Error::other(message))
+  }
+
+  /// Quartz reports its own error codes instead.
+  #[cfg(not(target_os = "macos"))]
+  pub(crate) fn last_os_error() -> Self {
+    io::Error::last_os_error().into()
+  }
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::BackendUnavailable(message) => write!(f, "Backend unavailable: {}", message),
+      Self::PermissionDenied(message) => write!(f, "Permission denied: {}", message),
+      Self::DisplayDisconnected(message) => {
+        write!(f, "Display disconnected: {}", message)
+      }
+      Self::DisplayChanged(message) => write!(f, "Display changed: {}", message),
+      Self::UnsupportedFormat(message) => write!(f, "Unsupported format: {}", message),
+      Self::InvalidInput(message) => write!(f, "Invalid input: {}", message),
+      Self::Backend { backend, code } => write!(f, "{} error {}", backend, code),
+      Self::Io(error) => error.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Io(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(target_os = "macos")]
+impl From<crate::ffi::macos::CGError> for Error {
+  fn from(error: crate::ffi::macos::CGError) -> Self {
+    Self::Backend {
+      backend: "quartz",
+      code: error as i64,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    match error.kind() {
+      io::ErrorKind::PermissionDenied => Self::PermissionDenied(error.to_string()),
+      _ => Self::Io(error),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+
+  use crate::Error;
+
+  #[test]
+  fn test_from_io() {
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    assert!(matches!(Error::from(denied), Error::PermissionDenied(_)));
+
+    let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+    assert!(matches!(Error::from(eof), Error::Io(_)));
+  }
+
+  #[test]
+  fn test_display() {
+    let error = Error::Backend {
+      backend: "quartz",
+      code: 1001,
+    };
+
+    assert_eq!(error.to_string(), "quartz error 1001");
+    assert_eq!(
+      Error::unavailable("No X server").to_string(),
+      "Backend unavailable: No X server"
+    );
+  }
+}
@@ -0,0 +1,172 @@
+#![allow(dead_code)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+
+use std::os::raw::{c_char, c_int, c_ulong, c_void};
+
+pub const DRM_MODE_CONNECTED: c_int = 1;
+
+pub const DRM_MODE_FB_MODIFIERS: u32 = 1 << 1;
+
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// Little-endian fourcc codes, named after their layout in a 32-bit word.
+pub const DRM_FORMAT_XRGB8888: u32 = 0x3432_5258;
+pub const DRM_FORMAT_ARGB8888: u32 = 0x3432_5241;
+pub const DRM_FORMAT_XBGR8888: u32 = 0x3432_4258;
+pub const DRM_FORMAT_ABGR8888: u32 = 0x3432_4241;
+pub const DRM_FORMAT_RGB565: u32 = 0x3631_4752;
+
+pub const DRM_CLOEXEC: u32 = 0o2000000;
+pub const DRM_RDWR: u32 = 0o2;
+
+pub const DRM_IOCTL_MODE_MAP_DUMB: c_ulong = 0xc010_64b3;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct drm_mode_map_dumb {
+  pub handle: u32,
+  pub pad: u32,
+  pub offset: u64,
+}
+
+#[repr(C)]
+pub struct drmModeRes {
+  pub count_fbs: c_int,
+  pub fbs: *mut u32,
+  pub count_crtcs: c_int,
+  pub crtcs: *mut u32,
+  pub count_connectors: c_int,
+  pub connectors: *mut u32,
+  pub count_encoders: c_int,
+  pub encoders: *mut u32,
+  pub min_width: u32,
+  pub max_width: u32,
+  pub min_height: u32,
+  pub max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct drmModeModeInfo {
+  pub clock: u32,
+  pub hdisplay: u16,
+  pub hsync_start: u16,
+  pub hsync_end: u16,
+  pub htotal: u16,
+  pub hskew: u16,
+  pub vdisplay: u16,
+  pub vsync_start: u16,
+  pub vsync_end: u16,
+  pub vtotal: u16,
+  pub vscan: u16,
+  pub vrefresh: u32,
+  pub flags: u32,
+  pub kind: u32,
+  pub name: [c_char; 32],
+}
+
+#[repr(C)]
+pub struct drmModeConnector {
+  pub connector_id: u32,
+  pub encoder_id: u32,
+  pub connector_type: u32,
+  pub connector_type_id: u32,
+  pub connection: c_int,
+  pub mmWidth: u32,
+  pub mmHeight: u32,
+  pub subpixel: c_int,
+  pub count_modes: c_int,
+  pub modes: *mut drmModeModeInfo,
+  pub count_props: c_int,
+  pub props: *mut u32,
+  pub prop_values: *mut u64,
+  pub count_encoders: c_int,
+  pub encoders: *mut u32,
+}
+
+#[repr(C)]
+pub struct drmModeEncoder {
+  pub encoder_id: u32,
+  pub encoder_type: u32,
+  pub crtc_id: u32,
+  pub possible_crtcs: u32,
+  pub possible_clones: u32,
+}
+
+#[repr(C)]
+pub struct drmModeCrtc {
+  pub crtc_id: u32,
+  pub buffer_id: u32,
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+  pub mode_valid: c_int,
+  pub mode: drmModeModeInfo,
+  pub gamma_size: c_int,
+}
+
+#[repr(C)]
+pub struct drmModePropertyRes {
+  pub prop_id: u32,
+  pub flags: u32,
+  pub name: [c_char; 32],
+  pub count_values: c_int,
+  pub values: *mut u64,
+  pub count_enums: c_int,
+  pub enums: *mut c_void,
+  pub count_blobs: c_int,
+  pub blob_ids: *mut u32,
+}
+
+#[repr(C)]
+pub struct drmModePropertyBlobRes {
+  pub id: u32,
+  pub length: u32,
+  pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct drmModeFB2 {
+  pub fb_id: u32,
+  pub width: u32,
+  pub height: u32,
+  pub pixel_format: u32,
+  pub modifier: u64,
+  pub flags: u32,
+  pub handles: [u32; 4],
+  pub pitches: [u32; 4],
+  pub offsets: [u32; 4],
+}
+
+#[link(name = "drm")]
+extern "C" {
+  pub fn drmIoctl(fd: c_int, request: c_ulong, arg: *mut c_void) -> c_int;
+  pub fn drmPrimeHandleToFD(
+    fd: c_int,
+    handle: u32,
+    flags: u32,
+    prime_fd: *mut c_int,
+  ) -> c_int;
+  pub fn drmCloseBufferHandle(fd: c_int, handle: u32) -> c_int;
+
+  // Mode setting
+
+  pub fn drmModeGetResources(fd: c_int) -> *mut drmModeRes;
+  pub fn drmModeFreeResources(resources: *mut drmModeRes);
+  pub fn drmModeGetConnector(fd: c_int, connector_id: u32) -> *mut drmModeConnector;
+  pub fn drmModeFreeConnector(connector: *mut drmModeConnector);
+  pub fn drmModeGetEncoder(fd: c_int, encoder_id: u32) -> *mut drmModeEncoder;
+  pub fn drmModeFreeEncoder(encoder: *mut drmModeEncoder);
+  pub fn drmModeGetCrtc(fd: c_int, crtc_id: u32) -> *mut drmModeCrtc;
+  pub fn drmModeFreeCrtc(crtc: *mut drmModeCrtc);
+  pub fn drmModeGetProperty(fd: c_int, property_id: u32) -> *mut drmModePropertyRes;
+  pub fn drmModeFreeProperty(property: *mut drmModePropertyRes);
+  pub fn drmModeGetPropertyBlob(fd: c_int, blob_id: u32) -> *mut drmModePropertyBlobRes;
+  pub fn drmModeFreePropertyBlob(blob: *mut drmModePropertyBlobRes);
+  pub fn drmModeGetFB2(fd: c_int, fb_id: u32) -> *mut drmModeFB2;
+  pub fn drmModeFreeFB2(fb: *mut drmModeFB2);
+}
@@ -0,0 +1,126 @@
+#![allow(dead_code)]
+#![allow(non_camel_case_types)]
+
+use std::os::raw::{c_char, c_int, c_long, c_short, c_uint, c_ulong, c_void};
+
+pub type off_t = c_long;
+
+pub const IPC_PRIVATE: c_int = 0;
+pub const IPC_CREAT: c_int = 0o1000;
+pub const IPC_RMID: c_int = 0;
+
+pub const PROT_READ: c_int = 0x1;
+pub const PROT_WRITE: c_int = 0x2;
+pub const MAP_SHARED: c_int = 0x01;
+pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+pub const ENODEV: c_int = 19;
+
+pub const POLLIN: c_short = 0x001;
+
+pub const MFD_CLOEXEC: c_uint = 0x0001;
+
+pub const FBIOGET_VSCREENINFO: c_ulong = 0x4600;
+pub const FBIOGET_FSCREENINFO: c_ulong = 0x4602;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct pollfd {
+  pub fd: c_int,
+  pub events: c_short,
+  pub revents: c_short,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct fb_bitfield {
+  pub offset: u32,
+  pub length: u32,
+  pub msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct fb_var_screeninfo {
+  pub xres: u32,
+  pub yres: u32,
+  pub xres_virtual: u32,
+  pub yres_virtual: u32,
+  pub xoffset: u32,
+  pub yoffset: u32,
+  pub bits_per_pixel: u32,
+  pub grayscale: u32,
+  pub red: fb_bitfield,
+  pub green: fb_bitfield,
+  pub blue: fb_bitfield,
+  pub transp: fb_bitfield,
+  pub nonstd: u32,
+  pub activate: u32,
+  pub height: u32,
+  pub width: u32,
+  pub accel_flags: u32,
+  pub pixclock: u32,
+  pub left_margin: u32,
+  pub right_margin: u32,
+  pub upper_margin: u32,
+  pub lower_margin: u32,
+  pub hsync_len: u32,
+  pub vsync_len: u32,
+  pub sync: u32,
+  pub vmode: u32,
+  pub rotate: u32,
+  pub colorspace: u32,
+  pub reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct fb_fix_screeninfo {
+  pub id: [c_char; 16],
+  pub smem_start: c_ulong,
+  pub smem_len: u32,
+  pub kind: u32,
+  pub type_aux: u32,
+  pub visual: u32,
+  pub xpanstep: u16,
+  pub ypanstep: u16,
+  pub ywrapstep: u16,
+  pub line_length: u32,
+  pub mmio_start: c_ulong,
+  pub mmio_len: u32,
+  pub accel: u32,
+  pub capabilities: u16,
+  pub reserved: [u16; 2],
+}
+
+extern "C" {
+  // System V shared memory
+
+  pub fn shmget(key: c_int, size: usize, flags: c_int) -> c_int;
+  pub fn shmat(id: c_int, addr: *const c_void, flags: c_int) -> *mut c_void;
+  pub fn shmdt(addr: *const c_void) -> c_int;
+  pub fn shmctl(id: c_int, cmd: c_int, buf: *mut c_void) -> c_int;
+
+  // Memory mapping
+
+  pub fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+
+  pub fn mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: off_t,
+  ) -> *mut c_void;
+
+  pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+
+  // Devices
+
+  pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+
+  // Polling
+
+  pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
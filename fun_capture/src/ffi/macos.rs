@@ -13,6 +13,7 @@ pub type IOSurfaceRef = *mut c_void;
 pub type DispatchQueue = *mut c_void;
 pub type DispatchQueueAttr = *mut c_void;
 pub type CFAllocatorRef = *mut c_void;
+pub type CGDisplayModeRef = *mut c_void;
 
 #[repr(C)]
 pub struct CFDictionaryKeyCallBacks {
@@ -47,6 +48,7 @@ pub fn cfbool(x: bool) -> CFBooleanRef {
 
 #[repr(i32)]
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
 pub enum CGDisplayStreamFrameStatus {
   /// A new frame was generated.
   FrameComplete = 0,
@@ -56,8 +58,6 @@ pub enum CGDisplayStreamFrameStatus {
   FrameBlank = 2,
   /// The display stream was stopped.
   Stopped = 3,
-  #[doc(hidden)]
-  __Nonexhaustive,
 }
 
 #[repr(i32)]
@@ -88,6 +88,7 @@ pub enum CFNumberType {
 #[repr(i32)]
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 #[must_use]
+#[non_exhaustive]
 pub enum CGError {
   Success = 0,
   Failure = 1000,
@@ -100,12 +101,11 @@ pub enum CGError {
   TypeCheck = 1008,
   InvalidOperation = 1010,
   NoneAvailable = 1011,
-  #[doc(hidden)]
-  __Nonexhaustive,
 }
 
 #[repr(i32)]
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
 pub enum PixelFormat {
   /// Packed Little Endian ARGB8888
   Argb8888 = pixel_format!('B', 'G', 'R', 'A'),
@@ -115,8 +115,15 @@ pub enum PixelFormat {
   YCbCr420Video = pixel_format!('4', '2', '0', 'v'),
   /// 2-plane "full" range YCbCr 4:2:0
   YCbCr420Full = pixel_format!('4', '2', '0', 'f'),
-  #[doc(hidden)]
-  __Nonexhaustive,
+}
+
+#[repr(i32)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CGDisplayStreamUpdateRectType {
+  RefreshedRects = 0,
+  MovedRects = 1,
+  DirtyRects = 2,
+  ReducedDirtyRects = 3,
 }
 
 pub type CGDisplayStreamFrameAvailableHandler = Block<
@@ -141,14 +148,14 @@ pub type FrameAvailableHandler = RcBlock<
 
 #[repr(C)]
 pub struct CGPoint {
-  pub x: u64,
-  pub y: u64,
+  pub x: f64,
+  pub y: f64,
 }
 
 #[repr(C)]
 pub struct CGSize {
-  pub width: u64,
-  pub height: u64,
+  pub width: f64,
+  pub height: f64,
 }
 
 #[repr(C)]
@@ -157,18 +164,18 @@ pub struct CGRect {
   pub size: CGSize,
 }
 
-#[link(name = "System", kind = "dylib")]
 #[link(name = "CoreGraphics", kind = "framework")]
-#[link(name = "CoreFoundation", kind = "framework")]
-#[link(name = "IOSurface", kind = "framework")]
 extern "C" {
-  // CoreGraphics
-
   pub static kCGDisplayStreamShowCursor: CFStringRef;
   pub static kCGDisplayStreamPreserveAspectRatio: CFStringRef;
   pub static kCGDisplayStreamMinimumFrameTime: CFStringRef;
   pub static kCGDisplayStreamQueueDepth: CFStringRef;
+  pub static kCGDisplayStreamSourceRect: CFStringRef;
+  pub static kCGDisplayStreamYCbCrMatrix: CFStringRef;
+  pub static kCGDisplayStreamYCbCrMatrix_ITU_R_709_2: CFStringRef;
 
+  // Blocks are only ever passed by reference, which C sees as a plain pointer.
+  #[allow(improper_ctypes)]
   pub fn CGDisplayStreamCreateWithDispatchQueue(
     display: u32,
     output_width: usize,
@@ -181,12 +188,21 @@ extern "C" {
 
   pub fn CGDisplayStreamStart(displayStream: CGDisplayStreamRef) -> CGError;
 
+  pub fn CGPreflightScreenCaptureAccess() -> bool;
+
   pub fn CGDisplayStreamStop(displayStream: CGDisplayStreamRef) -> CGError;
 
+  pub fn CGDisplayStreamUpdateGetRects(
+    updateRef: CGDisplayStreamUpdateRef,
+    rectType: CGDisplayStreamUpdateRectType,
+    rectCount: *mut usize,
+  ) -> *const CGRect;
+
   pub fn CGMainDisplayID() -> u32;
   pub fn CGDisplayPixelsWide(display: u32) -> usize;
   pub fn CGDisplayPixelsHigh(display: u32) -> usize;
   pub fn CGDisplayBounds(display: u32) -> CGRect;
+  pub fn CGRectCreateDictionaryRepresentation(rect: CGRect) -> CFDictionaryRef;
 
   pub fn CGGetOnlineDisplayList(
     max_displays: u32,
@@ -199,26 +215,49 @@ extern "C" {
   pub fn CGDisplayIsActive(display: u32) -> i32;
   pub fn CGDisplayIsOnline(display: u32) -> i32;
 
-  // IOSurface
+  pub fn CGDisplayVendorNumber(display: u32) -> u32;
+  pub fn CGDisplayModelNumber(display: u32) -> u32;
+  pub fn CGDisplaySerialNumber(display: u32) -> u32;
+  pub fn CGDisplayScreenSize(display: u32) -> CGSize;
+  pub fn CGDisplayRotation(display: u32) -> f64;
+
+  pub fn CGDisplayCopyDisplayMode(display: u32) -> CGDisplayModeRef;
+  pub fn CGDisplayModeGetRefreshRate(mode: CGDisplayModeRef) -> f64;
+  pub fn CGDisplayModeGetWidth(mode: CGDisplayModeRef) -> usize;
+  pub fn CGDisplayModeGetPixelWidth(mode: CGDisplayModeRef) -> usize;
+  pub fn CGDisplayModeRelease(mode: CGDisplayModeRef);
+}
 
+#[link(name = "IOSurface", kind = "framework")]
+extern "C" {
   pub fn IOSurfaceGetAllocSize(buffer: IOSurfaceRef) -> usize;
+  pub fn IOSurfaceGetWidth(buffer: IOSurfaceRef) -> usize;
+  pub fn IOSurfaceGetHeight(buffer: IOSurfaceRef) -> usize;
+  pub fn IOSurfaceGetBytesPerRow(buffer: IOSurfaceRef) -> usize;
   pub fn IOSurfaceGetBaseAddress(buffer: IOSurfaceRef) -> *mut c_void;
+  pub fn IOSurfaceGetBaseAddressOfPlane(
+    buffer: IOSurfaceRef,
+    plane: usize,
+  ) -> *mut c_void;
+  pub fn IOSurfaceGetBytesPerRowOfPlane(buffer: IOSurfaceRef, plane: usize) -> usize;
   pub fn IOSurfaceIncrementUseCount(buffer: IOSurfaceRef);
   pub fn IOSurfaceDecrementUseCount(buffer: IOSurfaceRef);
   pub fn IOSurfaceLock(buffer: IOSurfaceRef, options: u32, seed: *mut u32) -> i32;
   pub fn IOSurfaceUnlock(buffer: IOSurfaceRef, options: u32, seed: *mut u32) -> i32;
+}
 
-  // Dispatch
-
+#[link(name = "System", kind = "dylib")]
+extern "C" {
   pub fn dispatch_queue_create(
     label: *const i8,
     attr: DispatchQueueAttr,
   ) -> DispatchQueue;
 
   pub fn dispatch_release(object: DispatchQueue);
+}
 
-  // Core Foundation
-
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
   pub static kCFTypeDictionaryKeyCallBacks: CFDictionaryKeyCallBacks;
   pub static kCFTypeDictionaryValueCallBacks: CFDictionaryValueCallBacks;
 
@@ -1,3 +1,12 @@
-
+#[cfg(all(target_os = "linux", feature = "drm"))]
+pub mod drm;
+#[cfg(target_os = "linux")]
+pub mod linux;
 #[cfg(target_os = "macos")]
 pub mod macos;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub mod pipewire;
+#[cfg(all(target_os = "linux", feature = "udev"))]
+pub mod udev;
+#[cfg(all(target_os = "linux", feature = "x11"))]
+pub mod x11;
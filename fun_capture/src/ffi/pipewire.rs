@@ -0,0 +1,288 @@
+#![allow(dead_code)]
+#![allow(non_camel_case_types)]
+#![allow(non_upper_case_globals)]
+
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::null_mut;
+
+pub enum pw_thread_loop {}
+pub enum pw_loop {}
+pub enum pw_context {}
+pub enum pw_core {}
+pub enum pw_stream {}
+pub enum pw_properties {}
+pub enum spa_dict {}
+
+pub const PW_ID_ANY: u32 = 0xffffffff;
+
+pub const PW_VERSION_STREAM_EVENTS: u32 = 0;
+
+pub const PW_STREAM_STATE_ERROR: c_int = -1;
+pub const PW_STREAM_STATE_UNCONNECTED: c_int = 0;
+pub const PW_STREAM_STATE_CONNECTING: c_int = 1;
+pub const PW_STREAM_STATE_PAUSED: c_int = 2;
+pub const PW_STREAM_STATE_STREAMING: c_int = 3;
+
+pub const PW_STREAM_FLAG_AUTOCONNECT: u32 = 1 << 0;
+pub const PW_STREAM_FLAG_MAP_BUFFERS: u32 = 1 << 2;
+pub const PW_STREAM_FLAG_DRIVER: u32 = 1 << 3;
+
+pub const SPA_DIRECTION_INPUT: u32 = 0;
+pub const SPA_DIRECTION_OUTPUT: u32 = 1;
+
+// POD types
+
+pub const SPA_TYPE_Id: u32 = 3;
+pub const SPA_TYPE_Int: u32 = 4;
+pub const SPA_TYPE_Rectangle: u32 = 10;
+pub const SPA_TYPE_Fraction: u32 = 11;
+pub const SPA_TYPE_Object: u32 = 15;
+pub const SPA_TYPE_Choice: u32 = 19;
+
+pub const SPA_TYPE_OBJECT_Format: u32 = 0x40003;
+pub const SPA_TYPE_OBJECT_ParamBuffers: u32 = 0x40004;
+pub const SPA_TYPE_OBJECT_ParamMeta: u32 = 0x40005;
+
+pub const SPA_CHOICE_None: u32 = 0;
+pub const SPA_CHOICE_Range: u32 = 1;
+pub const SPA_CHOICE_Enum: u32 = 3;
+pub const SPA_CHOICE_Flags: u32 = 4;
+
+// Params
+
+pub const SPA_PARAM_EnumFormat: u32 = 3;
+pub const SPA_PARAM_Format: u32 = 4;
+pub const SPA_PARAM_Buffers: u32 = 5;
+pub const SPA_PARAM_Meta: u32 = 6;
+
+pub const SPA_FORMAT_mediaType: u32 = 1;
+pub const SPA_FORMAT_mediaSubtype: u32 = 2;
+pub const SPA_FORMAT_VIDEO_format: u32 = 0x20001;
+pub const SPA_FORMAT_VIDEO_size: u32 = 0x20003;
+pub const SPA_FORMAT_VIDEO_framerate: u32 = 0x20004;
+
+pub const SPA_MEDIA_TYPE_video: u32 = 2;
+pub const SPA_MEDIA_SUBTYPE_raw: u32 = 1;
+
+pub const SPA_VIDEO_FORMAT_BGRx: u32 = 8;
+pub const SPA_VIDEO_FORMAT_BGRA: u32 = 12;
+
+pub const SPA_PARAM_BUFFERS_buffers: u32 = 1;
+pub const SPA_PARAM_BUFFERS_blocks: u32 = 2;
+pub const SPA_PARAM_BUFFERS_size: u32 = 3;
+pub const SPA_PARAM_BUFFERS_stride: u32 = 4;
+pub const SPA_PARAM_BUFFERS_dataType: u32 = 6;
+
+pub const SPA_PARAM_META_type: u32 = 1;
+pub const SPA_PARAM_META_size: u32 = 2;
+
+pub const SPA_META_VideoDamage: u32 = 3;
+
+pub const SPA_DATA_MemPtr: u32 = 1;
+pub const SPA_DATA_MemFd: u32 = 2;
+
+#[repr(C)]
+pub struct spa_pod {
+  pub size: u32,
+  pub kind: u32,
+}
+
+#[repr(C)]
+pub struct spa_list {
+  pub next: *mut spa_list,
+  pub prev: *mut spa_list,
+}
+
+#[repr(C)]
+pub struct spa_callbacks {
+  pub funcs: *const c_void,
+  pub data: *mut c_void,
+}
+
+/// Must stay put while registered, as PipeWire links it into a list.
+#[repr(C)]
+pub struct spa_hook {
+  pub link: spa_list,
+  pub cb: spa_callbacks,
+  pub removed: Option<unsafe extern "C" fn(*mut spa_hook)>,
+  pub priv_: *mut c_void,
+}
+
+impl Default for spa_hook {
+  fn default() -> Self {
+    Self {
+      link: spa_list {
+        next: null_mut(),
+        prev: null_mut(),
+      },
+      cb: spa_callbacks {
+        funcs: std::ptr::null(),
+        data: null_mut(),
+      },
+      removed: None,
+      priv_: null_mut(),
+    }
+  }
+}
+
+#[repr(C)]
+pub struct spa_point {
+  pub x: i32,
+  pub y: i32,
+}
+
+#[repr(C)]
+pub struct spa_rectangle {
+  pub width: u32,
+  pub height: u32,
+}
+
+#[repr(C)]
+pub struct spa_region {
+  pub position: spa_point,
+  pub size: spa_rectangle,
+}
+
+#[repr(C)]
+pub struct spa_meta_region {
+  pub region: spa_region,
+}
+
+#[repr(C)]
+pub struct spa_meta {
+  pub kind: u32,
+  pub size: u32,
+  pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct spa_chunk {
+  pub offset: u32,
+  pub size: u32,
+  pub stride: i32,
+  pub flags: i32,
+}
+
+#[repr(C)]
+pub struct spa_data {
+  pub kind: u32,
+  pub flags: u32,
+  pub fd: i64,
+  pub mapoffset: u32,
+  pub maxsize: u32,
+  pub data: *mut c_void,
+  pub chunk: *mut spa_chunk,
+}
+
+#[repr(C)]
+pub struct spa_buffer {
+  pub n_metas: u32,
+  pub n_datas: u32,
+  pub metas: *mut spa_meta,
+  pub datas: *mut spa_data,
+}
+
+/// Only the leading field, which is all we read, is common to every version.
+#[repr(C)]
+pub struct pw_buffer {
+  pub buffer: *mut spa_buffer,
+}
+
+#[repr(C)]
+pub struct pw_stream_events {
+  pub version: u32,
+  pub destroy: Option<unsafe extern "C" fn(data: *mut c_void)>,
+  pub state_changed: Option<
+    unsafe extern "C" fn(
+      data: *mut c_void,
+      old: c_int,
+      state: c_int,
+      error: *const c_char,
+    ),
+  >,
+  pub control_info:
+    Option<unsafe extern "C" fn(data: *mut c_void, id: u32, control: *const c_void)>,
+  pub io_changed: Option<
+    unsafe extern "C" fn(data: *mut c_void, id: u32, area: *mut c_void, size: u32),
+  >,
+  pub param_changed:
+    Option<unsafe extern "C" fn(data: *mut c_void, id: u32, param: *const spa_pod)>,
+  pub add_buffer: Option<unsafe extern "C" fn(data: *mut c_void, buffer: *mut pw_buffer)>,
+  pub remove_buffer:
+    Option<unsafe extern "C" fn(data: *mut c_void, buffer: *mut pw_buffer)>,
+  pub process: Option<unsafe extern "C" fn(data: *mut c_void)>,
+  pub drained: Option<unsafe extern "C" fn(data: *mut c_void)>,
+}
+
+#[link(name = "pipewire-0.3")]
+extern "C" {
+  pub fn pw_init(argc: *mut c_int, argv: *mut *mut *mut c_char);
+
+  // Thread loop
+
+  pub fn pw_thread_loop_new(
+    name: *const c_char,
+    props: *const spa_dict,
+  ) -> *mut pw_thread_loop;
+  pub fn pw_thread_loop_destroy(thread_loop: *mut pw_thread_loop);
+  pub fn pw_thread_loop_get_loop(thread_loop: *mut pw_thread_loop) -> *mut pw_loop;
+  pub fn pw_thread_loop_start(thread_loop: *mut pw_thread_loop) -> c_int;
+  pub fn pw_thread_loop_stop(thread_loop: *mut pw_thread_loop);
+  pub fn pw_thread_loop_lock(thread_loop: *mut pw_thread_loop);
+  pub fn pw_thread_loop_unlock(thread_loop: *mut pw_thread_loop);
+
+  // Context and core
+
+  pub fn pw_properties_new_string(args: *const c_char) -> *mut pw_properties;
+
+  pub fn pw_context_new(
+    main_loop: *mut pw_loop,
+    props: *mut pw_properties,
+    user_data_size: usize,
+  ) -> *mut pw_context;
+  pub fn pw_context_destroy(context: *mut pw_context);
+  pub fn pw_context_connect(
+    context: *mut pw_context,
+    props: *mut pw_properties,
+    user_data_size: usize,
+  ) -> *mut pw_core;
+  pub fn pw_context_connect_fd(
+    context: *mut pw_context,
+    fd: c_int,
+    props: *mut pw_properties,
+    user_data_size: usize,
+  ) -> *mut pw_core;
+  pub fn pw_core_disconnect(core: *mut pw_core) -> c_int;
+
+  // Streams
+
+  pub fn pw_stream_new(
+    core: *mut pw_core,
+    name: *const c_char,
+    props: *mut pw_properties,
+  ) -> *mut pw_stream;
+  pub fn pw_stream_destroy(stream: *mut pw_stream);
+  pub fn pw_stream_add_listener(
+    stream: *mut pw_stream,
+    listener: *mut spa_hook,
+    events: *const pw_stream_events,
+    data: *mut c_void,
+  );
+  pub fn pw_stream_connect(
+    stream: *mut pw_stream,
+    direction: u32,
+    target_id: u32,
+    flags: u32,
+    params: *mut *const spa_pod,
+    n_params: u32,
+  ) -> c_int;
+  pub fn pw_stream_disconnect(stream: *mut pw_stream) -> c_int;
+  pub fn pw_stream_get_node_id(stream: *mut pw_stream) -> u32;
+  pub fn pw_stream_update_params(
+    stream: *mut pw_stream,
+    params: *mut *const spa_pod,
+    n_params: u32,
+  ) -> c_int;
+  pub fn pw_stream_dequeue_buffer(stream: *mut pw_stream) -> *mut pw_buffer;
+  pub fn pw_stream_queue_buffer(stream: *mut pw_stream, buffer: *mut pw_buffer) -> c_int;
+}
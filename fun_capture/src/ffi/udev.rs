@@ -0,0 +1,30 @@
+#![allow(non_camel_case_types)]
+
+use std::os::raw::{c_char, c_int};
+
+pub enum udev {}
+pub enum udev_monitor {}
+pub enum udev_device {}
+
+#[link(name = "udev")]
+extern "C" {
+  pub fn udev_new() -> *mut udev;
+  pub fn udev_unref(udev: *mut udev) -> *mut udev;
+
+  pub fn udev_monitor_new_from_netlink(
+    udev: *mut udev,
+    name: *const c_char,
+  ) -> *mut udev_monitor;
+  pub fn udev_monitor_unref(monitor: *mut udev_monitor) -> *mut udev_monitor;
+
+  pub fn udev_monitor_filter_add_match_subsystem_devtype(
+    monitor: *mut udev_monitor,
+    subsystem: *const c_char,
+    devtype: *const c_char,
+  ) -> c_int;
+  pub fn udev_monitor_enable_receiving(monitor: *mut udev_monitor) -> c_int;
+  pub fn udev_monitor_get_fd(monitor: *mut udev_monitor) -> c_int;
+  pub fn udev_monitor_receive_device(monitor: *mut udev_monitor) -> *mut udev_device;
+
+  pub fn udev_device_unref(device: *mut udev_device) -> *mut udev_device;
+}
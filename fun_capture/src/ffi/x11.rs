@@ -0,0 +1,555 @@
+#![allow(dead_code)]
+#![allow(non_upper_case_globals)]
+#![allow(non_snake_case)]
+
+use std::os::raw::{
+  c_char, c_int, c_long, c_short, c_uchar, c_uint, c_ulong, c_ushort, c_void,
+};
+
+pub type XDisplay = *mut c_void;
+pub type Visual = *mut c_void;
+pub type XID = c_ulong;
+pub type Window = XID;
+pub type Drawable = XID;
+pub type Pixmap = XID;
+pub type Colormap = XID;
+pub type Atom = XID;
+pub type Damage = XID;
+pub type XserverRegion = XID;
+pub type RROutput = XID;
+pub type RRCrtc = XID;
+pub type RRMode = XID;
+pub type Rotation = c_ushort;
+pub type Connection = c_ushort;
+pub type SubpixelOrder = c_ushort;
+pub type Time = c_ulong;
+pub type Bool = c_int;
+pub type Status = c_int;
+
+pub const False: Bool = 0;
+pub const True: Bool = 1;
+
+pub const ZPixmap: c_int = 2;
+pub const AllPlanes: c_ulong = !0;
+
+pub const LSBFirst: c_int = 0;
+pub const MSBFirst: c_int = 1;
+
+pub const IsViewable: c_int = 2;
+pub const InputOutput: c_int = 1;
+pub const StructureNotifyMask: c_long = 1 << 17;
+
+pub const AnyPropertyType: Atom = 0;
+pub const XA_CARDINAL: Atom = 6;
+pub const XA_STRING: Atom = 31;
+pub const XA_WINDOW: Atom = 33;
+pub const XA_WM_NAME: Atom = 39;
+pub const XA_WM_CLASS: Atom = 67;
+pub const PropModeReplace: c_int = 0;
+pub const Success: c_int = 0;
+pub const BadWindow: c_int = 3;
+pub const BadMatch: c_int = 8;
+pub const BadDrawable: c_int = 9;
+
+pub const CompositeRedirectAutomatic: c_int = 0;
+
+pub const DPMSModeOn: c_ushort = 0;
+pub const DPMSModeStandby: c_ushort = 1;
+pub const DPMSModeSuspend: c_ushort = 2;
+pub const DPMSModeOff: c_ushort = 3;
+
+pub const RR_Connected: Connection = 0;
+pub const RR_Rotate_0: Rotation = 1;
+pub const RR_Rotate_90: Rotation = 2;
+pub const RR_Rotate_180: Rotation = 4;
+pub const RR_Rotate_270: Rotation = 8;
+
+pub const RRScreenChangeNotifyMask: c_int = 1 << 0;
+pub const RRCrtcChangeNotifyMask: c_int = 1 << 1;
+pub const RROutputChangeNotifyMask: c_int = 1 << 2;
+
+pub const XDamageReportNonEmpty: c_int = 3;
+pub const XDamageNotify: c_int = 0;
+
+#[repr(C)]
+pub struct XRRModeInfo {
+  pub id: RRMode,
+  pub width: c_uint,
+  pub height: c_uint,
+  pub dotClock: c_ulong,
+  pub hSyncStart: c_uint,
+  pub hSyncEnd: c_uint,
+  pub hTotal: c_uint,
+  pub hSkew: c_uint,
+  pub vSyncStart: c_uint,
+  pub vSyncEnd: c_uint,
+  pub vTotal: c_uint,
+  pub name: *mut c_char,
+  pub nameLength: c_uint,
+  pub modeFlags: c_ulong,
+}
+
+#[repr(C)]
+pub struct XRRScreenResources {
+  pub timestamp: Time,
+  pub configTimestamp: Time,
+  pub ncrtc: c_int,
+  pub crtcs: *mut RRCrtc,
+  pub noutput: c_int,
+  pub outputs: *mut RROutput,
+  pub nmode: c_int,
+  pub modes: *mut XRRModeInfo,
+}
+
+#[repr(C)]
+pub struct XRROutputInfo {
+  pub timestamp: Time,
+  pub crtc: RRCrtc,
+  pub name: *mut c_char,
+  pub nameLen: c_int,
+  pub mm_width: c_ulong,
+  pub mm_height: c_ulong,
+  pub connection: Connection,
+  pub subpixel_order: SubpixelOrder,
+  pub ncrtc: c_int,
+  pub crtcs: *mut RRCrtc,
+  pub nclone: c_int,
+  pub clones: *mut RROutput,
+  pub nmode: c_int,
+  pub npreferred: c_int,
+  pub modes: *mut RRMode,
+}
+
+#[repr(C)]
+pub struct XRRCrtcInfo {
+  pub timestamp: Time,
+  pub x: c_int,
+  pub y: c_int,
+  pub width: c_uint,
+  pub height: c_uint,
+  pub mode: RRMode,
+  pub rotation: Rotation,
+  pub noutput: c_int,
+  pub outputs: *mut RROutput,
+  pub rotations: Rotation,
+  pub npossible: c_int,
+  pub possible: *mut RROutput,
+}
+
+pub type XErrorHandler =
+  Option<unsafe extern "C" fn(XDisplay, *mut XErrorEvent) -> c_int>;
+
+#[repr(C)]
+pub struct XErrorEvent {
+  pub kind: c_int,
+  pub display: XDisplay,
+  pub resource_id: XID,
+  pub serial: c_ulong,
+  pub error_code: c_uchar,
+  pub request_code: c_uchar,
+  pub minor_code: c_uchar,
+}
+
+/// `XEvent` is a union padded to 24 longs, only its leading `type` is read directly.
+#[repr(C)]
+#[derive(Default)]
+pub struct XEvent {
+  pub kind: c_int,
+  pad: [c_long; 23],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct XRectangle {
+  pub x: c_short,
+  pub y: c_short,
+  pub width: c_ushort,
+  pub height: c_ushort,
+}
+
+#[repr(C)]
+pub struct XWindowAttributes {
+  pub x: c_int,
+  pub y: c_int,
+  pub width: c_int,
+  pub height: c_int,
+  pub border_width: c_int,
+  pub depth: c_int,
+  pub visual: Visual,
+  pub root: Window,
+  pub class: c_int,
+  pub bit_gravity: c_int,
+  pub win_gravity: c_int,
+  pub backing_store: c_int,
+  pub backing_planes: c_ulong,
+  pub backing_pixel: c_ulong,
+  pub save_under: Bool,
+  pub colormap: Colormap,
+  pub map_installed: Bool,
+  pub map_state: c_int,
+  pub all_event_masks: c_long,
+  pub your_event_mask: c_long,
+  pub do_not_propagate_mask: c_long,
+  pub override_redirect: Bool,
+  pub screen: *mut c_void,
+}
+
+#[repr(C)]
+pub struct XImageFuncs {
+  pub create_image: *const c_void,
+  pub destroy_image: Option<unsafe extern "C" fn(*mut XImage) -> c_int>,
+  pub get_pixel: *const c_void,
+  pub put_pixel: *const c_void,
+  pub sub_image: *const c_void,
+  pub add_pixel: *const c_void,
+}
+
+#[repr(C)]
+pub struct XImage {
+  pub width: c_int,
+  pub height: c_int,
+  pub xoffset: c_int,
+  pub format: c_int,
+  pub data: *mut c_char,
+  pub byte_order: c_int,
+  pub bitmap_unit: c_int,
+  pub bitmap_bit_order: c_int,
+  pub bitmap_pad: c_int,
+  pub depth: c_int,
+  pub bytes_per_line: c_int,
+  pub bits_per_pixel: c_int,
+  pub red_mask: c_ulong,
+  pub green_mask: c_ulong,
+  pub blue_mask: c_ulong,
+  pub obdata: *mut c_char,
+  pub f: XImageFuncs,
+}
+
+#[repr(C)]
+pub struct XFixesCursorImage {
+  pub x: c_short,
+  pub y: c_short,
+  pub width: c_ushort,
+  pub height: c_ushort,
+  pub xhot: c_ushort,
+  pub yhot: c_ushort,
+  pub cursor_serial: c_ulong,
+  /// ARGB8888 pixels, one per `unsigned long` (the upper bits are unused on LP64).
+  pub pixels: *mut c_ulong,
+  pub atom: Atom,
+  pub name: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct XShmSegmentInfo {
+  pub shmseg: XID,
+  pub shmid: c_int,
+  pub shmaddr: *mut c_char,
+  pub read_only: Bool,
+}
+
+/// `XDestroyImage` is a macro in Xlib, dispatching through the image's function table.
+///
+/// # Safety
+///
+/// `image` must be a live image returned by Xlib.
+pub unsafe fn XDestroyImage(image: *mut XImage) -> c_int {
+  match (*image).f.destroy_image {
+    Some(destroy_image) => destroy_image(image),
+    None => 0,
+  }
+}
+
+#[link(name = "X11")]
+extern "C" {
+  pub fn XOpenDisplay(name: *const c_char) -> XDisplay;
+  pub fn XCloseDisplay(display: XDisplay) -> c_int;
+  pub fn XDefaultScreen(display: XDisplay) -> c_int;
+  pub fn XScreenCount(display: XDisplay) -> c_int;
+  pub fn XRootWindow(display: XDisplay, screen: c_int) -> Window;
+  pub fn XDisplayWidth(display: XDisplay, screen: c_int) -> c_int;
+  pub fn XDisplayHeight(display: XDisplay, screen: c_int) -> c_int;
+  pub fn XDisplayWidthMM(display: XDisplay, screen: c_int) -> c_int;
+  pub fn XDisplayHeightMM(display: XDisplay, screen: c_int) -> c_int;
+  pub fn XDefaultVisual(display: XDisplay, screen: c_int) -> Visual;
+  pub fn XDefaultDepth(display: XDisplay, screen: c_int) -> c_int;
+
+  pub fn XGetImage(
+    display: XDisplay,
+    drawable: Drawable,
+    x: c_int,
+    y: c_int,
+    width: c_uint,
+    height: c_uint,
+    plane_mask: c_ulong,
+    format: c_int,
+  ) -> *mut XImage;
+
+  pub fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+  pub fn XSync(display: XDisplay, discard: Bool) -> c_int;
+  pub fn XPending(display: XDisplay) -> c_int;
+  pub fn XConnectionNumber(display: XDisplay) -> c_int;
+  pub fn XNextEvent(display: XDisplay, event: *mut XEvent) -> c_int;
+  pub fn XFree(data: *mut c_void) -> c_int;
+
+  pub fn XSelectInput(display: XDisplay, window: Window, mask: c_long) -> c_int;
+  pub fn XGetWindowAttributes(
+    display: XDisplay,
+    window: Window,
+    attributes: *mut XWindowAttributes,
+  ) -> Status;
+
+  pub fn XQueryTree(
+    display: XDisplay,
+    window: Window,
+    root: *mut Window,
+    parent: *mut Window,
+    children: *mut *mut Window,
+    count: *mut c_uint,
+  ) -> Status;
+
+  pub fn XTranslateCoordinates(
+    display: XDisplay,
+    src: Window,
+    dest: Window,
+    src_x: c_int,
+    src_y: c_int,
+    dest_x: *mut c_int,
+    dest_y: *mut c_int,
+    child: *mut Window,
+  ) -> Bool;
+
+  pub fn XInternAtom(
+    display: XDisplay,
+    name: *const c_char,
+    only_if_exists: Bool,
+  ) -> Atom;
+
+  pub fn XGetWindowProperty(
+    display: XDisplay,
+    window: Window,
+    property: Atom,
+    offset: c_long,
+    length: c_long,
+    delete: Bool,
+    kind: Atom,
+    actual_kind: *mut Atom,
+    actual_format: *mut c_int,
+    count: *mut c_ulong,
+    bytes_after: *mut c_ulong,
+    data: *mut *mut c_uchar,
+  ) -> c_int;
+
+  pub fn XChangeProperty(
+    display: XDisplay,
+    window: Window,
+    property: Atom,
+    kind: Atom,
+    format: c_int,
+    mode: c_int,
+    data: *const c_uchar,
+    count: c_int,
+  ) -> c_int;
+
+  pub fn XFreePixmap(display: XDisplay, pixmap: Pixmap) -> c_int;
+
+  pub fn XCreateSimpleWindow(
+    display: XDisplay,
+    parent: Window,
+    x: c_int,
+    y: c_int,
+    width: c_uint,
+    height: c_uint,
+    border_width: c_uint,
+    border: c_ulong,
+    background: c_ulong,
+  ) -> Window;
+
+  pub fn XDestroyWindow(display: XDisplay, window: Window) -> c_int;
+  pub fn XMapWindow(display: XDisplay, window: Window) -> c_int;
+  pub fn XUnmapWindow(display: XDisplay, window: Window) -> c_int;
+  pub fn XResizeWindow(
+    display: XDisplay,
+    window: Window,
+    width: c_uint,
+    height: c_uint,
+  ) -> c_int;
+  pub fn XStoreName(display: XDisplay, window: Window, name: *const c_char) -> c_int;
+
+  pub fn XSetWindowBackground(display: XDisplay, window: Window, pixel: c_ulong)
+    -> c_int;
+  pub fn XClearWindow(display: XDisplay, window: Window) -> c_int;
+}
+
+#[link(name = "Xext")]
+extern "C" {
+  pub fn XShmQueryExtension(display: XDisplay) -> Bool;
+
+  pub fn XShmCreateImage(
+    display: XDisplay,
+    visual: Visual,
+    depth: c_uint,
+    format: c_int,
+    data: *mut c_char,
+    shminfo: *mut XShmSegmentInfo,
+    width: c_uint,
+    height: c_uint,
+  ) -> *mut XImage;
+
+  pub fn XShmAttach(display: XDisplay, shminfo: *mut XShmSegmentInfo) -> Bool;
+  pub fn XShmDetach(display: XDisplay, shminfo: *mut XShmSegmentInfo) -> Bool;
+
+  pub fn XShmGetImage(
+    display: XDisplay,
+    drawable: Drawable,
+    image: *mut XImage,
+    x: c_int,
+    y: c_int,
+    plane_mask: c_ulong,
+  ) -> Bool;
+
+  pub fn DPMSQueryExtension(
+    display: XDisplay,
+    event_base: *mut c_int,
+    error_base: *mut c_int,
+  ) -> Bool;
+
+  pub fn DPMSCapable(display: XDisplay) -> Bool;
+  pub fn DPMSInfo(
+    display: XDisplay,
+    power_level: *mut c_ushort,
+    state: *mut c_uchar,
+  ) -> Status;
+  pub fn DPMSForceLevel(display: XDisplay, level: c_ushort) -> Status;
+}
+
+#[link(name = "Xfixes")]
+extern "C" {
+  pub fn XFixesQueryExtension(
+    display: XDisplay,
+    event_base: *mut c_int,
+    error_base: *mut c_int,
+  ) -> Bool;
+
+  pub fn XFixesQueryVersion(
+    display: XDisplay,
+    major: *mut c_int,
+    minor: *mut c_int,
+  ) -> Status;
+
+  pub fn XFixesGetCursorImage(display: XDisplay) -> *mut XFixesCursorImage;
+
+  pub fn XFixesCreateRegion(
+    display: XDisplay,
+    rectangles: *mut XRectangle,
+    count: c_int,
+  ) -> XserverRegion;
+
+  pub fn XFixesDestroyRegion(display: XDisplay, region: XserverRegion);
+
+  pub fn XFixesFetchRegion(
+    display: XDisplay,
+    region: XserverRegion,
+    count: *mut c_int,
+  ) -> *mut XRectangle;
+}
+
+#[link(name = "Xcomposite")]
+extern "C" {
+  pub fn XCompositeQueryExtension(
+    display: XDisplay,
+    event_base: *mut c_int,
+    error_base: *mut c_int,
+  ) -> Bool;
+
+  pub fn XCompositeQueryVersion(
+    display: XDisplay,
+    major: *mut c_int,
+    minor: *mut c_int,
+  ) -> Status;
+
+  pub fn XCompositeRedirectWindow(display: XDisplay, window: Window, update: c_int);
+  pub fn XCompositeUnredirectWindow(display: XDisplay, window: Window, update: c_int);
+  pub fn XCompositeNameWindowPixmap(display: XDisplay, window: Window) -> Pixmap;
+}
+
+#[link(name = "Xrandr")]
+extern "C" {
+  pub fn XRRQueryExtension(
+    display: XDisplay,
+    event_base: *mut c_int,
+    error_base: *mut c_int,
+  ) -> Bool;
+
+  pub fn XRRQueryVersion(
+    display: XDisplay,
+    major: *mut c_int,
+    minor: *mut c_int,
+  ) -> Status;
+
+  pub fn XRRGetScreenResourcesCurrent(
+    display: XDisplay,
+    window: Window,
+  ) -> *mut XRRScreenResources;
+  pub fn XRRFreeScreenResources(resources: *mut XRRScreenResources);
+
+  pub fn XRRGetOutputInfo(
+    display: XDisplay,
+    resources: *mut XRRScreenResources,
+    output: RROutput,
+  ) -> *mut XRROutputInfo;
+  pub fn XRRFreeOutputInfo(info: *mut XRROutputInfo);
+
+  pub fn XRRGetCrtcInfo(
+    display: XDisplay,
+    resources: *mut XRRScreenResources,
+    crtc: RRCrtc,
+  ) -> *mut XRRCrtcInfo;
+  pub fn XRRFreeCrtcInfo(info: *mut XRRCrtcInfo);
+
+  pub fn XRRGetOutputPrimary(display: XDisplay, window: Window) -> RROutput;
+
+  pub fn XRRSelectInput(display: XDisplay, window: Window, mask: c_int);
+
+  pub fn XRRGetOutputProperty(
+    display: XDisplay,
+    output: RROutput,
+    property: Atom,
+    offset: c_long,
+    length: c_long,
+    delete: Bool,
+    pending: Bool,
+    kind: Atom,
+    actual_kind: *mut Atom,
+    actual_format: *mut c_int,
+    count: *mut c_ulong,
+    bytes_after: *mut c_ulong,
+    data: *mut *mut c_uchar,
+  ) -> c_int;
+}
+
+#[link(name = "Xdamage")]
+extern "C" {
+  pub fn XDamageQueryExtension(
+    display: XDisplay,
+    event_base: *mut c_int,
+    error_base: *mut c_int,
+  ) -> Bool;
+
+  pub fn XDamageQueryVersion(
+    display: XDisplay,
+    major: *mut c_int,
+    minor: *mut c_int,
+  ) -> Status;
+
+  pub fn XDamageCreate(display: XDisplay, drawable: Drawable, level: c_int) -> Damage;
+  pub fn XDamageDestroy(display: XDisplay, damage: Damage);
+
+  pub fn XDamageSubtract(
+    display: XDisplay,
+    damage: Damage,
+    repair: XserverRegion,
+    parts: XserverRegion,
+  );
+}
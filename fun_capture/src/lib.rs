@@ -1,3 +1,15 @@
 pub mod ffi;
 pub mod capture;
 pub mod display;
+mod error;
+
+pub use error::{Error, Result};
+
+#[cfg(all(test, target_os = "linux", feature = "pipewire"))]
+mod pipewire_daemon;
+#[cfg(all(test, target_os = "linux", feature = "wayland"))]
+mod sway;
+#[cfg(test)]
+mod vnc_server;
+#[cfg(all(test, target_os = "linux"))]
+mod xvfb;
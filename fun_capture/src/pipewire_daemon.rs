@@ -0,0 +1,80 @@
+//! Spawns throwaway PipeWire daemons, with a session manager to link streams, for the
+//! PipeWire tests.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+static NEXT_DAEMON: AtomicU32 = AtomicU32::new(0);
+
+pub struct PipeWireDaemon {
+  runtime_dir: PathBuf,
+  remote: String,
+  pipewire: Child,
+  wireplumber: Child,
+}
+
+impl PipeWireDaemon {
+  pub fn start() -> Self {
+    let runtime_dir = std::env::temp_dir().join(format!(
+      "fun_capture-pipewire-{}-{}",
+      std::process::id(),
+      NEXT_DAEMON.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    fs::create_dir_all(&runtime_dir).unwrap();
+
+    let spawn = |program: &str| {
+      Command::new(program)
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .env("PIPEWIRE_RUNTIME_DIR", &runtime_dir)
+        .env_remove("DBUS_SESSION_BUS_ADDRESS")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap_or_else(|_| {
+          panic!("{} must be installed to run the PipeWire tests", program)
+        })
+    };
+
+    let pipewire = spawn("pipewire");
+    let socket = runtime_dir.join("pipewire-0");
+
+    let started = Instant::now();
+    while !socket.exists() {
+      assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "pipewire did not start"
+      );
+      sleep(Duration::from_millis(10));
+    }
+
+    let wireplumber = spawn("wireplumber");
+
+    Self {
+      remote: socket.to_string_lossy().into_owned(),
+      runtime_dir,
+      pipewire,
+      wireplumber,
+    }
+  }
+
+  /// The absolute path of the daemon's socket.
+  pub fn remote(&self) -> &str {
+    &self.remote
+  }
+}
+
+impl Drop for PipeWireDaemon {
+  fn drop(&mut self) {
+    for child in [&mut self.wireplumber, &mut self.pipewire] {
+      let _ = child.kill();
+      let _ = child.wait();
+    }
+
+    let _ = fs::remove_dir_all(&self.runtime_dir);
+  }
+}
@@ -0,0 +1,86 @@
+//! Spawns throwaway headless sway compositors for the Wayland tests.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+static NEXT_COMPOSITOR: AtomicU32 = AtomicU32::new(0);
+
+pub struct Sway {
+  runtime_dir: PathBuf,
+  socket: String,
+  child: Child,
+}
+
+impl Sway {
+  /// Starts sway with the headless wlroots backend and a single output.
+  pub fn start() -> Self {
+    let runtime_dir = std::env::temp_dir().join(format!(
+      "fun_capture-sway-{}-{}",
+      std::process::id(),
+      NEXT_COMPOSITOR.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let config = runtime_dir.join("config");
+    fs::create_dir_all(&runtime_dir).unwrap();
+    fs::write(&config, "").unwrap();
+
+    let child = Command::new("sway")
+      .arg("--config")
+      .arg(&config)
+      .env("XDG_RUNTIME_DIR", &runtime_dir)
+      .env("WLR_BACKENDS", "headless")
+      .env("WLR_RENDERER", "pixman")
+      .env("WLR_LIBINPUT_NO_DEVICES", "1")
+      .env_remove("WAYLAND_DISPLAY")
+      .env_remove("DISPLAY")
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn()
+      .expect("sway must be installed to run the Wayland tests");
+
+    let started = Instant::now();
+    let socket = loop {
+      let socket = fs::read_dir(&runtime_dir).unwrap().find_map(|entry| {
+        let name = entry.ok()?.file_name().into_string().ok()?;
+        if name.starts_with("wayland-") && !name.ends_with(".lock") {
+          Some(name)
+        } else {
+          None
+        }
+      });
+
+      if let Some(socket) = socket {
+        break runtime_dir.join(socket).to_string_lossy().into_owned();
+      }
+
+      assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "sway did not start"
+      );
+      sleep(Duration::from_millis(10));
+    };
+
+    Self {
+      runtime_dir,
+      socket,
+      child,
+    }
+  }
+
+  /// The absolute path of the compositor's socket.
+  pub fn socket(&self) -> &str {
+    &self.socket
+  }
+}
+
+impl Drop for Sway {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+    let _ = fs::remove_dir_all(&self.runtime_dir);
+  }
+}
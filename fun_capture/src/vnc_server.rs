@@ -0,0 +1,158 @@
+//! A stand-in RFB server for the VNC tests, which paints its framebuffer gray, then a
+//! white corner on the first incremental update, then nothing.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+
+use crate::capture::vnc::{vnc_auth_response, ReadBytes};
+
+const CHALLENGE: [u8; 16] = *b"0123456789abcdef";
+
+pub struct VncServer {
+  address: String,
+  stopped: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl VncServer {
+  pub fn start(width: u16, height: u16, password: Option<&str>) -> Self {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    let address = listener.local_addr().unwrap().to_string();
+    let stopped = Arc::new(AtomicBool::new(false));
+    let password = password.map(ToOwned::to_owned);
+
+    let thread = {
+      let stopped = Arc::clone(&stopped);
+      spawn(move || {
+        while !stopped.load(Ordering::SeqCst) {
+          match listener.accept() {
+            Ok((stream, _)) => {
+              let password = password.clone();
+              spawn(move || {
+                stream.set_nonblocking(false).unwrap();
+                let _ = serve(stream, width, height, password.as_deref());
+              });
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+              sleep(Duration::from_millis(5));
+            }
+            Err(err) => panic!("{}", err),
+          }
+        }
+      })
+    };
+
+    Self {
+      address,
+      stopped,
+      thread: Some(thread),
+    }
+  }
+
+  /// Where the server listens, e.g. `127.0.0.1:45678`.
+  pub fn address(&self) -> &str {
+    &self.address
+  }
+}
+
+impl Drop for VncServer {
+  fn drop(&mut self) {
+    self.stopped.store(true, Ordering::SeqCst);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+fn serve(
+  mut stream: TcpStream,
+  width: u16,
+  height: u16,
+  password: Option<&str>,
+) -> std::io::Result<()> {
+  stream.write_all(b"RFB 003.008\n")?;
+  stream.read_vec(12)?;
+
+  match password {
+    Some(password) => {
+      stream.write_all(&[1, 2])?;
+      stream.read_u8()?;
+      stream.write_all(&CHALLENGE)?;
+
+      let mut response = [0; 16];
+      stream.read_exact(&mut response)?;
+      if response != vnc_auth_response(password, CHALLENGE) {
+        let reason = b"Wrong password";
+        stream.write_all(&1u32.to_be_bytes())?;
+        stream.write_all(&(reason.len() as u32).to_be_bytes())?;
+        return stream.write_all(reason);
+      }
+    }
+    None => {
+      stream.write_all(&[1, 1])?;
+      stream.read_u8()?;
+    }
+  }
+
+  stream.write_all(&0u32.to_be_bytes())?;
+  stream.read_u8()?;
+
+  let name = b"fun_capture test";
+  let mut init = Vec::new();
+  init.extend_from_slice(&width.to_be_bytes());
+  init.extend_from_slice(&height.to_be_bytes());
+  init.extend_from_slice(&[32, 24, 0, 1, 0, 255, 0, 255, 0, 255, 16, 8, 0, 0, 0, 0]);
+  init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+  init.extend_from_slice(name);
+  stream.write_all(&init)?;
+
+  let mut updates = 0;
+  loop {
+    match stream.read_u8()? {
+      0 => {
+        stream.read_vec(19)?;
+      }
+      2 => {
+        stream.read_u8()?;
+        let count = stream.read_u16()?;
+        stream.read_vec(count as usize * 4)?;
+      }
+      3 => {
+        let incremental = stream.read_u8()? != 0;
+        stream.read_vec(8)?;
+
+        if !incremental {
+          let mut update = update_header(0, 0, width, height, 0);
+          for _ in 0..width as usize * height as usize {
+            update.extend_from_slice(&[0x40, 0x40, 0x40, 0]);
+          }
+          stream.write_all(&update)?;
+        } else if updates == 0 {
+          // A white Tight fill.
+          let mut update = update_header(0, 0, 2, 2, 7);
+          update.extend_from_slice(&[0x80, 0xff, 0xff, 0xff]);
+          stream.write_all(&update)?;
+          updates += 1;
+        }
+      }
+      kind => panic!("Unexpected client message {}", kind),
+    }
+  }
+}
+
+/// A FramebufferUpdate of a single rectangle.
+fn update_header(x: u16, y: u16, width: u16, height: u16, encoding: i32) -> Vec<u8> {
+  let mut header = vec![0, 0, 0, 1];
+  for value in [x, y, width, height] {
+    header.extend_from_slice(&value.to_be_bytes());
+  }
+
+  header.extend_from_slice(&encoding.to_be_bytes());
+  header
+}
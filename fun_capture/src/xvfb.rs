@@ -0,0 +1,68 @@
+//! Spawns throwaway Xvfb servers for the X11 tests.
+
+// Without the X11 tests, the XWD ones only need to start servers.
+#![cfg_attr(not(feature = "x11"), allow(dead_code))]
+
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+static NEXT_SERVER: AtomicU32 = AtomicU32::new(0);
+
+pub struct Xvfb {
+  name: String,
+  child: Child,
+}
+
+impl Xvfb {
+  /// Starts an Xvfb server with one screen per `WxHxD` entry in `screens`.
+  pub fn start(screens: &[&str]) -> Self {
+    Self::start_with(screens, &[])
+  }
+
+  /// Like [`Xvfb::start`], passing `args` through to Xvfb.
+  pub fn start_with(screens: &[&str], args: &[&str]) -> Self {
+    let number =
+      90 + std::process::id() % 1000 * 10 + NEXT_SERVER.fetch_add(1, Ordering::SeqCst);
+    let name = format!(":{}", number);
+
+    let mut command = Command::new("Xvfb");
+    command.arg(&name).args(["-nolisten", "tcp", "-noreset"]);
+    for (i, screen) in screens.iter().enumerate() {
+      command.arg("-screen").arg(i.to_string()).arg(screen);
+    }
+
+    let child = command
+      .args(args)
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .spawn()
+      .expect("Xvfb must be installed to run the X11 tests");
+
+    let socket = format!("/tmp/.X11-unix/X{}", number);
+    let started = Instant::now();
+    while !Path::new(&socket).exists() {
+      assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "Xvfb {} did not start",
+        name
+      );
+      sleep(Duration::from_millis(10));
+    }
+
+    Self { name, child }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
+
+impl Drop for Xvfb {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
@@ -0,0 +1,15 @@
+use fun_capture::capture::synthetic::{Pattern, SyntheticCapture};
+use fun_capture::capture::{Capture, CaptureOpts, Frame};
+use fun_capture::display::Display;
+
+fn main() {
+  let opts = CaptureOpts::new(Display::synthetic(64, 48));
+  let mut capture = SyntheticCapture::new(opts, Pattern::Gradient);
+  let frame = capture.frame();
+
+  drop(capture);
+
+  if let Ok(Frame::Ready(frame)) = frame {
+    println!("{}", frame.len());
+  }
+}
@@ -0,0 +1,9 @@
+use fun_capture::capture::{Capture, Frame};
+use fun_capture::Result;
+
+// Whatever the backend, a frame can't be kept past its capture without copying it.
+fn last_frame<C: Capture>(mut capture: C) -> Result<Frame<C::Frame<'static>>> {
+  capture.frame()
+}
+
+fn main() {}
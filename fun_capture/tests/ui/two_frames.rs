@@ -0,0 +1,12 @@
+use fun_capture::capture::synthetic::{Pattern, SyntheticCapture};
+use fun_capture::capture::{Capture, CaptureOpts};
+use fun_capture::display::Display;
+
+fn main() {
+  let opts = CaptureOpts::new(Display::synthetic(64, 48));
+  let mut capture = SyntheticCapture::new(opts, Pattern::Gradient);
+  let first = capture.frame();
+  let second = capture.frame();
+
+  println!("{:?} {:?}", first, second);
+}